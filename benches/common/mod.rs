@@ -0,0 +1,100 @@
+#![allow(dead_code)]
+use std::io::Stdout;
+use std::path::Path;
+use tracing::Subscriber;
+use tracing_appender::non_blocking::{NonBlockingBuilder, WorkerGuard};
+use tracing_subscriber::fmt as tsfmt;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber_serde::bench_util::{InMemoryWriter, InMemoryWriterFlushGuard};
+use tracing_subscriber_serde::SerdeLayerBuilder;
+use tracing_subscriber_serde::{
+    format as sfmt,
+    time::SystemClock,
+    writer::{FlushGuard, NonBlocking},
+    SerdeFormat, SerdeLayer,
+};
+
+pub use tracing_subscriber_serde::bench_util::workloads;
+
+const MB: usize = 0xfffff;
+const WRITE_BUF_SIZE: usize = 200 * MB;
+
+pub type TsJsonLayer<S> = tracing_subscriber::fmt::Layer<
+    S,
+    tsfmt::format::JsonFields,
+    tsfmt::format::Format<tsfmt::format::Json>,
+>;
+
+fn tsjson_layer<S>() -> TsJsonLayer<S> {
+    tracing_subscriber::fmt::Layer::new()
+        .json()
+        .with_target(true)
+        .with_span_list(true)
+        .with_current_span(false)
+        .with_span_events(FmtSpan::FULL)
+}
+
+fn serde_layer<F: SerdeFormat>(f: F) -> SerdeLayerBuilder<F, SystemClock, Stdout> {
+    SerdeLayer::new()
+        .with_clock(SystemClock::default())
+        .with_source_location(false)
+        .with_span_events(FmtSpan::FULL)
+        .with_format(f)
+}
+
+pub fn setup_tsjson_nb() -> (impl Subscriber + Send + Sync + 'static, WorkerGuard) {
+    let (writer, g) =
+        NonBlockingBuilder::default().finish(Vec::<u8>::with_capacity(WRITE_BUF_SIZE));
+
+    let l = tsjson_layer().with_writer(writer);
+    let s = tracing_subscriber::registry().with(l);
+    (s, g)
+}
+
+pub fn setup_serde_json_nb() -> (impl Subscriber + Send + Sync + 'static, FlushGuard) {
+    let (writer, g) = NonBlocking::new().finish(Vec::<u8>::with_capacity(WRITE_BUF_SIZE));
+
+    let l = serde_layer(sfmt::Json).with_writer(writer).finish();
+    let s = tracing_subscriber::registry().with(l);
+    (s, g)
+}
+
+pub fn setup_tsjson(
+    filepath: Option<impl AsRef<Path>>,
+) -> (
+    impl Subscriber + Send + Sync + 'static,
+    Option<InMemoryWriterFlushGuard>,
+) {
+    let (writer, g) = InMemoryWriter::new(filepath);
+    let l = tsjson_layer().with_writer(writer);
+    let s = tracing_subscriber::registry().with(l);
+    (s, g)
+}
+
+pub fn setup_serde_json(
+    filepath: Option<impl AsRef<Path>>,
+) -> (
+    impl Subscriber + Send + Sync + 'static,
+    Option<InMemoryWriterFlushGuard>,
+) {
+    let (writer, g) = InMemoryWriter::new(filepath);
+    let l = serde_layer(sfmt::Json).with_writer(writer).finish();
+    let s = tracing_subscriber::registry().with(l);
+    (s, g)
+}
+
+#[cfg(feature = "messagepack")]
+pub fn setup_serde_messagepack(
+    filepath: Option<impl AsRef<Path>>,
+) -> (
+    impl Subscriber + Send + Sync + 'static,
+    Option<InMemoryWriterFlushGuard>,
+) {
+    let (writer, g) = InMemoryWriter::new(filepath);
+    let l = serde_layer(sfmt::MessagePack::default())
+        .with_writer(writer)
+        .finish();
+    let s = tracing_subscriber::registry().with(l);
+    (s, g)
+}
@@ -0,0 +1,83 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::fmt::Write;
+use tracing_subscriber_serde::bench_util::{generate_fixture, workloads};
+use tracing_subscriber_serde::consumer::{PrettyPrinter, StreamFormat};
+use tracing_subscriber_serde::format::{Json, MessagePack};
+use tracing_subscriber_serde::{Event, SerdeFormat};
+
+fn fixtures<F>(fmt: F) -> Vec<(&'static str, Vec<u8>)>
+where
+    F: SerdeFormat + Copy + Send + Sync + 'static,
+{
+    vec![
+        (
+            "deeply_nested",
+            generate_fixture(fmt, || workloads::deeply_nested((15, 1000))),
+        ),
+        (
+            "long_strings",
+            generate_fixture(fmt, || workloads::long_strings(1000)),
+        ),
+        (
+            "many_fields",
+            generate_fixture(fmt, || workloads::many_fields(1000)),
+        ),
+    ]
+}
+
+fn deserialize<F>(c: &mut Criterion, method: &str, fmt: F)
+where
+    F: SerdeFormat + Copy + Send + Sync + 'static + for<'a> StreamFormat<&'a [u8]>,
+{
+    for (workload, data) in fixtures(fmt) {
+        let num_events = fmt.iter_reader(data.as_slice()).count() as u64;
+        let throughputs = [
+            ("events", Throughput::Elements(num_events)),
+            ("bytes", Throughput::Bytes(data.len() as u64)),
+        ];
+
+        for (unit, throughput) in throughputs {
+            let mut group = c.benchmark_group(format!("de/{}/{}", method, unit));
+            group.throughput(throughput);
+            group.bench_function(BenchmarkId::from_parameter(workload), |b| {
+                b.iter(|| {
+                    for e in fmt.iter_reader(data.as_slice()) {
+                        black_box(e.unwrap());
+                    }
+                })
+            });
+            group.finish();
+        }
+    }
+}
+
+fn comparison(c: &mut Criterion) {
+    deserialize(c, "SerdeJson", Json);
+    deserialize(c, "SerdeMsgPack", MessagePack::struct_as_map());
+    deserialize(c, "SerdeMsgPackArray", MessagePack::struct_as_array());
+}
+
+fn pretty_printing(c: &mut Criterion) {
+    let data = generate_fixture(Json, || workloads::simple(10));
+    let events: Vec<Event> = Json
+        .iter_reader(data.as_slice())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let printer = PrettyPrinter::default();
+
+    let mut group = c.benchmark_group("pprint");
+    group.throughput(Throughput::Elements(events.len() as u64));
+    group.bench_function("simple", |b| {
+        b.iter(|| {
+            let mut output = String::new();
+            for e in &events {
+                write!(&mut output, "{}", printer.fmt(e)).unwrap();
+            }
+            black_box(output)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, comparison, pretty_printing);
+criterion_main!(benches);
@@ -29,6 +29,7 @@ fn comparison(c: &mut Criterion) {
     benchmark!(c, workloads::simple, 5, 10, 100);
     benchmark!(c, workloads::deeply_nested, (15, 10));
     benchmark!(c, workloads::long_strings, 5, 10);
+    benchmark!(c, workloads::repeated_enter, (15, 10));
 }
 
 criterion_group!(benches, comparison);
@@ -0,0 +1,217 @@
+//! Workloads and helpers for benchmarking [`SerdeFormat`] and [`WriteEvent`] implementations.
+//!
+//! These are the same workloads used by this crate's own benchmarks.  Requires the **`bench-util`** feature.
+//!
+//! # Examples
+//! Measure how long a user-defined format takes to serialize the [`simple`](workloads::simple) workload:
+//! ```
+//! use std::io::Write;
+//! use std::time::Instant;
+//! use tracing_subscriber_serde::bench_util::{run_workload_with_layer, workloads};
+//! use tracing_subscriber_serde::{serde::Serialize, SerdeFormat, SerdeLayer};
+//!
+//! struct MyFormat;
+//!
+//! impl SerdeFormat for MyFormat {
+//!     fn message_size_hint(&self) -> usize { 512 }
+//!
+//!     fn serialize(&self, mut buf: impl Write, event: impl Serialize) -> std::io::Result<()> {
+//!         serde_json::to_writer_pretty(&mut buf, &event)?;
+//!         buf.write_all(b"\n")
+//!     }
+//! }
+//!
+//! let start = Instant::now();
+//! let output = run_workload_with_layer(SerdeLayer::new().with_format(MyFormat), || {
+//!     workloads::simple(10)
+//! });
+//! println!("wrote {} bytes in {:?}", output.len(), start.elapsed());
+//! ```
+use crate::time::Clock;
+use crate::{SerdeFormat, SerdeLayer, SerdeLayerBuilder, SpanEvents, WriteEvent};
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::fmt::{writer::MutexGuardWriter, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+
+const MB: usize = 0xfffff;
+const WRITE_BUF_SIZE: usize = 200 * MB;
+
+/// A writer which stores everything written to it in memory.
+///
+/// Implements both [`WriteEvent`] and [`MakeWriter`], so it can be used with
+/// [`SerdeLayer`](crate::SerdeLayer) and [`tracing_subscriber::fmt::Layer`] alike.  Clones share the same buffer.
+#[derive(Clone, Default)]
+pub struct InMemoryWriter {
+    inner: Arc<Mutex<Vec<u8>>>,
+}
+
+/// Writes the contents of an [`InMemoryWriter`] to a file when dropped.
+pub struct InMemoryWriterFlushGuard {
+    inner: Arc<Mutex<Vec<u8>>>,
+    dest: PathBuf,
+}
+
+impl InMemoryWriter {
+    /// Create a new writer with a large pre-allocated buffer, so that re-allocation doesn't skew
+    /// benchmark results.
+    ///
+    /// If a path is given, the returned guard will write the buffer's contents to it when dropped.
+    pub fn new(p: Option<impl AsRef<Path>>) -> (Self, Option<InMemoryWriterFlushGuard>) {
+        let inner = Arc::new(Mutex::new(Vec::with_capacity(WRITE_BUF_SIZE)));
+        let g = p.map(|p| InMemoryWriterFlushGuard {
+            dest: p.as_ref().to_path_buf(),
+            inner: Arc::clone(&inner),
+        });
+        let w = InMemoryWriter { inner };
+        (w, g)
+    }
+
+    /// Take the bytes written so far, leaving the buffer empty.
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.inner.lock().unwrap())
+    }
+}
+
+impl<'a> MakeWriter<'a> for InMemoryWriter {
+    type Writer = MutexGuardWriter<'a, Vec<u8>>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.inner.make_writer()
+    }
+}
+
+impl WriteEvent for InMemoryWriter {
+    fn write(&self, fmt: impl SerdeFormat, record: impl Serialize) -> io::Result<()> {
+        let buf = &mut *self.inner.lock().unwrap();
+        fmt.serialize(buf, record)
+    }
+}
+
+impl Drop for InMemoryWriterFlushGuard {
+    fn drop(&mut self) {
+        let buf = self.inner.lock().expect("poisoned");
+        std::fs::write(&self.dest, buf.as_slice()).unwrap();
+    }
+}
+
+/// Finish configuring `layer` with an [`InMemoryWriter`], run `workload` with it as the default subscriber
+/// and return the bytes written.
+pub fn run_workload_with_layer<F, C, W>(
+    layer: SerdeLayerBuilder<F, C, W>,
+    workload: impl FnOnce(),
+) -> Vec<u8>
+where
+    F: SerdeFormat + Send + Sync + 'static,
+    C: Clock + Send + Sync + 'static,
+    W: WriteEvent,
+{
+    let writer = InMemoryWriter::default();
+    let layer = layer.with_writer(writer.clone()).finish();
+    let subscriber = tracing_subscriber::registry().with(layer);
+    tracing::subscriber::with_default(subscriber, workload);
+    writer.take()
+}
+
+/// Serialize the output of `workload` with `fmt`, for use as input to deserialization benchmarks.
+///
+/// No timestamps, thread information or span IDs are recorded, so the output is identical across runs
+/// and machines.
+pub fn generate_fixture<F>(fmt: F, workload: impl FnOnce()) -> Vec<u8>
+where
+    F: SerdeFormat + Send + Sync + 'static,
+{
+    let layer = SerdeLayer::new()
+        .with_span_events(SpanEvents::FULL)
+        .with_format(fmt);
+    run_workload_with_layer(layer, workload)
+}
+
+/// Workloads which produce a realistic mix of spans and events.
+pub mod workloads {
+    use tracing::*;
+
+    /// A few nested spans with a handful of fields and events of various levels.
+    pub fn simple(iters: usize) {
+        for _ in 0..iters {
+            let _outer = warn_span!("outer", x = 6).entered();
+            for i in 0..10 {
+                let _a = error_span!("a", i, p = "egg").entered();
+                error!(cat = true, bacon = 4, foo = "mao", "hello");
+                let _b = debug_span!("check_for_egg", i).entered();
+                if i % 2 == 0 {
+                    info!("egg");
+                } else {
+                    trace!("no egg")
+                }
+            }
+        }
+    }
+
+    /// Events and spans with long string fields.
+    pub fn long_strings(iters: usize) {
+        let s1 = "x".repeat(100);
+        let s2 = "y".repeat(200);
+        let _outer = warn_span!("outer", x=%s1, y=?s2).entered();
+        for _ in 0..iters {
+            error!(whatever="shall", we="do", x=23, %s1, ?s2);
+        }
+    }
+
+    /// Events with many fields of every type.
+    pub fn many_fields(iters: usize) {
+        let _outer = info_span!("outer", a = 1, b = true, c = "c").entered();
+        for i in 0..iters {
+            info!(
+                f0 = i,
+                f1 = "one",
+                f2 = 2.0,
+                f3 = false,
+                f4 = 4,
+                f5 = "five",
+                f6 = 6.0,
+                f7 = true,
+                f8 = 8,
+                f9 = "nine",
+                f10 = 10.0,
+                f11 = false,
+                f12 = 12,
+                f13 = "thirteen",
+                f14 = 14.0,
+                f15 = true,
+                "many fields"
+            );
+        }
+    }
+
+    /// Events emitted inside `depth` nested spans.
+    pub fn deeply_nested((depth, iters): (usize, usize)) {
+        let mut spans = Vec::with_capacity(depth);
+
+        for k in 0..depth {
+            let s = warn_span!("egg", d = k, hello = "world").entered();
+            spans.push(s);
+        }
+        for _ in 0..iters {
+            error!(whatever = "shall", we = "do", x = 23, "oh no");
+        }
+        for s in spans.drain(..).rev() {
+            drop(s);
+        }
+    }
+
+    /// A span inside `depth` nested spans which is entered and exited `polls` times, as an async task is
+    /// each time it is polled.
+    pub fn repeated_enter((depth, polls): (usize, usize)) {
+        let mut parent = warn_span!("egg", d = 0, hello = "world");
+        for k in 1..depth {
+            parent = warn_span!(parent: &parent, "egg", d = k, hello = "world");
+        }
+        let task = info_span!(parent: &parent, "task", polls);
+        for _ in 0..polls {
+            let _poll = task.enter();
+        }
+    }
+}
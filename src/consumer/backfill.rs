@@ -0,0 +1,324 @@
+use crate::{Event, EventKind, FieldValue};
+use indexmap::IndexMap;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::num::NonZeroU64;
+
+/// A span field which was recorded with more than one value, so it could not be backfilled.  See
+/// [`backfill_span_fields`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct AmbiguousField {
+    /// The ID of the span
+    pub span_id: NonZeroU64,
+    /// The name of the span
+    pub span_name: String,
+    /// The name of the field
+    pub field: String,
+    /// The values of the field, in the order they were recorded
+    pub values: Vec<FieldValue>,
+}
+
+/// Copy the final value of each span field into the events of the span which came before the value was
+/// recorded, e.g. fields declared [`Empty`](tracing::field::Empty) and later recorded with
+/// [`Span::record`](tracing::Span::record).  Returns the events, and the fields which were recorded with
+/// more than one value.  Those fields are not backfilled.
+///
+/// Spans are told apart by their IDs, so the events must be recorded with
+/// [`SerdeLayerBuilder::with_span_ids`](crate::SerdeLayerBuilder::with_span_ids).  Since IDs may be re-used,
+/// a span ends at its [`EventKind::SpanClose`] event and begins at its [`EventKind::SpanCreate`] event, if
+/// these were recorded.  Spans without an ID are left unchanged.  Values which were replaced before the next
+/// event in the span are only seen if recorded with
+/// [`SerdeLayerBuilder::with_span_records`](crate::SerdeLayerBuilder::with_span_records).
+///
+/// See [`backfill_span_fields_windowed`] to backfill a stream of events.
+pub fn backfill_span_fields(events: Vec<Event>) -> (Vec<Event>, Vec<AmbiguousField>) {
+    let window = events.len();
+    let mut backfill = backfill_span_fields_windowed(events.into_iter().map(Ok), window);
+    let events = backfill
+        .by_ref()
+        .map(|e| e.expect("events are infallible"))
+        .collect();
+    (events, backfill.ambiguous)
+}
+
+/// Like [`backfill_span_fields`], but holds back at most `window` events at a time.  Events are only
+/// backfilled with values recorded before they leave the window.  Errors are passed through immediately.
+///
+/// Ambiguous fields are available from [`BackfillSpanFields::ambiguous`] once their span has closed, or
+/// the stream has ended.
+pub fn backfill_span_fields_windowed<I>(events: I, window: usize) -> BackfillSpanFields<I::IntoIter>
+where
+    I: IntoIterator<Item = io::Result<Event>>,
+{
+    BackfillSpanFields {
+        events: events.into_iter(),
+        window: window.max(1),
+        buffer: VecDeque::new(),
+        open: HashMap::new(),
+        spans: HashMap::new(),
+        next_instance: 0,
+        ambiguous: Vec::new(),
+        done: false,
+    }
+}
+
+#[derive(Default)]
+struct SpanState {
+    id: Option<NonZeroU64>,
+    name: String,
+    /// The distinct values of each field, in the order they were recorded
+    fields: IndexMap<String, Vec<FieldValue>>,
+}
+
+impl SpanState {
+    fn observe<'a>(&mut self, fields: impl IntoIterator<Item = (&'a String, &'a FieldValue)>) {
+        for (name, val) in fields {
+            let values = self.fields.entry(name.clone()).or_default();
+            if values.last() != Some(val) {
+                values.push(val.clone());
+            }
+        }
+    }
+}
+
+struct Buffered {
+    event: Event,
+    /// The instance of each of the event's spans
+    instances: Vec<Option<u64>>,
+    /// The instance closed by this event
+    closes: Option<u64>,
+}
+
+/// An iterator which backfills span fields.  Created with [`backfill_span_fields_windowed`].
+pub struct BackfillSpanFields<I> {
+    events: I,
+    window: usize,
+    buffer: VecDeque<Buffered>,
+    /// The current instance of each open span ID
+    open: HashMap<NonZeroU64, u64>,
+    spans: HashMap<u64, SpanState>,
+    next_instance: u64,
+    ambiguous: Vec<AmbiguousField>,
+    done: bool,
+}
+
+impl<I> BackfillSpanFields<I> {
+    /// The fields found to be ambiguous so far.
+    pub fn ambiguous(&self) -> &[AmbiguousField] {
+        &self.ambiguous
+    }
+
+    fn push(&mut self, event: Event) {
+        let innermost = event.spans.len().checked_sub(1);
+        let mut instances = Vec::with_capacity(event.spans.len());
+        for (k, span) in event.spans.iter().enumerate() {
+            let id = match span.id {
+                Some(id) => id,
+                None => {
+                    instances.push(None);
+                    continue;
+                }
+            };
+            let created = event.kind == EventKind::SpanCreate && Some(k) == innermost;
+            let instance = match self.open.get(&id) {
+                Some(&instance) if !created => instance,
+                _ => {
+                    let instance = self.next_instance;
+                    self.next_instance += 1;
+                    self.open.insert(id, instance);
+                    self.spans.insert(
+                        instance,
+                        SpanState {
+                            id: Some(id),
+                            name: span.name.clone(),
+                            ..SpanState::default()
+                        },
+                    );
+                    instance
+                }
+            };
+            let state = self.spans.get_mut(&instance).unwrap();
+            state.observe(&span.fields);
+            if let (EventKind::SpanRecord(fields), true) = (&event.kind, Some(k) == innermost) {
+                state.observe(fields);
+            }
+            instances.push(Some(instance));
+        }
+
+        let mut closes = None;
+        if let EventKind::SpanClose(_) = event.kind {
+            if let Some(id) = event.spans.last().and_then(|s| s.id) {
+                closes = self.open.remove(&id);
+            }
+        }
+
+        self.buffer.push_back(Buffered {
+            event,
+            instances,
+            closes,
+        });
+    }
+
+    fn pop(&mut self) -> Option<Event> {
+        let Buffered {
+            mut event,
+            instances,
+            closes,
+        } = self.buffer.pop_front()?;
+
+        for (span, instance) in event.spans.iter_mut().zip(instances) {
+            let state = match instance.and_then(|i| self.spans.get(&i)) {
+                Some(state) => state,
+                None => continue,
+            };
+            for (name, values) in &state.fields {
+                if let [value] = values.as_slice() {
+                    if !span.fields.contains_key(name) {
+                        span.fields.insert(name.clone(), value.clone());
+                    }
+                }
+            }
+        }
+
+        if let Some(state) = closes.and_then(|i| self.spans.remove(&i)) {
+            self.report(state);
+        }
+        Some(event)
+    }
+
+    fn report(&mut self, state: SpanState) {
+        let id = match state.id {
+            Some(id) => id,
+            None => return,
+        };
+        for (field, values) in state.fields {
+            if values.len() > 1 {
+                self.ambiguous.push(AmbiguousField {
+                    span_id: id,
+                    span_name: state.name.clone(),
+                    field,
+                    values,
+                });
+            }
+        }
+    }
+}
+
+impl<I> Iterator for BackfillSpanFields<I>
+where
+    I: Iterator<Item = io::Result<Event>>,
+{
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done && self.buffer.len() < self.window {
+            match self.events.next() {
+                Some(Ok(e)) => self.push(e),
+                Some(Err(e)) => return Some(Err(e)),
+                None => self.done = true,
+            }
+        }
+
+        let event = self.pop();
+        if self.done && self.buffer.is_empty() && !self.spans.is_empty() {
+            // The stream has ended: spans which never closed are complete
+            let mut spans: Vec<_> = self.spans.drain().collect();
+            spans.sort_by_key(|(instance, _)| *instance);
+            for (_, state) in spans {
+                self.report(state);
+            }
+            self.open.clear();
+        }
+        event.map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::capture;
+    use crate::{SerdeLayer, SpanEvents};
+
+    fn recorded() -> Vec<Event> {
+        let layer = SerdeLayer::new()
+            .with_span_events(SpanEvents::NEW | SpanEvents::CLOSE)
+            .with_span_ids(true)
+            .with_span_records(true);
+        capture(layer, || {
+            let request = tracing::info_span!("request", user = tracing::field::Empty);
+            {
+                let _entered = request.enter();
+                tracing::info!("early");
+                request.record("user", &"alice");
+                tracing::info!("late");
+            }
+            drop(request);
+
+            let retry = tracing::info_span!("retry", attempt = tracing::field::Empty);
+            let _entered = retry.enter();
+            tracing::info!("first");
+            retry.record("attempt", &1);
+            retry.record("attempt", &2);
+            tracing::info!("second");
+        })
+    }
+
+    fn message(e: &Event) -> Option<&FieldValue> {
+        match &e.kind {
+            EventKind::Event(fields) => fields.get("message"),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn backfills_recorded_fields() {
+        let events = recorded();
+        let early = events
+            .iter()
+            .find(|e| message(e) == Some(&"early".into()))
+            .unwrap();
+        assert!(early.spans[0].fields.is_empty());
+
+        let (events, ambiguous) = backfill_span_fields(events);
+        for e in &events[..4] {
+            assert_eq!(e.spans[0].name, "request");
+            assert_eq!(e.spans[0].fields["user"], "alice".into(), "{:?}", e);
+        }
+
+        assert_eq!(ambiguous.len(), 1);
+        assert_eq!(ambiguous[0].span_name, "retry");
+        assert_eq!(ambiguous[0].field, "attempt");
+        assert_eq!(
+            ambiguous[0].values,
+            vec![FieldValue::Int(1), FieldValue::Int(2)]
+        );
+        let first = events
+            .iter()
+            .find(|e| message(e) == Some(&"first".into()))
+            .unwrap();
+        assert!(first.spans[0].fields.is_empty());
+    }
+
+    #[test]
+    fn windowed() {
+        let events = recorded();
+        let (batch, _) = backfill_span_fields(events.clone());
+
+        let mut windowed = backfill_span_fields_windowed(events.iter().cloned().map(Ok), 100);
+        let streamed: Vec<_> = windowed.by_ref().collect::<io::Result<_>>().unwrap();
+        assert_eq!(windowed.ambiguous().len(), 1);
+        for (a, b) in batch.iter().zip(&streamed) {
+            assert_eq!(a.spans[0].fields, b.spans[0].fields);
+        }
+
+        // The early event leaves a window of one before the value is recorded
+        let streamed: Vec<_> = backfill_span_fields_windowed(events.into_iter().map(Ok), 1)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        let early = streamed
+            .iter()
+            .find(|e| message(e) == Some(&"early".into()))
+            .unwrap();
+        assert!(early.spans[0].fields.is_empty());
+    }
+}
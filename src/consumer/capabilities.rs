@@ -0,0 +1,217 @@
+use crate::{Event, EventKind};
+use std::fmt;
+
+/// Information which may or may not have been recorded in a stream of events, depending on the
+/// configuration of the [`SerdeLayer`](crate::SerdeLayer) which produced it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Capability {
+    /// [`EventKind::SpanCreate`] events, see [`SpanEvents::NEW`](crate::SpanEvents::NEW)
+    SpanCreate,
+    /// [`EventKind::SpanClose`] events, see [`SpanEvents::CLOSE`](crate::SpanEvents::CLOSE)
+    SpanClose,
+    /// [`EventKind::SpanEnter`] events, see [`SpanEvents::ENTER`](crate::SpanEvents::ENTER)
+    SpanEnter,
+    /// [`EventKind::SpanExit`] events, see [`SpanEvents::EXIT`](crate::SpanEvents::EXIT)
+    SpanExit,
+    /// [`EventKind::SpanRecord`] events, see
+    /// [`SerdeLayerBuilder::with_span_records`](crate::SerdeLayerBuilder::with_span_records)
+    SpanRecord,
+    /// [`EventKind::SpanFollowsFrom`] events, see
+    /// [`SerdeLayerBuilder::with_span_follows_from`](crate::SerdeLayerBuilder::with_span_follows_from)
+    SpanFollowsFrom,
+    /// Span busy/idle times, see [`SerdeLayerBuilder::with_time_spans`](crate::SerdeLayerBuilder::with_time_spans)
+    SpanTimes,
+    /// Span IDs, see [`SerdeLayerBuilder::with_span_ids`](crate::SerdeLayerBuilder::with_span_ids)
+    SpanIds,
+    /// Event timestamps, see [`SerdeLayerBuilder::with_clock`](crate::SerdeLayerBuilder::with_clock)
+    Timestamps,
+    /// Source file and line numbers, see
+    /// [`SerdeLayerBuilder::with_source_location`](crate::SerdeLayerBuilder::with_source_location)
+    SourceLocation,
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Capability::SpanCreate => "span create events",
+            Capability::SpanClose => "span close events",
+            Capability::SpanEnter => "span enter events",
+            Capability::SpanExit => "span exit events",
+            Capability::SpanRecord => "span record events",
+            Capability::SpanFollowsFrom => "span follows-from events",
+            Capability::SpanTimes => "span times",
+            Capability::SpanIds => "span IDs",
+            Capability::Timestamps => "timestamps",
+            Capability::SourceLocation => "source locations",
+        };
+        f.write_str(s)
+    }
+}
+
+/// The information a stream of events was found to contain.  Created with [`StreamCapabilities::detect`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StreamCapabilities {
+    /// Whether any event occurred inside a span.  If not, a stream without span events is still complete.
+    pub has_spans: bool,
+    found: Vec<Capability>,
+}
+
+impl StreamCapabilities {
+    /// Infer the capabilities of a stream by inspecting its events, typically the first few hundred.
+    ///
+    /// A capability is only considered present if at least one event shows evidence of it, so too short a
+    /// prefix may miss some.
+    pub fn detect<'a>(events: impl IntoIterator<Item = &'a Event>) -> Self {
+        let mut caps = StreamCapabilities::default();
+        for e in events {
+            caps.has_spans |= !e.spans.is_empty();
+            match &e.kind {
+                EventKind::SpanCreate => caps.add(Capability::SpanCreate),
+                EventKind::SpanClose(times) => {
+                    caps.add(Capability::SpanClose);
+                    if times.is_some() {
+                        caps.add(Capability::SpanTimes);
+                    }
+                }
+                EventKind::SpanProgress(_) => caps.add(Capability::SpanTimes),
+                EventKind::SpanEnter => caps.add(Capability::SpanEnter),
+                EventKind::SpanExit => caps.add(Capability::SpanExit),
+                EventKind::SpanRecord(_) => caps.add(Capability::SpanRecord),
+                EventKind::SpanFollowsFrom { .. } => caps.add(Capability::SpanFollowsFrom),
+                EventKind::Event(_) => {}
+            }
+            if e.spans.iter().any(|s| s.id.is_some()) {
+                caps.add(Capability::SpanIds);
+            }
+            if e.time.is_some() {
+                caps.add(Capability::Timestamps);
+            }
+            if e.src_file.is_some() || e.src_line.is_some() {
+                caps.add(Capability::SourceLocation);
+            }
+        }
+        caps
+    }
+
+    fn add(&mut self, c: Capability) {
+        if !self.found.contains(&c) {
+            self.found.push(c);
+        }
+    }
+
+    /// Whether the stream was found to have the capability.
+    pub fn has(&self, c: Capability) -> bool {
+        self.found.contains(&c)
+    }
+
+    /// Check the stream has all the `required` capabilities.
+    pub fn require(&self, required: &[Capability]) -> Result<(), UnsupportedStream> {
+        let missing: Vec<_> = required.iter().copied().filter(|&c| !self.has(c)).collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(UnsupportedStream { missing })
+        }
+    }
+}
+
+/// The error returned when a stream of events lacks information needed by a consumer.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnsupportedStream {
+    /// The capabilities which are required but were not found
+    pub missing: Vec<Capability>,
+}
+
+impl fmt::Display for UnsupportedStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("stream does not contain ")?;
+        for (k, c) in self.missing.iter().enumerate() {
+            if k > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnsupportedStream {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::SpanTree;
+    use crate::format::Json;
+    use crate::test_utils::capture;
+    use crate::time::SystemClock;
+    use crate::{SerdeLayer, SerdeLayerBuilder, SpanEvents};
+    use std::io::Stdout;
+
+    fn run<C>(layer: SerdeLayerBuilder<Json, C, Stdout>) -> Vec<Event>
+    where
+        C: crate::time::Clock + Send + Sync + 'static,
+    {
+        capture(layer, || {
+            let _outer = tracing::info_span!("outer").entered();
+            tracing::info!("hello");
+        })
+    }
+
+    #[test]
+    fn inferred() {
+        let events = run(SerdeLayer::new()
+            .with_span_events(SpanEvents::FULL)
+            .with_time_spans(true)
+            .with_span_ids(true)
+            .with_clock(SystemClock::default()));
+        let caps = StreamCapabilities::detect(&events);
+        assert!(caps.has_spans);
+        for &c in &[
+            Capability::SpanCreate,
+            Capability::SpanClose,
+            Capability::SpanEnter,
+            Capability::SpanExit,
+            Capability::SpanTimes,
+            Capability::SpanIds,
+            Capability::Timestamps,
+            Capability::SourceLocation,
+        ] {
+            assert!(caps.has(c), "{:?}", c);
+        }
+        assert_eq!(SpanTree::try_from_events(&events).unwrap().nodes().len(), 1);
+    }
+
+    #[test]
+    fn missing() {
+        let events = run(SerdeLayer::new().with_source_location(false));
+        let caps = StreamCapabilities::detect(&events);
+        assert!(caps.has_spans);
+        let err = caps
+            .require(&[Capability::SpanCreate, Capability::Timestamps])
+            .unwrap_err();
+        assert_eq!(
+            err.missing,
+            vec![Capability::SpanCreate, Capability::Timestamps]
+        );
+        assert_eq!(
+            err.to_string(),
+            "stream does not contain span create events, timestamps"
+        );
+
+        let err = SpanTree::try_from_events(&events).unwrap_err();
+        assert_eq!(err.missing, vec![Capability::SpanCreate]);
+    }
+
+    #[test]
+    fn no_spans() {
+        let events: Vec<_> = run(SerdeLayer::new())
+            .into_iter()
+            .map(|mut e| {
+                e.spans.clear();
+                e
+            })
+            .collect();
+        assert!(!StreamCapabilities::detect(&events).has_spans);
+        assert!(SpanTree::try_from_events(&events).is_ok());
+    }
+}
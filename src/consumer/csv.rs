@@ -0,0 +1,139 @@
+use crate::{Event, FieldValue};
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+/// A column of the CSV written by [`to_csv`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CsvColumn {
+    /// The timestamp in RFC 3339 format.  The header is `time`.
+    Time,
+    /// The level, e.g. `INFO`.  The header is `level`.
+    Level,
+    /// The target.  The header is `target`.
+    Target,
+    /// The `message` field.  The header is `message`.
+    Message,
+    /// The value of a field, looked up with [`Event::field`], so it may belong to the event or any of its
+    /// spans.  The header is the name of the field.
+    Field(String),
+}
+
+impl CsvColumn {
+    /// A [`CsvColumn::Field`] column.
+    pub fn field(name: impl Into<String>) -> Self {
+        CsvColumn::Field(name.into())
+    }
+
+    fn header(&self) -> &str {
+        match self {
+            CsvColumn::Time => "time",
+            CsvColumn::Level => "level",
+            CsvColumn::Target => "target",
+            CsvColumn::Message => "message",
+            CsvColumn::Field(name) => name,
+        }
+    }
+
+    fn value<'a>(&self, event: &'a Event) -> Option<Cow<'a, str>> {
+        match self {
+            CsvColumn::Time => event.time.as_ref().map(|t| t.to_rfc3339().into()),
+            CsvColumn::Level => Some(tracing::Level::from(event.level).as_str().into()),
+            CsvColumn::Target => Some(event.target.as_str().into()),
+            CsvColumn::Message => event.field("message").and_then(cell),
+            CsvColumn::Field(name) => event.field(name).and_then(cell),
+        }
+    }
+}
+
+fn cell(v: &FieldValue) -> Option<Cow<'_, str>> {
+    Some(match v {
+        FieldValue::Bool(b) => b.to_string().into(),
+        FieldValue::Int(i) => i.to_string().into(),
+        FieldValue::UInt(i) => i.to_string().into(),
+        FieldValue::I128(i) => i.to_string().into(),
+        FieldValue::U128(i) => i.to_string().into(),
+        FieldValue::Float(f) => f.to_string().into(),
+        FieldValue::Str(s) => s.as_str().into(),
+        FieldValue::Null => return None,
+    })
+}
+
+/// Write a cell, quoting it if it contains a comma, quote or line break, as described in
+/// [RFC 4180](https://datatracker.ietf.org/doc/html/rfc4180).
+fn write_cell(w: &mut impl Write, s: &str) -> io::Result<()> {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        write!(w, "\"{}\"", s.replace('"', "\"\""))
+    } else {
+        w.write_all(s.as_bytes())
+    }
+}
+
+fn write_row<'a>(
+    w: &mut impl Write,
+    cells: impl Iterator<Item = Option<Cow<'a, str>>>,
+) -> io::Result<()> {
+    for (k, c) in cells.enumerate() {
+        if k > 0 {
+            w.write_all(b",")?;
+        }
+        if let Some(c) = c {
+            write_cell(w, &c)?;
+        }
+    }
+    w.write_all(b"\r\n")
+}
+
+/// Write events as CSV, e.g. for analysis in a spreadsheet.  The first row is a header with the name of each
+/// column, followed by one row per event.  Values which an event doesn't have, such as a missing field or
+/// timestamp, are written as empty cells.
+///
+/// Span events are included: filter the events first to leave them out.
+pub fn to_csv(
+    events: impl IntoIterator<Item = io::Result<Event>>,
+    columns: &[CsvColumn],
+    mut w: impl Write,
+) -> io::Result<()> {
+    write_row(&mut w, columns.iter().map(|c| Some(c.header().into())))?;
+    for event in events {
+        let event = event?;
+        write_row(&mut w, columns.iter().map(|c| c.value(&event)))?;
+    }
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::capture;
+    use crate::time::{ManualClock, UnixTime};
+    use crate::SerdeLayer;
+    use std::time::Duration;
+
+    #[test]
+    fn export() {
+        let clock = ManualClock::new(UnixTime::from(Duration::from_secs(1643619900)));
+        let layer = SerdeLayer::new().with_clock(clock);
+        let events = capture(layer, || {
+            let _span = tracing::info_span!("request", user = "ann").entered();
+            tracing::info!(target: "app", status = 200, "ok");
+            tracing::warn!(target: "app", "said \"hi\", twice");
+        });
+
+        let mut csv = Vec::new();
+        let columns = [
+            CsvColumn::Time,
+            CsvColumn::Level,
+            CsvColumn::Target,
+            CsvColumn::Message,
+            CsvColumn::field("status"),
+            CsvColumn::field("user"),
+        ];
+        to_csv(events.into_iter().map(Ok), &columns, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "time,level,target,message,status,user\r\n\
+             2022-01-31T09:05:00.000000000Z,INFO,app,ok,200,ann\r\n\
+             2022-01-31T09:05:00.000000000Z,WARN,app,\"said \"\"hi\"\", twice\",,ann\r\n"
+        );
+    }
+}
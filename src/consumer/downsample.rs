@@ -0,0 +1,119 @@
+use crate::rng::SplitMix64;
+use crate::{Event, Level};
+use std::io;
+
+/// Uniformly sample `target_n` events, e.g. to share a representative part of a large log.  The events
+/// are returned in their original order.
+///
+/// If `keep_errors` is set, every [`Level::Error`] event is kept, and the other events are sampled to make
+/// up the rest of `target_n`.  If there are more than `target_n` errors, all of them are returned.
+///
+/// Uses reservoir sampling, so only the sample and the errors are held in memory.  The sample is the same each time for
+/// the same events.  Returns the first error from reading the events.
+pub fn downsample(
+    events: impl IntoIterator<Item = io::Result<Event>>,
+    target_n: usize,
+    keep_errors: bool,
+) -> io::Result<impl Iterator<Item = Event>> {
+    let mut rng = SplitMix64::new(0);
+    let mut errors = Vec::new();
+    // Events are stored with their position in the stream, to restore the order
+    let mut reservoir = Vec::new();
+    let mut seen = 0;
+
+    for (k, event) in events.into_iter().enumerate() {
+        let event = event?;
+        if keep_errors && event.level == Level::Error {
+            errors.push((k, event));
+            continue;
+        }
+        seen += 1;
+        if reservoir.len() < target_n {
+            reservoir.push((k, event));
+        } else {
+            let j = rng.below(seen);
+            if j < target_n {
+                reservoir[j] = (k, event);
+            }
+        }
+    }
+
+    // A uniformly random subset of a uniform sample is still uniform
+    let keep = target_n.saturating_sub(errors.len()).min(reservoir.len());
+    for i in 0..keep {
+        let j = i + rng.below(reservoir.len() - i);
+        reservoir.swap(i, j);
+    }
+    reservoir.truncate(keep);
+
+    reservoir.extend(errors);
+    reservoir.sort_unstable_by_key(|(k, _)| *k);
+    Ok(reservoir.into_iter().map(|(_, event)| event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(n: usize) -> Vec<Event> {
+        (0..n)
+            .map(|i| {
+                let level = if i % 50 == 7 { 4 } else { 2 };
+                serde_json::from_str(&format!(
+                    r#"{{"ty":{{"event":{{"i":{}}}}},"l":{},"s":[],"t":"test"}}"#,
+                    i, level
+                ))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn index(e: &Event) -> i64 {
+        match &e.kind {
+            crate::EventKind::Event(fields) => match fields["i"] {
+                crate::FieldValue::Int(i) => i,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn keeps_errors() {
+        let events = events(1000);
+        let sample: Vec<_> = downsample(events.iter().cloned().map(Ok), 100, true)
+            .unwrap()
+            .collect();
+        assert_eq!(sample.len(), 100);
+        let errors = sample.iter().filter(|e| e.level == Level::Error).count();
+        assert_eq!(errors, 20);
+
+        let indices: Vec<_> = sample.iter().map(index).collect();
+        assert!(indices.windows(2).all(|w| w[0] < w[1]), "out of order");
+        // The sample is spread over the whole input
+        let non_errors = || sample.iter().filter(|e| e.level != Level::Error).map(index);
+        assert!(non_errors().any(|i| i < 250));
+        assert!(non_errors().any(|i| i >= 750));
+
+        let again: Vec<_> = downsample(events.iter().cloned().map(Ok), 100, true)
+            .unwrap()
+            .map(|e| index(&e))
+            .collect();
+        assert_eq!(indices, again);
+    }
+
+    #[test]
+    fn edge_cases() {
+        let sample = |n, target, keep_errors| {
+            downsample(events(n).into_iter().map(Ok), target, keep_errors)
+                .unwrap()
+                .count()
+        };
+        // More errors than the target
+        assert_eq!(sample(1000, 10, true), 20);
+        assert_eq!(sample(1000, 10, false), 10);
+        // Fewer events than the target
+        assert_eq!(sample(30, 100, true), 30);
+        assert_eq!(sample(1000, 0, false), 0);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{Event, EventKind, FieldValue};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+type Lookup = dyn Fn(&FieldValue) -> Option<FieldValue> + Send + Sync;
+
+/// Adds a field to events by looking up the value of another field.  See [`enrich`].
+pub struct EnrichRule {
+    source: String,
+    dest: String,
+    lookup: Box<Lookup>,
+    default: Option<FieldValue>,
+}
+
+impl fmt::Debug for EnrichRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnrichRule")
+            .field("source", &self.source)
+            .field("dest", &self.dest)
+            .field("default", &self.default)
+            .finish()
+    }
+}
+
+impl EnrichRule {
+    /// Look up the value of the field `source` in `table`, and insert the result as the field `dest`.
+    pub fn map(
+        source: impl Into<String>,
+        dest: impl Into<String>,
+        table: HashMap<FieldValue, FieldValue>,
+    ) -> Self {
+        EnrichRule::with(source, dest, move |v| table.get(v).cloned())
+    }
+
+    /// Like [`EnrichRule::map`], but look up values with a closure.  Values for which `lookup` returns `None`
+    /// are treated as missing.
+    pub fn with(
+        source: impl Into<String>,
+        dest: impl Into<String>,
+        lookup: impl Fn(&FieldValue) -> Option<FieldValue> + Send + Sync + 'static,
+    ) -> Self {
+        EnrichRule {
+            source: source.into(),
+            dest: dest.into(),
+            lookup: Box::new(lookup),
+            default: None,
+        }
+    }
+
+    /// Insert `value` as the field `dest` when the value of `source` is not in the table.  By default,
+    /// nothing is inserted.
+    pub fn default(mut self, value: impl Into<FieldValue>) -> Self {
+        self.default = Some(value.into());
+        self
+    }
+
+    /// Enrich `fields` if they contain the source field.  Returns `false` if they don't.
+    fn apply(&self, fields: &mut IndexMap<String, FieldValue>) -> bool {
+        let val = match fields.get(&self.source) {
+            Some(val) => val,
+            None => return false,
+        };
+        if let Some(val) = (self.lookup)(val).or_else(|| self.default.clone()) {
+            fields.insert(self.dest.clone(), val);
+        }
+        true
+    }
+}
+
+/// Add fields to events by looking up the values of other fields, e.g. to add customer names to events
+/// with a `customer_id`, before sharing logs.
+///
+/// Each rule looks for its source field in the fields of [`EventKind::Event`] events first, and then in
+/// the event's spans, from innermost to outermost.  The destination field is added next to the source
+/// field: to the event's fields, or to the fields of the span the source was found on.  Values missing
+/// from the lookup table are skipped, unless the rule has a [default](EnrichRule::default).  Events without
+/// the source field are passed on unchanged.
+///
+/// Rules are applied in order, so a rule can use a field added by an earlier one.
+pub fn enrich<I>(events: I, rules: Vec<EnrichRule>) -> Enrich<I::IntoIter>
+where
+    I: IntoIterator<Item = io::Result<Event>>,
+{
+    Enrich {
+        events: events.into_iter(),
+        rules,
+    }
+}
+
+/// An iterator which adds fields to events.  Created with [`enrich`].
+pub struct Enrich<I> {
+    events: I,
+    rules: Vec<EnrichRule>,
+}
+
+impl<I> Iterator for Enrich<I>
+where
+    I: Iterator<Item = io::Result<Event>>,
+{
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut event = match self.events.next()? {
+            Ok(e) => e,
+            Err(e) => return Some(Err(e)),
+        };
+
+        for rule in &self.rules {
+            if let EventKind::Event(fields) = &mut event.kind {
+                if rule.apply(fields) {
+                    continue;
+                }
+            }
+            for span in event.spans.iter_mut().rev() {
+                if rule.apply(&mut span.fields) {
+                    break;
+                }
+            }
+        }
+
+        Some(Ok(event))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.events.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::capture;
+    use crate::{SerdeLayer, SpanEvents};
+
+    fn recorded() -> Vec<Event> {
+        let layer = SerdeLayer::new().with_span_events(SpanEvents::NEW);
+        capture(layer, || {
+            let _request = tracing::info_span!("request", customer_id = 1).entered();
+            tracing::info!(region = "eu-w", "in span");
+            tracing::info!(customer_id = 2, region = "us-e", "overridden");
+            tracing::info!(customer_id = 3, region = "xx", "unknown");
+        })
+    }
+
+    fn rules(region_default: bool) -> Vec<EnrichRule> {
+        let customers = vec![(1.into(), "Acme".into()), (2.into(), "Globex".into())]
+            .into_iter()
+            .collect();
+        let region = EnrichRule::with("region", "region_name", |v| match v {
+            FieldValue::Str(s) if s == "eu-w" => Some("Europe (West)".into()),
+            FieldValue::Str(s) if s == "us-e" => Some("US (East)".into()),
+            _ => None,
+        });
+        let region = if region_default {
+            region.default("unknown")
+        } else {
+            region
+        };
+        vec![
+            EnrichRule::map("customer_id", "customer", customers),
+            region,
+        ]
+    }
+
+    fn fields(e: &Event) -> &IndexMap<String, FieldValue> {
+        match &e.kind {
+            EventKind::Event(fields) => fields,
+            _ => panic!("not an event"),
+        }
+    }
+
+    #[test]
+    fn event_and_span_sources() {
+        let events: Vec<_> = enrich(recorded().into_iter().map(Ok), rules(false))
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        // Span events are enriched from their spans
+        assert_eq!(events[0].kind, EventKind::SpanCreate);
+        assert_eq!(events[0].spans[0].fields["customer"], "Acme".into());
+
+        let in_span = &events[1];
+        assert_eq!(in_span.spans[0].fields["customer"], "Acme".into());
+        assert!(!fields(in_span).contains_key("customer"));
+        assert_eq!(fields(in_span)["region_name"], "Europe (West)".into());
+
+        // The event's own field takes precedence
+        let overridden = &events[2];
+        assert_eq!(fields(overridden)["customer"], "Globex".into());
+        assert_eq!(fields(overridden)["region_name"], "US (East)".into());
+    }
+
+    #[test]
+    fn missing_keys() {
+        let events: Vec<_> = enrich(recorded().into_iter().map(Ok), rules(false))
+            .collect::<io::Result<_>>()
+            .unwrap();
+        let unknown = fields(&events[3]);
+        assert!(!unknown.contains_key("customer"));
+        assert!(!unknown.contains_key("region_name"));
+
+        let events: Vec<_> = enrich(recorded().into_iter().map(Ok), rules(true))
+            .collect::<io::Result<_>>()
+            .unwrap();
+        let unknown = fields(&events[3]);
+        assert!(!unknown.contains_key("customer"));
+        assert_eq!(unknown["region_name"], "unknown".into());
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{Event, EventKind, FieldValue};
+use indexmap::IndexMap;
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+/// Write each event as a flat JSON object on its own line, for querying with tools such as
+/// [`jq`](https://jqlang.github.io/jq/).
+///
+/// This is a lossy convenience form for querying: it cannot be read back as [`Event`]s.  Each object has
+/// the following keys, where present:
+/// - `kind`: `event` for regular events, otherwise the kind of span event, e.g. `span_create`
+/// - `level`, e.g. `INFO`, and `target`
+/// - `message`, and each other field of the event as `fields.<name>`.  The fields recorded by
+///   [`EventKind::SpanRecord`] events are also written as `fields.<name>`.
+/// - `span_path`, the names of the spans from outermost to innermost, separated by `:`
+/// - each field of each span as `span.<depth>.<name>`, where the outermost span has depth `0`
+/// - each span field merged as `ctx.<name>`.  If several spans have a field with the same name, the
+///   innermost span's value is used.
+/// - `time_ms`, the timestamp in milliseconds since the Unix epoch, and `time`, the timestamp in RFC 3339
+///   format
+/// - `thread_id`, `thread_name`, `src_file`, `src_line`, `crate_name` and `crate_version`
+///
+/// Integers which do not fit in 64 bits are written as strings.
+///
+/// For example, to find the events which happened while handling a request:
+/// ```text
+/// jq 'select(."ctx.request_id" == 42) | .message' events.jsonl
+/// ```
+pub fn flatten_for_query(
+    events: impl IntoIterator<Item = io::Result<Event>>,
+    mut w: impl Write,
+) -> io::Result<()> {
+    for event in events {
+        serde_json::to_writer(&mut w, &flatten(&event?))?;
+        w.write_all(b"\n")?;
+    }
+    w.flush()
+}
+
+fn flatten(event: &Event) -> IndexMap<String, Value> {
+    let mut obj = IndexMap::new();
+    let kind = match &event.kind {
+        EventKind::Event(_) => "event",
+        EventKind::SpanCreate => "span_create",
+        EventKind::SpanClose(_) => "span_close",
+        EventKind::SpanProgress(_) => "span_progress",
+        EventKind::SpanEnter => "span_enter",
+        EventKind::SpanExit => "span_exit",
+        EventKind::SpanRecord(_) => "span_record",
+        EventKind::SpanFollowsFrom { .. } => "span_follows_from",
+    };
+    obj.insert("kind".into(), kind.into());
+    obj.insert(
+        "level".into(),
+        tracing::Level::from(event.level).as_str().into(),
+    );
+    obj.insert("target".into(), event.target.as_str().into());
+
+    match &event.kind {
+        EventKind::Event(fields) | EventKind::SpanRecord(fields) => {
+            for (name, val) in fields {
+                let key = match name.as_str() {
+                    "message" => name.clone(),
+                    _ => format!("fields.{}", name),
+                };
+                obj.insert(key, value(val));
+            }
+        }
+        EventKind::SpanFollowsFrom { from, to } => {
+            obj.insert("follows_from".into(), from.get().into());
+            obj.insert("span_id".into(), to.get().into());
+        }
+        _ => {}
+    }
+
+    let span_path: Vec<_> = event.spans.iter().map(|s| s.name.as_str()).collect();
+    obj.insert("span_path".into(), span_path.join(":").into());
+    let mut ctx = IndexMap::new();
+    for (depth, span) in event.spans.iter().enumerate() {
+        for (name, val) in &span.fields {
+            obj.insert(format!("span.{}.{}", depth, name), value(val));
+            ctx.insert(format!("ctx.{}", name), value(val));
+        }
+    }
+    obj.extend(ctx);
+
+    if let Some(time) = &event.time {
+        let millis =
+            u64::try_from(std::time::Duration::from(time.clone()).as_millis()).unwrap_or(u64::MAX);
+        obj.insert("time_ms".into(), millis.into());
+        obj.insert("time".into(), time.to_rfc3339().into());
+    }
+    let optional = vec![
+        ("thread_id", event.thread_id.map(|t| t.get().into())),
+        ("thread_name", event.thread_name.as_deref().map(Value::from)),
+        ("src_file", event.src_file.as_deref().map(Value::from)),
+        ("src_line", event.src_line.map(Value::from)),
+        ("crate_name", event.crate_name.as_deref().map(Value::from)),
+        (
+            "crate_version",
+            event.crate_version.as_deref().map(Value::from),
+        ),
+    ];
+    for (key, val) in optional {
+        if let Some(val) = val {
+            obj.insert(key.into(), val);
+        }
+    }
+    obj
+}
+
+fn value(v: &FieldValue) -> Value {
+    match v {
+        FieldValue::Bool(b) => (*b).into(),
+        FieldValue::Int(i) => (*i).into(),
+        FieldValue::UInt(i) => (*i).into(),
+        FieldValue::I128(i) => i64::try_from(*i).map_or_else(|_| i.to_string().into(), Value::from),
+        FieldValue::U128(i) => u64::try_from(*i).map_or_else(|_| i.to_string().into(), Value::from),
+        // NaN and infinities become null
+        FieldValue::Float(f) => (*f).into(),
+        FieldValue::Str(s) => s.as_str().into(),
+        FieldValue::Null => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::capture;
+    use crate::time::{ManualClock, UnixTime};
+    use crate::SerdeLayer;
+    use std::time::Duration;
+
+    fn flattened() -> Vec<Value> {
+        let clock = ManualClock::new(UnixTime::from(Duration::new(1643619900, 123)));
+        let layer = SerdeLayer::new().with_clock(clock);
+        let events = capture(layer, || {
+            let request = tracing::info_span!("request", request_id = 42, user = "alice");
+            let _request = request.enter();
+            tracing::info!("started");
+            let db = tracing::debug_span!("db", user = "postgres");
+            let _db = db.enter();
+            tracing::warn!(rows = 3, "slow query");
+        });
+
+        let mut out = Vec::new();
+        flatten_for_query(events.into_iter().map(Ok), &mut out).unwrap();
+        out.split(|&b| b == b'\n')
+            .filter(|l| !l.is_empty())
+            .map(|l| serde_json::from_slice(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn flattens_span_context() {
+        let events = flattened();
+        assert_eq!(events.len(), 2);
+        let slow = &events[1];
+        assert_eq!(slow["kind"], "event");
+        assert_eq!(slow["level"], "WARN");
+        assert_eq!(slow["message"], "slow query");
+        assert_eq!(slow["fields.rows"], 3);
+        assert_eq!(slow["span_path"], "request:db");
+        assert_eq!(slow["span.0.user"], "alice");
+        assert_eq!(slow["span.1.user"], "postgres");
+        // The innermost span wins
+        assert_eq!(slow["ctx.user"], "postgres");
+        assert_eq!(slow["ctx.request_id"], 42);
+        assert_eq!(slow["time_ms"], 1643619900000u64);
+        assert_eq!(slow["time"], "2022-01-31T09:05:00.000000123Z");
+
+        assert_eq!(events[0]["ctx.user"], "alice");
+    }
+
+    #[test]
+    fn jq_select_by_span_field() {
+        // jq 'select(."ctx.request_id" == 42) | .message'
+        let messages: Vec<_> = flattened()
+            .into_iter()
+            .filter(|e| e["ctx.request_id"] == 42)
+            .map(|e| e["message"].clone())
+            .collect();
+        assert_eq!(messages, vec!["started", "slow query"]);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::Event;
+use std::io;
+
+/// Re-frame events as self-contained JSON objects, one `String` per event, for sending as individual
+/// WebSocket text messages.
+///
+/// The events may come from any [`StreamFormat`](super::StreamFormat).  Each string contains exactly one
+/// JSON object and no newlines, e.g.
+/// ```no_run
+/// use tracing_subscriber_serde::consumer::{json_frames, IterFile};
+/// use tracing_subscriber_serde::format::Json;
+///
+/// for frame in json_frames(Json.iter_file("log.json")) {
+///     let frame: String = frame.unwrap();
+///     // socket.send(Message::Text(frame))
+/// }
+/// ```
+pub fn json_frames<I>(events: I) -> JsonFrames<I::IntoIter>
+where
+    I: IntoIterator<Item = io::Result<Event>>,
+{
+    JsonFrames {
+        events: events.into_iter(),
+    }
+}
+
+/// An iterator of JSON text frames.  Created with [`json_frames`].
+pub struct JsonFrames<I> {
+    events: I,
+}
+
+impl<I> Iterator for JsonFrames<I>
+where
+    I: Iterator<Item = io::Result<Event>>,
+{
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = match self.events.next()? {
+            Ok(e) => e,
+            Err(e) => return Some(Err(e)),
+        };
+        // serde_json escapes control characters in strings, so the frame never contains a newline
+        Some(serde_json::to_string(&event).map_err(io::Error::from))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.events.size_hint()
+    }
+}
+
+impl<I> ExactSizeIterator for JsonFrames<I> where I: ExactSizeIterator<Item = io::Result<Event>> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::capture;
+    use crate::{EventKind, SerdeLayer, SpanEvents};
+
+    #[test]
+    fn one_event_per_frame() {
+        let layer = SerdeLayer::new().with_span_events(SpanEvents::FULL);
+        let events = capture(layer, || {
+            let _span = tracing::info_span!("span", text = "multiple\nlines").entered();
+            tracing::info!("first\nsecond");
+        });
+        let frames: Vec<_> = json_frames(events.iter().cloned().map(Ok))
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(frames.len(), events.len());
+
+        for frame in &frames {
+            assert!(!frame.contains('\n'), "{}", frame);
+            let mut stream = serde_json::Deserializer::from_str(frame).into_iter::<Event>();
+            stream.next().unwrap().unwrap();
+            assert!(stream.next().is_none());
+        }
+
+        let message: Event = serde_json::from_str(&frames[2]).unwrap();
+        assert!(matches!(message.kind, EventKind::Event(_)));
+        assert_eq!(message.spans[0].fields["text"], "multiple\nlines".into());
+    }
+}
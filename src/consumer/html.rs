@@ -0,0 +1,387 @@
+use crate::{Event, EventKind, FieldValue, Level, Span};
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Options for [`export_html`].
+#[derive(Debug, Clone)]
+pub struct HtmlOptions {
+    title: String,
+    max_events: usize,
+    local_time: bool,
+}
+
+impl Default for HtmlOptions {
+    fn default() -> Self {
+        HtmlOptions {
+            title: "Events".to_string(),
+            max_events: usize::MAX,
+            local_time: false,
+        }
+    }
+}
+
+impl HtmlOptions {
+    /// The title of the report.  Default is `"Events"`.
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Only include the first `max` events.  Default is no limit.
+    pub fn max_events(mut self, max: usize) -> Self {
+        self.max_events = max;
+        self
+    }
+
+    /// Show timestamps in the local time of the browser viewing the report, rather than UTC.
+    /// Default is UTC.
+    pub fn local_time(mut self, on: bool) -> Self {
+        self.local_time = on;
+        self
+    }
+}
+
+/// Escapes text for use in HTML element content and quoted attribute values.
+struct Escape<'a>(&'a str);
+
+impl Display for Escape<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut last = 0;
+        for (i, c) in self.0.char_indices() {
+            let escaped = match c {
+                '&' => "&amp;",
+                '<' => "&lt;",
+                '>' => "&gt;",
+                '"' => "&quot;",
+                '\'' => "&#39;",
+                _ => continue,
+            };
+            f.write_str(&self.0[last..i])?;
+            f.write_str(escaped)?;
+            last = i + c.len_utf8();
+        }
+        f.write_str(&self.0[last..])
+    }
+}
+
+struct EscapeValue<'a>(&'a FieldValue);
+
+impl Display for EscapeValue<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.0 {
+            FieldValue::Bool(b) => write!(f, "{}", b),
+            FieldValue::Int(i) => write!(f, "{}", i),
+            FieldValue::UInt(i) => write!(f, "{}", i),
+            FieldValue::I128(i) => write!(f, "{}", i),
+            FieldValue::U128(i) => write!(f, "{}", i),
+            FieldValue::Null => f.write_str("null"),
+            FieldValue::Float(x) => write!(f, "{}", x),
+            FieldValue::Str(s) => Escape(s).fmt(f),
+        }
+    }
+}
+
+/// Formats a duration since the UNIX epoch as `YYYY-MM-DD hh:mm:ss.sss` (UTC).
+struct Utc(Duration);
+
+impl Display for Utc {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+        // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+            year,
+            month,
+            day,
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60,
+            self.0.subsec_millis()
+        )
+    }
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Trace => "TRACE",
+        Level::Debug => "DEBUG",
+        Level::Info => "INFO",
+        Level::Warn => "WARN",
+        Level::Error => "ERROR",
+    }
+}
+
+fn kind_name(kind: &EventKind) -> &'static str {
+    match kind {
+        EventKind::Event(_) => "event",
+        EventKind::SpanCreate => "create",
+        EventKind::SpanClose(_) => "close",
+        EventKind::SpanProgress(_) => "progress",
+        EventKind::SpanEnter => "enter",
+        EventKind::SpanExit => "exit",
+        EventKind::SpanRecord(_) => "record",
+        EventKind::SpanFollowsFrom { .. } => "follows from",
+    }
+}
+
+fn write_fields<'a>(
+    w: &mut impl Write,
+    fields: impl IntoIterator<Item = (&'a String, &'a FieldValue)>,
+) -> io::Result<()> {
+    w.write_all(b"<dl>")?;
+    for (name, val) in fields {
+        write!(w, "<dt>{}</dt><dd>{}</dd>", Escape(name), EscapeValue(val))?;
+    }
+    w.write_all(b"</dl>")
+}
+
+fn write_spans(w: &mut impl Write, spans: &[Span]) -> io::Result<()> {
+    w.write_all(b"<ol class=\"spans\">")?;
+    for span in spans {
+        write!(w, "<li><b>{}</b>", Escape(&span.name))?;
+        if let Some(id) = span.id {
+            write!(w, " <i>#{}</i>", id)?;
+        }
+        write_fields(w, &span.fields)?;
+        w.write_all(b"</li>")?;
+    }
+    w.write_all(b"</ol>")
+}
+
+fn write_row(w: &mut impl Write, event: &Event) -> io::Result<()> {
+    let level = level_name(event.level);
+    write!(
+        w,
+        "<tr class=\"{}\" data-level=\"{}\" data-target=\"{}\">",
+        level,
+        event.level as u8,
+        Escape(&event.target)
+    )?;
+
+    match &event.time {
+        Some(t) => {
+            let t = Duration::from(t.clone());
+            write!(w, "<td data-t=\"{}\">{}</td>", t.as_millis(), Utc(t))?
+        }
+        None => w.write_all(b"<td></td>")?,
+    }
+    write!(w, "<td class=\"lvl\">{}</td>", level)?;
+    write!(w, "<td>{}</td>", Escape(&event.target))?;
+
+    let fields = match &event.kind {
+        EventKind::Event(fields) | EventKind::SpanRecord(fields) => Some(fields),
+        _ => None,
+    };
+    w.write_all(b"<td>")?;
+    match &event.kind {
+        EventKind::Event(fields) => {
+            if let Some(msg) = fields.get("message") {
+                write!(w, "{}", EscapeValue(msg))?;
+            }
+        }
+        kind => {
+            if let Some(span) = event.spans.last() {
+                write!(w, "<b>{}</b> ", Escape(&span.name))?;
+            }
+            write!(w, "<i>{}</i>", kind_name(kind))?;
+        }
+    }
+    w.write_all(b"</td><td>")?;
+
+    let fields = fields
+        .into_iter()
+        .flatten()
+        .filter(|(name, _)| name.as_str() != "message");
+    let has_fields = fields.clone().next().is_some();
+    if has_fields || !event.spans.is_empty() {
+        w.write_all(b"<details><summary>details</summary>")?;
+        if has_fields {
+            write_fields(w, fields)?;
+        }
+        if !event.spans.is_empty() {
+            write_spans(w, &event.spans)?;
+        }
+        w.write_all(b"</details>")?;
+    }
+    w.write_all(b"</td></tr>\n")
+}
+
+const STYLE: &str = "body{font-family:sans-serif}table{border-collapse:collapse;width:100%}\
+td{border-bottom:1px solid #ddd;padding:2px 6px;vertical-align:top;font-family:monospace}\
+tr.TRACE .lvl{color:#a0a}tr.DEBUG .lvl{color:#080}tr.INFO .lvl{color:#00a}\
+tr.WARN .lvl{color:#a60}tr.ERROR .lvl{color:#c00;font-weight:bold}dl{margin:0}\
+dt{float:left;clear:left;color:#00a}dt:after{content:'='}dd{margin-left:8em}.spans{margin:0}";
+
+const SCRIPT: &str = "function f(){var l=+document.getElementById('level').value,\
+t=document.getElementById('target').value,q=document.getElementById('text').value.toLowerCase();\
+document.querySelectorAll('tbody tr').forEach(function(r){r.style.display=(+r.dataset.level>=l&&\
+r.dataset.target.indexOf(t)>=0&&r.textContent.toLowerCase().indexOf(q)>=0)?'':'none'})}\
+['level','target','text'].forEach(function(i){document.getElementById(i).addEventListener('input',f)});";
+
+const LOCAL_TIME_SCRIPT: &str = "document.querySelectorAll('[data-t]').forEach(function(c){\
+c.textContent=new Date(+c.dataset.t).toLocaleString()});";
+
+/// Write a self-contained HTML report of `events` to `w`, for sharing with people who don't have the tools
+/// to read the logs themselves.
+///
+/// The report contains a table of events, which can be filtered by level, target and text, and a summary
+/// of the number of events at each level and the time range they span.  Span context and fields can be
+/// expanded for each event.
+/// ```no_run
+/// use tracing_subscriber_serde::consumer::{export_html, HtmlOptions, IterFile};
+/// use tracing_subscriber_serde::format::Json;
+///
+/// let out = std::fs::File::create("report.html").unwrap();
+/// let options = HtmlOptions::default().title("Incident 42").local_time(true);
+/// export_html(Json.iter_file("log.json"), out, &options).unwrap();
+/// ```
+pub fn export_html<I>(events: I, mut w: impl Write, options: &HtmlOptions) -> io::Result<()>
+where
+    I: IntoIterator<Item = io::Result<Event>>,
+{
+    let events = events
+        .into_iter()
+        .take(options.max_events)
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut by_level = [0usize; 5];
+    for e in &events {
+        by_level[e.level as usize] += 1;
+    }
+    let start = events.iter().filter_map(|e| e.time.clone()).min();
+    let end = events.iter().filter_map(|e| e.time.clone()).max();
+
+    let title = Escape(&options.title);
+    write!(
+        w,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title><style>{}</style></head>\n\
+         <body><h1>{}</h1>\n<p>{} events",
+        title,
+        STYLE,
+        title,
+        events.len()
+    )?;
+    for level in [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ] {
+        write!(w, ", {} {}", by_level[level as usize], level_name(level))?;
+    }
+    w.write_all(b"</p>\n")?;
+    if let (Some(start), Some(end)) = (start, end) {
+        let (start, end) = (Duration::from(start), Duration::from(end));
+        writeln!(
+            w,
+            "<p>From <span data-t=\"{}\">{}</span> to <span data-t=\"{}\">{}</span> ({:?})</p>",
+            start.as_millis(),
+            Utc(start),
+            end.as_millis(),
+            Utc(end),
+            end - start
+        )?;
+    }
+
+    w.write_all(
+        b"<p><select id=\"level\"><option value=\"0\">TRACE</option><option value=\"1\">DEBUG</option>\
+          <option value=\"2\">INFO</option><option value=\"3\">WARN</option><option value=\"4\">ERROR</option>\
+          </select> <input id=\"target\" placeholder=\"target\"> <input id=\"text\" placeholder=\"text\"></p>\n\
+          <table><thead><tr><th>time</th><th>level</th><th>target</th><th>message</th><th></th></tr></thead>\n\
+          <tbody>\n",
+    )?;
+    for e in &events {
+        write_row(&mut w, e)?;
+    }
+    write!(w, "</tbody></table>\n<script>{}", SCRIPT)?;
+    if options.local_time {
+        w.write_all(LOCAL_TIME_SCRIPT.as_bytes())?;
+    }
+    w.write_all(b"</script></body></html>\n")?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::StreamFormat;
+    use crate::format::Json;
+    use crate::test_utils::capture_output;
+    use crate::time::SystemClock;
+    use crate::{SerdeLayer, SpanEvents};
+
+    const PAYLOAD: &str = "<script>alert(\"pwned\" & 'x')</script>";
+
+    fn report(options: &HtmlOptions) -> String {
+        let layer = SerdeLayer::new()
+            .with_clock(SystemClock::default())
+            .with_span_events(SpanEvents::ACTIVE);
+        let buffer = capture_output(layer, || {
+            let _span = tracing::info_span!("<b>request</b>", user = PAYLOAD).entered();
+            tracing::info!(payload = PAYLOAD, "hello");
+            tracing::error!(PAYLOAD);
+        });
+        let mut html = Vec::new();
+        export_html(Json.iter_reader(buffer.as_slice()), &mut html, options).unwrap();
+        String::from_utf8(html).unwrap()
+    }
+
+    #[test]
+    fn escapes_payloads() {
+        let html = report(&HtmlOptions::default().title("<title>"));
+
+        assert_eq!(html.matches("<script").count(), 1, "{}", html);
+        assert_eq!(html.matches("</script>").count(), 1, "{}", html);
+        assert!(!html.contains("<b>request"));
+        assert!(html.contains("&lt;b&gt;request&lt;/b&gt;"));
+        assert!(html
+            .contains("&lt;script&gt;alert(&quot;pwned&quot; &amp; &#39;x&#39;)&lt;/script&gt;"));
+        assert!(html.contains("<title>&lt;title&gt;</title>"));
+
+        for tag in [
+            "tr", "td", "details", "dl", "ol", "li", "p", "table", "tbody",
+        ] {
+            assert_eq!(
+                html.matches(&format!("<{}>", tag)).count()
+                    + html.matches(&format!("<{} ", tag)).count(),
+                html.matches(&format!("</{}>", tag)).count(),
+                "unbalanced <{}>",
+                tag
+            );
+        }
+    }
+
+    #[test]
+    fn summary_and_options() {
+        let html = report(&HtmlOptions::default());
+        assert_eq!(html.matches("<tr ").count(), 4);
+        assert!(html.contains("<p>4 events, 1 ERROR, 0 WARN, 3 INFO, 0 DEBUG, 0 TRACE</p>"));
+        assert!(!html.contains("toLocaleString"));
+
+        let html = report(&HtmlOptions::default().max_events(2).local_time(true));
+        assert_eq!(html.matches("<tr ").count(), 2);
+        assert!(html.contains("toLocaleString"));
+    }
+
+    #[test]
+    fn utc() {
+        let t = Duration::from_millis(951_782_400_123);
+        assert_eq!(Utc(t).to_string(), "2000-02-29 00:00:00.123");
+        assert_eq!(Utc(Duration::ZERO).to_string(), "1970-01-01 00:00:00.000");
+    }
+}
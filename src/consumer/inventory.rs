@@ -0,0 +1,399 @@
+use crate::{Event, EventKind, FieldValue};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+
+const DEFAULT_MAX_GROUPS: usize = 1000;
+const DEFAULT_MAX_FIELDS: usize = 1000;
+
+/// The type of a [`FieldValue`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[allow(missing_docs)]
+pub enum FieldType {
+    Bool,
+    Int,
+    Float,
+    Str,
+    Null,
+}
+
+impl FieldType {
+    /// The type of `value`.
+    pub fn of(value: &FieldValue) -> Self {
+        match value {
+            FieldValue::Bool(_) => FieldType::Bool,
+            FieldValue::Int(_)
+            | FieldValue::UInt(_)
+            | FieldValue::I128(_)
+            | FieldValue::U128(_) => FieldType::Int,
+            FieldValue::Float(_) => FieldType::Float,
+            FieldValue::Str(_) => FieldType::Str,
+            FieldValue::Null => FieldType::Null,
+        }
+    }
+}
+
+/// Where a field was recorded.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum FieldGroup {
+    /// The fields of events with this target.
+    Target(String),
+    /// The fields of spans with this name.
+    Span(String),
+}
+
+/// The types of a field, with the number of times each was seen.
+pub type FieldTypes = BTreeMap<FieldType, u64>;
+
+/// The names and types of the fields seen in a stream of events, per target and per span name, for detecting
+/// renamed fields and changed types between two versions of a program.  Created with
+/// [`FieldInventory::from_stream`] and compared with [`FieldInventory::diff`].
+///
+/// Span fields are counted on every event inside the span, so counts are the number of events on which
+/// a field was seen.  Serialize the inventory (e.g. as JSON) to keep it for later comparison.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FieldInventory {
+    targets: BTreeMap<String, BTreeMap<String, FieldTypes>>,
+    spans: BTreeMap<String, BTreeMap<String, FieldTypes>>,
+    /// The number of fields which were not recorded because of [`FieldInventory::max_groups`].
+    pub overflow_groups: u64,
+    /// The number of fields which were not recorded because of [`FieldInventory::max_fields`].
+    pub overflow_fields: u64,
+    #[serde(skip, default = "default_max_groups")]
+    max_groups: usize,
+    #[serde(skip, default = "default_max_fields")]
+    max_fields: usize,
+}
+
+fn default_max_groups() -> usize {
+    DEFAULT_MAX_GROUPS
+}
+
+fn default_max_fields() -> usize {
+    DEFAULT_MAX_FIELDS
+}
+
+impl Default for FieldInventory {
+    fn default() -> Self {
+        FieldInventory {
+            targets: BTreeMap::new(),
+            spans: BTreeMap::new(),
+            overflow_groups: 0,
+            overflow_fields: 0,
+            max_groups: DEFAULT_MAX_GROUPS,
+            max_fields: DEFAULT_MAX_FIELDS,
+        }
+    }
+}
+
+/// How a field differs between two [`FieldInventory`]s.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FieldChangeKind {
+    /// The field only appears in the new inventory, with these types.
+    Added(Vec<FieldType>),
+    /// The field only appears in the old inventory, with these types.
+    Removed(Vec<FieldType>),
+    /// The field appears in both, with different types.
+    TypeChanged {
+        /// The types in the old inventory
+        old: Vec<FieldType>,
+        /// The types in the new inventory
+        new: Vec<FieldType>,
+    },
+}
+
+/// A difference between two [`FieldInventory`]s, see [`FieldInventory::diff`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FieldChange {
+    /// Where the field was recorded
+    pub group: FieldGroup,
+    /// The name of the field
+    pub field: String,
+    /// How the field changed
+    pub kind: FieldChangeKind,
+}
+
+impl FieldInventory {
+    /// An empty inventory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record at most `n` distinct targets and `n` distinct span names.  Fields of any others are counted in
+    /// [`FieldInventory::overflow_groups`].  Default is 1000.
+    pub fn max_groups(mut self, n: usize) -> Self {
+        self.max_groups = n;
+        self
+    }
+
+    /// Record at most `n` distinct field names per target or span name.  Any others are counted in
+    /// [`FieldInventory::overflow_fields`].  Default is 1000.
+    pub fn max_fields(mut self, n: usize) -> Self {
+        self.max_fields = n;
+        self
+    }
+
+    /// Build an inventory of a stream of events, with the default limits.
+    pub fn from_stream(events: impl IntoIterator<Item = io::Result<Event>>) -> io::Result<Self> {
+        let mut inventory = Self::new();
+        for event in events {
+            inventory.record(&event?);
+        }
+        Ok(inventory)
+    }
+
+    /// Add the fields of `event` to the inventory.
+    pub fn record(&mut self, event: &Event) {
+        match &event.kind {
+            EventKind::Event(fields) => {
+                self.record_fields(FieldGroup::Target(event.target.clone()), fields)
+            }
+            EventKind::SpanRecord(fields) => {
+                if let Some(span) = event.spans.last() {
+                    self.record_fields(FieldGroup::Span(span.name.clone()), fields)
+                }
+            }
+            _ => {}
+        }
+        for span in &event.spans {
+            self.record_fields(FieldGroup::Span(span.name.clone()), &span.fields);
+        }
+    }
+
+    fn record_fields(&mut self, group: FieldGroup, fields: &IndexMap<String, FieldValue>) {
+        if fields.is_empty() {
+            return;
+        }
+        let (groups, name) = match group {
+            FieldGroup::Target(name) => (&mut self.targets, name),
+            FieldGroup::Span(name) => (&mut self.spans, name),
+        };
+        if !groups.contains_key(&name) && groups.len() >= self.max_groups {
+            self.overflow_groups += fields.len() as u64;
+            return;
+        }
+        let group = groups.entry(name).or_default();
+        for (field, value) in fields {
+            if !group.contains_key(field) && group.len() >= self.max_fields {
+                self.overflow_fields += 1;
+                continue;
+            }
+            *group
+                .entry(field.clone())
+                .or_default()
+                .entry(FieldType::of(value))
+                .or_default() += 1;
+        }
+    }
+
+    /// The fields recorded for `group`, with their types.
+    pub fn fields(&self, group: &FieldGroup) -> Option<&BTreeMap<String, FieldTypes>> {
+        match group {
+            FieldGroup::Target(name) => self.targets.get(name),
+            FieldGroup::Span(name) => self.spans.get(name),
+        }
+    }
+
+    fn groups(&self) -> impl Iterator<Item = (FieldGroup, &BTreeMap<String, FieldTypes>)> {
+        let targets = self
+            .targets
+            .iter()
+            .map(|(name, fields)| (FieldGroup::Target(name.clone()), fields));
+        let spans = self
+            .spans
+            .iter()
+            .map(|(name, fields)| (FieldGroup::Span(name.clone()), fields));
+        targets.chain(spans)
+    }
+
+    /// The fields which were added, removed or changed type between `old` and `new`, ordered by group and
+    /// field name.  A field which was seen with several types is only reported as changed if the set of
+    /// types differs.
+    pub fn diff(old: &FieldInventory, new: &FieldInventory) -> Vec<FieldChange> {
+        let types = |t: &FieldTypes| t.keys().copied().collect::<Vec<_>>();
+        let empty = BTreeMap::new();
+        let mut changes = Vec::new();
+
+        for (group, old_fields) in old.groups() {
+            let new_fields = new.fields(&group).unwrap_or(&empty);
+            for (field, old_types) in old_fields {
+                let kind = match new_fields.get(field) {
+                    None => FieldChangeKind::Removed(types(old_types)),
+                    Some(new_types) if types(old_types) != types(new_types) => {
+                        FieldChangeKind::TypeChanged {
+                            old: types(old_types),
+                            new: types(new_types),
+                        }
+                    }
+                    Some(_) => continue,
+                };
+                changes.push(FieldChange {
+                    group: group.clone(),
+                    field: field.clone(),
+                    kind,
+                });
+            }
+        }
+
+        for (group, new_fields) in new.groups() {
+            let old_fields = old.fields(&group).unwrap_or(&empty);
+            for (field, new_types) in new_fields {
+                if !old_fields.contains_key(field) {
+                    changes.push(FieldChange {
+                        group: group.clone(),
+                        field: field.clone(),
+                        kind: FieldChangeKind::Added(types(new_types)),
+                    });
+                }
+            }
+        }
+
+        changes.sort_by(|a, b| (&a.group, &a.field).cmp(&(&b.group, &b.field)));
+        changes
+    }
+}
+
+/// The types seen for each field name, see [`discover_schema`].
+pub type Schema = IndexMap<String, HashSet<FieldType>>;
+
+/// Every field name in a stream of events, in the order they were first seen, with the types of the values
+/// they took.  Unlike [`FieldInventory`], fields are not grouped by target or span name, so this gives a quick
+/// overview of an unknown log.  Both event fields and span fields are included.
+///
+/// Fields seen with more than one type can be found with [`mixed_type_fields`].
+pub fn discover_schema(events: impl IntoIterator<Item = io::Result<Event>>) -> io::Result<Schema> {
+    let mut schema = Schema::new();
+    let mut record = |fields: &IndexMap<String, FieldValue>| {
+        for (field, value) in fields {
+            let ty = FieldType::of(value);
+            match schema.get_mut(field) {
+                Some(types) => {
+                    types.insert(ty);
+                }
+                None => {
+                    schema.insert(field.clone(), std::iter::once(ty).collect());
+                }
+            }
+        }
+    };
+    for event in events {
+        let event = event?;
+        if let EventKind::Event(fields) | EventKind::SpanRecord(fields) = &event.kind {
+            record(fields);
+        }
+        for span in &event.spans {
+            record(&span.fields);
+        }
+    }
+    Ok(schema)
+}
+
+/// The fields in `schema` which were seen with more than one type.
+pub fn mixed_type_fields(schema: &Schema) -> impl Iterator<Item = &str> {
+    schema
+        .iter()
+        .filter(|(_, types)| types.len() > 1)
+        .map(|(field, _)| field.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::StreamFormat;
+    use crate::format::Json;
+    use crate::test_utils::capture;
+    use crate::SerdeLayer;
+
+    fn inventory(f: impl FnOnce()) -> FieldInventory {
+        let events = capture(SerdeLayer::new(), f);
+        let inventory = FieldInventory::from_stream(events.into_iter().map(Ok)).unwrap();
+        // Inventories are kept by serializing them
+        serde_json::from_str(&serde_json::to_string(&inventory).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn diff_reports_renames_and_type_changes() {
+        let old = inventory(|| {
+            let _span = tracing::info_span!("request", path = "/").entered();
+            tracing::info!(target: "app", user_id = 1, count = 2, "login");
+            tracing::info!(target: "app", user_id = 2, count = 3, "login");
+        });
+        let new = inventory(|| {
+            let _span = tracing::info_span!("request", path = "/").entered();
+            tracing::info!(target: "app", uid = 1, count = 2.5, "login");
+        });
+
+        let target = FieldGroup::Target("app".to_string());
+        assert_eq!(
+            old.fields(&target).unwrap()["user_id"],
+            std::iter::once((FieldType::Int, 2)).collect()
+        );
+        assert_eq!(
+            FieldInventory::diff(&old, &new),
+            vec![
+                FieldChange {
+                    group: target.clone(),
+                    field: "count".to_string(),
+                    kind: FieldChangeKind::TypeChanged {
+                        old: vec![FieldType::Int],
+                        new: vec![FieldType::Float],
+                    },
+                },
+                FieldChange {
+                    group: target.clone(),
+                    field: "uid".to_string(),
+                    kind: FieldChangeKind::Added(vec![FieldType::Int]),
+                },
+                FieldChange {
+                    group: target,
+                    field: "user_id".to_string(),
+                    kind: FieldChangeKind::Removed(vec![FieldType::Int]),
+                },
+            ]
+        );
+        assert!(FieldInventory::diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn schema_with_mixed_types() {
+        let events = Json.iter_reader(
+            br#"{"ty":{"event":{"x":1,"y":true}},"l":2,"s":[{"n":"req","f":{"id":"a"}}],"t":"one"}
+{"ty":{"event":{"x":"one"}},"l":2,"s":[{"n":"req","f":{"id":2.5}}],"t":"two"}
+"#
+            .as_ref(),
+        );
+        let schema = discover_schema(events).unwrap();
+        assert_eq!(schema.keys().collect::<Vec<_>>(), ["x", "y", "id"]);
+        assert_eq!(
+            schema["x"],
+            vec![FieldType::Int, FieldType::Str].into_iter().collect()
+        );
+        assert_eq!(schema["y"], std::iter::once(FieldType::Bool).collect());
+        assert_eq!(mixed_type_fields(&schema).collect::<Vec<_>>(), ["x", "id"]);
+    }
+
+    #[test]
+    fn limits() {
+        let mut inventory = FieldInventory::new().max_groups(1).max_fields(2);
+        let events = Json.iter_reader(
+            br#"{"ty":{"event":{"a":1,"b":2,"c":3}},"l":2,"s":[],"t":"one"}
+{"ty":{"event":{"a":1}},"l":2,"s":[],"t":"two"}
+"#
+            .as_ref(),
+        );
+        for e in events {
+            inventory.record(&e.unwrap());
+        }
+        assert_eq!(inventory.overflow_fields, 1);
+        assert_eq!(inventory.overflow_groups, 1);
+        assert_eq!(
+            inventory
+                .fields(&FieldGroup::Target("one".to_string()))
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use crate::Event;
+use std::io;
+use std::time::Duration;
+
+/// Statistics on how far a writer lagged behind the events it was writing.  Created with
+/// [`writer_lag_stats`].
+#[derive(Debug, Clone, Default)]
+pub struct LagStats {
+    lags: Vec<Duration>,
+}
+
+/// Compute the lag (written time minus event timestamp) of each event which has both timestamps.
+///
+/// Written times are recorded by wrapping the underlying writer with [`Annotate`](crate::writer::Annotate).
+/// Events written before they were timestamped (e.g. due to clock adjustments) are counted as having no lag.
+pub fn writer_lag_stats(
+    events: impl IntoIterator<Item = io::Result<Event>>,
+) -> io::Result<LagStats> {
+    let mut lags = Vec::new();
+    for event in events {
+        let event = event?;
+        if let (Some(t), Some(wt)) = (event.time, event.written_time) {
+            let lag = Duration::from(wt)
+                .checked_sub(Duration::from(t))
+                .unwrap_or_default();
+            lags.push(lag);
+        }
+    }
+    lags.sort_unstable();
+    Ok(LagStats { lags })
+}
+
+impl LagStats {
+    /// The number of events which had both timestamps.
+    pub fn count(&self) -> usize {
+        self.lags.len()
+    }
+
+    /// The smallest lag
+    pub fn min(&self) -> Option<Duration> {
+        self.lags.first().copied()
+    }
+
+    /// The largest lag
+    pub fn max(&self) -> Option<Duration> {
+        self.lags.last().copied()
+    }
+
+    /// The mean lag
+    pub fn mean(&self) -> Option<Duration> {
+        if self.lags.is_empty() {
+            return None;
+        }
+        let total: Duration = self.lags.iter().sum();
+        Some(total / self.lags.len() as u32)
+    }
+
+    /// The lag at percentile `p`, which should be between 0 and 100.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.lags.is_empty() {
+            return None;
+        }
+        let k = ((p / 100.0) * (self.lags.len() - 1) as f64).round() as usize;
+        self.lags.get(k.min(self.lags.len() - 1)).copied()
+    }
+}
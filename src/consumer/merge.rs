@@ -0,0 +1,46 @@
+use crate::Event;
+use std::io;
+use std::iter::Peekable;
+
+/// Merge several streams of events, each sorted by timestamp, into a single stream sorted by timestamp.
+///
+/// Events without a timestamp are treated as occurring before any timestamped event.  Ties are broken by
+/// the order of the sources.  Errors are passed through as soon as they are encountered.
+pub fn merge_by_time<I>(sources: impl IntoIterator<Item = I>) -> MergeByTime<I>
+where
+    I: Iterator<Item = io::Result<Event>>,
+{
+    MergeByTime {
+        sources: sources.into_iter().map(Iterator::peekable).collect(),
+    }
+}
+
+/// An iterator which merges time-sorted streams of events.  Created with [`merge_by_time`].
+pub struct MergeByTime<I: Iterator> {
+    sources: Vec<Peekable<I>>,
+}
+
+impl<I> Iterator for MergeByTime<I>
+where
+    I: Iterator<Item = io::Result<Event>>,
+{
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut earliest = None;
+
+        for (k, source) in self.sources.iter_mut().enumerate() {
+            match source.peek() {
+                Some(Err(_)) => return source.next(),
+                Some(Ok(e)) => match earliest {
+                    Some((_, t)) if t <= &e.time => {}
+                    _ => earliest = Some((k, &e.time)),
+                },
+                None => {}
+            }
+        }
+
+        let k = earliest?.0;
+        self.sources[k].next()
+    }
+}
@@ -7,8 +7,47 @@ use std::fs::File;
 use std::io::{self, BufReader};
 use std::path::Path;
 
+mod backfill;
+mod capabilities;
+mod csv;
+mod downsample;
+mod enrich;
+mod flatten;
+mod frames;
+mod html;
+mod inventory;
+mod lag;
+mod merge;
 mod pprint;
-pub use pprint::{FmtEvent, PrettyPrinter};
+mod rate;
+mod rejoin;
+mod scrub;
+mod slice;
+mod traces;
+mod tree;
+pub use backfill::{
+    backfill_span_fields, backfill_span_fields_windowed, AmbiguousField, BackfillSpanFields,
+};
+pub use capabilities::{Capability, StreamCapabilities, UnsupportedStream};
+pub use csv::{to_csv, CsvColumn};
+pub use downsample::downsample;
+pub use enrich::{enrich, Enrich, EnrichRule};
+pub use flatten::flatten_for_query;
+pub use frames::{json_frames, JsonFrames};
+pub use html::{export_html, HtmlOptions};
+pub use inventory::{
+    discover_schema, mixed_type_fields, FieldChange, FieldChangeKind, FieldGroup, FieldInventory,
+    FieldType, FieldTypes, Schema,
+};
+pub use lag::{writer_lag_stats, LagStats};
+pub use merge::{merge_by_time, MergeByTime};
+pub use pprint::{FmtEvent, LevelStyle, PrettyPrinter, Theme};
+pub use rate::{rate, Rate};
+pub use rejoin::{rejoin_span_fields, RejoinSpanFields};
+pub use scrub::{scrub, Scrub};
+pub use slice::{span_slice, SpanSelector, SpanSlice, SpanSliceSummary};
+pub use traces::{collect_by_key, GroupByKey, IterByKey, Traces};
+pub use tree::{SpanNode, SpanTree};
 
 /// Describes how events should be deserialized for a serde-supported format.
 ///
@@ -89,4 +128,89 @@ impl<T, I: Iterator<Item = io::Result<T>>> Iterator for TryOpenStream<I> {
             Success(iter) => iter.next(),
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        use TryOpenStream::*;
+        match self {
+            OpenError(Some(_)) => (1, Some(1)),
+            OpenError(None) => (0, Some(0)),
+            Success(iter) => iter.size_hint(),
+        }
+    }
+}
+
+/// A gzip-decompressing reader, see [`iter_reader_gz`].
+#[cfg(feature = "gzip")]
+#[cfg_attr(docsrs, doc(cfg(feature = "gzip")))]
+pub type GzReader<R> = BufReader<flate2::read::MultiGzDecoder<R>>;
+
+/// Parse events from gzip-compressed data, e.g. a `.gz` log file.  Concatenated gzip members, such as
+/// produced by `cat a.gz b.gz`, are read one after the other.
+///
+/// Requires the **`gzip`** crate feature to be enabled.
+#[cfg(feature = "gzip")]
+#[cfg_attr(docsrs, doc(cfg(feature = "gzip")))]
+pub fn iter_reader_gz<F, R>(fmt: &F, reader: R) -> F::Stream
+where
+    F: StreamFormat<GzReader<R>>,
+    R: io::Read,
+{
+    fmt.iter_reader(BufReader::new(flate2::read::MultiGzDecoder::new(reader)))
+}
+
+/// Parse events from gzip-compressed data piped to stdin, without the need for `zcat`.  See
+/// [`iter_reader_gz`].
+///
+/// Requires the **`gzip`** crate feature to be enabled.
+#[cfg(feature = "gzip")]
+#[cfg_attr(docsrs, doc(cfg(feature = "gzip")))]
+pub fn iter_stdin_gz<F>(fmt: &F) -> F::Stream
+where
+    F: StreamFormat<GzReader<io::StdinLock<'static>>>,
+{
+    iter_reader_gz(fmt, io::stdin().lock())
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use super::*;
+    use crate::format::Json;
+    use crate::{EventKind, FieldValue};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn gzipped_reader() {
+        let event = |i| {
+            format!(
+                "{{\"ty\":{{\"event\":{{\"i\":{}}}}},\"l\":2,\"s\":[],\"t\":\"gz\"}}\n",
+                i
+            )
+        };
+        // Two gzip members, as if concatenated with `cat`
+        let mut compressed = gzip(format!("{}{}", event(0), event(1)).as_bytes());
+        compressed.extend(gzip(event(2).as_bytes()));
+
+        let events: Vec<_> = iter_reader_gz(&Json, compressed.as_slice())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        let values: Vec<_> = events
+            .iter()
+            .map(|e| match &e.kind {
+                EventKind::Event(fields) => fields["i"].clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec![FieldValue::Int(0), FieldValue::Int(1), FieldValue::Int(2)]
+        );
+    }
 }
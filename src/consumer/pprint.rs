@@ -1,8 +1,29 @@
+use crate::subscriber::is_control;
 use crate::{Event, EventKind, FieldValue, Level, Span};
-use ansi_term::Colour;
+use ansi_term::{Colour, Style};
+use std::borrow::Cow;
 use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io;
 use std::num::NonZeroU64;
 
+/// Escape control characters other than `\n` and `\t`, so that strings in events can't change the
+/// terminal's colours or overwrite earlier output.
+fn escape(s: &str) -> Cow<'_, str> {
+    if s.chars().any(is_control) {
+        let mut escaped = String::with_capacity(s.len());
+        for c in s.chars() {
+            if is_control(c) {
+                escaped.extend(c.escape_default());
+            } else {
+                escaped.push(c);
+            }
+        }
+        Cow::Owned(escaped)
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
 fn base64_id(id: NonZeroU64) -> [u8; 12] {
     const ALPHABET: &'static [u8] =
         "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/".as_bytes();
@@ -24,7 +45,7 @@ fn base64_id(id: NonZeroU64) -> [u8; 12] {
         let chunk = [
             ((bytes[start] & 0b1111_1100) >> 2),
             ((bytes[start] & 0b0000_0011) << 4) | ((bytes[start + 1] & 0b1111_0000) >> 4),
-            ((bytes[start + 1] & 0b0000_1111) << 2) | (bytes[start + 2] & 0b1100_0000 >> 6),
+            ((bytes[start + 1] & 0b0000_1111) << 2) | ((bytes[start + 2] & 0b1100_0000) >> 6),
             (bytes[start + 2] & 0b0011_1111),
         ];
 
@@ -38,14 +59,102 @@ fn base64_id(id: NonZeroU64) -> [u8; 12] {
     output
 }
 
+/// How the level of an event is displayed.  All labels in a style have the same width, so columns line up.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LevelStyle {
+    /// The level's name, e.g. ` INFO` (the default)
+    Word,
+    /// A single glyph: `·` (trace), `◆` (debug), `●` (info), `▲` (warn) or `✖` (error)
+    Glyph,
+    /// The first letter of the level's name, e.g. `I`
+    Char,
+}
+
+impl LevelStyle {
+    fn label(&self, level: Level) -> &'static str {
+        match self {
+            LevelStyle::Word => match level {
+                Level::Trace => "TRACE",
+                Level::Debug => "DEBUG",
+                Level::Info => " INFO",
+                Level::Warn => " WARN",
+                Level::Error => "ERROR",
+            },
+            LevelStyle::Glyph => match level {
+                Level::Trace => "·",
+                Level::Debug => "◆",
+                Level::Info => "●",
+                Level::Warn => "▲",
+                Level::Error => "✖",
+            },
+            LevelStyle::Char => match level {
+                Level::Trace => "T",
+                Level::Debug => "D",
+                Level::Info => "I",
+                Level::Warn => "W",
+                Level::Error => "E",
+            },
+        }
+    }
+}
+
+/// Colours used by [`PrettyPrinter`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Theme {
+    levels: [Style; 5],
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            levels: [
+                Colour::Purple.bold(),
+                Colour::Green.bold(),
+                Colour::Blue.bold(),
+                Colour::Yellow.bold(),
+                Colour::Red.bold(),
+            ],
+        }
+    }
+}
+
+impl Theme {
+    /// A theme with no colours or other styling.
+    pub fn plain() -> Self {
+        Theme {
+            levels: [Style::default(); 5],
+        }
+    }
+
+    /// Override the style of a level, e.g. `theme.level(Level::Warn, Colour::RGB(255, 165, 0).bold())`.
+    pub fn level(mut self, level: Level, style: impl Into<Style>) -> Self {
+        self.levels[level as usize] = style.into();
+        self
+    }
+
+    /// The style of a level
+    pub fn level_style(&self, level: Level) -> Style {
+        self.levels[level as usize]
+    }
+}
+
 /// Configuration of pretty formatting for events.
 #[derive(Debug, Copy, Clone)]
 pub struct PrettyPrinter {
+    theme: Theme,
+    level_style: LevelStyle,
     source: bool,
     target: bool,
+    time: bool,
+    ansi: bool,
     span_times: bool,
     limit_spans: usize,
     span_ids: bool,
+    instance: bool,
+    globals: bool,
+    pid: bool,
+    group_by_span: bool,
+    span_context: bool,
     continue_line: &'static str,
 }
 
@@ -65,11 +174,20 @@ struct FmtSpan<'a> {
 impl Default for PrettyPrinter {
     fn default() -> Self {
         PrettyPrinter {
+            theme: Theme::default(),
+            level_style: LevelStyle::Word,
             source: true,
             target: true,
+            time: false,
+            ansi: true,
             span_times: true,
             span_ids: false,
+            instance: true,
+            globals: false,
+            pid: false,
             limit_spans: usize::MAX,
+            group_by_span: false,
+            span_context: true,
             continue_line: "  | ",
         }
     }
@@ -95,30 +213,146 @@ impl PrettyPrinter {
         self
     }
 
+    /// Show a short, coloured tag of the [instance ID](crate::SerdeLayerBuilder::with_instance_id) of events
+    /// which have one.  On by default.
+    pub fn show_instance(mut self, on: bool) -> Self {
+        self.instance = on;
+        self
+    }
+
     /// Show target of the event
     pub fn show_target(mut self, on: bool) -> Self {
         self.target = on;
         self
     }
 
+    /// Show the [timestamp](Event::time) of events at the start of each line, in RFC 3339 format.  Nothing is
+    /// shown for events without one.  Off by default.
+    pub fn show_time(mut self, on: bool) -> Self {
+        self.time = on;
+        self
+    }
+
+    /// Show the [process ID](Event::pid) of events which have one, after the level.  Off by default.
+    pub fn show_pid(mut self, on: bool) -> Self {
+        self.pid = on;
+        self
+    }
+
+    /// Show the [global fields](Event::globals) of events, on a line after the spans.  Off by default, since
+    /// they are usually the same for every event.
+    pub fn show_globals(mut self, on: bool) -> Self {
+        self.globals = on;
+        self
+    }
+
     /// Show span times for [`EventKind::SpanClose`] events.
     pub fn show_span_times(mut self, on: bool) -> Self {
         self.span_times = on;
         self
     }
+
+    /// Use the given [`Theme`]
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    /// Use ANSI escape codes for colours and styles.  Turn this off when writing to files or CI logs, to
+    /// print plain text.  On by default.
+    pub fn with_ansi(mut self, on: bool) -> Self {
+        self.ansi = on;
+        self
+    }
+
+    /// Choose how levels are displayed.  Default is [`LevelStyle::Word`].
+    pub fn level_style(mut self, style: LevelStyle) -> Self {
+        self.level_style = style;
+        self
+    }
+
+    /// When printing a sequence of events with [`print_all`](Self::print_all), print the spans of consecutive
+    /// events with the same spans once, as a header, and indent the events beneath it.
+    pub fn group_by_span(mut self, on: bool) -> Self {
+        self.group_by_span = on;
+        self
+    }
+}
+
+fn same_spans(a: &[Span], b: &[Span]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| a.name == b.name && a.id == b.id && a.fields == b.fields)
+}
+
+/// The header of a group of events printed with [`PrettyPrinter::group_by_span`].
+struct FmtSpanGroup<'a> {
+    printer: &'a PrettyPrinter,
+    spans: &'a [Span],
+}
+
+impl Display for FmtSpanGroup<'_> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "{}",
+            self.printer.style(Colour::White.italic()).paint("in")
+        )?;
+        for (k, span) in self.spans.iter().enumerate() {
+            let sep = if k == 0 { " " } else { " > " };
+            write!(f, "{}{}", sep, self.printer.fmt_span(span))?;
+        }
+        f.write_str("\n")
+    }
 }
 
 impl Display for FmtEvent<'_> {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        let lvl = match self.event.level {
-            Level::Trace => Colour::Purple.bold().paint("TRACE"),
-            Level::Debug => Colour::Green.bold().paint("DEBUG"),
-            Level::Info => Colour::Blue.bold().paint(" INFO"),
-            Level::Warn => Colour::Yellow.bold().paint(" WARN"),
-            Level::Error => Colour::Red.bold().paint("ERROR"),
-        };
+        let level = self.event.level;
+        let lvl = self
+            .printer
+            .style(self.printer.theme.level_style(level))
+            .paint(self.printer.level_style.label(level));
 
-        f.write_fmt(format_args!("{}: ", lvl))?;
+        if let Some(time) = self.event.time.as_ref().filter(|_| self.printer.time) {
+            write!(
+                f,
+                "{} ",
+                self.printer
+                    .style(Style::new().dimmed())
+                    .paint(time.to_rfc3339())
+            )?;
+        }
+        f.write_fmt(format_args!("{}", lvl))?;
+        if let Some(id) = self.event.instance_id.filter(|_| self.printer.instance) {
+            const COLOURS: [Colour; 6] = [
+                Colour::Red,
+                Colour::Green,
+                Colour::Yellow,
+                Colour::Blue,
+                Colour::Purple,
+                Colour::Cyan,
+            ];
+            let colour = COLOURS[(id % COLOURS.len() as u64) as usize];
+            write!(
+                f,
+                " {}",
+                self.printer
+                    .style(colour)
+                    .paint(format!("[{:06x}]", id & 0xff_ffff))
+            )?;
+        }
+        if let Some(pid) = self.event.pid.filter(|_| self.printer.pid) {
+            write!(
+                f,
+                " {}",
+                self.printer
+                    .style(Style::new().dimmed())
+                    .paint(format!("pid={}", pid))
+            )?;
+        }
+        f.write_str(": ")?;
 
         let mut spans = self.event.spans.iter().rev().take(self.printer.limit_spans);
 
@@ -150,13 +384,18 @@ impl Display for FmtEvent<'_> {
                     EventKind::SpanExit => "exit",
                     EventKind::SpanEnter => "enter",
                     EventKind::SpanClose(_) => "close",
+                    EventKind::SpanProgress(_) => "progress",
                     EventKind::SpanCreate => "create",
+                    EventKind::SpanRecord(_) => "record",
+                    EventKind::SpanFollowsFrom { .. } => "follows from",
                 };
 
-                let verb = Colour::Cyan.underline().paint(verb);
+                let verb = self.printer.style(Colour::Cyan.underline()).paint(verb);
 
                 match kind {
-                    EventKind::SpanClose(Some(times)) if self.printer.span_times => {
+                    EventKind::SpanClose(Some(times)) | EventKind::SpanProgress(times)
+                        if self.printer.span_times =>
+                    {
                         write!(
                             f,
                             "{}: {:?} busy, {:?} idle\n",
@@ -165,6 +404,14 @@ impl Display for FmtEvent<'_> {
                             times.idle()
                         )?;
                     }
+                    EventKind::SpanRecord(fields) => {
+                        write!(f, "{}: ", verb)?;
+                        self.printer.fmt_fields(f, fields.iter())?;
+                        f.write_str("\n")?;
+                    }
+                    EventKind::SpanFollowsFrom { from, .. } => {
+                        writeln!(f, "{} {}", verb, from)?;
+                    }
                     _ => {
                         write!(f, "{}\n", verb)?;
                     }
@@ -172,7 +419,7 @@ impl Display for FmtEvent<'_> {
             }
         }
 
-        for span in spans {
+        for span in spans.filter(|_| self.printer.span_context) {
             write!(
                 f,
                 "{}in {}\n",
@@ -181,14 +428,27 @@ impl Display for FmtEvent<'_> {
             )?;
         }
 
+        if self.printer.globals && !self.event.globals.is_empty() {
+            write!(
+                f,
+                "{}{} ",
+                self.printer.continue_line,
+                self.printer.style(Colour::White.italic()).paint("globals")
+            )?;
+            self.printer.fmt_fields(f, &self.event.globals)?;
+            f.write_str("\n")?;
+        }
+
         if self.printer.target || self.printer.source {
             f.write_str(self.printer.continue_line)?;
 
             if self.printer.target {
                 f.write_fmt(format_args!(
                     "{} {} ",
-                    Colour::White.italic().paint("target"),
-                    Colour::White.bold().paint(&self.event.target)
+                    self.printer.style(Colour::White.italic()).paint("target"),
+                    self.printer
+                        .style(Colour::White.bold())
+                        .paint(escape(&self.event.target))
                 ))?;
             }
 
@@ -196,8 +456,8 @@ impl Display for FmtEvent<'_> {
                 if let Some(file) = self.event.src_file.as_ref() {
                     f.write_fmt(format_args!(
                         "{} {}",
-                        Colour::White.italic().paint("at"),
-                        file
+                        self.printer.style(Colour::White.italic()).paint("at"),
+                        escape(file)
                     ))?;
                     if let Some(lineno) = self.event.src_line {
                         f.write_fmt(format_args!(":{}", lineno))?;
@@ -219,11 +479,16 @@ impl Display for FmtSpan<'_> {
                 write!(
                     f,
                     "{} ",
-                    Colour::RGB(150, 150, 150).paint(std::str::from_utf8(&id).unwrap())
+                    self.printer
+                        .style(Colour::RGB(150, 150, 150))
+                        .paint(std::str::from_utf8(&id).unwrap())
                 )?;
             }
         }
-        Colour::White.bold().paint(&self.span.name).fmt(f)?;
+        self.printer
+            .style(Colour::White.bold())
+            .paint(escape(&self.span.name))
+            .fmt(f)?;
         f.write_str("{")?;
         self.printer.fmt_fields(f, &self.span.fields)?;
         f.write_str("}")?;
@@ -240,9 +505,68 @@ impl PrettyPrinter {
         }
     }
 
-    /// Convenience method for `println!("{}", printer.fmt(event))`
+    /// Write `event` to `w`, followed by a newline.
+    pub fn write(&self, w: &mut impl io::Write, event: &Event) -> io::Result<()> {
+        writeln!(w, "{}", self.fmt(event))
+    }
+
+    /// Convenience method for `println!("{}", printer.fmt(event))`.  Like `println!`, panics if writing to
+    /// stdout fails.
     pub fn print(&self, event: &Event) {
-        println!("{}", self.fmt(event));
+        self.write(&mut io::stdout().lock(), event)
+            .expect("failed printing to stdout");
+    }
+
+    /// Print a sequence of events.  See [`group_by_span`](Self::group_by_span).
+    pub fn print_all<'a>(&self, events: impl IntoIterator<Item = &'a Event>) {
+        let mut group = None;
+        for event in events {
+            print!("{}", self.fmt_in_group(&mut group, event));
+        }
+    }
+
+    /// Format the next event of a sequence, where `group` holds the spans of the current group.
+    fn fmt_in_group<'a>(&self, group: &mut Option<&'a [Span]>, event: &'a Event) -> String {
+        if !self.group_by_span {
+            return format!("{}\n", self.fmt(event));
+        }
+
+        let mut out = String::new();
+        let new_group = match group {
+            Some(spans) => !same_spans(spans, &event.spans),
+            None => true,
+        };
+        if new_group {
+            *group = Some(&event.spans);
+            if !event.spans.is_empty() {
+                let header = FmtSpanGroup {
+                    printer: self,
+                    spans: &event.spans,
+                };
+                out.push_str(&format!("\n{}", header));
+            }
+        }
+
+        let indent = if event.spans.is_empty() { "" } else { "    " };
+        let printer = PrettyPrinter {
+            span_context: false,
+            ..*self
+        };
+        for line in printer.fmt(event).to_string().lines() {
+            out.push_str(indent);
+            out.push_str(line);
+            out.push('\n');
+        }
+        out
+    }
+
+    /// `style`, or no style at all if ANSI escape codes are disabled.
+    fn style(&self, style: impl Into<Style>) -> Style {
+        if self.ansi {
+            style.into()
+        } else {
+            Style::default()
+        }
     }
 
     fn fmt_span<'a>(&'a self, span: &'a Span) -> FmtSpan<'a> {
@@ -254,22 +578,44 @@ impl PrettyPrinter {
 
     fn fmt_fieldvalue(&self, f: &mut Formatter, v: &FieldValue) -> FmtResult {
         match v {
-            FieldValue::Int(n) => {
-                f.write_fmt(format_args!("{}", Colour::Purple.paint(format!("{}", n))))?
-            }
-            FieldValue::Float(v) => {
-                f.write_fmt(format_args!("{}", Colour::Purple.paint(format!("{}", v))))?
-            }
-            FieldValue::Bool(v) => {
-                f.write_fmt(format_args!("{}", Colour::Yellow.paint(format!("{}", v))))?
-            }
-            FieldValue::Str(v) => f.write_fmt(format_args!("{}", v))?,
+            FieldValue::Int(n) => f.write_fmt(format_args!(
+                "{}",
+                self.style(Colour::Purple).paint(format!("{}", n))
+            ))?,
+            FieldValue::UInt(n) => f.write_fmt(format_args!(
+                "{}",
+                self.style(Colour::Purple).paint(format!("{}", n))
+            ))?,
+            FieldValue::I128(n) => f.write_fmt(format_args!(
+                "{}",
+                self.style(Colour::Purple).paint(format!("{}", n))
+            ))?,
+            FieldValue::U128(n) => f.write_fmt(format_args!(
+                "{}",
+                self.style(Colour::Purple).paint(format!("{}", n))
+            ))?,
+            FieldValue::Float(v) => f.write_fmt(format_args!(
+                "{}",
+                self.style(Colour::Purple).paint(format!("{}", v))
+            ))?,
+            FieldValue::Bool(v) => f.write_fmt(format_args!(
+                "{}",
+                self.style(Colour::Yellow).paint(format!("{}", v))
+            ))?,
+            FieldValue::Str(v) => f.write_str(&escape(v))?,
+            FieldValue::Null => f.write_fmt(format_args!(
+                "{}",
+                self.style(Style::new().dimmed()).paint("null")
+            ))?,
         };
         Ok(())
     }
 
     fn fmt_field(&self, f: &mut Formatter, field: (&str, &FieldValue)) -> FmtResult {
-        f.write_fmt(format_args!("{}= ", Colour::Blue.paint(field.0)))?;
+        f.write_fmt(format_args!(
+            "{}= ",
+            self.style(Colour::Blue).paint(escape(field.0))
+        ))?;
         self.fmt_fieldvalue(f, field.1)
     }
 
@@ -296,6 +642,305 @@ mod tests {
     use crate::consumer::*;
     use crate::format::Json;
 
+    fn event(level: Level) -> Event {
+        Event {
+            kind: EventKind::Event(std::iter::once(("message".to_string(), "hi".into())).collect()),
+            level,
+            spans: Vec::new(),
+            target: "test".to_string(),
+            thread_id: None,
+            thread_name: None,
+            src_line: None,
+            src_file: None,
+            time: None,
+            uptime_ns: None,
+            syslog_pri: None,
+            instance_id: None,
+            time_missing: None,
+            crate_name: None,
+            crate_version: None,
+            globals: Default::default(),
+            pid: None,
+            host: None,
+            name: None,
+            module: None,
+            seq: None,
+            id: None,
+            written_time: None,
+        }
+    }
+
+    const LEVELS: [Level; 5] = [
+        Level::Trace,
+        Level::Debug,
+        Level::Info,
+        Level::Warn,
+        Level::Error,
+    ];
+
+    fn prefixes(p: PrettyPrinter) -> Vec<String> {
+        LEVELS
+            .iter()
+            .map(|&l| {
+                let s = p.fmt(&event(l)).to_string();
+                s[..s.find("hi").unwrap()].to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn level_styles_without_colour() {
+        let p = PrettyPrinter::default().theme(Theme::plain());
+        assert_eq!(
+            prefixes(p),
+            vec!["TRACE: ", "DEBUG: ", " INFO: ", " WARN: ", "ERROR: "]
+        );
+        assert_eq!(
+            prefixes(p.level_style(LevelStyle::Glyph)),
+            vec!["·: ", "◆: ", "●: ", "▲: ", "✖: "]
+        );
+        assert_eq!(
+            prefixes(p.level_style(LevelStyle::Char)),
+            vec!["T: ", "D: ", "I: ", "W: ", "E: "]
+        );
+
+        for &style in &[LevelStyle::Word, LevelStyle::Glyph, LevelStyle::Char] {
+            let widths: Vec<_> = prefixes(p.level_style(style))
+                .iter()
+                .map(|s| s.chars().count())
+                .collect();
+            assert!(widths.iter().all(|&w| w == widths[0]), "{:?}", style);
+        }
+    }
+
+    #[test]
+    fn level_styles_with_colour() {
+        let orange = Colour::RGB(255, 165, 0).bold();
+        let p = PrettyPrinter::default().theme(Theme::default().level(Level::Warn, orange));
+        assert_eq!(
+            prefixes(p),
+            vec![
+                "\u{1b}[1;35mTRACE\u{1b}[0m: ",
+                "\u{1b}[1;32mDEBUG\u{1b}[0m: ",
+                "\u{1b}[1;34m INFO\u{1b}[0m: ",
+                "\u{1b}[1;38;2;255;165;0m WARN\u{1b}[0m: ",
+                "\u{1b}[1;31mERROR\u{1b}[0m: ",
+            ]
+        );
+        assert_eq!(
+            prefixes(p.level_style(LevelStyle::Glyph)),
+            vec![
+                "\u{1b}[1;35m·\u{1b}[0m: ",
+                "\u{1b}[1;32m◆\u{1b}[0m: ",
+                "\u{1b}[1;34m●\u{1b}[0m: ",
+                "\u{1b}[1;38;2;255;165;0m▲\u{1b}[0m: ",
+                "\u{1b}[1;31m✖\u{1b}[0m: ",
+            ]
+        );
+        assert_eq!(
+            prefixes(p.level_style(LevelStyle::Char)),
+            vec![
+                "\u{1b}[1;35mT\u{1b}[0m: ",
+                "\u{1b}[1;32mD\u{1b}[0m: ",
+                "\u{1b}[1;34mI\u{1b}[0m: ",
+                "\u{1b}[1;38;2;255;165;0mW\u{1b}[0m: ",
+                "\u{1b}[1;31mE\u{1b}[0m: ",
+            ]
+        );
+    }
+
+    #[test]
+    fn instance_tag() {
+        let p = PrettyPrinter::default().theme(Theme::plain());
+        let e = Event {
+            instance_id: Some(0x1234_5678_9abc),
+            time_missing: None,
+            ..event(Level::Info)
+        };
+        assert!(p
+            .fmt(&e)
+            .to_string()
+            .starts_with(" INFO \u{1b}[31m[789abc]\u{1b}[0m: hi"));
+        assert!(p
+            .show_instance(false)
+            .fmt(&e)
+            .to_string()
+            .starts_with(" INFO: hi"));
+    }
+
+    #[test]
+    fn show_time() {
+        let p = PrettyPrinter::default()
+            .theme(Theme::plain())
+            .show_time(true);
+        let e = Event {
+            time: Some(crate::time::UnixTime::from(std::time::Duration::new(
+                1643619900, 5,
+            ))),
+            ..event(Level::Info)
+        };
+        assert!(p
+            .fmt(&e)
+            .to_string()
+            .starts_with("\u{1b}[2m2022-01-31T09:05:00.000000005Z\u{1b}[0m  INFO: hi"));
+        assert!(p
+            .fmt(&event(Level::Info))
+            .to_string()
+            .starts_with(" INFO: hi"));
+    }
+
+    #[test]
+    fn without_ansi() {
+        let span = Span {
+            name: "request".to_string(),
+            id: NonZeroU64::new(3),
+            fields: std::iter::once(("n".to_string(), FieldValue::Int(1))).collect(),
+            declared_fields: None,
+        };
+        let e = Event {
+            kind: EventKind::Event(
+                vec![
+                    ("message".to_string(), "hi".into()),
+                    ("ok".to_string(), true.into()),
+                    ("x".to_string(), 1.5.into()),
+                    ("gone".to_string(), FieldValue::Null),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+            spans: vec![span.clone()],
+            src_file: Some("src/main.rs".to_string()),
+            src_line: Some(3),
+            time: Some(crate::time::UnixTime::from(std::time::Duration::from_secs(
+                1,
+            ))),
+            instance_id: Some(7),
+            ..event(Level::Warn)
+        };
+        let close = Event {
+            kind: EventKind::SpanClose(None),
+            ..e.clone()
+        };
+
+        let p = PrettyPrinter::default()
+            .show_span_ids(true)
+            .show_time(true)
+            .with_ansi(false);
+        for e in &[&e, &close] {
+            let s = p.fmt(e).to_string();
+            assert!(!s.contains("\x1b["), "{:?}", s);
+        }
+        assert!(p.fmt(&e).to_string().starts_with(
+            "1970-01-01T00:00:01.000000000Z  WARN [000007]: hi\n  | ok= true, x= 1.5, gone= null\n"
+        ));
+        assert!(PrettyPrinter::default()
+            .fmt(&e)
+            .to_string()
+            .contains("\x1b["));
+    }
+
+    #[test]
+    fn base64_ids_are_distinct() {
+        let ids: std::collections::HashSet<_> = (1..=100_000)
+            .map(|id| base64_id(NonZeroU64::new(id).unwrap()))
+            .collect();
+        assert_eq!(ids.len(), 100_000);
+        for id in &ids {
+            assert!(id
+                .iter()
+                .all(|&b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/'));
+        }
+    }
+
+    #[test]
+    fn show_pid() {
+        let e = Event {
+            pid: Some(42),
+            ..event(Level::Info)
+        };
+        let p = PrettyPrinter::default().with_ansi(false);
+        assert!(p.fmt(&e).to_string().starts_with(" INFO: hi"));
+        assert!(p
+            .show_pid(true)
+            .fmt(&e)
+            .to_string()
+            .starts_with(" INFO pid=42: hi"));
+    }
+
+    #[test]
+    fn show_globals() {
+        let e = Event {
+            globals: vec![("service".to_string(), FieldValue::from("billing"))]
+                .into_iter()
+                .collect(),
+            ..event(Level::Info)
+        };
+        let p = PrettyPrinter::default().with_ansi(false);
+        assert!(!p.fmt(&e).to_string().contains("globals"));
+        assert!(p
+            .show_globals(true)
+            .fmt(&e)
+            .to_string()
+            .contains("\n  | globals service= billing\n"));
+    }
+
+    #[test]
+    fn write_to_buffer() {
+        let p = PrettyPrinter::default().with_ansi(false);
+        let e = event(Level::Info);
+        let mut buf = Vec::new();
+        p.write(&mut buf, &e).unwrap();
+        p.write(&mut buf, &e).unwrap();
+        let line = format!("{}\n", p.fmt(&e));
+        assert_eq!(String::from_utf8(buf).unwrap(), line.repeat(2));
+    }
+
+    #[test]
+    fn group_by_span() {
+        let span = |name: &str, id| Span {
+            name: name.to_string(),
+            id: NonZeroU64::new(id),
+            fields: std::iter::once(("x".to_string(), FieldValue::Int(id as i64))).collect(),
+            declared_fields: None,
+        };
+        let in_spans = |spans: Vec<Span>| Event {
+            spans,
+            ..event(Level::Info)
+        };
+        let events = vec![
+            in_spans(vec![span("outer", 1), span("inner", 2)]),
+            in_spans(vec![span("outer", 1), span("inner", 2)]),
+            in_spans(vec![span("outer", 1), span("inner", 2)]),
+            in_spans(vec![span("outer", 1)]),
+            in_spans(vec![]),
+        ];
+
+        let p = PrettyPrinter::default()
+            .theme(Theme::plain())
+            .show_source(false)
+            .show_target(false)
+            .group_by_span(true);
+        let mut group = None;
+        let output: String = events
+            .iter()
+            .map(|e| p.fmt_in_group(&mut group, e))
+            .collect();
+
+        assert_eq!(output.matches("inner").count(), 1, "{}", output);
+        assert_eq!(output.matches("outer").count(), 2, "{}", output);
+        assert_eq!(output.matches("hi").count(), 5, "{}", output);
+        assert!(
+            output.contains("}\n     INFO: hi\n     INFO: hi\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.ends_with("}\n     INFO: hi\n INFO: hi\n"),
+            "{}",
+            output
+        );
+    }
+
     #[test]
     fn pretty_printing() -> anyhow::Result<()> {
         let p = PrettyPrinter::default();
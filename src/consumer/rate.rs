@@ -0,0 +1,122 @@
+use crate::time::UnixTime;
+use crate::Event;
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+
+/// The rate of events per second over a sliding window, e.g. to plot the throughput of a log.
+///
+/// For each event with a timestamp, yields the timestamp and the number of events in the `window` ending
+/// at it (including the event itself), divided by the length of the window in seconds.  Events without a
+/// timestamp are skipped.  The rate is underestimated until a whole window of events has been seen.
+///
+/// The events should be in time order, e.g. from [`merge_by_time`](super::merge_by_time).  Only the
+/// timestamps in the current window are held in memory.
+///
+/// # Panics
+/// Panics if `window` is zero.
+///
+/// ```no_run
+/// use tracing_subscriber_serde::consumer::{rate, IterFile};
+/// use tracing_subscriber_serde::format::Json;
+/// use std::time::Duration;
+///
+/// for r in rate(Json.iter_file("log.json"), Duration::from_secs(10)) {
+///     let (time, per_sec) = r.unwrap();
+///     println!("{} {:.1}", time.to_rfc3339(), per_sec);
+/// }
+/// ```
+pub fn rate<I>(events: I, window: Duration) -> Rate<I::IntoIter>
+where
+    I: IntoIterator<Item = io::Result<Event>>,
+{
+    assert!(window > Duration::ZERO, "rate needs a non-zero window");
+    Rate {
+        events: events.into_iter(),
+        window,
+        times: VecDeque::new(),
+    }
+}
+
+/// An iterator over the rate of events per second.  Created with [`rate`].
+pub struct Rate<I> {
+    events: I,
+    window: Duration,
+    /// The timestamps in the current window
+    times: VecDeque<Duration>,
+}
+
+impl<I> Iterator for Rate<I>
+where
+    I: Iterator<Item = io::Result<Event>>,
+{
+    type Item = io::Result<(UnixTime, f64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let time = loop {
+            match self.events.next()? {
+                Ok(Event { time: Some(t), .. }) => break t,
+                Ok(_) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        };
+
+        let now = Duration::from(time.clone());
+        let start = now.checked_sub(self.window);
+        while let Some(&t) = self.times.front() {
+            if Some(t) > start {
+                break;
+            }
+            self.times.pop_front();
+        }
+        self.times.push_back(now);
+
+        let per_sec = self.times.len() as f64 / self.window.as_secs_f64();
+        Some(Ok((time, per_sec)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(time: Option<Duration>) -> io::Result<Event> {
+        let mut event: Event =
+            serde_json::from_str(r#"{"ty":{"event":{}},"l":2,"s":[],"t":"test"}"#).unwrap();
+        event.time = time.map(UnixTime::from);
+        Ok(event)
+    }
+
+    #[test]
+    fn known_cadence() {
+        let start = Duration::from_secs(1_600_000_000);
+        // 10 events per second for 10 seconds, then 2 per second, with untimed events in between
+        let times = (0..100)
+            .map(|i| start + Duration::from_millis(100 * i))
+            .chain(
+                (0..10).map(|i| start + Duration::from_secs(10) + Duration::from_millis(500 * i)),
+            );
+        let events = times.flat_map(|t| vec![event(Some(t)), event(None)]);
+
+        let rates: Vec<_> = rate(events, Duration::from_secs(1))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rates.len(), 110);
+        assert_eq!(Duration::from(rates[0].0.clone()), start);
+
+        // Ramping up over the first window
+        assert!((rates[4].1 - 5.0).abs() < 1e-9);
+        for (_, per_sec) in &rates[10..100] {
+            assert!((per_sec - 10.0).abs() < 1e-9, "{}", per_sec);
+        }
+        for (_, per_sec) in &rates[102..] {
+            assert!((per_sec - 2.0).abs() < 1e-9, "{}", per_sec);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "non-zero window")]
+    fn zero_window() {
+        rate(vec![event(Some(Duration::from_secs(1)))], Duration::ZERO);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{Event, EventKind, FieldValue};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::io;
+use std::num::NonZeroU64;
+
+/// Restore the span fields of events recorded with
+/// [`SerdeLayerBuilder::with_span_fields_on_create_only`](crate::SerdeLayerBuilder::with_span_fields_on_create_only).
+///
+/// The fields recorded on each span's [`EventKind::SpanCreate`] event are remembered by span ID, updated by its
+/// [`EventKind::SpanRecord`] events, and copied into the spans of subsequent events.  Spans without an ID, or whose creation wasn't seen, are left
+/// unchanged.
+pub fn rejoin_span_fields<I>(events: I) -> RejoinSpanFields<I::IntoIter>
+where
+    I: IntoIterator<Item = io::Result<Event>>,
+{
+    RejoinSpanFields {
+        events: events.into_iter(),
+        fields: HashMap::new(),
+    }
+}
+
+/// An iterator which restores span fields.  Created with [`rejoin_span_fields`].
+pub struct RejoinSpanFields<I> {
+    events: I,
+    fields: HashMap<NonZeroU64, IndexMap<String, FieldValue>>,
+}
+
+impl<I> Iterator for RejoinSpanFields<I>
+where
+    I: Iterator<Item = io::Result<Event>>,
+{
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut event = match self.events.next()? {
+            Ok(e) => e,
+            Err(e) => return Some(Err(e)),
+        };
+
+        match &event.kind {
+            EventKind::SpanCreate => {
+                if let Some(span) = event.spans.last() {
+                    if let Some(id) = span.id {
+                        self.fields.insert(id, span.fields.clone());
+                    }
+                }
+            }
+            // Fields recorded later apply to all subsequent events of the span
+            EventKind::SpanRecord(recorded) => {
+                if let Some(fields) = event
+                    .spans
+                    .last()
+                    .and_then(|s| s.id)
+                    .and_then(|id| self.fields.get_mut(&id))
+                {
+                    fields.extend(recorded.iter().map(|(k, v)| (k.clone(), v.clone())));
+                }
+            }
+            _ => {}
+        }
+
+        for span in &mut event.spans {
+            if !span.fields.is_empty() {
+                continue;
+            }
+            if let Some(fields) = span.id.and_then(|id| self.fields.get(&id)) {
+                span.fields = fields.clone();
+            }
+        }
+
+        // Span IDs may be re-used once a span has closed.
+        if let EventKind::SpanClose(_) = event.kind {
+            if let Some(id) = event.spans.last().and_then(|s| s.id) {
+                self.fields.remove(&id);
+            }
+        }
+
+        Some(Ok(event))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.events.size_hint()
+    }
+}
+
+impl<I> ExactSizeIterator for RejoinSpanFields<I> where
+    I: ExactSizeIterator<Item = io::Result<Event>>
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Json;
+    use crate::test_utils::capture;
+    use crate::{SerdeLayer, SerdeLayerBuilder, SpanEvents};
+    use std::io::Stdout;
+
+    fn run(layer: SerdeLayerBuilder<Json, (), Stdout>) -> Vec<Event> {
+        let layer = layer
+            .with_span_events(SpanEvents::FULL)
+            .with_span_records(true);
+        capture(layer, || {
+            let outer =
+                tracing::info_span!("outer", x = 1, y = "why", attempt = tracing::field::Empty);
+            let _outer = outer.enter();
+            for i in 0..3 {
+                outer.record("attempt", i);
+                let _inner = tracing::info_span!("inner", i).entered();
+                tracing::info!(z = true, "hello");
+            }
+        })
+    }
+
+    #[test]
+    fn span_fields_on_create_only() {
+        let full = run(SerdeLayer::new().with_span_ids(true));
+        let compact = run(SerdeLayer::new().with_span_fields_on_create_only(true));
+        assert_eq!(full.len(), compact.len());
+
+        for e in &compact {
+            let num_spans = e.spans.len();
+            for (k, span) in e.spans.iter().enumerate() {
+                assert!(span.id.is_some());
+                let is_created_span = e.kind == EventKind::SpanCreate && k + 1 == num_spans;
+                assert_eq!(span.fields.is_empty(), !is_created_span, "{:?}", e);
+            }
+        }
+
+        let rejoined: Vec<_> = rejoin_span_fields(compact.into_iter().map(Ok))
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        for (a, b) in full.iter().zip(&rejoined) {
+            assert_eq!(a.kind, b.kind);
+            assert_eq!(a.spans.len(), b.spans.len());
+            for (a, b) in a.spans.iter().zip(&b.spans) {
+                assert_eq!(a.name, b.name);
+                assert_eq!(a.fields, b.fields);
+            }
+        }
+    }
+
+    #[test]
+    fn span_fields_on_events() {
+        let compact = run(SerdeLayer::new().with_span_fields_on_create_only(true));
+        let slim = run(SerdeLayer::new().with_span_fields_on_events(false));
+        let json = |events: &[Event]| serde_json::to_value(events).unwrap();
+        assert_eq!(json(&compact), json(&slim));
+    }
+}
@@ -0,0 +1,168 @@
+use crate::{Event, EventKind, FieldValue};
+use indexmap::IndexMap;
+use std::collections::HashSet;
+use std::io;
+
+/// Replace the values of the named fields, wherever they appear in an event or its spans, including fields
+/// recorded on spans later with [`EventKind::SpanRecord`] events.
+///
+/// This is intended for redacting logs which were captured before redaction was configured, e.g.
+/// ```no_run
+/// use tracing_subscriber_serde::consumer::{scrub, IterFile};
+/// use tracing_subscriber_serde::{format::Json, SerdeFormat};
+///
+/// let mut out = std::fs::File::create("scrubbed.json").unwrap();
+/// for event in scrub(Json.iter_file("log.json"), &["password", "token"], "<redacted>") {
+///     Json.serialize(&mut out, event.unwrap()).unwrap();
+/// }
+/// ```
+pub fn scrub<I, S>(
+    events: I,
+    field_names: impl IntoIterator<Item = S>,
+    replacement: impl Into<FieldValue>,
+) -> Scrub<I::IntoIter>
+where
+    I: IntoIterator<Item = io::Result<Event>>,
+    S: AsRef<str>,
+{
+    Scrub {
+        events: events.into_iter(),
+        field_names: field_names
+            .into_iter()
+            .map(|s| s.as_ref().to_string())
+            .collect(),
+        replacement: replacement.into(),
+    }
+}
+
+/// An iterator which replaces field values.  Created with [`scrub`].
+pub struct Scrub<I> {
+    events: I,
+    field_names: HashSet<String>,
+    replacement: FieldValue,
+}
+
+impl<I> Scrub<I> {
+    fn scrub_fields(&self, fields: &mut IndexMap<String, FieldValue>) {
+        for (name, val) in fields.iter_mut() {
+            if self.field_names.contains(name) {
+                *val = self.replacement.clone();
+            }
+        }
+    }
+}
+
+impl<I> Iterator for Scrub<I>
+where
+    I: Iterator<Item = io::Result<Event>>,
+{
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut event = match self.events.next()? {
+            Ok(e) => e,
+            Err(e) => return Some(Err(e)),
+        };
+
+        if let EventKind::Event(fields) | EventKind::SpanRecord(fields) = &mut event.kind {
+            self.scrub_fields(fields);
+        }
+        for span in &mut event.spans {
+            self.scrub_fields(&mut span.fields);
+        }
+
+        Some(Ok(event))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.events.size_hint()
+    }
+}
+
+impl<I> ExactSizeIterator for Scrub<I> where I: ExactSizeIterator<Item = io::Result<Event>> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::StreamFormat;
+    use crate::format::Json;
+    use crate::test_utils::capture_output;
+    use crate::{SerdeFormat, SerdeLayer, SpanEvents};
+
+    #[test]
+    fn scrubs_events_and_spans() {
+        let layer = SerdeLayer::new().with_span_events(SpanEvents::FULL);
+        let input = capture_output(layer, || {
+            let _login = tracing::info_span!("login", user = "bob", password = "hunter2").entered();
+            tracing::info!(password = "hunter2", attempt = 1, "logging in");
+            tracing::info!("done");
+        });
+        assert!(String::from_utf8_lossy(&input).contains("hunter2"));
+
+        let mut output = Vec::new();
+        for event in scrub(Json.iter_reader(input.as_slice()), &["password"], "***") {
+            Json.serialize(&mut output, event.unwrap()).unwrap();
+        }
+        assert!(!String::from_utf8_lossy(&output).contains("hunter2"));
+
+        let events: Vec<_> = Json
+            .iter_reader(output.as_slice())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(events.len(), 6);
+
+        let stars = FieldValue::from("***");
+        let mut replaced = 0;
+        for e in &events {
+            for span in &e.spans {
+                assert_eq!(span.fields["password"], stars);
+                assert_eq!(span.fields["user"], FieldValue::from("bob"));
+                replaced += 1;
+            }
+            if let EventKind::Event(fields) = &e.kind {
+                if let Some(val) = fields.get("password") {
+                    assert_eq!(val, &stars);
+                    assert_eq!(fields["attempt"], FieldValue::Int(1));
+                    replaced += 1;
+                }
+            }
+        }
+        assert_eq!(replaced, 7);
+    }
+
+    #[test]
+    fn scrubs_span_records() {
+        let layer = SerdeLayer::new().with_span_records(true);
+        let input = capture_output(layer, || {
+            let login = tracing::info_span!("login", password = tracing::field::Empty);
+            login.record("password", "hunter2");
+            login.in_scope(|| tracing::info!("logging in"));
+        });
+        assert!(String::from_utf8_lossy(&input).contains("hunter2"));
+
+        let events: Vec<_> = scrub(Json.iter_reader(input.as_slice()), &["password"], "***")
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        let stars = FieldValue::from("***");
+        match &events[0].kind {
+            EventKind::SpanRecord(fields) => assert_eq!(fields["password"], stars),
+            kind => panic!("expected a span record, got {:?}", kind),
+        }
+        for e in &events {
+            assert_eq!(e.spans[0].fields["password"], stars);
+        }
+    }
+
+    #[test]
+    fn size_hint() {
+        let input = r#"{"ty":"span_create","l":2,"s":[{"n":"a","f":{}}],"t":"x"}"#.repeat(3);
+        let events: Vec<_> = Json.iter_reader(input.as_bytes()).collect();
+        let mut scrubbed = scrub(events, &["password"], "***");
+        for remaining in (0..=3).rev() {
+            assert_eq!(scrubbed.len(), remaining);
+            assert_eq!(scrubbed.size_hint(), (remaining, Some(remaining)));
+            scrubbed.next();
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use crate::{Event, EventKind, FieldValue, Level, Span};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::num::NonZeroU64;
+use std::time::Duration;
+
+/// Selects a single span for [`span_slice`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpanSelector {
+    /// The span with this ID.  Requires span IDs to have been recorded.  If IDs were re-used, the first span
+    /// with the ID is selected.
+    ById(NonZeroU64),
+    /// The `index`-th span (counting from zero) named `name`.
+    ByNameOccurrence {
+        /// The span's name
+        name: String,
+        /// Which occurrence of the name to select
+        index: usize,
+    },
+    /// The first span named `name` whose field `field` equals `value`.
+    ByFieldEquals {
+        /// The span's name
+        name: String,
+        /// The field's name
+        field: String,
+        /// The field's value
+        value: FieldValue,
+    },
+}
+
+impl SpanSelector {
+    /// The position of the (outermost) matching span in `spans`
+    fn position(&self, spans: &[Span]) -> Option<usize> {
+        spans.iter().position(|s| match self {
+            SpanSelector::ById(id) => s.id == Some(*id),
+            SpanSelector::ByNameOccurrence { name, .. } => &s.name == name,
+            SpanSelector::ByFieldEquals { name, field, value } => {
+                &s.name == name && s.fields.get(field) == Some(value)
+            }
+        })
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            SpanSelector::ByNameOccurrence { index, .. } => *index,
+            _ => 0,
+        }
+    }
+}
+
+/// Finds the selected span the first time an event references it.
+struct Search<'a> {
+    selector: &'a SpanSelector,
+    remaining: usize,
+    /// The IDs of the spans named by a [`SpanSelector::ByNameOccurrence`] seen so far
+    seen: HashSet<NonZeroU64>,
+}
+
+enum Found {
+    /// The selected span, which has an ID
+    Id(NonZeroU64),
+    /// A matching span without an ID
+    Anonymous,
+}
+
+impl Search<'_> {
+    fn find(&mut self, spans: &[Span]) -> Option<Found> {
+        match self.selector {
+            SpanSelector::ById(id) => Some(Found::Id(*id)),
+            SpanSelector::ByNameOccurrence { name, .. } => {
+                for span in spans.iter().filter(|s| &s.name == name) {
+                    let id = match span.id {
+                        Some(id) => id,
+                        None => return Some(Found::Anonymous),
+                    };
+                    if self.seen.insert(id) {
+                        if self.remaining == 0 {
+                            return Some(Found::Id(id));
+                        }
+                        self.remaining -= 1;
+                    }
+                }
+                None
+            }
+            SpanSelector::ByFieldEquals { .. } => {
+                let span = &spans[self.selector.position(spans)?];
+                Some(span.id.map_or(Found::Anonymous, Found::Id))
+            }
+        }
+    }
+}
+
+/// Follows spans without IDs, which are told apart by their position, name and fields, so their events must be
+/// contiguous.
+struct Contiguous {
+    remaining: usize,
+    /// The selected span and its position, once found.
+    current: Option<(usize, Span)>,
+}
+
+impl Contiguous {
+    /// Add `event` to `slice` if it is in the selected span.  Returns true once the span has closed.
+    fn step(&mut self, selector: &SpanSelector, event: Event, slice: &mut SpanSlice) -> bool {
+        let depth = match selector.position(&event.spans) {
+            Some(d) => d,
+            None => return false,
+        };
+        let span = &event.spans[depth];
+        let is_innermost = depth + 1 == event.spans.len();
+
+        let same_span = match &self.current {
+            Some((d, s)) => {
+                *d == depth
+                    && s.name == span.name
+                    && (s.fields == span.fields || span.fields.is_empty())
+                    && !(is_innermost && event.kind == EventKind::SpanCreate)
+            }
+            None => false,
+        };
+
+        if !same_span {
+            if self.current.is_some() {
+                if self.remaining == 0 {
+                    return true;
+                }
+                self.remaining -= 1;
+                slice.events.clear();
+            }
+            self.current = Some((depth, span.clone()));
+        }
+
+        let closes = is_innermost && matches!(event.kind, EventKind::SpanClose(_));
+        slice.events.push(event);
+
+        if closes {
+            if self.remaining == 0 {
+                return true;
+            }
+            self.remaining -= 1;
+            slice.events.clear();
+            self.current = None;
+        }
+        false
+    }
+}
+
+enum State<'a> {
+    Searching(Search<'a>),
+    Id(NonZeroU64),
+    Contiguous(Contiguous),
+}
+
+/// Summary of a [`SpanSlice`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpanSliceSummary {
+    /// The time between the first and last event of the slice, if they have timestamps.
+    pub duration: Option<Duration>,
+    /// The number of regular (non-span) events in the slice, by level.
+    pub events_by_level: HashMap<Level, usize>,
+}
+
+/// The events which occurred during the lifetime of a single span.  Created with [`span_slice`].
+#[derive(Debug, Clone, Default)]
+pub struct SpanSlice {
+    /// The events whose spans contain the selected span, in order.  Includes the span's own lifecycle
+    /// events, if they were recorded.
+    pub events: Vec<Event>,
+    /// Summary of the events
+    pub summary: SpanSliceSummary,
+}
+
+/// Extract the events which occurred inside a single span.
+///
+/// The span's lifetime is bounded by its [`EventKind::SpanCreate`] and [`EventKind::SpanClose`] events.  If
+/// these weren't recorded, it is bounded by the first and last events which reference it.
+///
+/// Spans are identified by their ID, so the events of different spans may be interleaved, e.g. by other
+/// threads or by recursive spans of the same name, and recording new span fields doesn't end the slice.
+/// [`SpanSelector::ByNameOccurrence`] counts spans in the order they are first seen.  Spans recorded without
+/// IDs are told apart by their position, name and fields instead: their events must not be interleaved, and
+/// consecutive spans with the same name, position and fields are treated as one if lifecycle events weren't
+/// recorded either.
+///
+/// Returns `None` if no span matches.
+pub fn span_slice(
+    events: impl IntoIterator<Item = io::Result<Event>>,
+    selector: &SpanSelector,
+) -> io::Result<Option<SpanSlice>> {
+    let mut state = State::Searching(Search {
+        selector,
+        remaining: selector.index(),
+        seen: HashSet::new(),
+    });
+    let mut slice = SpanSlice::default();
+
+    for event in events {
+        let event = event?;
+        if let State::Searching(search) = &mut state {
+            state = match search.find(&event.spans) {
+                Some(Found::Id(id)) => State::Id(id),
+                Some(Found::Anonymous) => State::Contiguous(Contiguous {
+                    remaining: selector.index(),
+                    current: None,
+                }),
+                None => continue,
+            };
+        }
+        match &mut state {
+            State::Searching(_) => unreachable!(),
+            State::Id(id) => {
+                let depth = match event.spans.iter().position(|s| s.id == Some(*id)) {
+                    Some(d) => d,
+                    None => continue,
+                };
+                let closes =
+                    depth + 1 == event.spans.len() && matches!(event.kind, EventKind::SpanClose(_));
+                slice.events.push(event);
+                if closes {
+                    break;
+                }
+            }
+            State::Contiguous(contiguous) => {
+                if contiguous.step(selector, event, &mut slice) {
+                    break;
+                }
+            }
+        }
+    }
+
+    let incomplete = matches!(&state, State::Contiguous(c) if c.remaining > 0);
+    if incomplete || slice.events.is_empty() {
+        return Ok(None);
+    }
+
+    let times = (
+        slice.events.first().and_then(|e| e.time.clone()),
+        slice.events.last().and_then(|e| e.time.clone()),
+    );
+    if let (Some(start), Some(end)) = times {
+        slice.summary.duration = Duration::from(end).checked_sub(Duration::from(start));
+    }
+    for e in &slice.events {
+        if let EventKind::Event(_) = e.kind {
+            *slice.summary.events_by_level.entry(e.level).or_default() += 1;
+        }
+    }
+
+    Ok(Some(slice))
+}
+
+#[cfg(all(test, feature = "bench-util"))]
+mod tests {
+    use super::*;
+    use crate::bench_util::{run_workload_with_layer, workloads};
+    use crate::consumer::StreamFormat;
+    use crate::format::Json;
+    use crate::test_utils::capture;
+    use crate::{SerdeLayer, SpanEvents};
+
+    fn simple_workload(span_events: SpanEvents, span_ids: bool) -> Vec<Event> {
+        let layer = SerdeLayer::new()
+            .with_span_events(span_events)
+            .with_span_ids(span_ids);
+        let output = run_workload_with_layer(layer, || workloads::simple(1));
+        Json.iter_reader(output.as_slice())
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    fn slice(events: &[Event], selector: &SpanSelector) -> SpanSlice {
+        span_slice(events.iter().cloned().map(Ok), selector)
+            .unwrap()
+            .unwrap()
+    }
+
+    fn second_a() -> SpanSelector {
+        SpanSelector::ByNameOccurrence {
+            name: "a".to_string(),
+            index: 1,
+        }
+    }
+
+    fn assert_in_second_a(slice: &SpanSlice) {
+        for e in &slice.events {
+            let a = e.spans.iter().find(|s| s.name == "a").unwrap();
+            assert_eq!(a.fields["i"], FieldValue::Int(1));
+        }
+        assert_eq!(slice.summary.events_by_level[&Level::Error], 1);
+        assert_eq!(slice.summary.events_by_level[&Level::Trace], 1);
+        assert_eq!(slice.summary.events_by_level.len(), 2);
+    }
+
+    #[test]
+    fn slice_with_lifecycle_events() {
+        let events = simple_workload(SpanEvents::FULL, false);
+        let slice = slice(&events, &second_a());
+        assert_in_second_a(&slice);
+
+        let kinds: Vec<_> = slice
+            .events
+            .iter()
+            .map(|e| match &e.kind {
+                EventKind::Event(_) => format!("{:?}", e.level),
+                k => format!("{:?} {}", k, e.spans.last().unwrap().name),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "SpanCreate a",
+                "SpanEnter a",
+                "Error",
+                "SpanCreate check_for_egg",
+                "SpanEnter check_for_egg",
+                "Trace",
+                "SpanExit check_for_egg",
+                "SpanClose(None) check_for_egg",
+                "SpanExit a",
+                "SpanClose(None) a",
+            ]
+        );
+    }
+
+    #[test]
+    fn slice_without_lifecycle_events() {
+        let events = simple_workload(SpanEvents::NONE, false);
+        let slice = slice(&events, &second_a());
+        assert_eq!(slice.events.len(), 2);
+        assert_in_second_a(&slice);
+
+        let by_field = SpanSelector::ByFieldEquals {
+            name: "a".to_string(),
+            field: "i".to_string(),
+            value: FieldValue::Int(1),
+        };
+        assert_eq!(
+            format!("{:?}", slice.events),
+            format!("{:?}", self::slice(&events, &by_field).events)
+        );
+    }
+
+    #[test]
+    fn slice_by_id() {
+        let events = simple_workload(SpanEvents::FULL, true);
+        let by_name = slice(&events, &second_a());
+        let id = by_name.events[0].spans.last().unwrap().id.unwrap();
+        let by_id = slice(&events, &SpanSelector::ById(id));
+        assert_eq!(by_name.events.len(), by_id.events.len());
+        assert_in_second_a(&by_id);
+
+        let missing = SpanSelector::ByNameOccurrence {
+            name: "a".to_string(),
+            index: 10,
+        };
+        assert!(span_slice(events.into_iter().map(Ok), &missing)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn interleaved_spans() {
+        let layer = SerdeLayer::new()
+            .with_span_events(SpanEvents::FULL)
+            .with_span_ids(true)
+            .with_span_records(true);
+        let events = capture(layer, || {
+            let a0 = tracing::info_span!("a", i = 0, x = tracing::field::Empty);
+            let a1 = tracing::info_span!("a", i = 1);
+            a0.in_scope(|| tracing::info!("zero"));
+            a1.in_scope(|| tracing::info!("one"));
+            a0.in_scope(|| {
+                a0.record("x", 5);
+                tracing::info!("zero again");
+            });
+            a1.in_scope(|| {
+                let _nested = tracing::info_span!("a", i = 2).entered();
+                tracing::info!("nested");
+            });
+        });
+        let messages = |slice: &SpanSlice| -> Vec<String> {
+            slice
+                .events
+                .iter()
+                .filter_map(|e| match e.field("message") {
+                    Some(FieldValue::Str(m)) => Some(m.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let second = slice(&events, &second_a());
+        assert_eq!(messages(&second), ["one", "nested"]);
+        assert_eq!(second.events[0].kind, EventKind::SpanCreate);
+        assert!(matches!(
+            second.events.last().unwrap().kind,
+            EventKind::SpanClose(_)
+        ));
+
+        // Recording a field doesn't end the slice
+        let id = events[0].spans[0].id.unwrap();
+        let first = slice(&events, &SpanSelector::ById(id));
+        assert_eq!(messages(&first), ["zero", "zero again"]);
+        assert!(first
+            .events
+            .iter()
+            .any(|e| matches!(e.kind, EventKind::SpanRecord(_))));
+
+        let nested = SpanSelector::ByNameOccurrence {
+            name: "a".to_string(),
+            index: 2,
+        };
+        assert_eq!(messages(&slice(&events, &nested)), ["nested"]);
+    }
+}
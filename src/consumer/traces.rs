@@ -0,0 +1,359 @@
+use super::merge::{merge_by_time, MergeByTime};
+use crate::{Event, EventKind, FieldValue};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::io;
+
+/// Events grouped by the value of a correlation field.  Created with [`GroupByKey::collect`].
+#[derive(Debug, Clone, Default)]
+pub struct Traces {
+    /// The events of each group, in timestamp order.  Events which don't have the key field are grouped
+    /// under `None`.
+    pub events: HashMap<Option<FieldValue>, Vec<Event>>,
+    /// The number of events discarded from each group because the group was full.
+    /// See [`GroupByKey::max_per_key`].
+    pub overflow: HashMap<Option<FieldValue>, usize>,
+}
+
+/// Reconstruct traces (e.g. all the events for a single request) by grouping events on the value of a
+/// correlation field.
+///
+/// The key is looked up with [`Event::field`], so it may be a field of the event or any of its spans.
+/// Several sources may be given, which are merged with [`merge_by_time`](super::merge_by_time).
+///
+/// # Examples
+/// ```no_run
+/// use tracing_subscriber_serde::consumer::{GroupByKey, IterFile};
+/// use tracing_subscriber_serde::{format::Json, FieldValue};
+///
+/// let traces = GroupByKey::new("request_id")
+///     .collect(vec![Json.iter_file("a.json"), Json.iter_file("b.json")])
+///     .unwrap();
+///
+/// for event in &traces.events[&Some(FieldValue::Int(42))] {
+///     println!("{:?}", event);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct GroupByKey<'k> {
+    key: GroupKey<'k>,
+    max_per_key: usize,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum GroupKey<'k> {
+    Field(&'k str),
+    Instance,
+}
+
+impl GroupKey<'_> {
+    fn of(&self, event: &Event) -> Option<FieldValue> {
+        match self {
+            GroupKey::Field(name) => event.field(name).cloned(),
+            GroupKey::Instance => event.instance_id.map(|id| FieldValue::Int(id as i64)),
+        }
+    }
+}
+
+/// Convenience function for `GroupByKey::new(key).collect(sources)`.
+pub fn collect_by_key<I>(
+    sources: impl IntoIterator<Item = I>,
+    key: &str,
+) -> io::Result<HashMap<Option<FieldValue>, Vec<Event>>>
+where
+    I: Iterator<Item = io::Result<Event>>,
+{
+    GroupByKey::new(key).collect(sources).map(|t| t.events)
+}
+
+impl<'k> GroupByKey<'k> {
+    /// Group events by the field named `key`.
+    pub fn new(key: &'k str) -> Self {
+        GroupByKey {
+            key: GroupKey::Field(key),
+            max_per_key: usize::MAX,
+        }
+    }
+
+    /// Group events by the [instance ID](crate::SerdeLayerBuilder::with_instance_id) of the process which
+    /// produced them.  The keys are the IDs as [`FieldValue::Int`]s.
+    pub fn instance() -> Self {
+        GroupByKey {
+            key: GroupKey::Instance,
+            max_per_key: usize::MAX,
+        }
+    }
+
+    /// Limit the number of events stored per group.  Further events in the group are discarded and counted
+    /// in [`Traces::overflow`].
+    pub fn max_per_key(mut self, max: usize) -> Self {
+        self.max_per_key = max;
+        self
+    }
+
+    /// Read all events and group them.
+    pub fn collect<I>(&self, sources: impl IntoIterator<Item = I>) -> io::Result<Traces>
+    where
+        I: Iterator<Item = io::Result<Event>>,
+    {
+        let mut traces = Traces::default();
+        for event in merge_by_time(sources) {
+            let event = event?;
+            let key = self.key.of(&event);
+            let group = traces.events.entry(key.clone()).or_default();
+            if group.len() < self.max_per_key {
+                group.push(event);
+            } else {
+                *traces.overflow.entry(key).or_default() += 1;
+            }
+        }
+        Ok(traces)
+    }
+
+    /// Group events in a streaming fashion, assuming the sources are sorted by timestamp.
+    ///
+    /// A group is yielded as soon as the span which holds the key field closes, so this requires
+    /// [`SpanEvents::CLOSE`](crate::SpanEvents::CLOSE) to have been recorded.  Any remaining groups are
+    /// yielded in the order they were first seen once the sources are exhausted.  Overflowing events are
+    /// discarded.  When grouping by [`instance`](Self::instance), all groups are yielded once the sources
+    /// are exhausted.
+    pub fn iter<I>(&self, sources: impl IntoIterator<Item = I>) -> IterByKey<'k, I>
+    where
+        I: Iterator<Item = io::Result<Event>>,
+    {
+        IterByKey {
+            config: self.clone(),
+            events: merge_by_time(sources),
+            open: IndexMap::new(),
+            exhausted: false,
+        }
+    }
+}
+
+/// A streaming iterator over groups of events.  Created with [`GroupByKey::iter`].
+pub struct IterByKey<'k, I: Iterator> {
+    config: GroupByKey<'k>,
+    events: MergeByTime<I>,
+    open: IndexMap<Option<FieldValue>, Vec<Event>>,
+    exhausted: bool,
+}
+
+impl<I> Iterator for IterByKey<'_, I>
+where
+    I: Iterator<Item = io::Result<Event>>,
+{
+    type Item = io::Result<(Option<FieldValue>, Vec<Event>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.exhausted {
+            let event = match self.events.next() {
+                Some(Ok(e)) => e,
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.exhausted = true;
+                    break;
+                }
+            };
+
+            let key = self.config.key.of(&event);
+            let closes_group = match self.config.key {
+                GroupKey::Field(name) => {
+                    matches!(event.kind, EventKind::SpanClose(_))
+                        && key.is_some()
+                        && matches!(event.spans.last(), Some(s) if s.fields.contains_key(name))
+                }
+                GroupKey::Instance => false,
+            };
+
+            let group = self.open.entry(key.clone()).or_default();
+            if group.len() < self.config.max_per_key {
+                group.push(event);
+            }
+
+            if closes_group {
+                let group = self.open.shift_remove(&key).unwrap_or_default();
+                return Some(Ok((key, group)));
+            }
+        }
+
+        self.open.shift_remove_index(0).map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::IterFile;
+    use crate::format::Json;
+    use crate::time::UnixTime;
+    use crate::{Level, SerdeFormat, Span};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn event(t: u64, request_id: Option<i64>, kind: EventKind) -> Event {
+        let spans = request_id
+            .map(|id| Span {
+                name: "request".to_string(),
+                id: None,
+                fields: std::iter::once(("request_id".to_string(), FieldValue::Int(id))).collect(),
+                declared_fields: None,
+            })
+            .into_iter()
+            .collect();
+
+        Event {
+            kind,
+            level: Level::Info,
+            spans,
+            target: "test".to_string(),
+            thread_id: None,
+            thread_name: None,
+            src_line: None,
+            src_file: None,
+            time: Some(UnixTime::from(Duration::from_secs(t))),
+            uptime_ns: None,
+            syslog_pri: None,
+            instance_id: None,
+            time_missing: None,
+            crate_name: None,
+            crate_version: None,
+            globals: Default::default(),
+            pid: None,
+            host: None,
+            name: None,
+            module: None,
+            seq: None,
+            id: None,
+            written_time: None,
+        }
+    }
+
+    fn message(t: u64, request_id: Option<i64>) -> Event {
+        let fields = std::iter::once(("t".to_string(), FieldValue::Int(t as i64))).collect();
+        event(t, request_id, EventKind::Event(fields))
+    }
+
+    fn write_file(name: &str, events: &[Event]) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let mut buf = Vec::new();
+        for e in events {
+            Json.serialize(&mut buf, e).unwrap();
+        }
+        std::fs::write(&path, buf).unwrap();
+        path
+    }
+
+    fn times(events: &[Event]) -> Vec<u64> {
+        events
+            .iter()
+            .map(|e| Duration::from(e.time.clone().unwrap()).as_secs())
+            .collect()
+    }
+
+    fn sources(test_name: &str) -> (PathBuf, PathBuf) {
+        let a = write_file(
+            &format!("tracing-subscriber-serde-{}-a.json", test_name),
+            &[
+                message(1, Some(1)),
+                message(3, Some(2)),
+                message(5, Some(1)),
+                message(6, None),
+                event(8, Some(1), EventKind::SpanClose(None)),
+            ],
+        );
+        let b = write_file(
+            &format!("tracing-subscriber-serde-{}-b.json", test_name),
+            &[
+                message(2, Some(2)),
+                message(4, Some(1)),
+                message(7, Some(2)),
+                message(9, Some(2)),
+            ],
+        );
+        (a, b)
+    }
+
+    #[test]
+    fn collects_interleaved_requests() {
+        let (a, b) = sources("collects_interleaved_requests");
+        let groups = collect_by_key(vec![Json.iter_file(&a), Json.iter_file(&b)], "request_id");
+
+        std::fs::remove_file(a).ok();
+        std::fs::remove_file(b).ok();
+        let groups = groups.unwrap();
+
+        assert_eq!(groups.len(), 3);
+        assert_eq!(times(&groups[&Some(FieldValue::Int(1))]), vec![1, 4, 5, 8]);
+        assert_eq!(times(&groups[&Some(FieldValue::Int(2))]), vec![2, 3, 7, 9]);
+        assert_eq!(times(&groups[&None]), vec![6]);
+    }
+
+    #[test]
+    fn caps_groups() {
+        let events = vec![
+            message(1, Some(1)),
+            message(2, Some(1)),
+            message(3, Some(1)),
+        ];
+        let traces = GroupByKey::new("request_id")
+            .max_per_key(2)
+            .collect(vec![events.into_iter().map(Ok)])
+            .unwrap();
+
+        assert_eq!(times(&traces.events[&Some(FieldValue::Int(1))]), vec![1, 2]);
+        assert_eq!(traces.overflow[&Some(FieldValue::Int(1))], 1);
+    }
+
+    #[test]
+    fn groups_by_instance() {
+        let on = |instance_id, e: Event| Event {
+            instance_id: Some(instance_id),
+            time_missing: None,
+            ..e
+        };
+        let a = vec![
+            on(1, message(1, Some(1))),
+            on(1, message(3, Some(2))),
+            on(1, message(4, None)),
+        ];
+        let b = vec![
+            on(2, message(2, Some(1))),
+            on(2, message(3, Some(1))),
+            on(2, message(5, Some(2))),
+        ];
+
+        let traces = GroupByKey::instance()
+            .collect(vec![
+                a.clone().into_iter().map(Ok),
+                b.clone().into_iter().map(Ok),
+            ])
+            .unwrap();
+
+        assert_eq!(traces.events.len(), 2);
+        let debug = |events: &[Event]| format!("{:?}", events);
+        assert_eq!(debug(&traces.events[&Some(FieldValue::Int(1))]), debug(&a));
+        assert_eq!(debug(&traces.events[&Some(FieldValue::Int(2))]), debug(&b));
+    }
+
+    #[test]
+    fn streams_groups_on_close() {
+        let (a, b) = sources("streams_groups_on_close");
+        let groups: Vec<_> = GroupByKey::new("request_id")
+            .iter(vec![Json.iter_file(&a), Json.iter_file(&b)])
+            .collect();
+
+        std::fs::remove_file(a).ok();
+        std::fs::remove_file(b).ok();
+        let groups: Vec<_> = groups.into_iter().map(Result::unwrap).collect();
+
+        let summary: Vec<_> = groups.iter().map(|(k, g)| (k.clone(), times(g))).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Some(FieldValue::Int(1)), vec![1, 4, 5, 8]),
+                (Some(FieldValue::Int(2)), vec![2, 3, 7, 9]),
+                (None, vec![6]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use super::{Capability, StreamCapabilities, UnsupportedStream};
+use crate::{Event, EventKind, FieldValue, Span};
+use indexmap::IndexMap;
+use std::num::NonZeroU64;
+
+/// A span in a [`SpanTree`].
+#[derive(Debug, Clone)]
+pub struct SpanNode {
+    /// The span's name
+    pub name: String,
+    /// The span's ID, if it was recorded on its creation event
+    pub id: Option<NonZeroU64>,
+    /// The fields of the span, as recorded on its creation event
+    pub fields: IndexMap<String, FieldValue>,
+    /// Index of the parent span, if any
+    pub parent: Option<usize>,
+    /// Indices of the child spans, in order of creation
+    pub children: Vec<usize>,
+    /// Indices (in the input) of the events which occurred directly inside this span
+    pub events: Vec<usize>,
+    /// Whether a [`EventKind::SpanClose`] event was seen for this span
+    pub closed: bool,
+}
+
+/// The tree of spans reconstructed from a stream of events.
+///
+/// Requires [`EventKind::SpanCreate`] events to have been recorded (see [`SpanEvents::NEW`](crate::SpanEvents::NEW)),
+/// and if span IDs may be re-used, [`EventKind::SpanClose`] events as well.
+///
+/// Spans are identified by their ID where one was recorded.  Otherwise, for example with
+/// [`SpanIdMode::LifecycleOnly`](crate::SpanIdMode::LifecycleOnly), a span is matched to the most recently
+/// created open span with the same names along its path from the root.
+#[derive(Debug, Clone, Default)]
+pub struct SpanTree {
+    nodes: Vec<SpanNode>,
+    open: Vec<usize>,
+}
+
+impl SpanTree {
+    /// Build the span tree from a sequence of events.
+    pub fn from_events<'a>(events: impl IntoIterator<Item = &'a Event>) -> Self {
+        let mut tree = SpanTree::default();
+        for (k, e) in events.into_iter().enumerate() {
+            tree.add_event(k, e);
+        }
+        tree
+    }
+
+    /// Build the span tree, after checking the events contain the [`EventKind::SpanCreate`] events it is
+    /// built from.  Unlike [`SpanTree::from_events`], this returns an error rather than an empty tree for
+    /// streams recorded without them.
+    pub fn try_from_events(events: &[Event]) -> Result<Self, UnsupportedStream> {
+        let caps = StreamCapabilities::detect(events);
+        if caps.has_spans {
+            caps.require(&[Capability::SpanCreate])?;
+        }
+        Ok(Self::from_events(events))
+    }
+
+    /// All the spans, in order of creation.
+    pub fn nodes(&self) -> &[SpanNode] {
+        &self.nodes
+    }
+
+    /// Indices of the root spans, in order of creation.
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.parent.is_none())
+            .map(|(k, _)| k)
+    }
+
+    fn path_matches(&self, node: usize, path: &[Span]) -> bool {
+        let mut node = Some(node);
+        for span in path.iter().rev() {
+            match node {
+                Some(n) if self.nodes[n].name == span.name => node = self.nodes[n].parent,
+                _ => return false,
+            }
+        }
+        node.is_none()
+    }
+
+    /// Find the open span at the end of `path`.
+    fn resolve(&self, path: &[Span]) -> Option<usize> {
+        let span = path.last()?;
+        if let Some(id) = span.id {
+            if let Some(&n) = self
+                .open
+                .iter()
+                .rev()
+                .find(|&&n| self.nodes[n].id == Some(id))
+            {
+                return Some(n);
+            }
+        }
+        self.open
+            .iter()
+            .rev()
+            .copied()
+            .find(|&n| self.path_matches(n, path))
+    }
+
+    fn add_event(&mut self, index: usize, e: &Event) {
+        match e.kind {
+            EventKind::SpanCreate => {
+                let (span, parents) = match e.spans.split_last() {
+                    Some(x) => x,
+                    None => return,
+                };
+                let parent = self.resolve(parents);
+                let n = self.nodes.len();
+                self.nodes.push(SpanNode {
+                    name: span.name.clone(),
+                    id: span.id,
+                    fields: span.fields.clone(),
+                    parent,
+                    children: Vec::new(),
+                    events: Vec::new(),
+                    closed: false,
+                });
+                if let Some(p) = parent {
+                    self.nodes[p].children.push(n);
+                }
+                self.open.push(n);
+            }
+            EventKind::SpanClose(_) => {
+                if let Some(n) = self.resolve(&e.spans) {
+                    self.nodes[n].closed = true;
+                    self.open.retain(|&m| m != n);
+                }
+            }
+            EventKind::Event(_) => {
+                if let Some(n) = self.resolve(&e.spans) {
+                    self.nodes[n].events.push(index);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::StreamFormat;
+    use crate::format::Json;
+    use crate::test_utils::capture_output;
+    use crate::{SerdeLayer, SpanEvents, SpanIdMode};
+    use std::io;
+
+    fn run(mode: SpanIdMode) -> Vec<u8> {
+        let layer = SerdeLayer::new()
+            .with_span_events(SpanEvents::NEW | SpanEvents::CLOSE)
+            .with_span_ids(mode);
+        let buffer = capture_output(layer, || {
+            for i in 0..3 {
+                let _outer = tracing::info_span!("outer", i).entered();
+                for j in 0..2 {
+                    let _inner = tracing::info_span!("inner", j).entered();
+                    tracing::info!("hello");
+                }
+                tracing::info!("bye");
+            }
+        });
+        buffer.clone()
+    }
+
+    fn structure(tree: &SpanTree) -> Vec<(String, Option<usize>, usize, bool)> {
+        tree.nodes()
+            .iter()
+            .map(|n| (n.name.clone(), n.parent, n.events.len(), n.closed))
+            .collect()
+    }
+
+    #[test]
+    fn lifecycle_only_span_ids() {
+        let never = run(SpanIdMode::Never);
+        let lifecycle = run(SpanIdMode::LifecycleOnly);
+        let always = run(SpanIdMode::Always);
+        assert!(never.len() < lifecycle.len());
+        assert!(lifecycle.len() < always.len());
+
+        let parse = |data: &[u8]| -> Vec<Event> {
+            Json.iter_reader(data).collect::<io::Result<_>>().unwrap()
+        };
+        let lifecycle = parse(&lifecycle);
+        let always = parse(&always);
+
+        for e in &lifecycle {
+            let num_spans = e.spans.len();
+            for (k, span) in e.spans.iter().enumerate() {
+                let lifecycle_kind =
+                    matches!(e.kind, EventKind::SpanCreate | EventKind::SpanClose(_));
+                assert_eq!(span.id.is_some(), lifecycle_kind && k + 1 == num_spans);
+            }
+        }
+
+        let lifecycle_tree = SpanTree::from_events(&lifecycle);
+        let always_tree = SpanTree::from_events(&always);
+        assert_eq!(lifecycle_tree.nodes().len(), 9);
+        assert_eq!(lifecycle_tree.roots().count(), 3);
+        assert_eq!(structure(&lifecycle_tree), structure(&always_tree));
+        assert!(lifecycle_tree.nodes().iter().all(|n| n.id.is_some()));
+    }
+}
@@ -3,6 +3,7 @@ use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::hash::{Hash, Hasher};
 use std::num::NonZeroU64;
 
@@ -23,6 +24,60 @@ pub enum Level {
     Error = 4,
 }
 
+impl Level {
+    /// The [syslog severity](https://datatracker.ietf.org/doc/html/rfc5424#section-6.2.1) corresponding to this level.
+    ///
+    /// Syslog has no equivalent of [`Level::Trace`], so both [`Level::Trace`] and [`Level::Debug`] map to
+    /// `7` (Debug).
+    pub fn syslog_severity(&self) -> u8 {
+        match self {
+            Level::Trace => 7,
+            Level::Debug => 7,
+            Level::Info => 6,
+            Level::Warn => 4,
+            Level::Error => 3,
+        }
+    }
+}
+
+/// A [syslog facility](https://datatracker.ietf.org/doc/html/rfc5424#section-6.2.1).
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+#[repr(u8)]
+#[allow(missing_docs)]
+pub enum SyslogFacility {
+    Kern = 0,
+    User = 1,
+    Mail = 2,
+    Daemon = 3,
+    Auth = 4,
+    Syslog = 5,
+    Lpr = 6,
+    News = 7,
+    Uucp = 8,
+    Cron = 9,
+    AuthPriv = 10,
+    Ftp = 11,
+    Ntp = 12,
+    Audit = 13,
+    Alert = 14,
+    Clock = 15,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+impl SyslogFacility {
+    /// The syslog PRI value of an event with the given `level`, `facility * 8 + severity`.
+    pub fn pri(&self, level: Level) -> u8 {
+        *self as u8 * 8 + level.syslog_severity()
+    }
+}
+
 impl From<tracing::Level> for Level {
     fn from(l: tracing::Level) -> Self {
         match l {
@@ -55,14 +110,46 @@ impl From<Level> for tracing::Level {
 /// `Float(f64)` as equal to `Float(f64)` if and only if the bit patterns match.
 /// This is not the standard handling of `PartialEq` for `f64`, but is designed to be
 /// convenient for finding `NaN`s in logs (usually `NaN == NaN` is `false` despite the bit-patterns being identical).
+/// Integers are compared by value, so `Int(1) == UInt(1)`.
+///
+/// `Null` is a field without a value, see
+/// [`SerdeLayerBuilder::with_empty_fields_as_null`](crate::SerdeLayerBuilder::with_empty_fields_as_null).
+///
+/// # Integers
+/// Integers are recorded as `Int` if they fit in an `i64`, otherwise as `UInt`, `I128` or `U128`.
+/// Most formats can't represent integers which don't fit in 64 bits, so they are serialized as decimal
+/// strings, and read back as `Str`.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 #[allow(missing_docs)]
 pub enum FieldValue {
     Bool(bool),
     Int(i64),
+    UInt(u64),
+    #[serde(serialize_with = "serialize_i128")]
+    I128(i128),
+    #[serde(serialize_with = "serialize_u128")]
+    U128(u128),
     Float(f64),
     Str(String),
+    Null,
+}
+
+/// Serialize `x` as a 64-bit integer if it fits, otherwise as a decimal string.  See [`FieldValue`].
+pub(crate) fn serialize_i128<S: serde::Serializer>(x: &i128, s: S) -> Result<S::Ok, S::Error> {
+    match (i64::try_from(*x), u64::try_from(*x)) {
+        (Ok(x), _) => s.serialize_i64(x),
+        (_, Ok(x)) => s.serialize_u64(x),
+        _ => s.collect_str(x),
+    }
+}
+
+/// Serialize `x` as a 64-bit integer if it fits, otherwise as a decimal string.  See [`FieldValue`].
+pub(crate) fn serialize_u128<S: serde::Serializer>(x: &u128, s: S) -> Result<S::Ok, S::Error> {
+    match u64::try_from(*x) {
+        Ok(x) => s.serialize_u64(x),
+        Err(_) => s.collect_str(x),
+    }
 }
 
 #[inline(always)]
@@ -72,16 +159,41 @@ fn f64_bitpattern(x: f64) -> u64 {
     unsafe { std::mem::transmute::<f64, u64>(x) }
 }
 
+/// An integer field value, normalised so that equal integers have the same representation.
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+enum Integer {
+    Signed(i128),
+    /// Only used for values above `i128::MAX`
+    Unsigned(u128),
+}
+
+impl FieldValue {
+    fn integer(&self) -> Option<Integer> {
+        use FieldValue::*;
+        let i = match *self {
+            Int(x) => Integer::Signed(x.into()),
+            UInt(x) => Integer::Signed(x.into()),
+            I128(x) => Integer::Signed(x),
+            U128(x) => match i128::try_from(x) {
+                Ok(x) => Integer::Signed(x),
+                Err(_) => Integer::Unsigned(x),
+            },
+            _ => return None,
+        };
+        Some(i)
+    }
+}
+
 impl PartialEq for FieldValue {
     fn eq(&self, other: &FieldValue) -> bool {
         use FieldValue::*;
 
         match (self, other) {
-            (Int(a), Int(b)) => a == b,
             (Bool(a), Bool(b)) => a == b,
             (Str(a), Str(b)) => a == b,
             (Float(a), Float(b)) => f64_bitpattern(*a) == f64_bitpattern(*b),
-            _ => false,
+            (Null, Null) => true,
+            (a, b) => a.integer().is_some() && a.integer() == b.integer(),
         }
     }
 }
@@ -93,9 +205,10 @@ impl Hash for FieldValue {
         use FieldValue::*;
         match self {
             Bool(x) => x.hash(state),
-            Int(x) => x.hash(state),
             Str(x) => x.hash(state),
             Float(x) => f64_bitpattern(*x).hash(state),
+            Null => 0u8.hash(state),
+            x => x.integer().hash(state),
         }
     }
 }
@@ -118,6 +231,9 @@ impl_field_value_from! {
   i32 => Int,
   i16 => Int,
   i8 => Int,
+  u64 => UInt,
+  i128 => I128,
+  u128 => U128,
   f32 => Float,
   f64 => Float,
   String => Str,
@@ -138,10 +254,34 @@ pub enum EventKind {
     /// A synthesis event marking the destruction of a span.  If span timings were enabled (see [`SerdeLayerBuilder::with_time_spans`](crate::SerdeLayerBuilder::with_time_spans),
     /// will contain the span timings.
     SpanClose(Option<SpanTime>),
+    /// A synthesis event reporting the busy and idle time of an open span since its previous
+    /// `SpanProgress` event, or since it was created.  Only produced if enabled with
+    /// [`SerdeLayerBuilder::with_span_time_increments`](crate::SerdeLayerBuilder::with_span_time_increments).
+    SpanProgress(SpanTime),
     /// A synthesis event produced when a span is (re-)entered.
     SpanEnter,
     /// A synthesis event produced when a span is exited
     SpanExit,
+    /// A synthesis event produced when fields are recorded on an existing span with
+    /// [`Span::record`](tracing::Span::record).  Contains the recorded fields.  Only produced if enabled with
+    /// [`SerdeLayerBuilder::with_span_records`](crate::SerdeLayerBuilder::with_span_records).
+    SpanRecord(IndexMap<String, FieldValue>),
+    /// A synthesis event produced when a span declares that it follows from another with
+    /// [`Span::follows_from`](tracing::Span::follows_from).  Only produced if enabled with
+    /// [`SerdeLayerBuilder::with_span_follows_from`](crate::SerdeLayerBuilder::with_span_follows_from).
+    SpanFollowsFrom {
+        /// The ID of the span which caused the other span
+        from: NonZeroU64,
+        /// The ID of the span which follows from `from`, the last element of [`Event::spans`].
+        to: NonZeroU64,
+    },
+}
+
+impl Default for EventKind {
+    /// An event without fields.
+    fn default() -> Self {
+        EventKind::Event(IndexMap::new())
+    }
 }
 
 /// The information associated
@@ -155,12 +295,20 @@ pub struct Span {
     /// The [span ID](mod@tracing::span), if one was recorded
     #[serde(alias = "i")]
     #[serde(default)]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
     pub id: Option<NonZeroU64>,
 
     /// The fields of the span
     #[serde(alias = "f")]
     pub fields: IndexMap<String, FieldValue>,
+
+    /// The names of all fields declared by the span, including those without a value.  Only recorded on
+    /// [`EventKind::SpanCreate`] events, if enabled with
+    /// [`SerdeLayerBuilder::with_span_field_names`](crate::SerdeLayerBuilder::with_span_field_names).
+    #[serde(alias = "fd")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub declared_fields: Option<Vec<String>>,
 }
 
 /// A (de)serializable [`tracing`] event.
@@ -174,6 +322,7 @@ pub struct Event {
     /// For a regular, non-synthesised event (i.e `EventKind::Event(_)`), the event
     /// fields can be found in here.
     #[serde(alias = "ty")]
+    #[serde(default)]
     pub kind: EventKind,
 
     /// The log level of the event or span.
@@ -186,6 +335,7 @@ pub struct Event {
     /// If `kind` indicates a synthesised span event, the last element in this list contains
     /// the span which was created/destroyed/entered/exited.
     #[serde(alias = "s")]
+    #[serde(default)]
     pub spans: Vec<Span>,
 
     /// Target of event, by default the module path in which the event occurred.
@@ -194,31 +344,269 @@ pub struct Event {
 
     /// ID of the thread which produced the event
     #[serde(default)]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
     #[serde(alias = "tid")]
     pub thread_id: Option<NonZeroU64>,
 
     /// Name of the thread which produced the event
     #[serde(default)]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
     #[serde(alias = "tn")]
     pub thread_name: Option<String>,
 
     /// Line in the source file where the event was produced.
     #[serde(default)]
     #[serde(alias = "srl")]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
     pub src_line: Option<u32>,
 
     /// The source file where the event was produced.
     #[serde(default)]
     #[serde(alias = "srf")]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
     pub src_file: Option<String>,
 
     /// The timestamp of the event.
     #[serde(default)]
     #[serde(alias = "tm")]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
     pub time: Option<UnixTime>,
+
+    /// The time since the layer was created, in nanoseconds.
+    /// See [`SerdeLayerBuilder::with_uptime`](crate::SerdeLayerBuilder::with_uptime).
+    #[serde(default)]
+    #[serde(alias = "up")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub uptime_ns: Option<u64>,
+
+    /// The syslog PRI of the event.  See [`SerdeLayerBuilder::with_syslog`](crate::SerdeLayerBuilder::with_syslog).
+    #[serde(default)]
+    #[serde(alias = "pri")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub syslog_pri: Option<u8>,
+
+    /// The ID of the process instance which produced the event.
+    /// See [`SerdeLayerBuilder::with_instance_id`](crate::SerdeLayerBuilder::with_instance_id).
+    #[serde(default)]
+    #[serde(alias = "in")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub instance_id: Option<u64>,
+
+    /// `Some(true)` if the clock failed to produce a timestamp for this event.
+    /// See [`MissingTime::Mark`](crate::MissingTime::Mark).
+    #[serde(default)]
+    #[serde(alias = "tmm")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub time_missing: Option<bool>,
+
+    /// The name of the crate which produced the event.
+    /// See [`SerdeLayerBuilder::with_crate_info`](crate::SerdeLayerBuilder::with_crate_info).
+    #[serde(default)]
+    #[serde(alias = "cr")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub crate_name: Option<String>,
+
+    /// The version of the crate which produced the event.
+    /// See [`SerdeLayerBuilder::with_crate_info`](crate::SerdeLayerBuilder::with_crate_info).
+    #[serde(default)]
+    #[serde(alias = "crv")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub crate_version: Option<String>,
+
+    /// Fields attached to every event by the layer, such as the name of the service.
+    /// See [`SerdeLayerBuilder::with_global_fields`](crate::SerdeLayerBuilder::with_global_fields).
+    #[serde(default)]
+    #[serde(alias = "g")]
+    #[serde(skip_serializing_if = "crate::format::skip_empty_map")]
+    pub globals: IndexMap<String, FieldValue>,
+
+    /// The ID of the process which produced the event.
+    /// See [`SerdeLayerBuilder::with_process_id`](crate::SerdeLayerBuilder::with_process_id).
+    #[serde(default)]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub pid: Option<u32>,
+
+    /// The hostname of the machine which produced the event.
+    /// See [`SerdeLayerBuilder::with_hostname`](crate::SerdeLayerBuilder::with_hostname).
+    #[serde(default)]
+    #[serde(alias = "h")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub host: Option<String>,
+
+    /// The callsite name of the event, for regular events.
+    /// See [`SerdeLayerBuilder::with_event_names`](crate::SerdeLayerBuilder::with_event_names).
+    #[serde(default)]
+    #[serde(alias = "n")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub name: Option<String>,
+
+    /// The module path of the event's callsite, if it differs from the target.
+    /// See [`SerdeLayerBuilder::with_module_path`](crate::SerdeLayerBuilder::with_module_path).
+    #[serde(default)]
+    #[serde(alias = "mp")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub module: Option<String>,
+
+    /// The sequence number of the event, which orders events with equal timestamps.
+    /// See [`SerdeLayerBuilder::with_sequence_numbers`](crate::SerdeLayerBuilder::with_sequence_numbers).
+    #[serde(default)]
+    #[serde(alias = "sq")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub seq: Option<u64>,
+
+    /// The unique ID of the event.
+    /// See [`SerdeLayerBuilder::with_event_id`](crate::SerdeLayerBuilder::with_event_id).
+    #[serde(default)]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub id: Option<String>,
+
+    /// The time the event was written out, if recorded by [`Annotate`](crate::writer::Annotate).
+    #[serde(default)]
+    #[serde(alias = "wt")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub written_time: Option<UnixTime>,
+}
+
+impl Event {
+    /// Look up a field by name.  The event's own fields are searched first, followed by the fields of
+    /// its spans, from the innermost span outwards.
+    pub fn field(&self, name: &str) -> Option<&FieldValue> {
+        if let EventKind::Event(fields) = &self.kind {
+            if let Some(v) = fields.get(name) {
+                return Some(v);
+            }
+        }
+        self.spans.iter().rev().find_map(|s| s.fields.get(name))
+    }
+
+    /// A one-line key identifying the kind of event, for grouping similar events, e.g. to find the most
+    /// frequent ones.  Made up of the level, target, message and the sorted names of the event's own fields,
+    /// such as `Info app::db query failed [attempt,user_id]`.  Field values are not included, so events which
+    /// only differ in their field values have the same signature.
+    ///
+    /// Span events use the kind of event and the name of the span in place of the message, and the span's
+    /// field names, e.g. `Debug app span_create request [path]`.
+    pub fn signature(&self) -> String {
+        let innermost = self.spans.last();
+        let (message, fields) = match &self.kind {
+            EventKind::Event(fields) => {
+                let message = match fields.get("message") {
+                    Some(FieldValue::Str(s)) => s.clone(),
+                    Some(FieldValue::Bool(b)) => b.to_string(),
+                    Some(FieldValue::Int(i)) => i.to_string(),
+                    Some(FieldValue::UInt(i)) => i.to_string(),
+                    Some(FieldValue::I128(i)) => i.to_string(),
+                    Some(FieldValue::U128(i)) => i.to_string(),
+                    Some(FieldValue::Float(f)) => f.to_string(),
+                    Some(FieldValue::Null) | None => String::new(),
+                };
+                (message, Some(fields))
+            }
+            kind => {
+                let kind = match kind {
+                    EventKind::SpanCreate => "span_create",
+                    EventKind::SpanClose(_) => "span_close",
+                    EventKind::SpanProgress(_) => "span_progress",
+                    EventKind::SpanEnter => "span_enter",
+                    EventKind::SpanExit => "span_exit",
+                    EventKind::SpanRecord(_) => "span_record",
+                    EventKind::SpanFollowsFrom { .. } => "span_follows_from",
+                    EventKind::Event(_) => unreachable!(),
+                };
+                let name = innermost.map(|s| s.name.as_str()).unwrap_or_default();
+                (format!("{} {}", kind, name), innermost.map(|s| &s.fields))
+            }
+        };
+        let mut names: Vec<_> = fields
+            .into_iter()
+            .flat_map(|f| f.keys())
+            .map(String::as_str)
+            .filter(|&n| n != "message")
+            .collect();
+        names.sort_unstable();
+        format!(
+            "{:?} {} {} [{}]",
+            self.level,
+            self.target,
+            message.replace('\n', " "),
+            names.join(",")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syslog_severity() {
+        assert_eq!(Level::Trace.syslog_severity(), 7);
+        assert_eq!(Level::Debug.syslog_severity(), 7);
+        assert_eq!(Level::Info.syslog_severity(), 6);
+        assert_eq!(Level::Warn.syslog_severity(), 4);
+        assert_eq!(Level::Error.syslog_severity(), 3);
+    }
+
+    #[test]
+    fn signature() {
+        let event = |target: &str, message: &str, user_id: i64| -> Event {
+            serde_json::from_str(&format!(
+                r#"{{"ty":{{"event":{{"message":"{}","user_id":{},"attempt":1}}}},"l":2,"s":[],"t":"{}"}}"#,
+                message, user_id, target
+            ))
+            .unwrap()
+        };
+
+        let a = event("app::db", "query failed", 1);
+        assert_eq!(a.signature(), "Info app::db query failed [attempt,user_id]");
+        assert_eq!(
+            a.signature(),
+            event("app::db", "query failed", 2).signature()
+        );
+        assert_ne!(
+            a.signature(),
+            event("app::http", "query failed", 1).signature()
+        );
+        assert_ne!(a.signature(), event("app::db", "query ok", 1).signature());
+    }
+
+    #[test]
+    fn integers_compare_by_value() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash = |v: &FieldValue| {
+            let mut h = DefaultHasher::new();
+            v.hash(&mut h);
+            h.finish()
+        };
+        let ones = [
+            FieldValue::Int(1),
+            FieldValue::UInt(1),
+            FieldValue::I128(1),
+            FieldValue::U128(1),
+        ];
+        for a in &ones {
+            for b in &ones {
+                assert_eq!(a, b);
+                assert_eq!(hash(a), hash(b));
+            }
+        }
+        assert_ne!(FieldValue::Int(-1), FieldValue::U128(u128::MAX));
+        assert_ne!(FieldValue::Int(1), FieldValue::Float(1.0));
+        assert_eq!(FieldValue::U128(u128::MAX), FieldValue::from(u128::MAX));
+
+        let json = serde_json::to_string(&FieldValue::U128(u128::MAX)).unwrap();
+        assert_eq!(json, format!("\"{}\"", u128::MAX));
+        let json = serde_json::to_string(&FieldValue::I128(-1)).unwrap();
+        assert_eq!(json, "-1");
+        let big: FieldValue = serde_json::from_str(&u64::MAX.to_string()).unwrap();
+        assert!(matches!(big, FieldValue::UInt(u64::MAX)));
+    }
+
+    #[test]
+    fn syslog_pri() {
+        assert_eq!(SyslogFacility::Kern.pri(Level::Error), 3);
+        assert_eq!(SyslogFacility::Local0.pri(Level::Info), 134);
+        assert_eq!(SyslogFacility::Local7.pri(Level::Trace), 191);
+    }
 }
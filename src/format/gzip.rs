@@ -0,0 +1,169 @@
+use super::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+#[derive(Clone, Copy, Debug, Default)]
+/// Compress each event serialized by the inner format separately with gzip, and write it in base64
+/// followed by a newline.
+///
+/// Each line can be decompressed on its own (e.g. `base64 -d | zcat`), so compressed logs can still be
+/// split, sampled or searched line by line, at the cost of a worse compression ratio than compressing the
+/// whole stream.  Base64 is used since raw gzip data may itself contain newlines.
+///
+/// Requires the **`gzip`** crate feature to be enabled.
+pub struct GzipLines<F> {
+    inner: F,
+}
+
+impl<F: SerdeFormat> GzipLines<F> {
+    /// Compress events serialized with `inner`.
+    pub fn new(inner: F) -> Self {
+        GzipLines { inner }
+    }
+}
+
+impl<F: SerdeFormat> SerdeFormat for GzipLines<F> {
+    fn message_size_hint(&self) -> usize {
+        self.inner.message_size_hint()
+    }
+
+    fn serialize(&self, mut buf: impl Write, event: impl Serialize) -> std::io::Result<()> {
+        let mut encoder = GzEncoder::new(
+            Vec::with_capacity(self.inner.message_size_hint()),
+            Compression::default(),
+        );
+        self.inner.serialize(&mut encoder, event)?;
+        let compressed = encoder.finish()?;
+        let mut line = base64::encode(compressed);
+        line.push('\n');
+        buf.write_all(line.as_bytes())
+    }
+}
+
+#[cfg(feature = "consumer")]
+pub use consumer::GzipLinesStream;
+
+#[cfg(feature = "consumer")]
+mod consumer {
+    use super::*;
+    use crate::consumer::*;
+    use crate::Event;
+    use flate2::read::GzDecoder;
+    use std::io::{self, BufRead, BufReader, Read};
+
+    /// A stream of [`Event`s](crate::Event) serialized with [`GzipLines`].
+    ///
+    /// See [`IterFile`](crate::consumer::IterFile) or [`StreamFormat`](crate::consumer::StreamFormat) on
+    /// how to create one.
+    pub struct GzipLinesStream<F, R> {
+        inner: F,
+        reader: BufReader<R>,
+        line: String,
+        decompressed: Vec<u8>,
+    }
+
+    impl<F, R> GzipLinesStream<F, R>
+    where
+        F: for<'a> StreamFormat<&'a [u8]>,
+        R: Read,
+    {
+        fn next_event(&mut self) -> Option<io::Result<Event>> {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+
+            let compressed = match base64::decode(self.line.trim_end()) {
+                Ok(b) => b,
+                Err(e) => return Some(Err(io::Error::new(io::ErrorKind::InvalidData, e))),
+            };
+            self.decompressed.clear();
+            if let Err(e) =
+                GzDecoder::new(compressed.as_slice()).read_to_end(&mut self.decompressed)
+            {
+                return Some(Err(e));
+            }
+
+            let event = self.inner.iter_reader(self.decompressed.as_slice()).next();
+            Some(event.unwrap_or_else(|| {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "compressed line contains no event",
+                ))
+            }))
+        }
+    }
+
+    impl<F, R> Iterator for GzipLinesStream<F, R>
+    where
+        F: for<'a> StreamFormat<&'a [u8]>,
+        R: Read,
+    {
+        type Item = io::Result<Event>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.next_event()
+        }
+    }
+
+    impl<F, R> StreamFormat<R> for GzipLines<F>
+    where
+        F: for<'a> StreamFormat<&'a [u8]> + Clone,
+        R: Read,
+    {
+        type Stream = GzipLinesStream<F, R>;
+
+        fn iter_reader(&self, reader: R) -> Self::Stream {
+            GzipLinesStream {
+                inner: self.inner.clone(),
+                reader: BufReader::new(reader),
+                line: String::new(),
+                decompressed: Vec::new(),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "consumer")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn gzip_lines() {
+        super::super::tests::test_format(GzipLines::new(Json));
+    }
+
+    #[cfg(feature = "messagepack")]
+    #[test]
+    fn gzip_lines_msgpack() {
+        super::super::tests::test_format(GzipLines::new(MessagePack::struct_as_array()));
+    }
+
+    #[test]
+    fn lines_decompress_independently() {
+        let mut buffer = Vec::new();
+        for i in 0..5 {
+            GzipLines::new(Json)
+                .serialize(&mut buffer, serde_json::json!({ "i": i }))
+                .unwrap();
+        }
+
+        let text = String::from_utf8(buffer).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        // Decompress out of order to make sure lines don't depend on each other
+        for (i, line) in lines.iter().enumerate().rev() {
+            let compressed = base64::decode(line).unwrap();
+            let mut json = String::new();
+            GzDecoder::new(compressed.as_slice())
+                .read_to_string(&mut json)
+                .unwrap();
+            assert_eq!(json, format!("{{\"i\":{}}}\n", i));
+        }
+    }
+}
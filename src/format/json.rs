@@ -14,6 +14,29 @@ impl SerdeFormat for Json {
         buf.write("\n".as_bytes())?;
         Ok(())
     }
+
+    fn is_json_lines(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+/// Serialize each event as indented, multi-line JSON, followed by a newline.  Easier to read than [`Json`]
+/// when following a log file by hand, at the cost of size.
+///
+/// Records can be read back with [`StreamFormat`](crate::consumer::StreamFormat) like [`Json`].
+pub struct JsonPretty;
+
+impl SerdeFormat for JsonPretty {
+    fn message_size_hint(&self) -> usize {
+        1024
+    }
+
+    fn serialize(&self, mut buf: impl Write, event: impl Serialize) -> std::io::Result<()> {
+        serde_json::to_writer_pretty(&mut buf, &event)?;
+        buf.write_all(b"\n")?;
+        Ok(())
+    }
 }
 
 #[cfg(feature = "consumer")]
@@ -30,6 +53,9 @@ mod consumer {
     ///
     /// Created with `Json.iter_file("file.json")` (see [`IterFile`](crate::consumer::IterFile))  or `Json.iter_reader(reader)`
     /// (see [`StreamFormat`](crate::consumer::StreamFormat))
+    ///
+    /// The number of events can't be known without reading the whole stream, so the
+    /// [`size_hint`](Iterator::size_hint) is always `(0, None)`.
     pub struct JsonStream<R: Read> {
         stream: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<R>, Event>,
     }
@@ -37,8 +63,29 @@ mod consumer {
     impl<R: Read> Iterator for JsonStream<R> {
         type Item = io::Result<Event>;
 
+        /// Errors in the JSON, including invalid UTF-8, are reported with the byte offset of the event
+        /// they occurred in.
         fn next(&mut self) -> Option<Self::Item> {
-            self.stream.next().map(|r| r.map_err(From::from))
+            let result = self.stream.next()?;
+            Some(result.map_err(|e| {
+                if e.is_io() {
+                    e.into()
+                } else {
+                    let kind = if e.is_eof() {
+                        io::ErrorKind::UnexpectedEof
+                    } else {
+                        io::ErrorKind::InvalidData
+                    };
+                    io::Error::new(
+                        kind,
+                        format!(
+                            "{}, in the event at byte offset {}",
+                            e,
+                            self.stream.byte_offset()
+                        ),
+                    )
+                }
+            }))
         }
     }
 
@@ -51,6 +98,27 @@ mod consumer {
             }
         }
     }
+
+    impl<R: Read> StreamFormat<R> for JsonPretty {
+        type Stream = JsonStream<R>;
+
+        fn iter_reader(&self, reader: R) -> Self::Stream {
+            Json.iter_reader(reader)
+        }
+    }
+}
+
+#[cfg(feature = "consumer")]
+#[test]
+fn invalid_utf8_offset() {
+    use crate::consumer::StreamFormat;
+
+    let data: &[u8] = b"{\"ty\":\"span_create\",\"l\":2,\"s\":[],\"t\":\"a\"}\n{\"ty\":\"span_create\",\"l\":2,\"s\":[],\"t\":\"\xff\"}\n";
+    let mut events = Json.iter_reader(data);
+    assert!(events.next().unwrap().is_ok());
+    let err = events.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("byte offset 42"), "{}", err);
 }
 
 #[cfg(feature = "consumer")]
@@ -58,3 +126,9 @@ mod consumer {
 fn json() {
     super::tests::test_format(Json);
 }
+
+#[cfg(feature = "consumer")]
+#[test]
+fn json_pretty() {
+    super::tests::test_format(JsonPretty);
+}
@@ -1,12 +1,33 @@
 use super::*;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default)]
 /// Serialize events as a stream of binary [MessagePack](https://msgpack.org/) objects.
 /// Serialization speed is the same as [`Json`], but with smaller messages.  The trade-off is human-readability, but
 /// if you are planning to post-process your logs programmatically anyway, this format would be suitable.
 ///
+/// By default, structs are serialized as maps (see [`MessagePack::struct_as_map`]).
+///
 /// Requires the **`messagepack`** crate feature to be enabled.
-pub struct MessagePack;
+pub struct MessagePack {
+    struct_as_array: bool,
+}
+
+impl MessagePack {
+    /// Serialize structs as maps from field names to values.  This is the default.
+    pub fn struct_as_map() -> Self {
+        MessagePack {
+            struct_as_array: false,
+        }
+    }
+
+    /// Serialize structs as arrays of values, without field names.  This produces notably smaller
+    /// messages, but all fields are always serialized, even those which are empty.
+    pub fn struct_as_array() -> Self {
+        MessagePack {
+            struct_as_array: true,
+        }
+    }
+}
 
 impl SerdeFormat for MessagePack {
     fn message_size_hint(&self) -> usize {
@@ -16,15 +37,23 @@ impl SerdeFormat for MessagePack {
     fn serialize(&self, buf: impl Write, event: impl Serialize) -> std::io::Result<()> {
         use rmp::encode::ValueWriteError;
         use rmp_serde::encode::Error;
-        let mut s = rmp_serde::Serializer::new(buf).with_struct_map();
-        match event.serialize(&mut s) {
+
+        let result = if self.struct_as_array {
+            let mut s = rmp_serde::Serializer::new(buf).with_struct_tuple();
+            with_all_fields(|| event.serialize(&mut s))
+        } else {
+            let mut s = rmp_serde::Serializer::new(buf).with_struct_map();
+            event.serialize(&mut s)
+        };
+
+        match result {
             Err(Error::InvalidValueWrite(e)) => match e {
                 ValueWriteError::InvalidDataWrite(e) | ValueWriteError::InvalidMarkerWrite(e) => {
                     Err(e)
                 }
             },
             Ok(()) => Ok(()),
-            Err(_) => unreachable!(),
+            Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
         }
     }
 }
@@ -43,8 +72,13 @@ mod consumer {
 
     /// A stream of [`Event`s](crate::Event) serialized in MessagePack format.
     ///
+    /// Events may have been serialized with either [`MessagePack::struct_as_map`] or [`MessagePack::struct_as_array`].
+    ///
     /// See [`IterFile`](crate::consumer::IterFile) or [`StreamFormat`](crate::consumer::StreamFormat) on
     /// how to create one.
+    ///
+    /// The number of events can't be known without reading the whole stream, so the
+    /// [`size_hint`](Iterator::size_hint) is always `(0, None)`.
     pub struct MessagePackStream<R: Read> {
         deserializer: Deserializer<ReadReader<R>>,
     }
@@ -82,5 +116,11 @@ mod consumer {
 #[cfg(feature = "consumer")]
 #[test]
 fn messagepack() {
-    super::tests::test_format(MessagePack);
+    super::tests::test_format(MessagePack::struct_as_map());
+}
+
+#[cfg(feature = "consumer")]
+#[test]
+fn messagepack_struct_as_array() {
+    super::tests::test_format(MessagePack::struct_as_array());
 }
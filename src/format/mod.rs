@@ -1,11 +1,21 @@
 //! Serde formats and supporting trait.
 use serde::Serialize;
+use std::cell::Cell;
 use std::io::Write;
+use std::thread::LocalKey;
 
 mod json;
-pub use json::Json;
 #[cfg(feature = "consumer")]
 pub use json::JsonStream;
+pub use json::{Json, JsonPretty};
+
+#[cfg(feature = "gzip")]
+mod gzip;
+#[cfg(feature = "gzip")]
+#[cfg_attr(docsrs, doc(cfg(feature = "gzip")))]
+pub use gzip::GzipLines;
+#[cfg(all(feature = "gzip", feature = "consumer"))]
+pub use gzip::GzipLinesStream;
 
 #[cfg(feature = "messagepack")]
 mod messagepack;
@@ -43,6 +53,86 @@ pub trait SerdeFormat {
 
     /// Perform the serialization.
     fn serialize(&self, buf: impl Write, event: impl Serialize) -> std::io::Result<()>;
+
+    /// Whether each serialized event is a JSON object on a single line, terminated by a newline.
+    ///
+    /// Writers which edit serialized records, such as [`Annotate`](crate::writer::Annotate), use this to check
+    /// they are compatible with the format.  The default implementation returns `false`.
+    fn is_json_lines(&self) -> bool {
+        false
+    }
+}
+
+thread_local! {
+    static SERIALIZE_ALL_FIELDS: Cell<bool> = const { Cell::new(false) };
+    static OMIT_EMPTY: Cell<bool> = const { Cell::new(false) };
+    static SANITIZE_NAMES: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Puts back the previous value of a flag when dropped, so it is reset even if `f` panics.
+struct RestoreFlag {
+    flag: &'static LocalKey<Cell<bool>>,
+    prev: bool,
+}
+
+impl Drop for RestoreFlag {
+    fn drop(&mut self) {
+        self.flag.with(|c| c.set(self.prev));
+    }
+}
+
+fn with_flag<T>(flag: &'static LocalKey<Cell<bool>>, value: bool, f: impl FnOnce() -> T) -> T {
+    let _restore = RestoreFlag {
+        flag,
+        prev: flag.with(|c| c.replace(value)),
+    };
+    f()
+}
+
+/// Serialize every field of [`Event`](crate::Event)s within `f`, including those which are `None`.
+///
+/// Needed by formats which serialize structs positionally (as arrays), where skipping a field would
+/// shift the position of the remaining fields.
+#[cfg_attr(not(feature = "messagepack"), allow(dead_code))]
+pub(crate) fn with_all_fields<T>(f: impl FnOnce() -> T) -> T {
+    with_flag(&SERIALIZE_ALL_FIELDS, true, f)
+}
+
+/// Used with `#[serde(skip_serializing_if = ...)]` in place of `Option::is_none`.  See [`with_all_fields`].
+pub(crate) fn skip_none<T>(x: &Option<T>) -> bool {
+    x.is_none() && !SERIALIZE_ALL_FIELDS.with(Cell::get)
+}
+
+/// Used with `#[serde(skip_serializing_if = ...)]` in place of `<[T]>::is_empty`.  See [`with_all_fields`].
+pub(crate) fn skip_empty<T>(x: &&[T]) -> bool {
+    x.is_empty() && !SERIALIZE_ALL_FIELDS.with(Cell::get)
+}
+
+/// Used with `#[serde(skip_serializing_if = ...)]` in place of `IndexMap::is_empty`.  See [`with_all_fields`].
+pub(crate) fn skip_empty_map<K, V>(x: &indexmap::IndexMap<K, V>) -> bool {
+    x.is_empty() && !SERIALIZE_ALL_FIELDS.with(Cell::get)
+}
+
+/// Within `f`, leave out the fields and spans of [`Event`](crate::Event)s when they are empty, if `enable`
+/// is set.  Overridden by [`with_all_fields`].
+pub(crate) fn with_empty_omitted<T>(enable: bool, f: impl FnOnce() -> T) -> T {
+    with_flag(&OMIT_EMPTY, enable, f)
+}
+
+/// Whether empty fields and spans should be skipped.  See [`with_empty_omitted`].
+pub(crate) fn omit_empty() -> bool {
+    OMIT_EMPTY.with(Cell::get) && !SERIALIZE_ALL_FIELDS.with(Cell::get)
+}
+
+/// Within `f`, strip control characters from span names, if `enable` is set.  See
+/// [`SerdeLayerBuilder::with_sanitize_strings`](crate::SerdeLayerBuilder::with_sanitize_strings).
+pub(crate) fn with_sanitized_names<T>(enable: bool, f: impl FnOnce() -> T) -> T {
+    with_flag(&SANITIZE_NAMES, enable, f)
+}
+
+/// Whether span names should be sanitized.  See [`with_sanitized_names`].
+pub(crate) fn sanitize_names() -> bool {
+    SANITIZE_NAMES.with(Cell::get)
 }
 
 impl<'a, T: SerdeFormat> SerdeFormat for &'a T {
@@ -53,6 +143,10 @@ impl<'a, T: SerdeFormat> SerdeFormat for &'a T {
     fn serialize(&self, buf: impl Write, event: impl Serialize) -> std::io::Result<()> {
         T::serialize(self, buf, event)
     }
+
+    fn is_json_lines(&self) -> bool {
+        T::is_json_lines(self)
+    }
 }
 
 #[cfg(all(test, feature = "consumer"))]
@@ -127,6 +221,10 @@ mod tests {
             FieldValue::Int($v)
         };
 
+        (@VAL u $v:literal) => {
+            FieldValue::UInt($v)
+        };
+
         (@VAL f $v:literal) => {
             FieldValue::Float($v)
         };
@@ -139,6 +237,16 @@ mod tests {
             EventKind::SpanExit,
             EventKind::SpanClose(None),
             EventKind::SpanClose(Some(SpanTime { busy: 1, idle: 20 })),
+            EventKind::SpanProgress(SpanTime { busy: 3, idle: 4 }),
+            EventKind::SpanRecord(fields!(x = i 1, y = s "why")),
+            EventKind::SpanRecord(fields!(n = u 18446744073709551615)),
+            EventKind::SpanRecord(
+                std::iter::once(("gone".to_string(), FieldValue::Null)).collect(),
+            ),
+            EventKind::SpanFollowsFrom {
+                from: NonZeroU64::new(1).unwrap(),
+                to: NonZeroU64::new(5).unwrap(),
+            },
         ];
 
         let levels = [
@@ -154,16 +262,19 @@ mod tests {
                 name: "egg".to_string(),
                 id: NonZeroU64::new(5),
                 fields: fields!(q = b false, long = s "a very long string for me"),
+                declared_fields: None,
             },
             Span {
                 name: "cat".to_string(),
                 id: NonZeroU64::new(6),
                 fields: fields!(a = i 4, b= s "bval"),
+                declared_fields: None,
             },
             Span {
                 name: "egg".to_string(),
                 id: NonZeroU64::new(5),
                 fields: fields!(x = f 4.01),
+                declared_fields: None,
             },
         ];
 
@@ -179,6 +290,17 @@ mod tests {
 
         let times = [None, Some(UnixTime::from(Duration::default()))];
 
+        // Varied together to keep the number of events down
+        let optional_fields = [
+            (None, None, None, None),
+            (
+                Some("web-1".to_string()),
+                Some("app::db".to_string()),
+                Some(u64::MAX),
+                Some("01ARZ3NDEKTSV4RRFFQ69G5FAV".to_string()),
+            ),
+        ];
+
         iproduct!(
             kinds,
             levels,
@@ -188,10 +310,22 @@ mod tests {
             thread_names,
             src_files,
             src_lines,
-            times
+            times,
+            optional_fields
         )
         .map(
-            |(kind, level, spans, target, thread_id, thread_name, src_file, src_line, time)| {
+            |(
+                kind,
+                level,
+                spans,
+                target,
+                thread_id,
+                thread_name,
+                src_file,
+                src_line,
+                time,
+                (host, module, seq, id),
+            )| {
                 Event {
                     kind,
                     level,
@@ -202,8 +336,32 @@ mod tests {
                     src_file,
                     src_line,
                     time,
+                    uptime_ns: None,
+                    syslog_pri: None,
+                    instance_id: None,
+                    time_missing: None,
+                    crate_name: None,
+                    crate_version: None,
+                    globals: Default::default(),
+                    pid: None,
+                    host,
+                    name: None,
+                    module,
+                    seq,
+                    id,
+                    written_time: None,
                 }
             },
         )
     }
+
+    #[test]
+    fn flags_reset_after_panic() {
+        let result = std::panic::catch_unwind(|| {
+            with_all_fields(|| with_empty_omitted(true, || panic!("serializer panicked")))
+        });
+        assert!(result.is_err());
+        assert!(skip_none(&None::<()>));
+        assert!(!omit_empty());
+    }
 }
@@ -8,9 +8,17 @@
 //!
 //! | Feature | Default enabled? | Description | Dependencies |
 //! | --- | --- | --- | --- |
-//! | `thread_id` | No | Enable recording thread IDs in events | [`thread_id_value`](https://github.com/rust-lang/rust/issues/67939) unstable feature |
+//! | `thread_id` | No | Record the values of [`ThreadId`](std::thread::ThreadId)s as thread IDs, rather than numbering threads with a counter | [`thread_id_value`](https://github.com/rust-lang/rust/issues/67939) unstable feature |
 //! | `consumer` | Yes | Consumer API for pretty-printing events | [`ansi_term`] crate |
 //! | `messagepack` | No | [`MessagePack`](crate::format::MessagePack) format | [`rmp_serde`] crate |
+//! | `gzip` | No | [`GzipLines`](crate::format::GzipLines) format, and reading gzip-compressed streams with [`iter_reader_gz`](crate::consumer::iter_reader_gz) | [`flate2`] and [`base64`] crates |
+//! | `zstd` | No | Zstd compression in [`AnyCompression`](crate::writer::AnyCompression) | [`zstd`] crate |
+//! | `uuid` | No | Recording [UUIDv7](crate::IdKind::Uuid7) event IDs | [`uuid`] crate |
+//! | `ulid` | No | Recording [ULID](crate::IdKind::Ulid) event IDs | [`ulid`] crate |
+//! | `hostname` | No | Recording the machine's hostname with [`with_hostname`](crate::SerdeLayerBuilder::with_hostname) | [`gethostname`] crate |
+//! | `chrono` | No | Converting [`UnixTime`](crate::time::UnixTime) to [`chrono::DateTime`] | [`chrono`] crate |
+//! | `inline-fields-16` | No | Store up to 16 event fields without allocating, instead of 8, at the cost of 384 more bytes of stack per event | |
+//! | `bench-util` | No | [Workloads and helpers](crate::bench_util) for benchmarking formats and writers | |
 
 /// `SpanEvent` is re-exported [`FmtEvent`](tracing_subscriber::fmt::format::FmtSpan) from `tracing_subscriber` with
 /// a more suitable name.  Implements bitwise arithmetic operations so you can treat it as a set of bitflags.
@@ -18,12 +26,16 @@
 pub use tracing_subscriber::fmt::format::FmtSpan as SpanEvents;
 
 mod event;
+mod rng;
 mod subscriber;
 
 #[cfg(test)]
 pub(crate) mod test_utils;
 
 //
+#[cfg_attr(docsrs, doc(cfg(feature = "bench-util")))]
+#[cfg(feature = "bench-util")]
+pub mod bench_util;
 #[cfg_attr(docsrs, doc(cfg(feature = "consumer")))]
 #[cfg(any(feature = "consumer"))]
 pub mod consumer;
@@ -32,11 +44,17 @@ pub mod time;
 pub mod writer;
 
 #[doc(inline)]
-pub use event::{Event, EventKind, FieldValue, Level, Span};
+pub use event::{Event, EventKind, FieldValue, Level, Span, SyslogFacility};
 #[doc(inline)]
 pub use format::SerdeFormat;
 #[doc(inline)]
-pub use subscriber::{SerdeLayer, SerdeLayerBuilder};
+#[cfg(any(feature = "uuid", feature = "ulid"))]
+pub use subscriber::IdKind;
+#[doc(inline)]
+pub use subscriber::{
+    sequence_number, Budget, BudgetOverflow, CallsiteDiag, FieldPolicy, InheritMode, InstanceId,
+    MissingTime, SerdeLayer, SerdeLayerBuilder, SpanIdMode, SuppressReason,
+};
 #[doc(inline)]
 pub use writer::WriteEvent;
 
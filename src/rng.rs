@@ -0,0 +1,34 @@
+//! A small, seeded PRNG, so that samples are reproducible.
+
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Hash `x` with the SplitMix64 output function.  Also used on its own, to make one-off decisions from a
+/// counter and a seed.
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// The SplitMix64 generator.
+#[cfg(feature = "consumer")]
+pub(crate) struct SplitMix64(u64);
+
+#[cfg(feature = "consumer")]
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64(seed)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        let z = splitmix64(self.0);
+        self.0 = self.0.wrapping_add(GAMMA);
+        z
+    }
+
+    /// A uniformly distributed integer in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        ((u128::from(self.next()) * n as u128) >> 64) as usize
+    }
+}
@@ -0,0 +1,124 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::{Level, SerdeFormat};
+
+/// A quota on the size of the output of a [`SerdeLayer`](crate::SerdeLayer), see
+/// [`SerdeLayerBuilder::with_budget`](crate::SerdeLayerBuilder::with_budget).
+#[derive(Copy, Clone, Debug)]
+pub struct Budget {
+    /// The maximum number of serialized bytes written in each interval.
+    pub max_bytes_per_interval: u64,
+    /// The length of each interval.  The first interval starts when the layer is built.
+    pub interval: Duration,
+    /// Which events to drop when the budget runs low.
+    pub overflow: BudgetOverflow,
+}
+
+/// What a [`Budget`] does when it runs low.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BudgetOverflow {
+    /// Events below [`Level::Warn`] may only use three quarters of the budget, the rest is reserved for
+    /// [`Level::Warn`] and [`Level::Error`] events.
+    DropLowSeverityFirst,
+    /// Events of every level may use the whole budget, and are dropped once it is used up.
+    DropAll,
+}
+
+/// Counts bytes written, discarding them.
+struct CountBytes(u64);
+
+impl Write for CountBytes {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The size of `event` serialized with `fmt`, in bytes.
+pub(crate) fn serialized_len(fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<u64> {
+    let mut count = CountBytes(0);
+    fmt.serialize(&mut count, event)?;
+    Ok(count.0)
+}
+
+/// Enforces a [`Budget`].
+pub(crate) struct BudgetTracker {
+    budget: Budget,
+    start: Instant,
+    /// The number of the current interval, counting from `start`.
+    interval: AtomicU64,
+    used: AtomicU64,
+    dropped: [AtomicU64; 5],
+}
+
+impl BudgetTracker {
+    pub fn new(budget: Budget) -> Self {
+        BudgetTracker {
+            budget,
+            start: Instant::now(),
+            interval: AtomicU64::new(0),
+            used: AtomicU64::new(0),
+            dropped: Default::default(),
+        }
+    }
+
+    /// Start a new interval if the current one is over.  Returns the number of events dropped during the
+    /// previous interval, indexed by level, if any were dropped.
+    pub fn roll_over(&self) -> Option<[u64; 5]> {
+        let interval_nanos = self.budget.interval.as_nanos().max(1);
+        let now = (self.start.elapsed().as_nanos() / interval_nanos) as u64;
+        let current = self.interval.load(Ordering::Acquire);
+        if now <= current
+            || self
+                .interval
+                .compare_exchange(current, now, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+        {
+            return None;
+        }
+
+        self.used.store(0, Ordering::Release);
+        let mut dropped = [0; 5];
+        for (n, counter) in dropped.iter_mut().zip(&self.dropped) {
+            *n = counter.swap(0, Ordering::AcqRel);
+        }
+        if dropped.iter().any(|&n| n > 0) {
+            Some(dropped)
+        } else {
+            None
+        }
+    }
+
+    /// Spend `len` bytes on an event at `level`, if the budget allows it.  Otherwise the event is counted as
+    /// dropped and `false` is returned.
+    pub fn admit(&self, level: Level, len: u64) -> bool {
+        let max = self.budget.max_bytes_per_interval;
+        let limit = match self.budget.overflow {
+            BudgetOverflow::DropLowSeverityFirst if level < Level::Warn => max - max / 4,
+            _ => max,
+        };
+        let admitted = self
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(len).filter(|&used| used <= limit)
+            })
+            .is_ok();
+        if !admitted {
+            self.dropped[level as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        admitted
+    }
+
+    /// Spend `len` bytes regardless of the budget.
+    pub fn spend(&self, len: u64) {
+        self.used.fetch_add(len, Ordering::AcqRel);
+    }
+}
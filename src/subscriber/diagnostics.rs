@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use tracing::callsite::Identifier;
+use tracing::Metadata;
+
+use crate::writer::LockResultExt;
+
+/// The maximum number of callsites counted by [`FilterDiagnostics`].
+const MAX_CALLSITES: usize = 1024;
+
+/// Why a [`SerdeLayer`](crate::SerdeLayer) dropped an event.  See [`CallsiteDiag`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SuppressReason {
+    /// Below the level set with [`SerdeLayerBuilder::with_max_level`](crate::SerdeLayerBuilder::with_max_level).
+    Level,
+    /// Excluded by [`SerdeLayerBuilder::with_include_targets`](crate::SerdeLayerBuilder::with_include_targets) or
+    /// [`SerdeLayerBuilder::with_exclude_targets`](crate::SerdeLayerBuilder::with_exclude_targets).
+    Target,
+    /// Dropped by [`SerdeLayerBuilder::with_event_sampling`](crate::SerdeLayerBuilder::with_event_sampling).
+    EventSampling,
+    /// In a trace dropped by [`SerdeLayerBuilder::with_trace_sampling`](crate::SerdeLayerBuilder::with_trace_sampling).
+    TraceSampling,
+    /// Over the [`Budget`](crate::Budget).
+    Budget,
+    /// Emitted on a thread while the layer was writing another event, e.g. by a
+    /// [`WriteEvent`](crate::writer::WriteEvent) which logs.  Writing it could recurse forever or deadlock
+    /// on the writer.
+    Reentrant,
+}
+
+impl SuppressReason {
+    /// Every reason, in the order of the layer's checks.
+    pub const ALL: [SuppressReason; 6] = [
+        SuppressReason::Level,
+        SuppressReason::Target,
+        SuppressReason::EventSampling,
+        SuppressReason::TraceSampling,
+        SuppressReason::Budget,
+        SuppressReason::Reentrant,
+    ];
+
+    /// The name of the field counting this reason in diagnostics events.
+    fn field_name(self) -> &'static str {
+        match self {
+            SuppressReason::Level => "suppressed_level",
+            SuppressReason::Target => "suppressed_target",
+            SuppressReason::EventSampling => "suppressed_event_sampling",
+            SuppressReason::TraceSampling => "suppressed_trace_sampling",
+            SuppressReason::Budget => "suppressed_budget",
+            SuppressReason::Reentrant => "suppressed_reentrant",
+        }
+    }
+}
+
+/// The number of events a [`SerdeLayer`](crate::SerdeLayer) saw at a callsite, and how many of them it
+/// dropped for each [`SuppressReason`].  See [`SerdeLayerBuilder::with_filter_diagnostics`](crate::SerdeLayerBuilder::with_filter_diagnostics).
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct CallsiteDiag {
+    /// The target of the callsite's events.
+    pub target: String,
+    /// The source file of the callsite.
+    pub file: Option<String>,
+    /// The line of the callsite in `file`.
+    pub line: Option<u32>,
+    /// The number of events which reached the layer.
+    pub seen: u64,
+    suppressed: [u64; 6],
+}
+
+impl CallsiteDiag {
+    /// The number of events dropped for `reason`.
+    pub fn suppressed(&self, reason: SuppressReason) -> u64 {
+        self.suppressed[reason as usize]
+    }
+
+    /// The number of events which were written.
+    pub fn written(&self) -> u64 {
+        self.seen - self.suppressed.iter().sum::<u64>()
+    }
+
+    pub(crate) fn fields(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        std::iter::once(("seen", self.seen)).chain(
+            SuppressReason::ALL
+                .iter()
+                .map(move |&r| (r.field_name(), self.suppressed(r))),
+        )
+    }
+}
+
+/// Counts the events seen and dropped by a layer, by callsite.
+#[derive(Default)]
+pub(crate) struct FilterDiagnostics {
+    callsites: Mutex<HashMap<Identifier, CallsiteDiag>>,
+}
+
+impl FilterDiagnostics {
+    fn update(&self, meta: &Metadata<'_>, f: impl FnOnce(&mut CallsiteDiag)) {
+        let mut callsites = self.callsites.lock().ignore_poisoned();
+        let callsite = meta.callsite();
+        if let Some(diag) = callsites.get_mut(&callsite) {
+            f(diag);
+        } else if callsites.len() < MAX_CALLSITES {
+            let mut diag = CallsiteDiag {
+                target: meta.target().to_string(),
+                file: meta.file().map(str::to_string),
+                line: meta.line(),
+                seen: 0,
+                suppressed: [0; 6],
+            };
+            f(&mut diag);
+            callsites.insert(callsite, diag);
+        }
+    }
+
+    pub fn seen(&self, meta: &Metadata<'_>) {
+        self.update(meta, |diag| diag.seen += 1)
+    }
+
+    pub fn suppressed(&self, meta: &Metadata<'_>, reason: SuppressReason) {
+        self.update(meta, |diag| diag.suppressed[reason as usize] += 1)
+    }
+
+    /// The counts of every callsite, sorted by target and location.
+    pub fn snapshot(&self) -> Vec<CallsiteDiag> {
+        let mut diags: Vec<_> = self
+            .callsites
+            .lock()
+            .ignore_poisoned()
+            .values()
+            .cloned()
+            .collect();
+        diags.sort_unstable_by(|a, b| {
+            (&a.target, &a.file, a.line).cmp(&(&b.target, &b.file, b.line))
+        });
+        diags
+    }
+}
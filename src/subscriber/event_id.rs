@@ -0,0 +1,103 @@
+#[cfg(feature = "ulid")]
+use crate::writer::LockResultExt;
+#[cfg(feature = "ulid")]
+use std::sync::Mutex;
+
+/// The kind of unique ID recorded on every event.  See
+/// [`SerdeLayerBuilder::with_event_id`](super::SerdeLayerBuilder::with_event_id).
+///
+/// Both kinds start with a millisecond timestamp, so sorting events by ID sorts them by time.  Each kind
+/// requires the crate feature of the same name.
+#[cfg_attr(docsrs, doc(cfg(any(feature = "uuid", feature = "ulid"))))]
+#[cfg(any(feature = "uuid", feature = "ulid"))]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum IdKind {
+    /// A version 7 UUID in its hyphenated form, e.g. `01890a5d-ac96-774b-bcce-b302099a8057`.
+    #[cfg(feature = "uuid")]
+    Uuid7,
+    /// A [ULID](https://github.com/ulid/spec), e.g. `01ARZ3NDEKTSV4RRFFQ69G5FAV`.
+    #[cfg(feature = "ulid")]
+    Ulid,
+}
+
+/// Generates the IDs of an `IdKind`.  IDs generated in the same millisecond still increase, so they
+/// follow the order in which events were emitted.  Has no variants without the `uuid` and `ulid` features.
+pub(crate) enum EventIds {
+    #[cfg(feature = "uuid")]
+    Uuid7,
+    #[cfg(feature = "ulid")]
+    Ulid(Mutex<ulid::Generator>),
+}
+
+impl EventIds {
+    #[cfg(any(feature = "uuid", feature = "ulid"))]
+    pub fn new(kind: IdKind) -> Self {
+        match kind {
+            #[cfg(feature = "uuid")]
+            IdKind::Uuid7 => EventIds::Uuid7,
+            #[cfg(feature = "ulid")]
+            IdKind::Ulid => EventIds::Ulid(Mutex::new(ulid::Generator::new())),
+        }
+    }
+
+    pub fn next(&self) -> String {
+        match *self {
+            // Monotonic within the process
+            #[cfg(feature = "uuid")]
+            EventIds::Uuid7 => uuid::Uuid::now_v7().hyphenated().to_string(),
+            #[cfg(feature = "ulid")]
+            EventIds::Ulid(ref generator) => {
+                let id = generator.lock().ignore_poisoned().generate();
+                // Only fails if more than 2^80 IDs are generated in one millisecond
+                id.unwrap_or_else(|_| ulid::Ulid::new()).to_string()
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "consumer", any(feature = "uuid", feature = "ulid")))]
+mod tests {
+    use super::*;
+    use crate::consumer::StreamFormat;
+    use crate::format::Json;
+    use crate::test_utils::capture_output;
+    use crate::SerdeLayer;
+    use std::collections::HashSet;
+
+    fn event_ids(kind: IdKind) -> Vec<String> {
+        let layer = SerdeLayer::new().with_event_id(kind);
+        let buffer = capture_output(layer, || {
+            for i in 0..1000 {
+                tracing::info!(i, "identified");
+            }
+        });
+        let ids: Vec<_> = Json
+            .iter_reader(buffer.as_slice())
+            .map(|e| e.unwrap().id.unwrap())
+            .collect();
+
+        assert_eq!(ids.len(), 1000);
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+        assert!(ids.windows(2).all(|w| w[0] < w[1]), "not sorted by time");
+        ids
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn uuid7() {
+        for id in event_ids(IdKind::Uuid7) {
+            let uuid = uuid::Uuid::parse_str(&id).unwrap();
+            assert_eq!(uuid.get_version(), Some(uuid::Version::SortRand));
+            assert_eq!(uuid.hyphenated().to_string(), id);
+        }
+    }
+
+    #[cfg(feature = "ulid")]
+    #[test]
+    fn ulid() {
+        for id in event_ids(IdKind::Ulid) {
+            assert_eq!(ulid::Ulid::from_string(&id).unwrap().to_string(), id);
+        }
+    }
+}
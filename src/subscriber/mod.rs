@@ -1,80 +1,365 @@
+use std::backtrace::{Backtrace, BacktraceStatus};
 use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
 use std::fmt::{self, Debug, Write as FmtWrite};
-use std::io::Stdout;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io::{self, Stdout};
 use std::num::NonZeroU64;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tracing::{
     field::Field,
     field::Visit,
-    span::{Attributes, Id},
+    level_filters::LevelFilter,
+    span::{Attributes, Id, Record},
     Metadata, Subscriber,
 };
 use tracing_subscriber::layer::{Context, Layer};
-use tracing_subscriber::registry::{LookupSpan, SpanRef};
+use tracing_subscriber::registry::{Extensions, LookupSpan, Scope, SpanRef};
 
 use smallvec::SmallVec;
 use smartstring::alias::String as SString;
 
 use crate::format::Json;
 use crate::time::{Clock, SpanTime, SpanTimer, UnixTime};
-use crate::{SerdeFormat, SpanEvents, WriteEvent};
+use crate::writer::{EventInfo, Preamble};
+use crate::{SerdeFormat, SpanEvents, SyslogFacility, WriteEvent};
 
+mod budget;
+mod diagnostics;
+mod event_id;
+mod policy;
+mod sampling;
 pub(crate) mod serialize;
+mod targets;
 
+pub use budget::{Budget, BudgetOverflow};
+pub use diagnostics::{CallsiteDiag, SuppressReason};
+#[cfg(any(feature = "uuid", feature = "ulid"))]
+pub use event_id::IdKind;
+pub use policy::FieldPolicy;
+
+use budget::{serialized_len, BudgetTracker};
+use diagnostics::FilterDiagnostics;
+use event_id::EventIds;
+use policy::{allowed, FieldPolicies};
+use sampling::{Sampled, Sampler};
 use serialize::*;
+use targets::TargetFilter;
 
 trait AddFields {
     fn add_field(&mut self, name: &'static str, val: FieldValue);
 }
 
-struct FieldVisitor<T>(T);
+struct FieldVisitor<'p, T> {
+    fields: T,
+    /// The maximum length of `Debug`-formatted values, in bytes
+    max_debug_len: Option<usize>,
+    /// The maximum length of string and `Debug`-formatted values, in bytes, before the truncation suffix
+    max_field_len: Option<usize>,
+    policy: Option<&'p FieldPolicy>,
+    /// Whether to [`sanitize`] string values
+    sanitize: bool,
+}
+
+impl<'p, T> FieldVisitor<'p, T> {
+    fn new(fields: T, max_debug_len: Option<usize>) -> Self {
+        FieldVisitor {
+            fields,
+            max_debug_len,
+            max_field_len: None,
+            policy: None,
+            sanitize: false,
+        }
+    }
+
+    /// Truncate string and `Debug`-formatted values longer than `max` bytes.  See
+    /// [`SerdeLayerBuilder::with_max_field_length`].
+    fn truncated(mut self, max: Option<usize>) -> Self {
+        self.max_field_len = max;
+        self
+    }
+
+    /// [`sanitize`] string and `Debug`-formatted values if `enable` is set.
+    fn sanitized(mut self, enable: bool) -> Self {
+        self.sanitize = enable;
+        self
+    }
+
+    /// Only record the fields allowed by `policy`.
+    fn with_policy<'q>(self, policy: Option<&'q FieldPolicy>) -> FieldVisitor<'q, T> {
+        FieldVisitor {
+            fields: self.fields,
+            max_debug_len: self.max_debug_len,
+            max_field_len: self.max_field_len,
+            policy,
+            sanitize: self.sanitize,
+        }
+    }
 
-impl<T> FieldVisitor<T> {
     fn finish(self) -> T {
-        self.0
+        self.fields
+    }
+}
+
+/// Whether `c` is a control character other than `\n` and `\t`, including `DEL` and the C1 controls.  These
+/// are replaced by [`sanitize`], and escaped by the pretty printer.
+pub(crate) fn is_control(c: char) -> bool {
+    c.is_control() && c != '\n' && c != '\t'
+}
+
+/// Replace control characters other than `\n` and `\t` with `U+FFFD`.  See
+/// [`SerdeLayerBuilder::with_sanitize_strings`].
+pub(crate) fn sanitize(s: &str) -> Cow<'_, str> {
+    if s.chars().any(is_control) {
+        Cow::Owned(
+            s.chars()
+                .map(|c| if is_control(c) { '\u{FFFD}' } else { c })
+                .collect(),
+        )
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+/// The largest index of a character boundary in `s` which is at most `i`.
+fn floor_char_boundary(s: &str, i: usize) -> usize {
+    let mut end = i.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    end
+}
+
+/// A writer which stops accepting output after `remaining` bytes, cutting it at a character boundary.  Output
+/// is [sanitized](sanitize) before it counts towards the limit.
+struct Truncate<'a> {
+    s: &'a mut SString,
+    remaining: usize,
+    sanitize: bool,
+    /// Whether any output was cut off
+    cut: bool,
+}
+
+impl<'a> Truncate<'a> {
+    fn new(s: &'a mut SString, max: usize, sanitize: bool) -> Self {
+        Truncate {
+            s,
+            remaining: max,
+            sanitize,
+            cut: false,
+        }
+    }
+}
+
+impl fmt::Write for Truncate<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let s = if self.sanitize {
+            sanitize(s)
+        } else {
+            Cow::Borrowed(s)
+        };
+        if s.len() <= self.remaining {
+            self.s.push_str(&s);
+            self.remaining -= s.len();
+            return Ok(());
+        }
+        let end = floor_char_boundary(&s, self.remaining);
+        self.s.push_str(&s[..end]);
+        self.remaining = 0;
+        self.cut = true;
+        // Stop formatting the rest of the value
+        Err(fmt::Error)
+    }
+}
+
+impl<T: AddFields> FieldVisitor<'_, T> {
+    /// Add the field if the policy allows it.  `value` is only computed for allowed fields.
+    fn add(&mut self, field: &Field, value: impl FnOnce(&Self) -> FieldValue) {
+        if allowed(self.policy, field.name()) {
+            let value = value(self);
+            self.fields.add_field(field.name(), value)
+        }
+    }
+}
+
+impl<T> FieldVisitor<'_, T> {
+    fn str_value(&self, name: &str, value: &str) -> FieldValue {
+        let value = if self.sanitize {
+            sanitize(value)
+        } else {
+            Cow::Borrowed(value)
+        };
+        let (value, cut) = match self.max_field_len {
+            Some(max) if value.len() > max && name != "message" => {
+                let end = floor_char_boundary(&value, max);
+                (&value[..end], value.len() - end)
+            }
+            _ => (&value[..], 0),
+        };
+        let mut s = SString::from(value);
+        if cut > 0 {
+            write!(s, "...(truncated {} bytes)", cut).unwrap();
+        }
+        FieldValue::Str(s)
+    }
+
+    fn debug_value(&self, name: &str, value: &dyn fmt::Debug) -> FieldValue {
+        let mut s = SString::new();
+        // The message of an event is formatted with `Debug`, but is never truncated
+        let (max_debug_len, max_field_len) = if name == "message" {
+            (None, None)
+        } else {
+            (self.max_debug_len, self.max_field_len)
+        };
+        // The shorter of the two limits applies.  The rest of a value isn't formatted, so the length of what
+        // is cut off is unknown.
+        let (max, suffix) = match (max_field_len, max_debug_len) {
+            (Some(max), d) if d.map_or(true, |d| max <= d) => (max, "...(truncated)"),
+            (_, Some(max)) => (max, "…"),
+            _ => (usize::MAX, ""),
+        };
+        let mut w = Truncate::new(&mut s, max, self.sanitize);
+        let _ = write!(&mut w, "{:?}", value);
+        if w.cut {
+            s.push_str(suffix);
+        }
+        FieldValue::Str(s)
     }
 }
 
-impl<T: AddFields> Visit for FieldVisitor<T> {
+impl<T: AddFields> Visit for FieldVisitor<'_, T> {
     /// Visit a double-precision floating point value.
     fn record_f64(&mut self, field: &Field, value: f64) {
-        self.0.add_field(field.name(), FieldValue::Float(value))
+        self.add(field, |_| FieldValue::Float(value))
     }
 
     /// Visit a signed 64-bit integer value.
     fn record_i64(&mut self, field: &Field, value: i64) {
-        self.0.add_field(field.name(), FieldValue::Int(value))
+        self.add(field, |_| FieldValue::Int(value))
     }
 
     /// Visit an unsigned 64-bit integer value.
     fn record_u64(&mut self, field: &Field, value: u64) {
-        self.0
-            .add_field(field.name(), FieldValue::Int(value as i64))
+        self.add(field, |_| FieldValue::from_u128(value.into()))
+    }
+
+    /// Visit a signed 128-bit integer value.
+    fn record_i128(&mut self, field: &Field, value: i128) {
+        self.add(field, |_| FieldValue::from_i128(value))
+    }
+
+    /// Visit an unsigned 128-bit integer value.
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        self.add(field, |_| FieldValue::from_u128(value))
     }
 
     /// Visit a boolean value.
     fn record_bool(&mut self, field: &Field, value: bool) {
-        self.0.add_field(field.name(), FieldValue::Bool(value))
+        self.add(field, |_| FieldValue::Bool(value))
     }
 
     /// Visit a string value.
     fn record_str(&mut self, field: &Field, value: &str) {
-        self.0
-            .add_field(field.name(), FieldValue::Str(value.into()))
+        self.add(field, |v| v.str_value(field.name(), value))
     }
 
     /// Visit a value implementing `fmt::Debug`.
     fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
-        let mut s = SString::new();
-        write!(&mut s, "{:?}", value).unwrap();
-        self.0.add_field(field.name(), FieldValue::Str(s))
+        self.add(field, |v| v.debug_value(field.name(), value))
+    }
+}
+
+/// Controls which events [span IDs](mod@tracing::span) are recorded on.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpanIdMode {
+    /// Never record span IDs.
+    Never,
+    /// Only record the ID of the span which is created or closed, on [`EventKind::SpanCreate`](crate::EventKind::SpanCreate)
+    /// and [`EventKind::SpanClose`](crate::EventKind::SpanClose) events.  This is enough to reconstruct the
+    /// span tree (see [`SpanTree`](crate::consumer::SpanTree)), while keeping ordinary events small.
+    LifecycleOnly,
+    /// Record the IDs of all spans on every event.
+    Always,
+}
+
+impl From<bool> for SpanIdMode {
+    fn from(b: bool) -> Self {
+        if b {
+            SpanIdMode::Always
+        } else {
+            SpanIdMode::Never
+        }
+    }
+}
+
+/// Controls which span fields are copied into the fields of each event.  See
+/// [`SerdeLayerBuilder::with_inherited_span_fields`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum InheritMode {
+    /// Don't copy any span fields.
+    None,
+    /// Copy the fields of the innermost span only.
+    Innermost,
+    /// Copy the fields of all spans.  If several spans have a field with the same name, the innermost span's
+    /// value is used.
+    All,
+}
+
+/// What to do when the [`Clock`] returns `None` for an event.  See
+/// [`SerdeLayerBuilder::with_missing_time`].
+///
+/// This only matters for clocks which produce timestamps for some events but not others.  With the `()`
+/// clock, events never have a timestamp.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum MissingTime {
+    /// Leave out the timestamp of the event.
+    Omit,
+    /// Use the last timestamp the clock produced, so timestamps never go backwards.  Events before the
+    /// clock first produces a timestamp have none.
+    CarryForward,
+    /// Leave out the timestamp of the event, and set [`Event::time_missing`](crate::Event::time_missing).
+    Mark,
+}
+
+/// Identifies a process instance.  See [`SerdeLayerBuilder::with_instance_id`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct InstanceId(u64);
+
+impl InstanceId {
+    /// Use the given value as the ID, e.g. a hash of a pod name.
+    pub fn new(id: u64) -> Self {
+        InstanceId(id)
+    }
+
+    /// Generate a random ID.
+    pub fn random() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        std::process::id().hash(&mut hasher);
+        std::time::SystemTime::now().hash(&mut hasher);
+        InstanceId(hasher.finish())
+    }
+
+    /// The value of the ID.
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for InstanceId {
+    fn from(id: u64) -> Self {
+        InstanceId(id)
     }
 }
 
 /// Configuration for [`SerdeLayer`]
 pub struct SerdeLayerBuilder<F, C, W> {
-    source_location: bool,
+    source_location_events: bool,
+    source_location_spans: bool,
     span_events: SpanEvents,
     time_spans: bool,
     fmt: F,
@@ -82,7 +367,45 @@ pub struct SerdeLayerBuilder<F, C, W> {
     clock: C,
     thread_name: bool,
     thread_id: bool,
-    span_ids: bool,
+    span_ids: SpanIdMode,
+    span_fields_on_create_only: bool,
+    inherit_span_fields: InheritMode,
+    inherited_field_prefix: String,
+    uptime: bool,
+    span_records: bool,
+    span_follows_from: bool,
+    syslog: Option<SyslogFacility>,
+    instance_id: Option<u64>,
+    missing_time: MissingTime,
+    trace_sampling: Option<(f64, u64)>,
+    event_sampling: Option<(u64, u64)>,
+    always_keep_errors: bool,
+    budget: Option<Budget>,
+    backtrace: Option<crate::Level>,
+    max_level: crate::Level,
+    empty_fields_as_null: bool,
+    max_debug_len: Option<usize>,
+    max_field_len: Option<usize>,
+    crate_info: Option<(String, String)>,
+    targets: TargetFilter,
+    field_policies: FieldPolicies,
+    span_field_names: bool,
+    span_time_increments: Option<Duration>,
+    first_in_span: bool,
+    omit_empty: bool,
+    global_fields: Vec<(String, crate::FieldValue)>,
+    sanitize_strings: bool,
+    filter_diagnostics: bool,
+    process_id: bool,
+    hostname: bool,
+    hostname_override: Option<String>,
+    event_names: bool,
+    module_path: bool,
+    max_level_snapshot: bool,
+    span_list: bool,
+    max_span_depth: Option<usize>,
+    sequence_numbers: bool,
+    event_ids: Option<EventIds>,
 }
 
 /// A tracing-subscriber [`Layer`](tracing_subscriber::Layer) which serializes events to any
@@ -98,14 +421,56 @@ pub struct SerdeLayerBuilder<F, C, W> {
 /// See [`SerdeLayerBuilder`] for details on configuration and options.
 pub struct SerdeLayer<F, C, W> {
     thread_name: bool,
-    #[cfg_attr(not(feature = "thread_id"), allow(dead_code))]
     thread_id: bool,
-    source_location: bool,
+    source_location_events: bool,
+    source_location_spans: bool,
     record_span_enter: bool,
     record_span_exit: bool,
     record_span_create: bool,
     record_span_close: bool,
-    span_ids: bool,
+    record_span_record: bool,
+    record_span_follows_from: bool,
+    span_ids: SpanIdMode,
+    span_fields_on_create_only: bool,
+    inherit_span_fields: InheritMode,
+    inherited_field_prefix: String,
+    start: Option<Instant>,
+    syslog: Option<SyslogFacility>,
+    instance_id: Option<u64>,
+    missing_time: MissingTime,
+    /// The last timestamp produced by the clock, in nanoseconds since the epoch, or 0 if there is none.
+    last_time: AtomicU64,
+    trace_sampling: Option<Sampler>,
+    event_sampling: Option<Sampler>,
+    /// The number of events dropped by `event_sampling`
+    sampled_out: AtomicU64,
+    always_keep_errors: bool,
+    budget: Option<BudgetTracker>,
+    backtrace: Option<crate::Level>,
+    max_level: crate::Level,
+    empty_fields_as_null: bool,
+    max_debug_len: Option<usize>,
+    max_field_len: Option<usize>,
+    crate_info: Option<(String, String)>,
+    targets: TargetFilter,
+    field_policies: FieldPolicies,
+    span_field_names: bool,
+    span_time_increments: Option<Duration>,
+    first_in_span: bool,
+    omit_empty: bool,
+    global_fields: Vec<(String, crate::FieldValue)>,
+    sanitize_strings: bool,
+    diagnostics: Option<FilterDiagnostics>,
+    pid: Option<u32>,
+    host: Option<String>,
+    event_names: bool,
+    module_path: bool,
+    span_list: bool,
+    max_span_depth: Option<usize>,
+    sequence_numbers: bool,
+    event_ids: Option<EventIds>,
+    /// The preamble, held back until the layer is attached to a subscriber to record its max level.
+    pending_preamble: Option<Preamble>,
     time_spans: bool,
     fmt: F,
     writer: W,
@@ -121,10 +486,49 @@ impl SerdeLayer<Json, (), Stdout> {
             writer: std::io::stdout(),
             clock: (),
             fmt: Json,
-            source_location: true,
+            source_location_events: true,
+            source_location_spans: true,
             time_spans: false,
             span_events: SpanEvents::NONE,
-            span_ids: false,
+            span_ids: SpanIdMode::Never,
+            span_fields_on_create_only: false,
+            inherit_span_fields: InheritMode::None,
+            inherited_field_prefix: String::new(),
+            uptime: false,
+            span_records: false,
+            span_follows_from: false,
+            syslog: None,
+            instance_id: None,
+            missing_time: MissingTime::Omit,
+            trace_sampling: None,
+            event_sampling: None,
+            always_keep_errors: false,
+            budget: None,
+            backtrace: None,
+            max_level: crate::Level::Trace,
+            empty_fields_as_null: false,
+            max_debug_len: None,
+            max_field_len: None,
+            crate_info: None,
+            targets: TargetFilter::default(),
+            field_policies: FieldPolicies::default(),
+            span_field_names: false,
+            span_time_increments: None,
+            first_in_span: false,
+            omit_empty: false,
+            global_fields: Vec::new(),
+            sanitize_strings: false,
+            filter_diagnostics: false,
+            process_id: false,
+            hostname: false,
+            hostname_override: None,
+            event_names: false,
+            module_path: false,
+            max_level_snapshot: false,
+            span_list: true,
+            max_span_depth: None,
+            sequence_numbers: false,
+            event_ids: None,
         }
     }
 }
@@ -139,7 +543,7 @@ where
     ///
     /// See the trait documentation on how to implement.
     ///
-    /// Will accept [`Stdout`], [`Stdout`], [`NonBlocking`](crate::writer::NonBlocking)
+    /// Will accept [`Stdout`], [`Stdout`], [`NonBlocking`](crate::writer::NonBlocking), [`File`](std::fs::File)
     /// and [`Mutex<W>`](std::sync::Mutex) where `W: io::Write`.
     pub fn with_writer<W2>(self, writer: W2) -> SerdeLayerBuilder<F, C, W2>
     where
@@ -148,10 +552,49 @@ where
         SerdeLayerBuilder {
             thread_name: self.thread_name,
             thread_id: self.thread_id,
-            source_location: self.source_location,
+            source_location_events: self.source_location_events,
+            source_location_spans: self.source_location_spans,
             span_events: self.span_events,
             time_spans: self.time_spans,
             span_ids: self.span_ids,
+            span_fields_on_create_only: self.span_fields_on_create_only,
+            inherit_span_fields: self.inherit_span_fields,
+            inherited_field_prefix: self.inherited_field_prefix,
+            uptime: self.uptime,
+            span_records: self.span_records,
+            span_follows_from: self.span_follows_from,
+            syslog: self.syslog,
+            instance_id: self.instance_id,
+            missing_time: self.missing_time,
+            trace_sampling: self.trace_sampling,
+            event_sampling: self.event_sampling,
+            always_keep_errors: self.always_keep_errors,
+            budget: self.budget,
+            backtrace: self.backtrace,
+            max_level: self.max_level,
+            empty_fields_as_null: self.empty_fields_as_null,
+            max_debug_len: self.max_debug_len,
+            max_field_len: self.max_field_len,
+            crate_info: self.crate_info,
+            targets: self.targets,
+            field_policies: self.field_policies,
+            span_field_names: self.span_field_names,
+            span_time_increments: self.span_time_increments,
+            first_in_span: self.first_in_span,
+            omit_empty: self.omit_empty,
+            global_fields: self.global_fields,
+            sanitize_strings: self.sanitize_strings,
+            filter_diagnostics: self.filter_diagnostics,
+            process_id: self.process_id,
+            hostname: self.hostname,
+            hostname_override: self.hostname_override,
+            event_names: self.event_names,
+            module_path: self.module_path,
+            max_level_snapshot: self.max_level_snapshot,
+            span_list: self.span_list,
+            max_span_depth: self.max_span_depth,
+            sequence_numbers: self.sequence_numbers,
+            event_ids: self.event_ids,
             writer,
             fmt: self.fmt,
             clock: self.clock,
@@ -160,23 +603,62 @@ where
 
     /// Use the supplied [`Clock`] to produce timestamps.
     ///
-    /// Span timings (busy/idle) will use [`std::time::Instant`] regardless
-    /// of the clock, this only affects the `timestamp` field of [`Event`](crate::Event).
+    /// Span timings (busy/idle) use [`Clock::instant`], which is [`std::time::Instant`] unless the clock
+    /// overrides it, such as [`ManualClock`](crate::time::ManualClock).
     pub fn with_clock<C2: Clock>(self, clock: C2) -> SerdeLayerBuilder<F, C2, W> {
         SerdeLayerBuilder {
             thread_name: self.thread_name,
             thread_id: self.thread_id,
-            source_location: self.source_location,
+            source_location_events: self.source_location_events,
+            source_location_spans: self.source_location_spans,
             span_events: self.span_events,
             time_spans: self.time_spans,
             span_ids: self.span_ids,
+            span_fields_on_create_only: self.span_fields_on_create_only,
+            inherit_span_fields: self.inherit_span_fields,
+            inherited_field_prefix: self.inherited_field_prefix,
+            uptime: self.uptime,
+            span_records: self.span_records,
+            span_follows_from: self.span_follows_from,
+            syslog: self.syslog,
+            instance_id: self.instance_id,
+            missing_time: self.missing_time,
+            trace_sampling: self.trace_sampling,
+            event_sampling: self.event_sampling,
+            always_keep_errors: self.always_keep_errors,
+            budget: self.budget,
+            backtrace: self.backtrace,
+            max_level: self.max_level,
+            empty_fields_as_null: self.empty_fields_as_null,
+            max_debug_len: self.max_debug_len,
+            max_field_len: self.max_field_len,
+            crate_info: self.crate_info,
+            targets: self.targets,
+            field_policies: self.field_policies,
+            span_field_names: self.span_field_names,
+            span_time_increments: self.span_time_increments,
+            first_in_span: self.first_in_span,
+            omit_empty: self.omit_empty,
+            global_fields: self.global_fields,
+            sanitize_strings: self.sanitize_strings,
+            filter_diagnostics: self.filter_diagnostics,
+            process_id: self.process_id,
+            hostname: self.hostname,
+            hostname_override: self.hostname_override,
+            event_names: self.event_names,
+            module_path: self.module_path,
+            max_level_snapshot: self.max_level_snapshot,
+            span_list: self.span_list,
+            max_span_depth: self.max_span_depth,
+            sequence_numbers: self.sequence_numbers,
+            event_ids: self.event_ids,
             writer: self.writer,
             fmt: self.fmt,
             clock,
         }
     }
 
-    /// Time spans' busy and idle time using [`std::time::Instant`].
+    /// Time spans' busy and idle time using [`Clock::instant`].
     ///
     /// Enabling this will always enable [`SpanEvents::CLOSE`]. Disabled by default.
     ///
@@ -186,6 +668,19 @@ where
         self
     }
 
+    /// Emit an [`EventKind::SpanProgress`](crate::EventKind::SpanProgress) event with the busy and idle time
+    /// of a span since its previous one, so long-lived spans can be followed before they close.  Increments
+    /// are emitted when the span is entered or exited, or an event occurs inside it, if at least `interval`
+    /// has passed since the last one, and once more just before the span is closed, so that they sum to the
+    /// times of the [`EventKind::SpanClose`](crate::EventKind::SpanClose) event.  A span which stays entered
+    /// without any events inside it only reports its busy time when it is exited.
+    ///
+    /// Enabling this will always enable [`with_time_spans`](Self::with_time_spans).  Disabled by default.
+    pub fn with_span_time_increments(mut self, interval: Duration) -> Self {
+        self.span_time_increments = Some(interval);
+        self
+    }
+
     /// Control the output of synthesised events when spans
     /// are constructed/entered and destroyed/exited.
     pub fn with_span_events(mut self, e: SpanEvents) -> Self {
@@ -193,23 +688,425 @@ where
         self
     }
 
-    /// Record [span IDs](mod@tracing::span)
-    pub fn with_span_ids(mut self, include: bool) -> Self {
-        self.span_ids = include;
+    /// Record [span IDs](mod@tracing::span).  Accepts a [`SpanIdMode`], or a `bool` which is
+    /// equivalent to [`SpanIdMode::Always`] or [`SpanIdMode::Never`].  Default is [`SpanIdMode::Never`].
+    pub fn with_span_ids(mut self, mode: impl Into<SpanIdMode>) -> Self {
+        self.span_ids = mode.into();
+        self
+    }
+
+    /// Emit an [`EventKind::SpanRecord`](crate::EventKind::SpanRecord) event whenever fields are recorded on
+    /// an existing span with [`Span::record`](tracing::Span::record).  Disabled by default.
+    pub fn with_span_records(mut self, enable: bool) -> Self {
+        self.span_records = enable;
+        self
+    }
+
+    /// Emit an [`EventKind::SpanFollowsFrom`](crate::EventKind::SpanFollowsFrom) event whenever a span declares
+    /// that it follows from another with [`Span::follows_from`](tracing::Span::follows_from).  The span IDs are
+    /// always included in the event.  Disabled by default.
+    pub fn with_span_follows_from(mut self, enable: bool) -> Self {
+        self.span_follows_from = enable;
+        self
+    }
+
+    /// Record the names of all fields declared by a span on its
+    /// [`EventKind::SpanCreate`](crate::EventKind::SpanCreate) event, including fields without a value (see
+    /// [`Span::declared_fields`](crate::Span::declared_fields)).  This tells apart spans which declare no
+    /// fields from spans whose fields were skipped or left [`Empty`](tracing::field::Empty).  Disabled by default.
+    pub fn with_span_field_names(mut self, enable: bool) -> Self {
+        self.span_field_names = enable;
+        self
+    }
+
+    /// Add the field `first_in_span: true` to the first event recorded in each span, e.g. to find the
+    /// "request started" event of each request.  Only the innermost span of the event counts, so an event
+    /// may be the first in its span but not in the span's parent.  Events which are dropped, e.g. by sampling or
+    /// a [`Budget`], don't count.  Disabled by default.
+    pub fn with_first_in_span(mut self, enable: bool) -> Self {
+        self.first_in_span = enable;
+        self
+    }
+
+    /// Attach `fields` to every event, under the `globals` key, e.g. to tag events with the name and version of
+    /// the service when collecting logs from many services in one place.  Fields with the same name as an
+    /// earlier global field replace it.  See [`Event::globals`](crate::Event::globals).
+    pub fn with_global_fields(
+        mut self,
+        fields: impl IntoIterator<Item = (String, crate::FieldValue)>,
+    ) -> Self {
+        self.global_fields
+            .merge_with(fields, MergePolicy::Overwrite);
+        self
+    }
+
+    /// Count the events seen at each callsite, and how many were dropped by this layer's level and target
+    /// filters, sampling and budget, to find out why events are missing.  See [`SerdeLayer::diagnostics`] and
+    /// [`SerdeLayer::emit_diagnostics`].
+    ///
+    /// Only events which reach the layer are counted: events disabled by the subscriber, or by a filter applied
+    /// to the layer such as an [`EnvFilter`](tracing_subscriber::EnvFilter), are invisible to it.  At most 1024
+    /// callsites are counted.  Disabled by default.
+    pub fn with_filter_diagnostics(mut self, enable: bool) -> Self {
+        self.filter_diagnostics = enable;
+        self
+    }
+
+    /// Replace control characters other than newlines and tabs in string and `Debug`-formatted field values,
+    /// messages and span names with `U+FFFD`, so a value containing e.g. an ANSI escape sequence can't mangle
+    /// a terminal or be rejected by log ingestion.  Disabled by default.
+    pub fn with_sanitize_strings(mut self, enable: bool) -> Self {
+        self.sanitize_strings = enable;
+        self
+    }
+
+    /// Leave out the fields of events without fields, and the spans of events outside of any span, rather
+    /// than writing an empty map and list.  [`Event`](crate::Event) deserializes them as empty.  Formats which
+    /// serialize events as arrays, such as [`MessagePack`](crate::format::MessagePack), always write them.
+    /// Disabled by default.
+    pub fn with_omit_empty(mut self, enable: bool) -> Self {
+        self.omit_empty = enable;
+        self
+    }
+
+    /// Record all the spans an event is in, from the root to the innermost span.  When disabled, only the
+    /// innermost span is recorded, which makes events from deeply nested spans much smaller.  Span events
+    /// then only record their own span.  Default is `true`.
+    pub fn with_span_list(mut self, enable: bool) -> Self {
+        self.span_list = enable;
+        self
+    }
+
+    /// Only record the innermost `depth` spans an event is in, e.g. for deeply recursive code.  The outer
+    /// spans are replaced by a single span named `"..."`, without fields or an ID, so consumers can tell
+    /// that the list was truncated.  At least one span is always recorded.  Unlimited by default.
+    pub fn with_max_span_depth(mut self, depth: usize) -> Self {
+        self.max_span_depth = Some(depth.max(1));
+        self
+    }
+
+    /// Only record span fields once, on the [`EventKind::SpanCreate`](crate::EventKind::SpanCreate) event
+    /// of each span.  All other events will only record the names and IDs of their spans.
+    ///
+    /// Enabling this will always enable [`SpanEvents::NEW`] and span IDs, so the fields can be rejoined later
+    /// with [`rejoin_span_fields`](crate::consumer::rejoin_span_fields).  Disabled by default.
+    pub fn with_span_fields_on_create_only(mut self, enable: bool) -> Self {
+        self.span_fields_on_create_only = enable;
+        self
+    }
+
+    /// Whether events record the fields of their spans.  An alias for
+    /// [`with_span_fields_on_create_only(!enable)`](Self::with_span_fields_on_create_only).
+    pub fn with_span_fields_on_events(self, enable: bool) -> Self {
+        self.with_span_fields_on_create_only(!enable)
+    }
+
+    /// Copy span fields into the fields of each event, for consumers which can only index the top-level
+    /// fields of an event.  Fields are never copied into span lifecycle events.
+    ///
+    /// An event's own fields always take precedence over span fields with the same name.  Span fields are
+    /// still recorded on the spans as usual.  Default is [`InheritMode::None`].
+    pub fn with_inherited_span_fields(mut self, mode: InheritMode) -> Self {
+        self.inherit_span_fields = mode;
+        self
+    }
+
+    /// Prefix the names of fields copied by [`with_inherited_span_fields`](Self::with_inherited_span_fields),
+    /// e.g. `"span."` will copy a span field `request_id` as `span.request_id`.  Default is no prefix.
+    pub fn with_inherited_span_field_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.inherited_field_prefix = prefix.into();
+        self
+    }
+
+    /// Record the time since the layer was created on each event, in nanoseconds.  This is cheap, and
+    /// unaffected by changes to the system clock.  Disabled by default.
+    pub fn with_uptime(mut self, enable: bool) -> Self {
+        self.uptime = enable;
+        self
+    }
+
+    /// Record the [syslog PRI](https://datatracker.ietf.org/doc/html/rfc5424#section-6.2.1) of each event,
+    /// computed from `facility` and the event's level (see [`Level::syslog_severity`](crate::Level::syslog_severity)).
+    /// Disabled by default.
+    pub fn with_syslog(mut self, facility: SyslogFacility) -> Self {
+        self.syslog = Some(facility);
+        self
+    }
+
+    /// Choose what happens when the clock doesn't produce a timestamp for an event, e.g. because it failed.
+    /// Default is [`MissingTime::Omit`].
+    pub fn with_missing_time(mut self, policy: MissingTime) -> Self {
+        self.missing_time = policy;
+        self
+    }
+
+    /// Record an ID for this process instance on every event, to tell apart events from several processes
+    /// writing to the same place.  Disabled by default.
+    pub fn with_instance_id(mut self, id: InstanceId) -> Self {
+        self.instance_id = Some(id.0);
+        self
+    }
+
+    /// Sample whole traces: when a root span (a span without a parent) is created, keep it with probability
+    /// `keep`, along with all of its descendants.  Events and span lifecycle events belonging to traces which
+    /// aren't kept are dropped, while events outside of any span are always kept.
+    ///
+    /// The decisions are deterministic for a given `seed` and order of root span creation.  Disabled by default.
+    pub fn with_trace_sampling(mut self, keep: f64, seed: u64) -> Self {
+        self.trace_sampling = Some((keep, seed));
+        self
+    }
+
+    /// Sample events: keep each [`EventKind::Event`](crate::EventKind::Event) with probability `1 / one_in`,
+    /// to reduce the volume of events from hot paths.  Span events are unaffected.  The events which are
+    /// dropped are counted by [`SerdeLayer::sampled_out_events`], and `one_in` is recorded in the
+    /// [`Preamble`] so consumers know the stream is downsampled.
+    ///
+    /// Sampling is lock-free, and the decisions are deterministic for a given `seed` and order of events.
+    /// Events dropped by sampling are dropped before their fields are recorded, and before
+    /// [`with_trace_sampling`](Self::with_trace_sampling) is considered.  A `one_in` of `0` or `1` keeps
+    /// every event.  Disabled by default.
+    pub fn with_event_sampling(mut self, one_in: u64, seed: u64) -> Self {
+        self.event_sampling = Some((one_in.max(1), seed));
+        self
+    }
+
+    /// Always keep [`Level::Warn`](crate::Level::Warn) and [`Level::Error`](crate::Level::Error) events,
+    /// even if their trace was dropped by [`with_trace_sampling`](Self::with_trace_sampling).
+    ///
+    /// The [`EventKind::SpanCreate`](crate::EventKind::SpanCreate) events of the event's spans are emitted
+    /// before the event (if enabled with [`SpanEvents::NEW`]), and their remaining lifecycle events are no
+    /// longer dropped.  Disabled by default.
+    pub fn with_always_keep_errors(mut self, enable: bool) -> Self {
+        self.always_keep_errors = enable;
+        self
+    }
+
+    /// Limit the number of bytes written in each interval.  Events are serialized before deciding whether
+    /// to keep them, so the limit is exact.  Events which don't fit are dropped according to
+    /// [`Budget::overflow`].
+    ///
+    /// At the start of the first interval after any events were dropped, a [`Level::Warn`](crate::Level::Warn)
+    /// event with the target `"tracing_subscriber_serde::budget"` is emitted, with the number of events
+    /// dropped at each level in the fields `dropped_trace`, `dropped_debug`, `dropped_info`, `dropped_warn`
+    /// and `dropped_error`.  It counts towards the budget of the new interval, but is never dropped.
+    /// Disabled by default.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(budget);
         self
     }
 
-    /// Record thread information (names and thread IDs).  Logging thread IDs requires the `thread_id`
-    /// feature which is only available on the Nightly compiler.
+    /// Capture a backtrace for [`Level::Error`](crate::Level::Error) events, and record it in the string field
+    /// `backtrace`.  See [`with_backtrace_level`](Self::with_backtrace_level) to choose the level.
+    ///
+    /// Backtraces are captured with [`Backtrace::capture`], so they are only recorded if enabled with the
+    /// `RUST_LIB_BACKTRACE` or `RUST_BACKTRACE` environment variables.  Disabled by default.
+    pub fn with_backtrace_on_error(mut self, enable: bool) -> Self {
+        self.backtrace = if enable {
+            Some(crate::Level::Error)
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Capture backtraces as in [`with_backtrace_on_error`](Self::with_backtrace_on_error), for events at
+    /// `level` or above.
+    pub fn with_backtrace_level(mut self, level: crate::Level) -> Self {
+        self.backtrace = Some(level);
+        self
+    }
+
+    /// Only record events at `level` or above, e.g. [`Level::Warn`](crate::Level::Warn) records warnings and
+    /// errors.  Span events are recorded according to the level of the span.  Spans below `level` still
+    /// appear in the span list of the events inside them.  Default is [`Level::Trace`](crate::Level::Trace),
+    /// which records everything.
+    ///
+    /// This only filters the output of this layer: unlike a
+    /// [`LevelFilter`](tracing_subscriber::filter::LevelFilter), it doesn't disable any callsites, so other
+    /// layers still see every event.  Events below `level` are dropped before their fields are recorded.
+    pub fn with_max_level(mut self, level: crate::Level) -> Self {
+        self.max_level = level;
+        self
+    }
+
+    /// Record fields which are declared without a value, such as [`Empty`](tracing::field::Empty) fields
+    /// or `Option`s which are `None`, as [`FieldValue::Null`](crate::FieldValue::Null).  Null span fields are
+    /// replaced when a value is recorded with [`Span::record`](tracing::Span::record).  Default is to omit
+    /// them.
+    pub fn with_empty_fields_as_null(mut self, enable: bool) -> Self {
+        self.empty_fields_as_null = enable;
+        self
+    }
+
+    /// Truncate fields recorded with their `Debug` implementation, such as `?value` or `%value`, to at most
+    /// `len` bytes, followed by `…`.  Truncation never splits a character.  Fields recorded as strings, and
+    /// the message of events, are not truncated.  Default is no limit.
+    pub fn with_max_debug_len(mut self, len: usize) -> Self {
+        self.max_debug_len = Some(len);
+        self
+    }
+
+    /// Truncate string and `Debug`-formatted field values of events and spans to at most `len` bytes, so a
+    /// single huge value can't blow up the output.  Strings are followed by a suffix such as
+    /// `...(truncated 10482 bytes)`, and `Debug`-formatted values by `...(truncated)`, since formatting stops
+    /// at the limit.  Truncation never splits a character, and the message of events is never truncated.  If
+    /// [`with_max_debug_len`](Self::with_max_debug_len) is shorter, it applies to `Debug`-formatted values
+    /// instead.  Default is no limit.
+    pub fn with_max_field_length(mut self, len: usize) -> Self {
+        self.max_field_len = Some(len);
+        self
+    }
+
+    /// Record the name and version of the crate which produced the events, typically
+    /// `with_crate_info(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))`.  Useful when several programs
+    /// log to the same stream.
+    pub fn with_crate_info(mut self, name: impl Into<String>, version: impl Into<String>) -> Self {
+        self.crate_info = Some((name.into(), version.into()));
+        self
+    }
+
+    /// Only record events and spans whose target starts with one of `targets`, matching whole path
+    /// segments like [`EnvFilter`](tracing_subscriber::EnvFilter), so `hyper` matches `hyper::client`.
+    ///
+    /// When a target matches both included and [excluded](Self::with_exclude_targets) prefixes, the longest
+    /// matching prefix decides, so `with_include_targets(["hyper"])` with `with_exclude_targets(["hyper::proto"])`
+    /// records `hyper::client` but not `hyper::proto::h1`.  Exclusion wins between prefixes of equal length.
+    ///
+    /// As with [`with_max_level`](Self::with_max_level), this only filters the output of this layer, so other
+    /// layers still see every event.  Spans of targets which aren't recorded produce no span events, but still
+    /// appear in the span list of the events inside them.
+    pub fn with_include_targets(mut self, targets: impl IntoIterator<Item = String>) -> Self {
+        self.targets.include(targets);
+        self
+    }
+
+    /// Don't record events and spans whose target starts with one of `targets`.  See
+    /// [`with_include_targets`](Self::with_include_targets) for how targets are matched.
+    pub fn with_exclude_targets(mut self, targets: impl IntoIterator<Item = String>) -> Self {
+        self.targets.exclude(targets);
+        self
+    }
+
+    /// Choose which fields are recorded for events and spans whose target starts with `target_prefix`,
+    /// matching whole path segments, e.g. to drop the full SQL recorded by a database crate.  Can be called
+    /// several times: the policy with the longest matching prefix is used, and calling this again with the
+    /// same prefix replaces its policy.  The empty prefix matches every target.  Fields of other targets are
+    /// all recorded.
+    ///
+    /// Fields which aren't allowed are skipped before they are formatted, and are also left out of
+    /// [`EventKind::SpanRecord`](crate::EventKind::SpanRecord) events and the fields recorded by
+    /// [`with_empty_fields_as_null`](Self::with_empty_fields_as_null).
+    pub fn with_field_policy(
+        mut self,
+        target_prefix: impl Into<String>,
+        policy: FieldPolicy,
+    ) -> Self {
+        self.field_policies.insert(target_prefix.into(), policy);
+        self
+    }
+
+    /// Record thread information (names and thread IDs).
+    ///
+    /// Thread IDs are process-local, like [`ThreadId`](std::thread::ThreadId), rather than the IDs the OS
+    /// gives threads.  With the `thread_id` feature, which requires the Nightly compiler, they are the
+    /// values of the thread's `ThreadId`.  Otherwise, each thread is given the next ID from a counter the
+    /// first time it records an event, so IDs are numbered from 1 in that order.
     pub fn with_thread_info(mut self, names: bool, ids: bool) -> Self {
         self.thread_name = names;
         self.thread_id = ids;
         self
     }
 
-    /// Record the line number and source file of the event .
-    pub fn with_source_location(mut self, include: bool) -> Self {
-        self.source_location = include;
+    /// Record the ID of the process on every event, e.g. to tell apart forked workers writing to the same
+    /// file.  The ID is read once, when the layer is built.  Disabled by default.
+    pub fn with_process_id(mut self, enable: bool) -> Self {
+        self.process_id = enable;
+        self
+    }
+
+    /// Record the hostname of the machine on every event, to tell apart hosts when aggregating logs.  The
+    /// hostname is read once, when the layer is built.  Disabled by default.
+    #[cfg_attr(docsrs, doc(cfg(feature = "hostname")))]
+    #[cfg(feature = "hostname")]
+    pub fn with_hostname(mut self, enable: bool) -> Self {
+        self.hostname = enable;
+        self
+    }
+
+    /// Record `hostname` on every event instead of the machine's hostname, e.g. for containers whose
+    /// hostname is meaningless.  Enables hostname recording, and doesn't need the `hostname` feature.
+    pub fn with_hostname_override(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = true;
+        self.hostname_override = Some(hostname.into());
+        self
+    }
+
+    /// Record the line number and source file of the event or span.  Shorthand for both
+    /// [`with_source_location_events`](Self::with_source_location_events) and
+    /// [`with_source_location_spans`](Self::with_source_location_spans).  Default is `true`.
+    pub fn with_source_location(self, include: bool) -> Self {
+        self.with_source_location_events(include)
+            .with_source_location_spans(include)
+    }
+
+    /// Record the line number and source file of regular events.  Default is `true`.
+    pub fn with_source_location_events(mut self, include: bool) -> Self {
+        self.source_location_events = include;
+        self
+    }
+
+    /// Record the callsite name of regular events (e.g. `"event src/main.rs:42"`), which identifies where
+    /// they were logged even when the source location is not recorded.  Default is `false`.
+    pub fn with_event_names(mut self, include: bool) -> Self {
+        self.event_names = include;
+        self
+    }
+
+    /// Record the module path of the callsite, when it differs from the target, e.g. because the target was
+    /// set with `target: "..."`.  Default is `false`.
+    pub fn with_module_path(mut self, include: bool) -> Self {
+        self.module_path = include;
+        self
+    }
+
+    /// Stamp each event with a sequence number from a process-wide counter, so events with equal timestamps,
+    /// e.g. from different threads, can still be put in order.  Numbers are unique across all layers, and
+    /// increase with the order in which events were emitted, but may have gaps where events were dropped or
+    /// recorded by another layer.  See [`sequence_number`](crate::sequence_number).  Default is `false`.
+    pub fn with_sequence_numbers(mut self, enable: bool) -> Self {
+        self.sequence_numbers = enable;
+        self
+    }
+
+    /// Record a unique ID of the given kind on every event, e.g. to deduplicate events when they are
+    /// ingested more than once.  IDs start with a timestamp and increase within the process, so they sort
+    /// roughly by time.  Disabled by default.
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "uuid", feature = "ulid"))))]
+    #[cfg(any(feature = "uuid", feature = "ulid"))]
+    pub fn with_event_id(mut self, kind: IdKind) -> Self {
+        self.event_ids = Some(EventIds::new(kind));
+        self
+    }
+
+    /// Record the most verbose level enabled when the layer is attached to a subscriber in the
+    /// [`Preamble`](crate::writer::Preamble), as [`max_level`](crate::writer::Preamble::max_level), to help
+    /// explain missing events.  This combines [`with_max_level`](Self::with_max_level) with the
+    /// [`max_level_hint`](tracing::Subscriber::max_level_hint) of the subscriber the layer is added to,
+    /// so only filters beneath this layer are taken into account.
+    ///
+    /// The preamble is handed to the writer when the layer is attached, rather than when it is built.
+    /// Disabled by default.
+    pub fn with_max_level_snapshot(mut self, enable: bool) -> Self {
+        self.max_level_snapshot = enable;
+        self
+    }
+
+    /// Record the line number and source file of the span on synthesised span events, such as
+    /// [`EventKind::SpanCreate`](crate::EventKind::SpanCreate).  Spans usually dominate the output when span
+    /// events are enabled, so disabling this can save a lot of space.  Default is `true`.
+    pub fn with_source_location_spans(mut self, include: bool) -> Self {
+        self.source_location_spans = include;
         self
     }
 
@@ -218,10 +1115,49 @@ where
         SerdeLayerBuilder {
             thread_name: self.thread_name,
             thread_id: self.thread_id,
-            source_location: self.source_location,
+            source_location_events: self.source_location_events,
+            source_location_spans: self.source_location_spans,
             span_events: self.span_events,
             time_spans: self.time_spans,
             span_ids: self.span_ids,
+            span_fields_on_create_only: self.span_fields_on_create_only,
+            inherit_span_fields: self.inherit_span_fields,
+            inherited_field_prefix: self.inherited_field_prefix,
+            uptime: self.uptime,
+            span_records: self.span_records,
+            span_follows_from: self.span_follows_from,
+            syslog: self.syslog,
+            instance_id: self.instance_id,
+            missing_time: self.missing_time,
+            trace_sampling: self.trace_sampling,
+            event_sampling: self.event_sampling,
+            always_keep_errors: self.always_keep_errors,
+            budget: self.budget,
+            backtrace: self.backtrace,
+            max_level: self.max_level,
+            empty_fields_as_null: self.empty_fields_as_null,
+            max_debug_len: self.max_debug_len,
+            max_field_len: self.max_field_len,
+            crate_info: self.crate_info,
+            targets: self.targets,
+            field_policies: self.field_policies,
+            span_field_names: self.span_field_names,
+            span_time_increments: self.span_time_increments,
+            first_in_span: self.first_in_span,
+            omit_empty: self.omit_empty,
+            global_fields: self.global_fields,
+            sanitize_strings: self.sanitize_strings,
+            filter_diagnostics: self.filter_diagnostics,
+            process_id: self.process_id,
+            hostname: self.hostname,
+            hostname_override: self.hostname_override,
+            event_names: self.event_names,
+            module_path: self.module_path,
+            max_level_snapshot: self.max_level_snapshot,
+            span_list: self.span_list,
+            max_span_depth: self.max_span_depth,
+            sequence_numbers: self.sequence_numbers,
+            event_ids: self.event_ids,
             writer: self.writer,
             fmt,
             clock: self.clock,
@@ -236,16 +1172,110 @@ where
             };
         }
 
+        let span_ids = if self.span_fields_on_create_only {
+            SpanIdMode::Always
+        } else {
+            self.span_ids
+        };
+
+        let time_spans = self.time_spans || self.span_time_increments.is_some();
+
+        let preamble = Preamble {
+            thread_names: self.thread_name,
+            thread_ids: self.thread_id,
+            span_ids,
+            span_fields_on_create_only: self.span_fields_on_create_only,
+            source_location: self.source_location_events || self.source_location_spans,
+            timestamps: self.clock.has_timestamps(),
+            uptime: self.uptime,
+            time_spans,
+            span_records: self.span_records,
+            span_follows_from: self.span_follows_from,
+            event_sample_one_in: self.event_sampling.map(|(one_in, _)| one_in),
+            trace_sampling: self.trace_sampling.is_some(),
+            syslog: self.syslog.is_some(),
+            sequence_numbers: self.sequence_numbers,
+            event_ids: self.event_ids.is_some(),
+            max_level: None,
+        };
+        let pending_preamble = if self.max_level_snapshot {
+            Some(preamble)
+        } else {
+            self.writer.serialize_preamble(&self.fmt, &preamble);
+            None
+        };
+
         SerdeLayer {
-            span_ids: self.span_ids,
-            record_span_create: bit_is_set!(self.span_events, SpanEvents::NEW),
-            record_span_close: bit_is_set!(self.span_events, SpanEvents::CLOSE) || self.time_spans,
+            span_ids,
+            span_fields_on_create_only: self.span_fields_on_create_only,
+            inherit_span_fields: self.inherit_span_fields,
+            inherited_field_prefix: self.inherited_field_prefix,
+            start: if self.uptime {
+                Some(Instant::now())
+            } else {
+                None
+            },
+            trace_sampling: self
+                .trace_sampling
+                .map(|(keep, seed)| Sampler::new(keep, seed)),
+            event_sampling: self
+                .event_sampling
+                .map(|(one_in, seed)| Sampler::new(1.0 / one_in as f64, seed)),
+            sampled_out: AtomicU64::new(0),
+            always_keep_errors: self.always_keep_errors,
+            budget: self.budget.map(BudgetTracker::new),
+            backtrace: self.backtrace,
+            max_level: self.max_level,
+            empty_fields_as_null: self.empty_fields_as_null,
+            max_debug_len: self.max_debug_len,
+            max_field_len: self.max_field_len,
+            crate_info: self.crate_info,
+            targets: self.targets,
+            field_policies: self.field_policies,
+            span_field_names: self.span_field_names,
+            span_time_increments: self.span_time_increments,
+            first_in_span: self.first_in_span,
+            omit_empty: self.omit_empty,
+            global_fields: self.global_fields,
+            sanitize_strings: self.sanitize_strings,
+            diagnostics: if self.filter_diagnostics {
+                Some(FilterDiagnostics::default())
+            } else {
+                None
+            },
+            pid: if self.process_id {
+                Some(std::process::id())
+            } else {
+                None
+            },
+            host: if self.hostname {
+                self.hostname_override.or_else(machine_hostname)
+            } else {
+                None
+            },
+            event_names: self.event_names,
+            module_path: self.module_path,
+            span_list: self.span_list,
+            max_span_depth: self.max_span_depth,
+            sequence_numbers: self.sequence_numbers,
+            event_ids: self.event_ids,
+            pending_preamble,
+            syslog: self.syslog,
+            instance_id: self.instance_id,
+            missing_time: self.missing_time,
+            last_time: AtomicU64::new(0),
+            record_span_create: bit_is_set!(self.span_events, SpanEvents::NEW)
+                || self.span_fields_on_create_only,
+            record_span_close: bit_is_set!(self.span_events, SpanEvents::CLOSE) || time_spans,
+            record_span_record: self.span_records,
+            record_span_follows_from: self.span_follows_from,
             record_span_enter: bit_is_set!(self.span_events, SpanEvents::ENTER),
             record_span_exit: bit_is_set!(self.span_events, SpanEvents::EXIT),
             thread_id: self.thread_id,
             thread_name: self.thread_name,
-            source_location: self.source_location,
-            time_spans: self.time_spans,
+            source_location_events: self.source_location_events,
+            source_location_spans: self.source_location_spans,
+            time_spans,
             writer: self.writer,
             clock: self.clock,
             fmt: self.fmt,
@@ -259,7 +1289,107 @@ where
     C: Clock,
     W: WriteEvent,
 {
-    fn emit_event<'a>(&self, meta: &Metadata<'a>, spans: Spans<'a>, e: EventKind<'a>) {
+    /// Check that the writer can currently accept events, see [`WriteEvent::healthcheck`].  Useful as a
+    /// startup self-test, or from a readiness probe.
+    pub fn healthcheck(&self) -> io::Result<()> {
+        self.writer.healthcheck()
+    }
+
+    /// The number of events dropped by [`SerdeLayerBuilder::with_event_sampling`].  Once the layer is
+    /// installed, it can be reached with [`Dispatch::downcast_ref`](tracing::Dispatch::downcast_ref).
+    pub fn sampled_out_events(&self) -> u64 {
+        self.sampled_out.load(Ordering::Relaxed)
+    }
+
+    /// The per-callsite counts of [`SerdeLayerBuilder::with_filter_diagnostics`], or nothing if it is disabled.
+    pub fn diagnostics(&self) -> Vec<CallsiteDiag> {
+        self.diagnostics
+            .as_ref()
+            .map_or_else(Vec::new, FilterDiagnostics::snapshot)
+    }
+
+    /// Write the counts of [`SerdeLayerBuilder::with_filter_diagnostics`] now, as one [`Level::Info`](crate::Level::Info)
+    /// event per callsite with the target `"tracing_subscriber_serde::diagnostics"`.  Each event has the fields
+    /// `callsite_target`, `callsite_file` and `callsite_line`, `seen`, and a `suppressed_<reason>` count for
+    /// each [`SuppressReason`], such as `suppressed_event_sampling`.  Call this before the program exits to
+    /// record the counts at shutdown.  Does nothing if diagnostics are disabled.
+    pub fn emit_diagnostics(&self) {
+        let _guard = match WriteGuard::enter() {
+            Some(guard) => guard,
+            None => return,
+        };
+        for diag in self.diagnostics() {
+            let mut fields = EventFields::new();
+            fields.push((
+                "callsite_target",
+                FieldValue::Str(diag.target.as_str().into()),
+            ));
+            if let Some(file) = &diag.file {
+                fields.push(("callsite_file", FieldValue::Str(file.as_str().into())));
+            }
+            if let Some(line) = diag.line {
+                fields.push(("callsite_line", FieldValue::Int(line.into())));
+            }
+            fields.extend(
+                diag.fields()
+                    .map(|(name, n)| (name, FieldValue::from_u128(n.into()))),
+            );
+            let event = self.synthetic_event(crate::Level::Info, DIAGNOSTICS_TARGET, fields);
+            let _ = self.writer.write_event(
+                &EventInfo::from_event(&event),
+                &self.fmt,
+                self.serializable(&event),
+            );
+        }
+    }
+
+    fn is_enabled(&self, meta: &Metadata<'_>) -> bool {
+        self.filtered_by(meta).is_none()
+    }
+
+    /// The reason this layer's level and target filters drop events of `meta`, if they do.
+    fn filtered_by(&self, meta: &Metadata<'_>) -> Option<SuppressReason> {
+        if crate::Level::from(*meta.level()) < self.max_level {
+            Some(SuppressReason::Level)
+        } else if !self.targets.enabled(meta.target()) {
+            Some(SuppressReason::Target)
+        } else {
+            None
+        }
+    }
+
+    fn suppressed(&self, meta: &Metadata<'_>, reason: SuppressReason) {
+        if let Some(diagnostics) = &self.diagnostics {
+            diagnostics.suppressed(meta, reason);
+        }
+    }
+
+    /// Write an event, unless it is dropped.  Returns whether the writer accepted it.
+    fn emit_event<'a>(&self, meta: &Metadata<'a>, mut spans: Spans<'a>, e: EventKind<'a>) -> bool {
+        if !self.is_enabled(meta) {
+            return false;
+        }
+        let _guard = match WriteGuard::enter() {
+            Some(guard) => guard,
+            None => {
+                if matches!(e, EventKind::Event(_) | EventKind::EventWithInherited(..)) {
+                    self.suppressed(meta, SuppressReason::Reentrant);
+                }
+                return false;
+            }
+        };
+        if self.span_ids == SpanIdMode::LifecycleOnly {
+            let lifecycle = matches!(
+                e,
+                EventKind::SpanCreate
+                    | EventKind::SpanClose(_)
+                    | EventKind::SpanProgress(_)
+                    | EventKind::SpanRecord(_)
+                    | EventKind::SpanFollowsFrom { .. }
+            );
+            spans.strip_ids(lifecycle);
+        }
+
         let thread = std::thread::current();
 
         let thread_name = thread
@@ -267,7 +1397,13 @@ where
             .map(Cow::Borrowed)
             .unwrap_or_else(|| Cow::Owned(format!("{:?}", thread.id())));
 
-        let (src_file, src_line) = if self.source_location {
+        let is_event = matches!(e, EventKind::Event(_) | EventKind::EventWithInherited(..));
+        let source_location = if is_event {
+            self.source_location_events
+        } else {
+            self.source_location_spans
+        };
+        let (src_file, src_line) = if source_location {
             (meta.file(), meta.line())
         } else {
             (None, None)
@@ -280,7 +1416,11 @@ where
             None
         };
         #[cfg(not(feature = "thread_id"))]
-        let thread_id = None;
+        let thread_id = if self.thread_id {
+            Some(THREAD_ID.with(|id| *id))
+        } else {
+            None
+        };
 
         let thread_name = if self.thread_name {
             Some(thread_name.as_ref())
@@ -288,34 +1428,415 @@ where
             None
         };
 
+        let level = (*meta.level()).into();
+        // Timestamp on the logging thread, so writers which defer I/O (e.g. `NonBlocking`) can't skew it
+        let (time, time_missing) = self.time();
         let event = Event {
-            level: (*meta.level()).into(),
+            level,
             kind: e,
             spans,
             target: meta.target(),
             src_file,
             src_line,
-            time: self.clock.time(),
+            time,
+            uptime_ns: self.start.map(|t| t.elapsed().as_nanos() as u64),
+            syslog_pri: self.syslog.map(|f| f.pri(level)),
+            instance_id: self.instance_id,
+            time_missing,
+            crate_name: self.crate_info.as_ref().map(|(name, _)| name.as_str()),
+            crate_version: self
+                .crate_info
+                .as_ref()
+                .map(|(_, version)| version.as_str()),
+            globals: &self.global_fields,
+            pid: self.pid,
+            host: self.host.as_deref(),
+            name: if self.event_names && is_event {
+                Some(meta.name())
+            } else {
+                None
+            },
+            module: if self.module_path {
+                meta.module_path().filter(|&m| m != meta.target())
+            } else {
+                None
+            },
+            seq: self.next_seq(),
+            id: self.event_ids.as_ref().map(EventIds::next),
             thread_id,
             thread_name,
         };
 
+        if let Some(budget) = &self.budget {
+            if let Some(dropped) = budget.roll_over() {
+                self.emit_budget_summary(budget, dropped);
+            }
+            let len = serialized_len(&self.fmt, self.serializable(&event)).unwrap_or(0);
+            if !budget.admit(level, len) {
+                if matches!(
+                    event.kind,
+                    EventKind::Event(_) | EventKind::EventWithInherited(..)
+                ) {
+                    self.suppressed(meta, SuppressReason::Budget);
+                }
+                return false;
+            }
+        }
+
         // If users want their errors handled they can choose themselves
         // using a wrapper type over their chosen WriteRecord
-        let _ = self.writer.write(&self.fmt, &event);
+        self.writer
+            .write_event(
+                &EventInfo::from_event(&event),
+                &self.fmt,
+                self.serializable(&event),
+            )
+            .is_ok()
+    }
+
+    /// Emit the number of events dropped by `budget` during the last interval, indexed by level.
+    fn emit_budget_summary(&self, budget: &BudgetTracker, dropped: [u64; 5]) {
+        let names = [
+            "dropped_trace",
+            "dropped_debug",
+            "dropped_info",
+            "dropped_warn",
+            "dropped_error",
+        ];
+        let mut fields: EventFields = smallvec::smallvec![(
+            "message",
+            FieldValue::Str("log budget exceeded, events were dropped".into())
+        )];
+        fields.extend(
+            names
+                .iter()
+                .zip(&dropped)
+                .map(|(&name, &n)| (name, FieldValue::Int(n as i64))),
+        );
+
+        let event = self.synthetic_event(crate::Level::Warn, BUDGET_TARGET, fields);
+        budget.spend(serialized_len(&self.fmt, self.serializable(&event)).unwrap_or(0));
+        let _ = self.writer.write_event(
+            &EventInfo::from_event(&event),
+            &self.fmt,
+            self.serializable(&event),
+        );
+    }
+
+    fn next_seq(&self) -> Option<u64> {
+        if self.sequence_numbers {
+            Some(SEQUENCE.fetch_add(1, Ordering::Relaxed))
+        } else {
+            None
+        }
+    }
+
+    /// An event produced by the layer itself, outside of any span.
+    fn synthetic_event<'a>(
+        &'a self,
+        level: crate::Level,
+        target: &'static str,
+        fields: EventFields<'a>,
+    ) -> Event<'a, 'a> {
+        let (time, time_missing) = self.time();
+        Event {
+            uptime_ns: self.start.map(|t| t.elapsed().as_nanos() as u64),
+            syslog_pri: self.syslog.map(|f| f.pri(level)),
+            instance_id: self.instance_id,
+            time_missing,
+            crate_name: self.crate_info.as_ref().map(|(name, _)| name.as_str()),
+            crate_version: self
+                .crate_info
+                .as_ref()
+                .map(|(_, version)| version.as_str()),
+            globals: &self.global_fields,
+            pid: self.pid,
+            host: self.host.as_deref(),
+            seq: self.next_seq(),
+            id: self.event_ids.as_ref().map(EventIds::next),
+            ..Event::synthetic(level, target, EventKind::Event(fields), time)
+        }
+    }
+
+    fn serializable<'e, 'a, 'b>(&self, event: &'e Event<'a, 'b>) -> SerializeEvent<'e, 'a, 'b> {
+        SerializeEvent {
+            event,
+            omit_empty: self.omit_empty,
+            sanitize: self.sanitize_strings,
+        }
+    }
+
+    /// The timestamp of an event, and whether it is missing, according to the [`MissingTime`] policy.
+    fn time(&self) -> (Option<UnixTime>, Option<bool>) {
+        let time = self.clock.time();
+        match (self.missing_time, time) {
+            (MissingTime::Omit, time) => (time, None),
+            (MissingTime::Mark, None) => (None, Some(true)),
+            (MissingTime::Mark, time) => (time, None),
+            (MissingTime::CarryForward, Some(time)) => {
+                let nanos = Duration::from(time.clone()).as_nanos() as u64;
+                self.last_time.fetch_max(nanos, Ordering::Relaxed);
+                (Some(time), None)
+            }
+            (MissingTime::CarryForward, None) => match self.last_time.load(Ordering::Relaxed) {
+                0 => (None, None),
+                nanos => (Some(UnixTime::from(Duration::from_nanos(nanos))), None),
+            },
+        }
+    }
+
+    /// The spans to record on events in `span`, or on the lifecycle events of `span`: all of them from the
+    /// root, or only `span` itself.  See [`SerdeLayerBuilder::with_span_list`].
+    fn spans_of<'a, R>(&self, span: &SpanRef<'_, R>) -> Spans<'a>
+    where
+        R: for<'l> LookupSpan<'l>,
+    {
+        if self.span_list {
+            Spans::of_span(span, !self.span_fields_on_create_only, self.max_span_depth)
+        } else {
+            Spans::innermost(span, !self.span_fields_on_create_only)
+        }
+    }
+
+    /// The spans to record when leaving `span`, which is no longer the current span.
+    fn leave_spans<'a, R, S>(&self, ctx: &'a Context<'_, S>, span: &SpanRef<'a, R>) -> Spans<'a>
+    where
+        R: for<'l> LookupSpan<'l>,
+        S: Subscriber + for<'l> LookupSpan<'l>,
+    {
+        if !self.span_list {
+            Spans::innermost(span, !self.span_fields_on_create_only)
+        } else if ctx.lookup_current().map(|s| s.id()) == span.parent().map(|s| s.id()) {
+            // Spans are usually left in order, so the current span is the parent of `span`
+            self.span_chain(span)
+        } else {
+            build_leave_span(
+                ctx,
+                span,
+                !self.span_fields_on_create_only,
+                self.parent_depth(),
+            )
+        }
+    }
+
+    /// The depth limit for the ancestors of a span, which leaves room for the span itself.
+    fn parent_depth(&self) -> Option<usize> {
+        self.max_span_depth.map(|depth| depth - 1)
+    }
+
+    /// The spans from the root to `span`, inclusive, as cached in its extensions for enter and exit events.
+    /// Async tasks enter and exit their spans every time they are polled, so the list is only rebuilt after
+    /// the fields of `span` or one of its ancestors are recorded.
+    fn span_chain<'a, R>(&self, span: &SpanRef<'_, R>) -> Spans<'a>
+    where
+        R: for<'l> LookupSpan<'l>,
+    {
+        let with_fields = !self.span_fields_on_create_only;
+        let max_depth = self.max_span_depth;
+        {
+            let ext = span.extensions();
+            if let Some(chain) = ext.get::<SpanChain>() {
+                if chain.with_fields == with_fields
+                    && chain.max_depth == max_depth
+                    && chain.is_current(fields_version(&ext), span)
+                {
+                    return chain.spans.clone();
+                }
+            }
+        }
+        let spans: Spans<'static> = Spans::of_span(span, with_fields, max_depth);
+        let versions = span
+            .scope()
+            .map(|s| fields_version(&s.extensions()))
+            .collect();
+        span.extensions_mut().replace(SpanChain {
+            versions,
+            with_fields,
+            max_depth,
+            spans: spans.clone(),
+        });
+        spans
+    }
+
+    fn emit_span_progress<R>(&self, span: &SpanRef<'_, R>, times: SpanTime)
+    where
+        R: for<'l> LookupSpan<'l>,
+    {
+        if Sampled::of(span).visible() {
+            let spans = self.spans_of(span);
+            self.emit_event(span.metadata(), spans, EventKind::SpanProgress(times));
+        }
+    }
+
+    /// Emit the time increments which are due of `span` and its ancestors, as an event inside them occurs, so
+    /// spans which stay entered are followed too.
+    fn emit_due_increments<R>(&self, span: &SpanRef<'_, R>, interval: Duration)
+    where
+        R: for<'l> LookupSpan<'l>,
+    {
+        let now = self.clock.instant();
+        for s in span.scope() {
+            let progress = s
+                .extensions_mut()
+                .get_mut::<SpanTimer>()
+                .and_then(|t| t.increment(now, interval));
+            if let Some(times) = progress {
+                self.emit_span_progress(&s, times);
+            }
+        }
+    }
+
+    /// Emit the creation of `span` and its ancestors, if they were dropped by trace sampling.  Their
+    /// remaining lifecycle events will no longer be dropped.
+    fn emit_trace_context<R>(&self, span: &SpanRef<'_, R>)
+    where
+        R: for<'l> LookupSpan<'l>,
+    {
+        for s in span.scope().from_root() {
+            let emit = match s.extensions_mut().get_mut::<Sampled>() {
+                Some(sampled) if !sampled.visible() => {
+                    sampled.context_emitted = true;
+                    true
+                }
+                _ => false,
+            };
+
+            if emit && self.record_span_create {
+                let mut spans = match s.parent() {
+                    Some(parent) if self.span_list => Spans::of_span(
+                        &parent,
+                        !self.span_fields_on_create_only,
+                        self.parent_depth(),
+                    ),
+                    _ => Spans::default(),
+                };
+                spans.append_child(s.extensions().get().expect(PANIC_MSG_SPANS_MISSING));
+                self.emit_event(s.metadata(), spans, EventKind::SpanCreate);
+            }
+        }
     }
 }
 
+/// Marks a span which has had an event recorded in it, stored in the span's extensions.
+struct HadEvent;
+
+const BUDGET_TARGET: &str = "tracing_subscriber_serde::budget";
+const DIAGNOSTICS_TARGET: &str = "tracing_subscriber_serde::diagnostics";
 const PANIC_MSG_SPAN_NOT_FOUND: &'static str = "bug: span not found";
 const PANIC_MSG_SPANS_MISSING: &'static str = "bug: Spans should be in span extensions";
 
-fn build_leave_span<'a, R, S>(ctx: &'a Context<'_, S>, innermost: &SpanRef<'a, R>) -> Spans<'a>
+/// The hostname of the machine, for [`SerdeLayerBuilder::with_hostname`].
+#[cfg(feature = "hostname")]
+fn machine_hostname() -> Option<String> {
+    Some(gethostname::gethostname().to_string_lossy().into_owned())
+}
+
+/// Without the `hostname` feature, only [`SerdeLayerBuilder::with_hostname_override`] records a hostname.
+#[cfg(not(feature = "hostname"))]
+fn machine_hostname() -> Option<String> {
+    None
+}
+
+/// The next thread ID on stable Rust.  See [`SerdeLayerBuilder::with_thread_info`].
+#[cfg(not(feature = "thread_id"))]
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+#[cfg(not(feature = "thread_id"))]
+thread_local! {
+    static THREAD_ID: NonZeroU64 =
+        NonZeroU64::new(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed)).expect("thread ID overflow");
+}
+
+thread_local! {
+    /// Whether this thread is writing an event, see [`WriteGuard`].
+    static WRITING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks the current thread as writing an event until dropped, so events emitted by the writer itself are
+/// dropped rather than re-entering it.  `tracing` only prevents this for scoped dispatchers, not the global
+/// default.  Shared by all layers, so events emitted while writing are dropped by every `SerdeLayer` on the
+/// thread.
+struct WriteGuard;
+
+impl WriteGuard {
+    /// `None` if the thread is already writing an event.
+    fn enter() -> Option<Self> {
+        if WRITING.with(|w| w.replace(true)) {
+            None
+        } else {
+            Some(WriteGuard)
+        }
+    }
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        WRITING.with(|w| w.set(false));
+    }
+}
+
+/// The next sequence number.  See [`SerdeLayerBuilder::with_sequence_numbers`].
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// The sequence number the next event will be stamped with, if it has sequence numbers.  See
+/// [`SerdeLayerBuilder::with_sequence_numbers`].
+pub fn sequence_number() -> u64 {
+    SEQUENCE.load(Ordering::Relaxed)
+}
+
+/// The number of times the fields of a span have been recorded, kept in its extensions to invalidate the
+/// [`SpanChain`]s of the span and its descendants.
+struct FieldsVersion(u64);
+
+fn fields_version(ext: &Extensions<'_>) -> u64 {
+    ext.get::<FieldsVersion>().map_or(0, |v| v.0)
+}
+
+/// The spans from the root to a span, cached in its extensions by [`SerdeLayer::span_chain`].  Only valid
+/// while the [`FieldsVersion`] of every span in the chain matches `versions`, innermost first.
+struct SpanChain {
+    versions: Vec<u64>,
+    with_fields: bool,
+    max_depth: Option<usize>,
+    spans: Spans<'static>,
+}
+
+impl SpanChain {
+    /// Whether no span in the chain has had fields recorded since it was cached.  `own` is the version of the
+    /// innermost span, whose extensions are already borrowed.
+    fn is_current<R>(&self, own: u64, span: &SpanRef<'_, R>) -> bool
+    where
+        R: for<'l> LookupSpan<'l>,
+    {
+        let ancestors = span
+            .scope()
+            .skip(1)
+            .map(|s| fields_version(&s.extensions()));
+        self.versions
+            .iter()
+            .copied()
+            .eq(std::iter::once(own).chain(ancestors))
+    }
+}
+
+fn build_leave_span<'a, R, S>(
+    ctx: &'a Context<'_, S>,
+    innermost: &SpanRef<'a, R>,
+    with_fields: bool,
+    max_depth: Option<usize>,
+) -> Spans<'a>
 where
     R: LookupSpan<'a>,
     S: Subscriber + for<'l> LookupSpan<'l>,
 {
-    let mut s = Spans::current(ctx);
-    s.append_child(innermost.extensions().get().expect(PANIC_MSG_SPANS_MISSING));
+    let mut s = Spans::current(ctx, with_fields, max_depth);
+    let ext = innermost.extensions();
+    let innermost = ext.get().expect(PANIC_MSG_SPANS_MISSING);
+    if with_fields {
+        s.append_child(innermost);
+    } else {
+        s.append_child_name(innermost);
+    }
     s
 }
 
@@ -326,27 +1847,58 @@ where
     W: WriteEvent + 'static,
     S: Subscriber + for<'l> LookupSpan<'l>,
 {
+    fn on_layer(&mut self, subscriber: &mut S) {
+        if let Some(mut preamble) = self.pending_preamble.take() {
+            let own = LevelFilter::from_level(self.max_level.into());
+            let max_level = match subscriber.max_level_hint() {
+                Some(hint) => hint.min(own),
+                None => own,
+            };
+            preamble.max_level = Some(max_level.to_string());
+            self.writer.serialize_preamble(&self.fmt, &preamble);
+        }
+    }
+
     fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
         let s = ctx.span(id).expect(PANIC_MSG_SPAN_NOT_FOUND);
+        let sampled = self.trace_sampling.as_ref().map(|sampler| Sampled {
+            kept: match s.parent() {
+                Some(parent) => Sampled::of(&parent).kept,
+                None => sampler.sample(),
+            },
+            context_emitted: false,
+        });
         let mut extensions = s.extensions_mut();
         let meta = s.metadata();
-        let mut spanlist = if self.record_span_create {
-            Some(Spans::current(&ctx))
+        let kept = sampled.unwrap_or(Sampled::KEPT).kept;
+        let mut spanlist = if self.record_span_create && kept && self.is_enabled(meta) {
+            Some(if self.span_list {
+                Spans::current(&ctx, !self.span_fields_on_create_only, self.parent_depth())
+            } else {
+                Spans::default()
+            })
         } else {
             None
         };
 
         if extensions.get_mut::<Spans>().is_none() {
             let mut span = Spans::default();
-            let id = if self.span_ids {
+            let id = if self.span_ids != SpanIdMode::Never {
                 Some(id.into_non_zero_u64())
             } else {
                 None
             };
             span.new_span(meta, id);
-            let mut visitor = FieldVisitor(span);
+            let policy = self.field_policies.lookup(meta.target());
+            let mut visitor = FieldVisitor::new(span, self.max_debug_len)
+                .truncated(self.max_field_len)
+                .with_policy(policy)
+                .sanitized(self.sanitize_strings);
             attrs.record(&mut visitor);
-            let span = visitor.finish();
+            let mut span = visitor.finish();
+            if self.empty_fields_as_null {
+                span.fill_empty_fields(meta.fields(), |name| allowed(policy, name));
+            }
             if let Some(ref mut spanlist) = spanlist {
                 spanlist.append_child(&span);
             }
@@ -357,23 +1909,159 @@ where
             }
         }
 
+        if let Some(sampled) = sampled {
+            extensions.insert(sampled);
+        }
+
         if self.time_spans && extensions.get_mut::<SpanTimer>().is_none() {
-            extensions.insert(SpanTimer::new());
+            extensions.insert(SpanTimer::new(self.clock.instant()));
         }
 
-        if let Some(spanlist) = spanlist.take() {
+        if let Some(mut spanlist) = spanlist.take() {
+            if self.span_field_names {
+                spanlist.set_declared_fields(meta.fields());
+            }
             self.emit_event(meta, spanlist, EventKind::SpanCreate);
         }
     }
 
+    /// Notifies this layer that a span with the given ID recorded the given values.
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let s = ctx.span(id).expect(PANIC_MSG_SPAN_NOT_FOUND);
+        let policy = self.field_policies.lookup(s.metadata().target());
+        let mut fields = FieldVisitor::new(EventFields::new(), self.max_debug_len)
+            .truncated(self.max_field_len)
+            .with_policy(policy)
+            .sanitized(self.sanitize_strings);
+        values.record(&mut fields);
+        let fields = fields.finish();
+
+        // Later events in the span should show the recorded values
+        {
+            let mut ext = s.extensions_mut();
+            if let Some(span) = ext.get_mut::<Spans>() {
+                span.merge_with(fields.iter().cloned(), MergePolicy::Overwrite);
+            }
+            match ext.get_mut::<FieldsVersion>() {
+                Some(version) => version.0 += 1,
+                None => ext.insert(FieldsVersion(1)),
+            }
+        }
+
+        // All of the fields may have been left out by a field policy
+        if self.record_span_record && !fields.is_empty() && Sampled::of(&s).visible() {
+            let spans = self.spans_of(&s);
+            self.emit_event(s.metadata(), spans, EventKind::SpanRecord(fields));
+        }
+    }
+
+    /// Notifies this layer that the span with the given ID follows from the span with ID `follows`.
+    fn on_follows_from(&self, id: &Id, follows: &Id, ctx: Context<'_, S>) {
+        if !self.record_span_follows_from {
+            return;
+        }
+        let s = ctx.span(id).expect(PANIC_MSG_SPAN_NOT_FOUND);
+        if Sampled::of(&s).visible() {
+            let spans = self.spans_of(&s);
+            let e = EventKind::SpanFollowsFrom {
+                from: follows.into_non_zero_u64(),
+                to: id.into_non_zero_u64(),
+            };
+            self.emit_event(s.metadata(), spans, e);
+        }
+    }
+
     /// Notifies this layer that an event has occurred.
     fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
         let meta = event.metadata();
-        let spanlist = Spans::current(&ctx);
-        let mut fields = FieldVisitor(EventFields::new());
+        if let Some(interval) = self.span_time_increments {
+            if let Some(span) = ctx.event_span(event) {
+                self.emit_due_increments(&span, interval);
+            }
+        }
+        if let Some(diagnostics) = &self.diagnostics {
+            diagnostics.seen(meta);
+        }
+        if let Some(reason) = self.filtered_by(meta) {
+            self.suppressed(meta, reason);
+            return;
+        }
+        if let Some(sampler) = &self.event_sampling {
+            if !sampler.sample() {
+                self.sampled_out.fetch_add(1, Ordering::Relaxed);
+                self.suppressed(meta, SuppressReason::EventSampling);
+                return;
+            }
+        }
+        // The explicit parent if there is one, otherwise the current span
+        let parent = ctx.event_span(event);
+        if let Some(span) = &parent {
+            if !Sampled::of(span).kept {
+                if self.always_keep_errors
+                    && crate::Level::from(*meta.level()) >= crate::Level::Warn
+                {
+                    self.emit_trace_context(span);
+                } else {
+                    self.suppressed(meta, SuppressReason::TraceSampling);
+                    return;
+                }
+            }
+        }
+        let spanlist = match &parent {
+            Some(span) => self.spans_of(span),
+            None => Spans::default(),
+        };
+        let policy = self.field_policies.lookup(meta.target());
+        let mut fields = FieldVisitor::new(EventFields::new(), self.max_debug_len)
+            .truncated(self.max_field_len)
+            .with_policy(policy)
+            .sanitized(self.sanitize_strings);
         event.record(&mut fields);
-        let e = EventKind::Event(fields.finish());
-        self.emit_event(meta, spanlist, e);
+        let mut fields = fields.finish();
+        if self.empty_fields_as_null {
+            for field in meta.fields() {
+                if allowed(policy, field.name())
+                    && !fields.iter().any(|(name, _)| *name == field.name())
+                {
+                    fields.push((field.name(), FieldValue::Null));
+                }
+            }
+        }
+        if self
+            .backtrace
+            .iter()
+            .any(|&level| crate::Level::from(*meta.level()) >= level)
+        {
+            let backtrace = Backtrace::capture();
+            if backtrace.status() == BacktraceStatus::Captured {
+                fields.push(("backtrace", FieldValue::Str(backtrace.to_string().into())));
+            }
+        }
+        // Only marked once an event has been written, so an event dropped later on doesn't use up the flag
+        let first_in = match &parent {
+            Some(span) if self.first_in_span && span.extensions().get::<HadEvent>().is_none() => {
+                fields.push(("first_in_span", FieldValue::Bool(true)));
+                Some(span)
+            }
+            _ => None,
+        };
+        let e = match self.inherit_span_fields {
+            InheritMode::None => EventKind::Event(fields),
+            mode => {
+                let inherited = InheritedFields::of_span(
+                    parent.as_ref(),
+                    mode,
+                    &self.inherited_field_prefix,
+                    &fields,
+                );
+                EventKind::EventWithInherited(fields, inherited)
+            }
+        };
+        if self.emit_event(meta, spanlist, e) {
+            if let Some(span) = first_in {
+                span.extensions_mut().replace(HadEvent);
+            }
+        }
     }
 
     /// Notifies this layer that a span with the given ID was entered.
@@ -381,14 +2069,26 @@ where
         if self.record_span_enter || self.time_spans {
             let s = ctx.span(&id).expect(PANIC_MSG_SPAN_NOT_FOUND);
 
-            if self.record_span_enter {
-                let spans = Spans::current(&ctx);
+            if self.record_span_enter && Sampled::of(&s).visible() {
+                let spans =
+                    if self.span_list && ctx.lookup_current().map(|c| c.id()) == Some(s.id()) {
+                        self.span_chain(&s)
+                    } else if self.span_list {
+                        Spans::current(&ctx, !self.span_fields_on_create_only, self.max_span_depth)
+                    } else {
+                        Spans::innermost(&s, !self.span_fields_on_create_only)
+                    };
                 self.emit_event(s.metadata(), spans, EventKind::SpanEnter);
             }
 
-            if let Some(t) = s.extensions_mut().get_mut::<SpanTimer>() {
-                t.start_busy();
-            };
+            let now = self.clock.instant();
+            let progress = s.extensions_mut().get_mut::<SpanTimer>().and_then(|t| {
+                t.start_busy(now);
+                t.increment(now, self.span_time_increments?)
+            });
+            if let Some(times) = progress {
+                self.emit_span_progress(&s, times);
+            }
         }
     }
 
@@ -397,14 +2097,19 @@ where
         if self.record_span_exit || self.time_spans {
             let s = ctx.span(id).expect(PANIC_MSG_SPAN_NOT_FOUND);
 
-            if self.record_span_exit {
-                let spans = build_leave_span(&ctx, &s);
+            if self.record_span_exit && Sampled::of(&s).visible() {
+                let spans = self.leave_spans(&ctx, &s);
                 self.emit_event(s.metadata(), spans, EventKind::SpanExit);
             }
 
-            if let Some(t) = s.extensions_mut().get_mut::<SpanTimer>() {
-                t.end_busy();
-            };
+            let now = self.clock.instant();
+            let progress = s.extensions_mut().get_mut::<SpanTimer>().and_then(|t| {
+                t.end_busy(now);
+                t.increment(now, self.span_time_increments?)
+            });
+            if let Some(times) = progress {
+                self.emit_span_progress(&s, times);
+            }
         }
     }
 
@@ -412,9 +2117,21 @@ where
     fn on_close(&self, id: Id, ctx: Context<'_, S>) {
         if self.record_span_close {
             let s = ctx.span(&id).expect(PANIC_MSG_SPAN_NOT_FOUND);
-            let spans = build_leave_span(&ctx, &s);
+            if !Sampled::of(&s).visible() {
+                return;
+            }
+            if self.span_time_increments.is_some() {
+                let remainder = s
+                    .extensions_mut()
+                    .get_mut::<SpanTimer>()
+                    .map(SpanTimer::remainder);
+                if let Some(times) = remainder {
+                    self.emit_span_progress(&s, times);
+                }
+            }
+            let spans = self.leave_spans(&ctx, &s);
             let times = s.extensions().get::<SpanTimer>().map(SpanTimer::finish);
-            self.emit_event(s.metadata(), spans, EventKind::SpanClose(times))
+            self.emit_event(s.metadata(), spans, EventKind::SpanClose(times));
         }
     }
 }
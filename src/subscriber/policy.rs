@@ -0,0 +1,63 @@
+/// Which fields of events and spans of a target are recorded.  See [`SerdeLayerBuilder::with_field_policy`](super::SerdeLayerBuilder::with_field_policy).
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum FieldPolicy {
+    /// Only record the fields with these names.  Include `message` to keep the message of events.
+    AllowOnly(Vec<String>),
+    /// Record all fields except those with these names.
+    Deny(Vec<String>),
+}
+
+impl FieldPolicy {
+    pub(crate) fn allows(&self, name: &str) -> bool {
+        match self {
+            FieldPolicy::AllowOnly(names) => names.iter().any(|n| n == name),
+            FieldPolicy::Deny(names) => !names.iter().any(|n| n == name),
+        }
+    }
+}
+
+/// Whether `policy` allows the field `name`.  Every field is allowed without a policy.
+pub(crate) fn allowed(policy: Option<&FieldPolicy>, name: &str) -> bool {
+    policy.map_or(true, |p| p.allows(name))
+}
+
+/// Field policies, sorted by target prefix.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FieldPolicies(Vec<(String, FieldPolicy)>);
+
+impl FieldPolicies {
+    /// Set the policy for `prefix`, replacing any previous policy for it.
+    pub fn insert(&mut self, prefix: String, policy: FieldPolicy) {
+        match self.0.binary_search_by(|(p, _)| p.as_str().cmp(&prefix)) {
+            Ok(k) => self.0[k].1 = policy,
+            Err(k) => self.0.insert(k, (prefix, policy)),
+        }
+    }
+
+    fn get(&self, prefix: &str) -> Option<&FieldPolicy> {
+        let k = self
+            .0
+            .binary_search_by(|(p, _)| p.as_str().cmp(prefix))
+            .ok()?;
+        Some(&self.0[k].1)
+    }
+
+    /// The policy with the longest prefix of `target`, matching whole path segments.  The empty prefix
+    /// matches every target, as in [`SerdeLayerBuilder::with_include_targets`](super::SerdeLayerBuilder::with_include_targets).
+    pub fn lookup(&self, target: &str) -> Option<&FieldPolicy> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let mut prefix = target;
+        loop {
+            if let Some(policy) = self.get(prefix) {
+                return Some(policy);
+            }
+            match prefix.rfind("::") {
+                Some(end) => prefix = &prefix[..end],
+                None if !prefix.is_empty() => prefix = "",
+                None => return None,
+            }
+        }
+    }
+}
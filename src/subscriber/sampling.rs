@@ -0,0 +1,70 @@
+use crate::rng::splitmix64;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+/// Decides which traces are kept by [`SerdeLayerBuilder::with_trace_sampling`](super::SerdeLayerBuilder::with_trace_sampling),
+/// and which events are kept by [`SerdeLayerBuilder::with_event_sampling`](super::SerdeLayerBuilder::with_event_sampling).
+pub(crate) struct Sampler {
+    threshold: u64,
+    seed: u64,
+    roots: AtomicU64,
+}
+
+impl Sampler {
+    pub fn new(keep: f64, seed: u64) -> Self {
+        let threshold = if keep >= 1.0 {
+            u64::MAX
+        } else if keep > 0.0 {
+            (keep * u64::MAX as f64) as u64
+        } else {
+            0
+        };
+        Sampler {
+            threshold,
+            seed,
+            roots: AtomicU64::new(0),
+        }
+    }
+
+    /// Decide whether to keep the next trace or event.  Span IDs are re-used, so the decision for a trace is
+    /// based on the number of root spans seen so far rather than the ID.
+    pub fn sample(&self) -> bool {
+        if self.threshold == u64::MAX {
+            return true;
+        }
+        let n = self.roots.fetch_add(1, Ordering::Relaxed);
+        splitmix64(self.seed ^ splitmix64(n)) < self.threshold
+    }
+}
+
+/// The sampling decision for a span's trace, stored in the span's extensions.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Sampled {
+    /// Whether the trace was kept by the sampler.
+    pub kept: bool,
+    /// Whether the span's creation has been emitted anyway, as context for an event which bypassed sampling.
+    pub context_emitted: bool,
+}
+
+impl Sampled {
+    pub const KEPT: Sampled = Sampled {
+        kept: true,
+        context_emitted: false,
+    };
+
+    /// The decision for `span`.  Spans without a decision (sampling is disabled) are kept.
+    pub fn of<R>(span: &SpanRef<'_, R>) -> Sampled
+    where
+        R: for<'l> LookupSpan<'l>,
+    {
+        span.extensions()
+            .get::<Sampled>()
+            .copied()
+            .unwrap_or(Sampled::KEPT)
+    }
+
+    /// Whether lifecycle events for the span should be emitted.
+    pub fn visible(&self) -> bool {
+        self.kept || self.context_emitted
+    }
+}
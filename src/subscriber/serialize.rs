@@ -3,6 +3,8 @@ use super::*;
 use crate::Level;
 use serde::ser::{SerializeMap, SerializeSeq};
 use serde::Serializer;
+use std::convert::TryFrom;
+use tracing::field::FieldSet;
 
 #[derive(Clone, Debug, Serialize)]
 #[serde(untagged)]
@@ -10,7 +12,37 @@ pub enum FieldValue {
     Bool(bool),
     Float(f64),
     Int(i64),
+    UInt(u64),
+    #[serde(serialize_with = "crate::event::serialize_i128")]
+    I128(i128),
+    #[serde(serialize_with = "crate::event::serialize_u128")]
+    U128(u128),
     Str(SString),
+    Null,
+}
+
+impl FieldValue {
+    /// The smallest integer variant which can hold `x`, preferring `Int`.
+    pub fn from_u128(x: u128) -> Self {
+        if let Ok(x) = i64::try_from(x) {
+            FieldValue::Int(x)
+        } else if let Ok(x) = u64::try_from(x) {
+            FieldValue::UInt(x)
+        } else {
+            FieldValue::U128(x)
+        }
+    }
+
+    /// The smallest integer variant which can hold `x`, preferring `Int`.
+    pub fn from_i128(x: i128) -> Self {
+        match u128::try_from(x) {
+            Ok(x) => Self::from_u128(x),
+            Err(_) => match i64::try_from(x) {
+                Ok(x) => FieldValue::Int(x),
+                Err(_) => FieldValue::I128(x),
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -18,13 +50,46 @@ pub enum FieldValue {
 pub enum EventKind<'a> {
     #[serde(serialize_with = "serialize_event_fields")]
     Event(EventFields<'a>),
+    /// An event with fields copied from its spans, see [`InheritMode`]
+    #[serde(rename = "event", serialize_with = "serialize_inherited_event_fields")]
+    EventWithInherited(EventFields<'a>, InheritedFields<'a>),
     SpanCreate,
     SpanClose(Option<SpanTime>),
+    SpanProgress(SpanTime),
     SpanEnter,
     SpanExit,
+    #[serde(serialize_with = "serialize_event_fields")]
+    SpanRecord(EventFields<'a>),
+    SpanFollowsFrom {
+        from: NonZeroU64,
+        to: NonZeroU64,
+    },
+}
+
+/// The number of event fields stored without allocating.  Each field takes up 48 bytes, and the fields are
+/// kept on the stack while an event is serialized, so a larger size trades stack space for fewer
+/// allocations.
+#[cfg(not(feature = "inline-fields-16"))]
+pub const INLINE_FIELDS: usize = 8;
+#[cfg(feature = "inline-fields-16")]
+pub const INLINE_FIELDS: usize = 16;
+
+impl EventKind<'_> {
+    /// Whether this is an event without fields which should be left out.  See
+    /// [`with_empty_omitted`](crate::format::with_empty_omitted).
+    fn skip_empty(&self) -> bool {
+        let empty = match self {
+            EventKind::Event(fields) => fields.is_empty(),
+            EventKind::EventWithInherited(fields, inherited) => {
+                fields.is_empty() && inherited.fields.is_empty()
+            }
+            _ => false,
+        };
+        empty && crate::format::omit_empty()
+    }
 }
 
-pub type EventFields<'a> = SmallVec<[(&'a str, FieldValue); 8]>;
+pub type EventFields<'a> = SmallVec<[(&'a str, FieldValue); INLINE_FIELDS]>;
 
 impl<'a> AddFields for EventFields<'a> {
     fn add_field(&mut self, name: &'static str, val: FieldValue) {
@@ -32,37 +97,283 @@ impl<'a> AddFields for EventFields<'a> {
     }
 }
 
+/// Which value is kept when merging fields with the same name.  See [`MergeFields::merge_with`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MergePolicy {
+    /// Keep the existing value, ignoring the new one.
+    KeepExisting,
+    /// Replace the existing value with the new one, keeping its position.
+    Overwrite,
+}
+
+/// A list of fields which are looked up by name.
+pub trait MergeFields<K, V> {
+    /// The value of the field called `name`, if present.
+    fn field_mut(&mut self, name: &K) -> Option<&mut V>;
+
+    /// Append a field.
+    fn push_field(&mut self, name: K, val: V);
+
+    /// Add `other` to these fields.  Fields with names which are already present are resolved with
+    /// `policy`; all other fields are appended in order.
+    fn merge_with(&mut self, other: impl IntoIterator<Item = (K, V)>, policy: MergePolicy) {
+        for (name, val) in other {
+            match self.field_mut(&name) {
+                Some(existing) => {
+                    if policy == MergePolicy::Overwrite {
+                        *existing = val;
+                    }
+                }
+                None => self.push_field(name, val),
+            }
+        }
+    }
+}
+
+fn find_field<'s, K: PartialEq, V>(fields: &'s mut [(K, V)], name: &K) -> Option<&'s mut V> {
+    fields.iter_mut().find(|(n, _)| n == name).map(|(_, v)| v)
+}
+
+impl<K: PartialEq, V> MergeFields<K, V> for Vec<(K, V)> {
+    fn field_mut(&mut self, name: &K) -> Option<&mut V> {
+        find_field(self, name)
+    }
+
+    fn push_field(&mut self, name: K, val: V) {
+        self.push((name, val))
+    }
+}
+
+impl<'a> MergeFields<&'a str, FieldValue> for EventFields<'a> {
+    fn field_mut(&mut self, name: &&'a str) -> Option<&mut FieldValue> {
+        find_field(self, name)
+    }
+
+    fn push_field(&mut self, name: &'a str, val: FieldValue) {
+        self.push((name, val))
+    }
+}
+
+/// Span fields to be added to an event's fields, innermost span first.
+#[derive(Debug, Clone)]
+pub struct InheritedFields<'a> {
+    prefix: &'a str,
+    fields: Vec<(&'a str, FieldValue)>,
+}
+
+impl<'a> InheritedFields<'a> {
+    /// Collect the fields of the event's innermost span (and its parents, if `mode` is [`InheritMode::All`]).
+    /// Fields which are shadowed by an inner span, or which would collide with a field in `event_fields`,
+    /// are skipped.
+    pub fn of_span<R>(
+        innermost: Option<&SpanRef<'_, R>>,
+        mode: InheritMode,
+        prefix: &'a str,
+        event_fields: &EventFields,
+    ) -> Self
+    where
+        R: for<'l> LookupSpan<'l>,
+    {
+        let mut inherited = InheritedFields {
+            prefix,
+            fields: Vec::new(),
+        };
+        let innermost = match innermost {
+            Some(s) => s,
+            None => return inherited,
+        };
+        let max_spans = match mode {
+            InheritMode::None => 0,
+            InheritMode::Innermost => 1,
+            InheritMode::All => usize::MAX,
+        };
+
+        for s in innermost.scope().take(max_spans) {
+            let ext = s.extensions();
+            let span = ext.get::<Spans>().expect(PANIC_MSG_SPANS_MISSING);
+            // Inner spans take precedence
+            let fields = span.0.iter().filter_map(|item| match item {
+                SpanItem::Field { name, val } => Some((*name, val.clone())),
+                _ => None,
+            });
+            inherited
+                .fields
+                .merge_with(fields, MergePolicy::KeepExisting);
+        }
+
+        // The event's own fields take precedence
+        inherited.fields.retain(|(name, _)| {
+            !event_fields
+                .iter()
+                .any(|(n, _)| n.strip_prefix(prefix) == Some(*name))
+        });
+        inherited
+    }
+}
+
+/// A field name with a prefix, serialized as a single string.
+struct PrefixedName<'a>(&'a str, &'a str);
+
+impl Serialize for PrefixedName<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if self.0.is_empty() {
+            serializer.serialize_str(self.1)
+        } else {
+            serializer.collect_str(&format_args!("{}{}", self.0, self.1))
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct Event<'a, 'b> {
     #[serde(rename = "ty")]
+    #[serde(skip_serializing_if = "EventKind::skip_empty")]
     pub kind: EventKind<'a>,
     #[serde(rename = "l")]
     pub level: Level,
     #[serde(rename = "s")]
+    #[serde(skip_serializing_if = "Spans::skip_empty")]
     pub spans: Spans<'a>,
 
     #[serde(rename = "t")]
     pub target: &'a str,
 
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
     #[serde(rename = "tid")]
     pub thread_id: Option<NonZeroU64>,
 
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
     #[serde(rename = "tn")]
     pub thread_name: Option<&'b str>,
 
     #[serde(rename = "srl")]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
     pub src_line: Option<u32>,
 
     #[serde(rename = "srf")]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
     pub src_file: Option<&'a str>,
 
     #[serde(rename = "tm")]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
     pub time: Option<UnixTime>,
+
+    #[serde(rename = "up")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub uptime_ns: Option<u64>,
+
+    #[serde(rename = "pri")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub syslog_pri: Option<u8>,
+
+    #[serde(rename = "in")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub instance_id: Option<u64>,
+
+    #[serde(rename = "tmm")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub time_missing: Option<bool>,
+
+    #[serde(rename = "cr")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub crate_name: Option<&'b str>,
+
+    #[serde(rename = "crv")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub crate_version: Option<&'b str>,
+
+    #[serde(rename = "g")]
+    #[serde(skip_serializing_if = "crate::format::skip_empty")]
+    #[serde(serialize_with = "serialize_globals")]
+    pub globals: &'b [(String, crate::FieldValue)],
+
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub pid: Option<u32>,
+
+    #[serde(rename = "h")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub host: Option<&'b str>,
+
+    #[serde(rename = "n")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub name: Option<&'a str>,
+
+    #[serde(rename = "mp")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub module: Option<&'a str>,
+
+    #[serde(rename = "sq")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub seq: Option<u64>,
+
+    #[serde(rename = "id")]
+    #[serde(skip_serializing_if = "crate::format::skip_none")]
+    pub id: Option<String>,
+}
+
+impl<'a> Event<'a, 'static> {
+    /// An event which wasn't recorded from a callsite, such as a heartbeat or a summary, outside of any span
+    /// and with no optional information.
+    pub fn synthetic(
+        level: Level,
+        target: &'a str,
+        kind: EventKind<'a>,
+        time: Option<UnixTime>,
+    ) -> Self {
+        Event {
+            kind,
+            level,
+            spans: Spans::default(),
+            target,
+            thread_id: None,
+            thread_name: None,
+            src_line: None,
+            src_file: None,
+            time,
+            uptime_ns: None,
+            syslog_pri: None,
+            instance_id: None,
+            time_missing: None,
+            crate_name: None,
+            crate_version: None,
+            globals: &[],
+            pid: None,
+            host: None,
+            name: None,
+            module: None,
+            seq: None,
+            id: None,
+        }
+    }
+}
+
+fn serialize_globals<S>(globals: &&[(String, crate::FieldValue)], s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    s.collect_map(globals.iter().map(|(name, val)| (name, val)))
+}
+
+/// Serializes an [`Event`], leaving out empty fields and spans if `omit_empty` is set, and
+/// [sanitizing](super::sanitize) span names if `sanitize` is set.
+pub struct SerializeEvent<'e, 'a, 'b> {
+    pub event: &'e Event<'a, 'b>,
+    pub omit_empty: bool,
+    pub sanitize: bool,
+}
+
+impl Serialize for SerializeEvent<'_, '_, '_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        crate::format::with_empty_omitted(self.omit_empty, || {
+            crate::format::with_sanitized_names(self.sanitize, || self.event.serialize(serializer))
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -70,6 +381,8 @@ pub enum SpanItem<'a> {
     Start {
         span_name: &'a str,
         id: Option<NonZeroU64>,
+        /// The names of the fields declared by the span, whether or not they have values.
+        declared_fields: Option<&'a FieldSet>,
     },
     Field {
         name: &'a str,
@@ -77,37 +390,61 @@ pub enum SpanItem<'a> {
     },
 }
 
+/// The name of the span which replaces the outer spans left out by
+/// [`SerdeLayerBuilder::with_max_span_depth`](crate::SerdeLayerBuilder::with_max_span_depth).
+pub const TRUNCATED_SPAN: &str = "...";
+
 #[derive(Default, Clone, Debug)]
 pub struct Spans<'a>(Vec<SpanItem<'a>>);
 
 impl<'a> Spans<'a> {
-    pub fn current<S>(ctx: &'a Context<'_, S>) -> Self
+    /// Whether there are no spans and they should be left out.  See
+    /// [`with_empty_omitted`](crate::format::with_empty_omitted).
+    fn skip_empty(&self) -> bool {
+        self.0.is_empty() && crate::format::omit_empty()
+    }
+
+    /// The spans in the current context, from the root.  If `with_fields` is false, only the span names
+    /// and IDs are included.  See [`Spans::from_scope`] for `max_depth`.
+    pub fn current<S>(ctx: &'a Context<'_, S>, with_fields: bool, max_depth: Option<usize>) -> Self
     where
         S: Subscriber + for<'l> LookupSpan<'l>,
     {
-        let mut spans = ctx
-            .lookup_current()
-            .into_iter()
-            .map(|s| s.scope().from_root())
-            .flatten();
-
-        let mut spanlist = match spans.next() {
-            Some(s) => s
-                .extensions()
-                .get::<Spans>()
-                .expect(PANIC_MSG_SPANS_MISSING)
-                .clone(),
-            None => return Self::default(),
-        };
-
-        for s in spans {
-            spanlist.append_child(
-                s.extensions()
-                    .get::<Spans>()
-                    .expect(PANIC_MSG_SPANS_MISSING),
-            );
+        match ctx.lookup_current() {
+            Some(s) => Self::from_scope(s.scope(), with_fields, max_depth),
+            None => Self::default(),
         }
+    }
+
+    /// The spans in `scope`, from the root.  Only the innermost `max_depth` spans are looked up, and the
+    /// outer spans are replaced by a single [`TRUNCATED_SPAN`] without fields or ID.
+    fn from_scope<'s, R>(scope: Scope<'s, R>, with_fields: bool, max_depth: Option<usize>) -> Self
+    where
+        R: LookupSpan<'s>,
+    {
+        let mut scope = scope;
+        let innermost: SmallVec<[SpanRef<'s, R>; 16]> = scope
+            .by_ref()
+            .take(max_depth.unwrap_or(usize::MAX))
+            .collect();
 
+        let mut spanlist = Self::default();
+        if scope.next().is_some() {
+            spanlist.0.push(SpanItem::Start {
+                span_name: TRUNCATED_SPAN,
+                id: None,
+                declared_fields: None,
+            });
+        }
+        for s in innermost.iter().rev() {
+            let ext = s.extensions();
+            let child = ext.get::<Spans>().expect(PANIC_MSG_SPANS_MISSING);
+            if with_fields {
+                spanlist.append_child(child);
+            } else {
+                spanlist.append_child_name(child);
+            }
+        }
         spanlist
     }
 
@@ -116,6 +453,7 @@ impl<'a> Spans<'a> {
         self.0.push(SpanItem::Start {
             span_name: span_meta.name(),
             id: span_id,
+            declared_fields: None,
         });
     }
 
@@ -123,7 +461,82 @@ impl<'a> Spans<'a> {
         self.0.extend_from_slice(&child.0)
     }
 
-    #[allow(dead_code)]
+    /// The spans from the root to `span`, inclusive.  If `with_fields` is false, only the span names and IDs
+    /// are included.  See [`Spans::from_scope`] for `max_depth`.
+    pub fn of_span<R>(span: &SpanRef<'_, R>, with_fields: bool, max_depth: Option<usize>) -> Self
+    where
+        R: for<'l> LookupSpan<'l>,
+    {
+        Self::from_scope(span.scope(), with_fields, max_depth)
+    }
+
+    /// Only `span` itself, without its ancestors.  If `with_fields` is false, only the span name and ID are
+    /// included.
+    pub fn innermost<R>(span: &SpanRef<'_, R>, with_fields: bool) -> Self
+    where
+        R: for<'l> LookupSpan<'l>,
+    {
+        let mut spanlist = Self::default();
+        let ext = span.extensions();
+        let child = ext.get::<Spans>().expect(PANIC_MSG_SPANS_MISSING);
+        if with_fields {
+            spanlist.append_child(child);
+        } else {
+            spanlist.append_child_name(child);
+        }
+        spanlist
+    }
+
+    /// Record the fields in `fields` which the innermost span has no value for as [`FieldValue::Null`].
+    pub fn fill_empty_fields(&mut self, fields: &FieldSet, allowed: impl Fn(&str) -> bool) {
+        for field in fields.iter().filter(|f| allowed(f.name())) {
+            let recorded = self
+                .0
+                .iter()
+                .rev()
+                .take_while(|item| matches!(item, SpanItem::Field { .. }))
+                .any(|item| matches!(item, SpanItem::Field { name, .. } if *name == field.name()));
+            if !recorded {
+                self.0.push(SpanItem::Field {
+                    name: field.name(),
+                    val: FieldValue::Null,
+                });
+            }
+        }
+    }
+
+    /// Record the names of the fields declared by the innermost span.
+    pub fn set_declared_fields(&mut self, fields: &'a FieldSet) {
+        let start = self.0.iter_mut().rev().find_map(|item| match item {
+            SpanItem::Start {
+                declared_fields, ..
+            } => Some(declared_fields),
+            _ => None,
+        });
+        if let Some(declared_fields) = start {
+            *declared_fields = Some(fields);
+        }
+    }
+
+    /// Append the child's name and ID, but not its fields.
+    pub fn append_child_name(&mut self, child: &Self) {
+        self.0.extend(child.0.first().cloned())
+    }
+
+    /// Remove the IDs of all spans, except the innermost span if `keep_innermost` is true.
+    pub fn strip_ids(&mut self, keep_innermost: bool) {
+        let mut starts = self.0.iter_mut().filter_map(|item| match item {
+            SpanItem::Start { id, .. } => Some(id),
+            _ => None,
+        });
+        if keep_innermost {
+            starts.next_back();
+        }
+        for id in starts {
+            *id = None;
+        }
+    }
+
     pub fn as_items(&self) -> &[SpanItem] {
         &*self.0
     }
@@ -135,6 +548,24 @@ impl<'a> AddFields for Spans<'a> {
     }
 }
 
+/// The fields of the innermost span, e.g. to update the fields of a single span as stored in its extensions.
+impl<'a> MergeFields<&'a str, FieldValue> for Spans<'a> {
+    fn field_mut(&mut self, name: &&'a str) -> Option<&mut FieldValue> {
+        self.0
+            .iter_mut()
+            .rev()
+            .take_while(|item| matches!(item, SpanItem::Field { .. }))
+            .find_map(|item| match item {
+                SpanItem::Field { name: n, val } if n == name => Some(val),
+                _ => None,
+            })
+    }
+
+    fn push_field(&mut self, name: &'a str, val: FieldValue) {
+        self.0.push(SpanItem::Field { name, val });
+    }
+}
+
 fn serialize_event_fields<S>(fields: &EventFields, s: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -146,6 +577,24 @@ where
     m.end()
 }
 
+fn serialize_inherited_event_fields<S>(
+    fields: &EventFields,
+    inherited: &InheritedFields,
+    s: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut m = s.serialize_map(Some(fields.len() + inherited.fields.len()))?;
+    for (field, val) in fields {
+        m.serialize_entry(field, val)?;
+    }
+    for (field, val) in &inherited.fields {
+        m.serialize_entry(&PrefixedName(inherited.prefix, field), val)?;
+    }
+    m.end()
+}
+
 struct SerializeSpanFields<'a>(&'a [SpanItem<'a>]);
 
 impl Serialize for SerializeSpanFields<'_> {
@@ -164,6 +613,17 @@ impl Serialize for SerializeSpanFields<'_> {
     }
 }
 
+struct SerializeFieldNames<'a>(&'a FieldSet);
+
+impl Serialize for SerializeFieldNames<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.0.iter().map(|f| f.name()))
+    }
+}
+
 struct SerializeSpan<'a>(&'a [SpanItem<'a>]);
 
 impl Serialize for SerializeSpan<'_> {
@@ -171,20 +631,30 @@ impl Serialize for SerializeSpan<'_> {
     where
         S: Serializer,
     {
-        let mut m = serializer.serialize_map(Some(3))?;
-        let name = &(self.0)[0];
+        let (span_name, id, declared_fields) = match &(self.0)[0] {
+            SpanItem::Start {
+                span_name,
+                id,
+                declared_fields,
+            } => (span_name, id, declared_fields),
+            _ => unreachable!(),
+        };
         let fields = SerializeSpanFields(&(self.0)[1..]);
 
-        match name {
-            SpanItem::Start { span_name, id } => {
-                m.serialize_entry("n", span_name)?;
-                if let Some(id) = id {
-                    m.serialize_entry("i", id)?;
-                }
-            }
-            _ => unreachable!(),
+        let len = 2 + id.is_some() as usize + declared_fields.is_some() as usize;
+        let mut m = serializer.serialize_map(Some(len))?;
+        if crate::format::sanitize_names() {
+            m.serialize_entry("n", &super::sanitize(span_name))?;
+        } else {
+            m.serialize_entry("n", span_name)?;
+        }
+        if let Some(id) = id {
+            m.serialize_entry("i", id)?;
         }
         m.serialize_entry("f", &fields)?;
+        if let Some(declared_fields) = declared_fields {
+            m.serialize_entry("fd", &SerializeFieldNames(declared_fields))?;
+        }
         m.end()
     }
 }
@@ -221,12 +691,8 @@ mod tests {
     use crate::consumer::*;
     use crate::test_utils::*;
 
-    // TODO: should probably fuzz this
-    fn serde_borrowed_to_owned<F>(fmt: F)
-    where
-        F: SerdeFormat + for<'a> StreamFormat<&'a [u8]>,
-    {
-        let e = Event {
+    fn example_event() -> Event<'static, 'static> {
+        Event {
             kind: EventKind::Event(smallvec::smallvec![
                 ("message", FieldValue::Str("oh no!".into())),
                 ("x", FieldValue::Int(42)),
@@ -236,6 +702,7 @@ mod tests {
                 SpanItem::Start {
                     span_name: "hello_world",
                     id: NonZeroU64::new(1),
+                    declared_fields: None,
                 },
                 SpanItem::Field {
                     name: "field",
@@ -251,7 +718,31 @@ mod tests {
                 seconds: 10,
                 nanos: 11,
             }),
-        };
+            uptime_ns: Some(12),
+            syslog_pri: Some(134),
+            instance_id: Some(0xdead_beef),
+            time_missing: None,
+            crate_name: Some("app"),
+            crate_version: Some("1.2.3"),
+            globals: Box::leak(Box::new([(
+                "service".to_string(),
+                crate::FieldValue::Str("billing".to_string()),
+            )])),
+            pid: Some(4321),
+            host: Some("web-1"),
+            name: Some("event src/main.rs:42"),
+            module: Some("my_app::handlers"),
+            seq: Some(7),
+            id: Some("01ARZ3NDEKTSV4RRFFQ69G5FAV".to_string()),
+        }
+    }
+
+    // TODO: should probably fuzz this
+    fn serde_borrowed_to_owned<F>(fmt: F)
+    where
+        F: SerdeFormat + for<'a> StreamFormat<&'a [u8]>,
+    {
+        let e = example_event();
 
         let mut buf = Vec::new();
         fmt.serialize(&mut buf, &e).unwrap();
@@ -281,6 +772,1899 @@ mod tests {
     #[cfg(feature = "messagepack")]
     #[test]
     fn serde_borrowed_to_owned_msgpack() {
-        serde_borrowed_to_owned(crate::format::MessagePack);
+        serde_borrowed_to_owned(crate::format::MessagePack::struct_as_map());
+    }
+
+    #[cfg(feature = "messagepack")]
+    #[test]
+    fn serde_borrowed_to_owned_msgpack_array() {
+        serde_borrowed_to_owned(crate::format::MessagePack::struct_as_array());
+    }
+
+    #[cfg(feature = "messagepack")]
+    #[test]
+    fn msgpack_struct_as_array_is_smaller() {
+        use crate::format::MessagePack;
+        let mut e = example_event();
+        e.thread_id = None;
+        e.time = None;
+
+        let mut map = Vec::new();
+        let mut array = Vec::new();
+        MessagePack::struct_as_map()
+            .serialize(&mut map, &e)
+            .unwrap();
+        MessagePack::struct_as_array()
+            .serialize(&mut array, &e)
+            .unwrap();
+        assert!(array.len() < map.len());
+    }
+
+    fn fields<'a>(fields: &[(&'a str, i64)]) -> EventFields<'a> {
+        fields
+            .iter()
+            .map(|&(name, x)| (name, FieldValue::Int(x)))
+            .collect()
+    }
+
+    fn field_ints<'a>(fields: &EventFields<'a>) -> Vec<(&'a str, i64)> {
+        fields
+            .iter()
+            .map(|(name, val)| match val {
+                FieldValue::Int(x) => (*name, *x),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merge_fields() {
+        let existing = fields(&[("a", 1), ("b", 2), ("c", 3)]);
+        let new = fields(&[("d", 40), ("b", 20), ("e", 50), ("a", 10)]);
+
+        let mut keep = existing.clone();
+        keep.merge_with(new.clone(), MergePolicy::KeepExisting);
+        assert_eq!(
+            field_ints(&keep),
+            vec![("a", 1), ("b", 2), ("c", 3), ("d", 40), ("e", 50)]
+        );
+
+        let mut overwrite = existing.clone();
+        overwrite.merge_with(new.clone(), MergePolicy::Overwrite);
+        assert_eq!(
+            field_ints(&overwrite),
+            vec![("a", 10), ("b", 20), ("c", 3), ("d", 40), ("e", 50)]
+        );
+
+        let mut empty = EventFields::new();
+        empty.merge_with(new, MergePolicy::KeepExisting);
+        assert_eq!(
+            field_ints(&empty),
+            vec![("d", 40), ("b", 20), ("e", 50), ("a", 10)]
+        );
+    }
+
+    fn run_inherited(mode: InheritMode, prefix: &str) -> Vec<crate::Event> {
+        use crate::{SerdeLayer, SpanEvents};
+
+        let layer = SerdeLayer::new()
+            .with_span_events(SpanEvents::FULL)
+            .with_inherited_span_fields(mode)
+            .with_inherited_span_field_prefix(prefix);
+        capture(layer, || {
+            let _outer =
+                tracing::info_span!("outer", request_id = 1, user = "bob", x = 1).entered();
+            let _inner = tracing::info_span!("inner", x = 2, y = true).entered();
+            tracing::info!(user = "alice", "hello");
+        })
+    }
+
+    fn event_fields(events: &[crate::Event]) -> Vec<(String, crate::FieldValue)> {
+        let fields: Vec<_> = events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                crate::EventKind::Event(fields) => Some(fields.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fields.len(), 1);
+        let mut fields: Vec<_> = fields[0].clone().into_iter().collect();
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        fields
+    }
+
+    fn field(name: &str, val: impl Into<crate::FieldValue>) -> (String, crate::FieldValue) {
+        (name.to_string(), val.into())
+    }
+
+    #[test]
+    fn inherit_span_fields() {
+        let none = run_inherited(InheritMode::None, "");
+        assert_eq!(
+            event_fields(&none),
+            vec![field("message", "hello"), field("user", "alice")]
+        );
+
+        let innermost = run_inherited(InheritMode::Innermost, "");
+        assert_eq!(
+            event_fields(&innermost),
+            vec![
+                field("message", "hello"),
+                field("user", "alice"),
+                field("x", 2i64),
+                field("y", true),
+            ]
+        );
+
+        let all = run_inherited(InheritMode::All, "");
+        assert_eq!(
+            event_fields(&all),
+            vec![
+                field("message", "hello"),
+                field("request_id", 1i64),
+                field("user", "alice"),
+                field("x", 2i64),
+                field("y", true),
+            ]
+        );
+
+        // Span fields and lifecycle events are unaffected
+        assert_eq!(none.len(), all.len());
+        for (a, b) in none.iter().zip(&all) {
+            assert_eq!(a.spans.len(), b.spans.len());
+            for (a, b) in a.spans.iter().zip(&b.spans) {
+                assert_eq!(a.fields, b.fields);
+            }
+            if !matches!(a.kind, crate::EventKind::Event(_)) {
+                assert_eq!(a.kind, b.kind);
+            }
+        }
+    }
+
+    #[test]
+    fn inherit_span_fields_with_prefix() {
+        let events = run_inherited(InheritMode::All, "span.");
+        assert_eq!(
+            event_fields(&events),
+            vec![
+                field("message", "hello"),
+                field("span.request_id", 1i64),
+                field("span.user", "bob"),
+                field("span.x", 2i64),
+                field("span.y", true),
+                field("user", "alice"),
+            ]
+        );
+    }
+
+    #[test]
+    fn uptime() {
+        use crate::SerdeLayer;
+        use std::time::Duration;
+
+        let layer = SerdeLayer::new().with_uptime(true);
+        let events = capture(layer, || {
+            for i in 0..10 {
+                tracing::info!(i);
+            }
+        });
+        let uptimes: Vec<_> = events.iter().map(|e| e.uptime_ns.unwrap()).collect();
+        assert_eq!(uptimes.len(), 10);
+        assert!(Duration::from_nanos(uptimes[0]) < Duration::from_secs(1));
+        assert!(uptimes.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn syslog_pri() {
+        use crate::{SerdeLayer, SyslogFacility};
+
+        let layer = SerdeLayer::new().with_syslog(SyslogFacility::Local0);
+        let events = capture(layer, || {
+            tracing::warn!("warning");
+            tracing::debug!("debug");
+        });
+        let pri: Vec<_> = events.iter().map(|e| e.syslog_pri).collect();
+        assert_eq!(pri, vec![Some(132), Some(135)]);
+    }
+
+    fn run_flaky_clock(policy: crate::MissingTime) -> Vec<crate::Event> {
+        use crate::time::Clock;
+        use crate::SerdeLayer;
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::time::Duration;
+
+        /// Tells the time on every third call, starting from the first.
+        struct FlakyClock(AtomicU64);
+
+        impl Clock for FlakyClock {
+            fn time(&self) -> Option<UnixTime> {
+                let n = self.0.fetch_add(1, Ordering::Relaxed);
+                if n % 3 == 1 {
+                    Some(UnixTime::from(Duration::from_secs(n)))
+                } else {
+                    None
+                }
+            }
+        }
+
+        let layer = SerdeLayer::new()
+            .with_clock(FlakyClock(AtomicU64::new(1)))
+            .with_missing_time(policy);
+        capture(layer, || {
+            for i in 0..6 {
+                tracing::info!(i);
+            }
+        })
+    }
+
+    #[test]
+    fn missing_time() {
+        use crate::MissingTime;
+        use std::time::Duration;
+
+        let secs = |events: &[crate::Event]| -> Vec<_> {
+            events
+                .iter()
+                .map(|e| e.time.clone().map(|t| Duration::from(t).as_secs()))
+                .collect()
+        };
+
+        let events = run_flaky_clock(MissingTime::Omit);
+        assert_eq!(
+            secs(&events),
+            vec![Some(1), None, None, Some(4), None, None]
+        );
+        assert!(events.iter().all(|e| e.time_missing.is_none()));
+
+        let events = run_flaky_clock(MissingTime::CarryForward);
+        assert_eq!(
+            secs(&events),
+            vec![Some(1), Some(1), Some(1), Some(4), Some(4), Some(4)]
+        );
+        assert!(events.iter().all(|e| e.time_missing.is_none()));
+
+        let events = run_flaky_clock(MissingTime::Mark);
+        assert_eq!(
+            secs(&events),
+            vec![Some(1), None, None, Some(4), None, None]
+        );
+        for e in &events {
+            assert_eq!(e.time.is_none(), e.time_missing == Some(true));
+        }
+    }
+
+    fn run_span_records(enable: bool) -> Vec<crate::Event> {
+        use crate::{SerdeLayer, SpanIdMode};
+
+        let layer = SerdeLayer::new()
+            .with_span_records(enable)
+            .with_span_ids(SpanIdMode::LifecycleOnly);
+        capture(layer, || {
+            let _outer = tracing::info_span!("outer").entered();
+            let span = tracing::info_span!("inner", a = 1, b = tracing::field::Empty);
+            tracing::info!("before");
+            span.record("b", &"bee");
+        })
+    }
+
+    #[test]
+    fn span_records() {
+        let events = run_span_records(false);
+        assert_eq!(events.len(), 1);
+
+        let events = run_span_records(true);
+        assert_eq!(events.len(), 2);
+        let record = &events[1];
+        let expected: indexmap::IndexMap<_, _> =
+            std::iter::once(("b".to_string(), crate::FieldValue::from("bee"))).collect();
+        assert_eq!(record.kind, crate::EventKind::SpanRecord(expected));
+
+        let names: Vec<_> = record.spans.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["outer", "inner"]);
+        assert!(record.spans[0].id.is_none());
+        assert!(record.spans[1].id.is_some());
+        assert_eq!(record.spans[1].fields["a"], crate::FieldValue::Int(1));
+        assert_eq!(record.spans[1].fields["b"], crate::FieldValue::from("bee"));
+    }
+
+    #[test]
+    fn recorded_span_fields() {
+        use crate::{EventKind, FieldValue, SerdeLayer, SpanEvents};
+
+        let layer = SerdeLayer::new().with_span_events(SpanEvents::CLOSE);
+        let events = capture(layer, || {
+            let _outer = tracing::info_span!("outer", a = "outer").entered();
+            let span = tracing::info_span!("span", a = 1, b = tracing::field::Empty);
+            let _entered = span.clone().entered();
+            tracing::info!("first");
+            span.record("a", &2);
+            span.record("b", &"bee");
+            tracing::info!("second");
+            span.record("b", &"final");
+        });
+        assert_eq!(events.len(), 4);
+
+        let fields = |e: &crate::Event| e.spans.last().unwrap().fields.clone();
+        assert_eq!(fields(&events[0])["a"], FieldValue::Int(1));
+        assert!(fields(&events[0]).get("b").is_none());
+        assert_eq!(fields(&events[1])["a"], FieldValue::Int(2));
+        assert_eq!(fields(&events[1])["b"], FieldValue::from("bee"));
+        assert_eq!(fields(&events[1]).len(), 2);
+        assert_eq!(events[1].spans[0].fields["a"], FieldValue::from("outer"));
+
+        assert!(matches!(events[2].kind, EventKind::SpanClose(_)));
+        assert_eq!(events[2].spans.last().unwrap().name, "span");
+        assert_eq!(fields(&events[2])["b"], FieldValue::from("final"));
+    }
+
+    #[test]
+    fn rerecorded_span_fields_are_not_duplicated() {
+        use crate::SerdeLayer;
+
+        let layer = SerdeLayer::new()
+            .with_empty_fields_as_null(true)
+            .with_source_location(false);
+        let buffer = capture_output(layer, || {
+            let span = tracing::info_span!("span", a = 1, b = tracing::field::Empty);
+            let _entered = span.clone().entered();
+            tracing::info!("first");
+            for i in 0..3 {
+                span.record("b", &i);
+                span.record("a", &i);
+            }
+            tracing::info!("second");
+        });
+
+        // Checked on the raw output, since deserializing would hide duplicate keys
+        let lines: Vec<_> = std::str::from_utf8(&buffer).unwrap().lines().collect();
+        assert!(lines[0].contains(r#""f":{"a":1,"b":null}"#), "{}", lines[0]);
+        assert!(lines[1].contains(r#""f":{"a":2,"b":2}"#), "{}", lines[1]);
+    }
+
+    #[test]
+    fn explicit_event_parents() {
+        use crate::{EventKind, FieldValue, InheritMode, SerdeLayer};
+
+        let layer = SerdeLayer::new().with_inherited_span_fields(InheritMode::All);
+        let events = capture(layer, || {
+            let root = tracing::info_span!("root", r = 1);
+            let parent = tracing::info_span!(parent: &root, "parent", p = 2);
+            let _current = tracing::info_span!("current", c = 3).entered();
+            tracing::info!("contextual");
+            tracing::info!(parent: &parent, "explicit parent");
+            tracing::info!(parent: None, "explicit root");
+        });
+        assert_eq!(events.len(), 3);
+
+        let names = |e: &crate::Event| e.spans.iter().map(|s| s.name.clone()).collect::<Vec<_>>();
+        let inherited = |e: &crate::Event| match &e.kind {
+            EventKind::Event(fields) => fields
+                .keys()
+                .filter(|k| *k != "message")
+                .cloned()
+                .collect::<Vec<_>>(),
+            _ => unreachable!(),
+        };
+        assert_eq!(names(&events[0]), vec!["current"]);
+        assert_eq!(inherited(&events[0]), vec!["c"]);
+
+        assert_eq!(names(&events[1]), vec!["root", "parent"]);
+        assert_eq!(events[1].spans[1].fields["p"], FieldValue::Int(2));
+        assert_eq!(inherited(&events[1]), vec!["p", "r"]);
+
+        assert!(events[2].spans.is_empty());
+        assert!(inherited(&events[2]).is_empty());
+    }
+
+    #[test]
+    fn span_follows_from() {
+        use crate::{EventKind, SerdeLayer};
+
+        let run = |enable: bool| {
+            let layer = SerdeLayer::new().with_span_follows_from(enable);
+            let mut ids = None;
+            let events = capture(layer, || {
+                let cause = tracing::info_span!("cause");
+                let effect = tracing::info_span!("effect");
+                effect.follows_from(&cause);
+                ids = Some((cause.id().unwrap(), effect.id().unwrap()));
+            });
+            (events, ids.unwrap())
+        };
+
+        let (events, (cause, effect)) = run(true);
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].kind,
+            EventKind::SpanFollowsFrom {
+                from: cause.into_non_zero_u64(),
+                to: effect.into_non_zero_u64(),
+            }
+        );
+        assert_eq!(events[0].spans.last().unwrap().name, "effect");
+
+        assert!(run(false).0.is_empty());
+    }
+
+    #[test]
+    fn wide_integers() {
+        use crate::{EventKind, FieldValue, SerdeLayer};
+
+        let layer = SerdeLayer::new().with_source_location(false);
+        let buffer = capture_output(layer, || {
+            tracing::info!(
+                small = 7u64,
+                big = u64::MAX,
+                negative = -5i128,
+                narrow = 9u128,
+                huge = u128::MAX,
+                tiny = i128::MIN
+            );
+        });
+        let output = std::str::from_utf8(&buffer).unwrap();
+        assert!(output.contains(&format!("\"huge\":\"{}\"", u128::MAX)));
+        assert!(output.contains(&format!("\"tiny\":\"{}\"", i128::MIN)));
+
+        let events: Vec<_> = Json
+            .iter_reader(buffer.as_slice())
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        let fields = match &events[0].kind {
+            EventKind::Event(fields) => fields,
+            _ => unreachable!(),
+        };
+        assert_eq!(fields["small"], FieldValue::Int(7));
+        assert_eq!(fields["big"], FieldValue::UInt(u64::MAX));
+        assert!(matches!(fields["big"], FieldValue::UInt(_)));
+        assert_eq!(fields["negative"], FieldValue::Int(-5));
+        assert!(matches!(fields["narrow"], FieldValue::Int(9)));
+        // Integers which don't fit in 64 bits are written as strings
+        assert_eq!(fields["huge"], FieldValue::Str(u128::MAX.to_string()));
+        assert_eq!(fields["tiny"], FieldValue::Str(i128::MIN.to_string()));
+
+        // MessagePack can't represent them either
+        #[cfg(feature = "messagepack")]
+        {
+            use crate::format::MessagePack;
+            let layer = SerdeLayer::new().with_format(MessagePack::struct_as_map());
+            let buffer = capture_output(layer, || tracing::info!(huge = u128::MAX));
+            let events: Vec<_> = MessagePack::struct_as_map()
+                .iter_reader(buffer.as_slice())
+                .collect::<std::io::Result<_>>()
+                .unwrap();
+            match &events[0].kind {
+                EventKind::Event(fields) => {
+                    assert_eq!(fields["huge"], FieldValue::Str(u128::MAX.to_string()))
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn source_location_events_only() {
+        use crate::{EventKind, SerdeLayer, SpanEvents};
+
+        let layer = SerdeLayer::new()
+            .with_span_events(SpanEvents::NEW | SpanEvents::CLOSE)
+            .with_source_location_spans(false);
+        let events = capture(layer, || {
+            let _span = tracing::info_span!("span").entered();
+            tracing::info!("event");
+        });
+        assert_eq!(events.len(), 3);
+        for e in &events {
+            match e.kind {
+                EventKind::Event(_) => {
+                    assert_eq!(e.src_file.as_deref(), Some(file!()));
+                    assert!(e.src_line.is_some());
+                }
+                _ => {
+                    assert!(e.src_file.is_none(), "{:?}", e);
+                    assert!(e.src_line.is_none(), "{:?}", e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn max_level() {
+        use crate::{EventKind, Level, SerdeLayer, SpanEvents};
+        use std::sync::{Arc, Mutex};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let other = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let layer = SerdeLayer::new()
+            .with_span_events(SpanEvents::FULL)
+            .with_max_level(Level::Warn)
+            .with_writer(Arc::clone(&buffer))
+            .finish();
+        // Only this layer drops the event, other layers still see it
+        let other_layer = SerdeLayer::new().with_writer(Arc::clone(&other)).finish();
+        let subscriber = tracing_subscriber::registry().with(layer).with(other_layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let _info = tracing::info_span!("info_span").entered();
+            let _warn = tracing::warn_span!("warn_span").entered();
+            tracing::info!("dropped");
+            tracing::warn!("kept");
+        });
+
+        let buffer = buffer.lock().unwrap();
+        let events: Vec<_> = Json
+            .iter_reader(buffer.as_slice())
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        let kinds: Vec<_> = events
+            .iter()
+            .map(|e| (e.kind.clone(), e.spans.last().unwrap().name.as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (EventKind::SpanCreate, "warn_span"),
+                (EventKind::SpanEnter, "warn_span"),
+                (events[2].kind.clone(), "warn_span"),
+                (EventKind::SpanExit, "warn_span"),
+                (EventKind::SpanClose(None), "warn_span"),
+            ]
+        );
+        assert_eq!(events[2].level, Level::Warn);
+        assert!(matches!(events[2].kind, EventKind::Event(_)));
+        assert_eq!(events[2].spans.len(), 2);
+
+        let other = other.lock().unwrap();
+        let messages: Vec<_> = Json
+            .iter_reader(other.as_slice())
+            .map(|e| e.unwrap().field("message").cloned().unwrap())
+            .collect();
+        assert_eq!(messages, vec!["dropped".into(), "kept".into()]);
+    }
+
+    #[test]
+    fn empty_fields_as_null() {
+        use crate::{EventKind, FieldValue, SerdeLayer, SpanEvents};
+
+        let run = |enable: bool| {
+            let layer = SerdeLayer::new()
+                .with_span_events(SpanEvents::CLOSE)
+                .with_empty_fields_as_null(enable);
+            let buffer = capture_output(layer, || {
+                let span = tracing::info_span!("span", user = tracing::field::Empty);
+                let _entered = span.enter();
+                let missing: Option<i64> = None;
+                tracing::info!(missing, "event");
+                span.record("user", &"alice");
+            });
+            let output = String::from_utf8(buffer.clone()).unwrap();
+            let events: Vec<_> = Json
+                .iter_reader(buffer.as_slice())
+                .collect::<std::io::Result<_>>()
+                .unwrap();
+            (output, events)
+        };
+
+        let (output, events) = run(true);
+        assert!(output.contains(r#""missing":null"#), "{}", output);
+        match &events[0].kind {
+            EventKind::Event(fields) => assert_eq!(fields["missing"], FieldValue::Null),
+            _ => unreachable!(),
+        }
+        assert_eq!(events[0].spans[0].fields["user"], FieldValue::Null);
+        assert_eq!(events[1].spans[0].fields["user"], FieldValue::from("alice"));
+
+        let (output, events) = run(false);
+        assert!(!output.contains("missing"));
+        assert!(events[0].spans[0].fields.is_empty());
+    }
+
+    #[test]
+    fn max_debug_len() {
+        use crate::{EventKind, FieldValue, SerdeLayer};
+
+        let layer = SerdeLayer::new().with_max_debug_len(10);
+        let dump = vec![0u8; 10_000];
+        let text = format!("a{}", "é".repeat(100));
+        let events = capture(layer, || {
+            tracing::info!(dump = ?dump, unicode = %text, small = ?1, text = text.as_str(), "{}", text);
+        });
+
+        let fields = match &events[0].kind {
+            EventKind::Event(fields) => fields,
+            _ => unreachable!(),
+        };
+        assert_eq!(fields["dump"], FieldValue::from("[0, 0, 0, …"));
+        // Not split inside a two-byte character
+        assert_eq!(fields["unicode"], FieldValue::from("aéééé…"));
+        assert_eq!(fields["small"], FieldValue::from("1"));
+        assert_eq!(fields["text"], FieldValue::from(text.as_str()));
+        assert_eq!(fields["message"], FieldValue::from(text.as_str()));
+    }
+
+    #[test]
+    fn max_field_len() {
+        use crate::{EventKind, FieldValue, SerdeLayer};
+
+        let layer = SerdeLayer::new().with_max_field_length(10);
+        let dump = vec![0u8; 100];
+        // Two-byte characters straddling the limit
+        let text = format!("a{}", "é".repeat(100));
+        // Four-byte characters straddling the limit
+        let emoji = format!("abc{}", "😀".repeat(10));
+        let events = capture(layer, || {
+            let span =
+                tracing::info_span!("s", body = text.as_str(), later = tracing::field::Empty);
+            span.record("later", emoji.as_str());
+            let _enter = span.enter();
+            tracing::info!(
+                text = text.as_str(),
+                emoji = emoji.as_str(),
+                unicode = %text,
+                dump = ?dump,
+                exact = "0123456789",
+                "{}",
+                text
+            );
+        });
+
+        let event = events.last().unwrap();
+        let fields = match &event.kind {
+            EventKind::Event(fields) => fields,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            fields["text"],
+            FieldValue::from("aéééé...(truncated 192 bytes)")
+        );
+        assert_eq!(
+            fields["emoji"],
+            FieldValue::from("abc😀...(truncated 36 bytes)")
+        );
+        assert_eq!(fields["unicode"], FieldValue::from("aéééé...(truncated)"));
+        assert_eq!(fields["dump"], FieldValue::from("[0, 0, 0, ...(truncated)"));
+        assert_eq!(fields["exact"], FieldValue::from("0123456789"));
+        assert_eq!(fields["message"], FieldValue::from(text.as_str()));
+
+        let span_fields = &event.spans[0].fields;
+        assert_eq!(
+            span_fields["body"],
+            FieldValue::from("aéééé...(truncated 192 bytes)")
+        );
+        assert_eq!(
+            span_fields["later"],
+            FieldValue::from("abc😀...(truncated 36 bytes)")
+        );
+    }
+
+    #[test]
+    fn manual_clock() {
+        use crate::time::ManualClock;
+        use crate::SerdeLayer;
+        use std::time::Duration;
+
+        let start = Duration::new(1_600_000_000, 250);
+        let clock = ManualClock::new(start.into());
+        let layer = SerdeLayer::new().with_clock(clock.clone());
+        let events = capture(layer, || {
+            tracing::info!("first");
+            clock.advance(Duration::from_millis(1500));
+            tracing::info!("second");
+        });
+        let times: Vec<Duration> = events
+            .iter()
+            .map(|e| e.time.clone().unwrap().into())
+            .collect();
+        assert_eq!(times, vec![start, start + Duration::from_millis(1500)]);
+    }
+
+    #[test]
+    fn boxed_clock() {
+        use crate::time::{Clock, ManualClock, SystemClock};
+        use crate::SerdeLayer;
+        use std::time::Duration;
+
+        let start = Duration::from_secs(1_600_000_000);
+        let clock = ManualClock::new(start.into());
+        let make_layer = |manual: bool| {
+            let boxed: Box<dyn Clock + Send + Sync> = if manual {
+                Box::new(clock.clone())
+            } else {
+                Box::new(SystemClock::default())
+            };
+            SerdeLayer::new().with_clock(boxed)
+        };
+        let layer = make_layer(true);
+        let events = capture(layer, || {
+            tracing::info!("first");
+            clock.advance(Duration::from_secs(2));
+            tracing::info!("second");
+        });
+
+        let times: Vec<Duration> = events
+            .iter()
+            .map(|e| e.time.clone().unwrap().into())
+            .collect();
+        assert_eq!(times, vec![start, start + Duration::from_secs(2)]);
+    }
+
+    #[test]
+    fn crate_info() {
+        use crate::SerdeLayer;
+
+        let layer = SerdeLayer::new().with_crate_info(env!("CARGO_PKG_NAME"), "1.2.3");
+        let buffer = capture_output(layer, || {
+            tracing::info!("hello");
+        });
+        let output = std::str::from_utf8(&buffer).unwrap();
+        assert!(output.contains(r#""cr":"tracing-subscriber-serde","crv":"1.2.3""#));
+        let events: Vec<_> = Json
+            .iter_reader(buffer.as_slice())
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            events[0].crate_name.as_deref(),
+            Some("tracing-subscriber-serde")
+        );
+        assert_eq!(events[0].crate_version.as_deref(), Some("1.2.3"));
+    }
+
+    #[test]
+    fn process_id() {
+        use crate::SerdeLayer;
+
+        fn run<F>(fmt: F, enable: bool) -> Option<u32>
+        where
+            F: SerdeFormat + for<'a> StreamFormat<&'a [u8]> + Clone + Send + Sync + 'static,
+        {
+            let layer = SerdeLayer::new()
+                .with_process_id(enable)
+                .with_format(fmt.clone());
+            let buffer = capture_output(layer, || {
+                tracing::info!("hello");
+            });
+            let event = fmt.iter_reader(buffer.as_slice()).next().unwrap();
+            event.unwrap().pid
+        }
+
+        let pid = Some(std::process::id());
+        assert_eq!(run(Json, true), pid);
+        assert_eq!(run(Json, false), None);
+        #[cfg(feature = "messagepack")]
+        {
+            use crate::format::MessagePack;
+            assert_eq!(run(MessagePack::struct_as_map(), true), pid);
+            assert_eq!(run(MessagePack::struct_as_array(), true), pid);
+            assert_eq!(run(MessagePack::struct_as_array(), false), None);
+        }
+    }
+
+    #[test]
+    fn event_names() {
+        use crate::{SerdeLayer, SpanEvents};
+
+        fn run(enable: bool) -> Vec<crate::Event> {
+            let layer = SerdeLayer::new()
+                .with_event_names(enable)
+                .with_source_location(false)
+                .with_span_events(SpanEvents::NEW);
+            capture(layer, || {
+                let _span = tracing::info_span!("outer").entered();
+                tracing::info!(name: "checkout", "named");
+                tracing::info!("unnamed");
+            })
+        }
+
+        let events = run(true);
+        assert_eq!(events[0].kind, crate::EventKind::SpanCreate);
+        assert_eq!(events[0].name, None);
+        assert_eq!(events[1].name.as_deref(), Some("checkout"));
+        let unnamed = events[2].name.as_deref().unwrap();
+        assert!(unnamed.starts_with("event src"), "{}", unnamed);
+        assert!(run(false).iter().all(|e| e.name.is_none()));
+    }
+
+    #[test]
+    fn module_path() {
+        use crate::SerdeLayer;
+
+        fn run(enable: bool) -> Vec<Option<String>> {
+            let layer = SerdeLayer::new().with_module_path(enable);
+            let events = capture(layer, || {
+                tracing::info!("default target");
+                tracing::info!(target: "audit", "custom target");
+            });
+            events.into_iter().map(|e| e.module).collect()
+        }
+
+        assert_eq!(run(true), vec![None, Some(module_path!().to_string())]);
+        assert_eq!(run(false), vec![None, None]);
+    }
+
+    #[test]
+    fn span_list() {
+        use crate::{EventKind, SerdeLayer, SpanEvents};
+
+        fn run(span_list: bool) -> Vec<crate::Event> {
+            let layer = SerdeLayer::new()
+                .with_span_list(span_list)
+                .with_span_events(SpanEvents::FULL);
+            capture(layer, || {
+                let _a = tracing::info_span!("a", depth = 0).entered();
+                let _b = tracing::info_span!("b", depth = 1).entered();
+                let _c = tracing::info_span!("c", depth = 2).entered();
+                tracing::info!("deep");
+            })
+        }
+
+        let full = run(true);
+        let current = run(false);
+        assert_eq!(full.len(), current.len());
+        for (f, c) in full.iter().zip(&current) {
+            assert_eq!(f.kind, c.kind);
+            assert_eq!(c.spans.len(), 1, "{:?}", c);
+            let (f, c) = (f.spans.last().unwrap(), &c.spans[0]);
+            assert_eq!((&f.name, f.id, &f.fields), (&c.name, c.id, &c.fields));
+        }
+        let event = current
+            .iter()
+            .find(|e| matches!(e.kind, EventKind::Event(_)))
+            .unwrap();
+        assert_eq!(event.spans[0].name, "c");
+        assert_eq!(event.spans[0].fields["depth"], 2.into());
+    }
+
+    #[test]
+    fn max_span_depth() {
+        use crate::{EventKind, SerdeLayer, SpanEvents};
+
+        let (depth, max_depth) = (20, 5);
+        let layer = SerdeLayer::new()
+            .with_max_span_depth(max_depth)
+            .with_span_events(SpanEvents::FULL);
+        let buffer = capture_output(layer, || {
+            // The same shape as `workloads::deeply_nested`
+            let mut spans = Vec::with_capacity(depth);
+            for k in 0..depth {
+                spans.push(tracing::warn_span!("egg", d = k, hello = "world").entered());
+            }
+            tracing::error!(whatever = "shall", we = "do", x = 23, "oh no");
+            for s in spans.drain(..).rev() {
+                drop(s);
+            }
+        });
+        let events: Vec<_> = Json
+            .iter_reader(buffer.as_slice())
+            .map(Result::unwrap)
+            .collect();
+        for e in &events {
+            let truncated = e.spans.first().map_or(false, |s| s.name == TRUNCATED_SPAN);
+            let recorded = &e.spans[truncated as usize..];
+            assert!(recorded.len() <= max_depth, "{:?}", e);
+            assert!(recorded.iter().all(|s| s.name == "egg"));
+            // Only the spans which were left out are replaced
+            let innermost = recorded.last().map_or(0, |s| match s.fields["d"] {
+                crate::FieldValue::Int(d) => d as usize + 1,
+                ref v => panic!("unexpected depth {:?}", v),
+            });
+            assert_eq!(truncated, innermost > max_depth, "{:?}", e);
+            assert_eq!(recorded.len(), innermost.min(max_depth));
+        }
+
+        let event = events
+            .iter()
+            .find(|e| matches!(e.kind, EventKind::Event(_)))
+            .unwrap();
+        assert_eq!(event.spans.len(), max_depth + 1);
+        assert_eq!(event.spans[0].name, TRUNCATED_SPAN);
+        assert!(event.spans[0].fields.is_empty() && event.spans[0].id.is_none());
+        assert_eq!(
+            event.spans[max_depth].fields["d"],
+            (depth as i64 - 1).into()
+        );
+    }
+
+    #[test]
+    fn cached_span_chains() {
+        use crate::{EventKind, FieldValue, SerdeLayer, SpanEvents};
+
+        let layer = SerdeLayer::new().with_span_events(SpanEvents::ENTER | SpanEvents::EXIT);
+        let buffer = capture_output(layer, || {
+            let a = tracing::info_span!("a", n = -1);
+            let b = tracing::info_span!(parent: &a, "b", n = -1);
+            for i in 0..2 {
+                a.record("n", i);
+                let _a = a.enter();
+                let _b = b.enter();
+                b.record("n", i);
+            }
+            // Outside of its parent, so the parent is left out when exiting
+            b.in_scope(|| {});
+        });
+        let events: Vec<_> = Json
+            .iter_reader(buffer.as_slice())
+            .map(|e| {
+                let e = e.unwrap();
+                let spans: Vec<_> = e
+                    .spans
+                    .iter()
+                    .map(|s| (s.name.clone(), s.fields["n"].clone()))
+                    .collect();
+                (e.kind, spans)
+            })
+            .collect();
+
+        let span = |name: &str, n: i64| (name.to_string(), FieldValue::from(n));
+        let mut expected = Vec::new();
+        for i in 0..2 {
+            expected.extend([
+                (EventKind::SpanEnter, vec![span("a", i)]),
+                (EventKind::SpanEnter, vec![span("a", i), span("b", i - 1)]),
+                (EventKind::SpanExit, vec![span("a", i), span("b", i)]),
+                (EventKind::SpanExit, vec![span("a", i)]),
+            ]);
+        }
+        expected.extend([
+            (EventKind::SpanEnter, vec![span("a", 1), span("b", 1)]),
+            (EventKind::SpanExit, vec![span("b", 1)]),
+        ]);
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn span_ids_round_trip() {
+        use crate::SerdeLayer;
+        use std::num::NonZeroU64;
+
+        fn run<F>(fmt: F, enable: bool) -> (Vec<Option<NonZeroU64>>, Vec<Option<NonZeroU64>>)
+        where
+            F: SerdeFormat + for<'a> StreamFormat<&'a [u8]> + Clone + Send + Sync + 'static,
+        {
+            let layer = SerdeLayer::new()
+                .with_span_ids(enable)
+                .with_format(fmt.clone());
+            let mut ids = Vec::new();
+            let buffer = capture_output(layer, || {
+                let outer = tracing::info_span!("outer").entered();
+                let inner = tracing::info_span!("inner").entered();
+                tracing::info!("hello");
+                ids = vec![
+                    outer.id().map(|id| id.into_non_zero_u64()),
+                    inner.id().map(|id| id.into_non_zero_u64()),
+                ];
+            });
+            let event = fmt.iter_reader(buffer.as_slice()).next().unwrap().unwrap();
+            (ids, event.spans.iter().map(|s| s.id).collect())
+        }
+
+        let (ids, recorded) = run(Json, true);
+        assert!(ids.iter().all(Option::is_some));
+        assert_eq!(recorded, ids);
+        assert_eq!(run(Json, false).1, vec![None, None]);
+        #[cfg(feature = "messagepack")]
+        {
+            use crate::format::MessagePack;
+            for fmt in vec![MessagePack::struct_as_map(), MessagePack::struct_as_array()] {
+                let (ids, recorded) = run(fmt.clone(), true);
+                assert_eq!(recorded, ids);
+                assert_eq!(run(fmt, false).1, vec![None, None]);
+            }
+        }
+    }
+
+    #[test]
+    fn thread_ids() {
+        use crate::SerdeLayer;
+        use std::sync::{Arc, Mutex};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let layer = SerdeLayer::new()
+            .with_thread_info(false, true)
+            .with_writer(Arc::clone(&buffer))
+            .finish();
+        let dispatch = tracing::Dispatch::new(tracing_subscriber::registry().with(layer));
+        let log = move || {
+            tracing::dispatcher::with_default(&dispatch, || {
+                tracing::info!("first");
+                tracing::info!("second");
+            })
+        };
+        let other = std::thread::spawn(log.clone());
+        other.join().unwrap();
+        log();
+
+        let buffer = buffer.lock().unwrap();
+        let ids: Vec<NonZeroU64> = Json
+            .iter_reader(buffer.as_slice())
+            .map(|e| e.unwrap().thread_id.unwrap())
+            .collect();
+        assert_eq!(ids.len(), 4);
+        assert_eq!(ids[0], ids[1]);
+        assert_eq!(ids[2], ids[3]);
+        assert_ne!(ids[0], ids[2]);
+    }
+
+    #[test]
+    fn hostname() {
+        use crate::SerdeLayer;
+
+        fn run(
+            f: impl FnOnce(
+                crate::SerdeLayerBuilder<Json, (), std::io::Stdout>,
+            ) -> crate::SerdeLayerBuilder<Json, (), std::io::Stdout>,
+        ) -> Option<String> {
+            let layer = f(SerdeLayer::new());
+            let events = capture(layer, || {
+                tracing::info!("hello");
+            });
+            events[0].host.clone()
+        }
+
+        assert_eq!(run(|b| b), None);
+        assert_eq!(
+            run(|b| b.with_hostname_override("container-7")).as_deref(),
+            Some("container-7")
+        );
+        #[cfg(feature = "hostname")]
+        {
+            assert_eq!(
+                run(|b| b.with_hostname(true)),
+                Some(gethostname::gethostname().to_string_lossy().into_owned())
+            );
+            assert_eq!(
+                run(|b| b.with_hostname_override("container-7").with_hostname(false)),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn include_exclude_targets() {
+        use crate::{EventKind, SerdeLayer, SpanEvents};
+
+        let layer = SerdeLayer::new()
+            .with_span_events(SpanEvents::NEW | SpanEvents::CLOSE)
+            .with_include_targets(vec!["app".to_string(), "both".to_string()])
+            .with_exclude_targets(vec!["app::db".to_string(), "both".to_string()])
+            .with_include_targets(vec!["app::db::pool".to_string()]);
+        let events = capture(layer, || {
+            let _conn = tracing::info_span!(target: "hyper::proto", "conn").entered();
+            let _request = tracing::info_span!(target: "app::http", "request").entered();
+            tracing::info!(target: "app", "app");
+            tracing::info!(target: "app::http", "app::http");
+            tracing::info!(target: "app::db", "app::db");
+            tracing::info!(target: "app::db::query", "app::db::query");
+            tracing::info!(target: "app::db::pool", "app::db::pool");
+            tracing::info!(target: "app::db::pool::conn", "app::db::pool::conn");
+            tracing::info!(target: "apps", "apps");
+            tracing::info!(target: "both", "both");
+            tracing::info!(target: "other", "other");
+        });
+        let targets: Vec<_> = events.iter().map(|e| e.target.as_str()).collect();
+        assert_eq!(
+            targets,
+            vec![
+                "app::http",
+                "app",
+                "app::http",
+                "app::db::pool",
+                "app::db::pool::conn",
+                "app::http",
+            ]
+        );
+        assert_eq!(events[0].kind, EventKind::SpanCreate);
+        assert_eq!(events[5].kind, EventKind::SpanClose(None));
+        // Spans of excluded targets still give context
+        assert_eq!(events[1].spans[0].name, "conn");
+    }
+
+    #[test]
+    fn excluded_targets_seen_by_other_layers() {
+        use crate::SerdeLayer;
+        use std::sync::{Arc, Mutex};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let other = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let layer = SerdeLayer::new()
+            .with_exclude_targets(vec!["noisy".to_string()])
+            .with_writer(Arc::clone(&buffer))
+            .finish();
+        let other_layer = SerdeLayer::new().with_writer(Arc::clone(&other)).finish();
+        let subscriber = tracing_subscriber::registry().with(layer).with(other_layer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "noisy", "excluded");
+            tracing::info!(target: "app", "included");
+        });
+
+        let targets = |buffer: &[u8]| -> Vec<String> {
+            Json.iter_reader(buffer)
+                .map(|e| e.unwrap().target)
+                .collect()
+        };
+        assert_eq!(targets(&buffer.lock().unwrap()), vec!["app"]);
+        assert_eq!(targets(&other.lock().unwrap()), vec!["noisy", "app"]);
+    }
+
+    #[test]
+    fn sanitize_strings() {
+        use crate::consumer::PrettyPrinter;
+        use crate::{SerdeLayer, SpanEvents};
+
+        let run = |sanitize| {
+            let layer = SerdeLayer::new()
+                .with_sanitize_strings(sanitize)
+                .with_span_events(SpanEvents::NEW);
+            let buffer = capture_output(layer, || {
+                let _span = tracing::info_span!("bad\x1b[31mspan", s = "a\rb").entered();
+                let nul = "nul\x00";
+                tracing::info!(s = "\x1b[31mred", nul, "message\x1b[0m\tend\n");
+            });
+            String::from_utf8(buffer.clone()).unwrap()
+        };
+
+        let output = run(true);
+        for escaped in &["\\u001b", "\\u0000", "\\r"] {
+            assert!(!output.contains(escaped), "{} in {}", escaped, output);
+        }
+        assert!(output.contains("\"n\":\"bad\u{FFFD}[31mspan\""));
+        assert!(output.contains("\"s\":\"\u{FFFD}[31mred\",\"nul\":\"nul\u{FFFD}\""));
+        assert!(output.contains("\"s\":\"a\u{FFFD}b\""));
+        assert!(output.contains("message\u{FFFD}[0m\\tend\\n"));
+        assert!(run(false).contains("\\u001b[31mred"));
+
+        // The pretty printer escapes control characters itself
+        let printer = PrettyPrinter::default().with_ansi(false);
+        let unsanitized = run(false);
+        for event in Json.iter_reader(unsanitized.as_bytes()) {
+            let pretty = printer.fmt(&event.unwrap()).to_string();
+            assert!(
+                !pretty
+                    .chars()
+                    .any(|c| c.is_control() && c != '\n' && c != '\t'),
+                "{:?}",
+                pretty
+            );
+            assert!(pretty.contains("\\u{1b}[31m"));
+        }
+
+        // DEL and C1 controls too, and values are sanitized before they are truncated
+        let layer = SerdeLayer::new()
+            .with_sanitize_strings(true)
+            .with_max_field_length(6);
+        let events = capture(layer, || {
+            tracing::info!(del = "\x7f\u{9b}", s = "\x01\x02\x03", d = %"\x01\x02\x03");
+        });
+        let fields = match &events[0].kind {
+            crate::EventKind::Event(fields) => fields,
+            _ => unreachable!(),
+        };
+        assert_eq!(fields["del"], "\u{FFFD}\u{FFFD}".into());
+        assert_eq!(fields["s"], "\u{FFFD}\u{FFFD}...(truncated 3 bytes)".into());
+        assert_eq!(fields["d"], "\u{FFFD}\u{FFFD}...(truncated)".into());
+    }
+
+    #[test]
+    fn global_fields() {
+        use crate::FieldValue;
+        use crate::SerdeLayer;
+
+        let layer = SerdeLayer::new()
+            .with_global_fields(vec![
+                (
+                    "service".to_string(),
+                    FieldValue::Str("billing".to_string()),
+                ),
+                ("version".to_string(), FieldValue::Str("1.4.1".to_string())),
+            ])
+            .with_global_fields(vec![
+                ("version".to_string(), FieldValue::Str("1.4.2".to_string())),
+                ("node".to_string(), FieldValue::Int(7)),
+            ]);
+        let buffer = capture_output(layer, || {
+            tracing::info!(x = 1, "hello");
+        });
+        assert!(std::str::from_utf8(&buffer)
+            .unwrap()
+            .contains(r#""g":{"service":"billing","version":"1.4.2","node":7}"#));
+        let events: Vec<_> = Json
+            .iter_reader(buffer.as_slice())
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        let globals: Vec<_> = events[0].globals.iter().collect();
+        assert_eq!(
+            globals,
+            vec![
+                (
+                    &"service".to_string(),
+                    &FieldValue::Str("billing".to_string())
+                ),
+                (
+                    &"version".to_string(),
+                    &FieldValue::Str("1.4.2".to_string())
+                ),
+                (&"node".to_string(), &FieldValue::Int(7)),
+            ]
+        );
+
+        // Events without globals don't carry the key, and read back with none
+        let e = Event {
+            globals: &[],
+            ..example_event()
+        };
+        let mut buf = Vec::new();
+        Json.serialize(&mut buf, &e).unwrap();
+        assert!(!std::str::from_utf8(&buf).unwrap().contains(r#""g":"#));
+        let de = Json.iter_reader(buf.as_slice()).next().unwrap().unwrap();
+        assert!(de.globals.is_empty());
+    }
+
+    #[test]
+    fn omit_empty() {
+        use crate::{EventKind, SerdeLayer};
+
+        let run = |omit_empty| {
+            let layer = SerdeLayer::new()
+                .with_omit_empty(omit_empty)
+                .with_field_policy("sparse", crate::FieldPolicy::AllowOnly(vec![]))
+                .with_source_location(false);
+            let buffer = capture_output(layer, || {
+                tracing::info!(target: "sparse", "dropped by the field policy");
+                let _span = tracing::info_span!("span").entered();
+                tracing::info!(target: "sparse", "dropped by the field policy");
+            });
+            String::from_utf8(buffer.clone()).unwrap()
+        };
+
+        let output = run(true);
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], r#"{"l":2,"t":"sparse"}"#);
+        assert!(lines[1].starts_with(r#"{"l":2,"s":[{"n":"span""#));
+        assert!(run(false).starts_with(r#"{"ty":{"event":{}},"l":2,"s":[],"#));
+
+        let events: Vec<_> = Json
+            .iter_reader(output.as_bytes())
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        assert_eq!(events[0].kind, EventKind::Event(Default::default()));
+        assert!(events[0].spans.is_empty());
+        assert_eq!(events[1].kind, EventKind::Event(Default::default()));
+        assert_eq!(events[1].spans[0].name, "span");
+    }
+
+    #[test]
+    fn first_in_span() {
+        use crate::{EventKind, SerdeLayer};
+
+        let layer = SerdeLayer::new().with_first_in_span(true);
+        let buffer = capture_output(layer, || {
+            tracing::info!("outside");
+            for _ in 0..2 {
+                let _request = tracing::info_span!("request").entered();
+                tracing::info!("started");
+                tracing::info!("finished");
+            }
+        });
+        let flags: Vec<_> = Json
+            .iter_reader(buffer.as_slice())
+            .map(|e| match e.unwrap().kind {
+                EventKind::Event(fields) => fields.get("first_in_span").cloned(),
+                _ => unreachable!(),
+            })
+            .collect();
+        let first = Some(crate::FieldValue::Bool(true));
+        assert_eq!(flags, vec![None, first.clone(), None, first, None]);
+    }
+
+    #[test]
+    fn first_in_span_after_dropped_event() {
+        use crate::{Budget, BudgetOverflow, EventKind, SerdeLayer};
+        use std::time::Duration;
+
+        let layer = SerdeLayer::new()
+            .with_first_in_span(true)
+            .with_budget(Budget {
+                max_bytes_per_interval: 400,
+                interval: Duration::from_secs(3600),
+                overflow: BudgetOverflow::DropAll,
+            });
+        let buffer = capture_output(layer, || {
+            let _request = tracing::info_span!("request").entered();
+            // Too large for the budget, so the next event is the first one written
+            tracing::info!(padding = "x".repeat(1000).as_str(), "dropped");
+            tracing::info!("started");
+            tracing::info!("finished");
+        });
+        let events: Vec<_> = Json
+            .iter_reader(buffer.as_slice())
+            .map(|e| match e.unwrap().kind {
+                EventKind::Event(fields) => fields,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["message"], "started".into());
+        assert_eq!(
+            events[0].get("first_in_span"),
+            Some(&crate::FieldValue::Bool(true))
+        );
+        assert_eq!(events[1].get("first_in_span"), None);
+    }
+
+    #[test]
+    fn first_in_span_after_sampled_out_event() {
+        use crate::{EventKind, SerdeLayer};
+        use std::collections::HashSet;
+
+        let layer = SerdeLayer::new()
+            .with_first_in_span(true)
+            .with_event_sampling(2, 7);
+        let buffer = capture_output(layer, || {
+            for i in 0..20 {
+                let _request = tracing::info_span!("request", i).entered();
+                for _ in 0..4 {
+                    tracing::info!("step");
+                }
+            }
+        });
+        // The first event written in each span is marked, whether or not earlier ones were sampled out
+        let mut seen = HashSet::new();
+        for e in Json.iter_reader(buffer.as_slice()).map(Result::unwrap) {
+            let span = e.spans[0].fields["i"].clone();
+            let first = match &e.kind {
+                EventKind::Event(fields) => fields.contains_key("first_in_span"),
+                _ => unreachable!(),
+            };
+            assert_eq!(first, seen.insert(span), "{:?}", e);
+        }
+        assert!(seen.len() > 1);
+    }
+
+    #[test]
+    fn event_sampling() {
+        use crate::{EventKind, SerdeLayer, SpanEvents};
+        use std::sync::{Arc, Mutex};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let run = || {
+            let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+            let layer = SerdeLayer::new()
+                .with_span_events(SpanEvents::NEW | SpanEvents::CLOSE)
+                .with_event_sampling(10, 7)
+                .with_writer(Arc::clone(&buffer))
+                .finish();
+            let dispatch = tracing::Dispatch::new(tracing_subscriber::registry().with(layer));
+            tracing::dispatcher::with_default(&dispatch, || {
+                let _span = tracing::info_span!("hot").entered();
+                for i in 0..1000 {
+                    tracing::info!(i);
+                }
+            });
+            let sampled_out = dispatch
+                .downcast_ref::<SerdeLayer<Json, (), Arc<Mutex<Vec<u8>>>>>()
+                .unwrap()
+                .sampled_out_events();
+
+            let buffer = buffer.lock().unwrap();
+            let events: Vec<_> = Json
+                .iter_reader(buffer.as_slice())
+                .collect::<std::io::Result<_>>()
+                .unwrap();
+            (events, sampled_out)
+        };
+
+        let (events, sampled_out) = run();
+        // Span events are never sampled
+        assert_eq!(events[0].kind, EventKind::SpanCreate);
+        assert!(matches!(
+            events.last().unwrap().kind,
+            EventKind::SpanClose(_)
+        ));
+        let kept: Vec<_> = events[1..events.len() - 1]
+            .iter()
+            .map(|e| match &e.kind {
+                EventKind::Event(fields) => fields["i"].clone(),
+                k => panic!("unexpected {:?}", k),
+            })
+            .collect();
+        assert_eq!(kept.len() as u64 + sampled_out, 1000);
+        assert!((50..150).contains(&kept.len()), "kept {}", kept.len());
+
+        let (again, _) = run();
+        assert_eq!(events.len(), again.len());
+        for (a, b) in events.iter().zip(&again) {
+            assert_eq!(a.kind, b.kind);
+        }
+    }
+
+    #[test]
+    fn inline_fields() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+        struct Spilled(Arc<AtomicBool>);
+
+        impl<S: tracing::Subscriber> Layer<S> for Spilled {
+            fn on_event(&self, event: &tracing::Event<'_>, _: Context<'_, S>) {
+                let mut visitor = FieldVisitor::new(EventFields::new(), None);
+                event.record(&mut visitor);
+                let fields = visitor.finish();
+                assert_eq!(fields.len(), 12);
+                self.0.store(fields.spilled(), Ordering::SeqCst);
+            }
+        }
+
+        let spilled = Arc::new(AtomicBool::new(false));
+        let layer = Spilled(Arc::clone(&spilled));
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::info!(
+                a = 1,
+                b = 2,
+                c = 3,
+                d = 4,
+                e = 5,
+                f = 6,
+                g = 7,
+                h = 8,
+                i = 9,
+                j = 10,
+                k = 11,
+                "twelve"
+            );
+        });
+        assert_eq!(spilled.load(Ordering::SeqCst), INLINE_FIELDS < 12);
+        assert_eq!(std::mem::size_of::<(&str, FieldValue)>(), 48);
+    }
+
+    #[test]
+    fn field_policy() {
+        use crate::{FieldPolicy, SerdeLayer, SpanEvents};
+
+        let layer = SerdeLayer::new()
+            .with_span_events(SpanEvents::NEW)
+            .with_span_records(true)
+            .with_field_policy("sqlx", FieldPolicy::Deny(vec!["sql".to_string()]))
+            .with_field_policy(
+                "sqlx::query",
+                FieldPolicy::AllowOnly(vec!["message".to_string(), "rows".to_string()]),
+            );
+        let events = capture(layer, || {
+            let span = tracing::info_span!(target: "sqlx::query", "query", sql = "SELECT *", rows = tracing::field::Empty);
+            span.record("sql", &"SELECT 1");
+            span.record("rows", &1);
+            let _entered = span.enter();
+            tracing::info!(target: "sqlx::query", sql = "SELECT *", rows = 1, elapsed = 2, "done");
+            tracing::info!(target: "sqlx::query::exec", sql = "SELECT *", rows = 1, elapsed = 2, "done");
+            tracing::info!(target: "sqlx::pool", sql = "SELECT *", rows = 1, elapsed = 2, "done");
+            tracing::info!(target: "sqlx_extra", sql = "SELECT *", rows = 1, elapsed = 2, "done");
+            tracing::info!(target: "app::db", sql = "SELECT *", rows = 1, elapsed = 2, "done");
+        });
+        let names = |fields: &indexmap::IndexMap<String, crate::FieldValue>| {
+            fields.keys().cloned().collect::<Vec<_>>()
+        };
+        assert_eq!(events[0].kind, crate::EventKind::SpanCreate);
+        assert!(events[0].spans[0].fields.is_empty());
+        let fields: Vec<_> = events[1..]
+            .iter()
+            .map(|e| match &e.kind {
+                crate::EventKind::Event(fields) | crate::EventKind::SpanRecord(fields) => {
+                    names(fields)
+                }
+                k => panic!("unexpected {:?}", k),
+            })
+            .collect();
+        let all = vec!["message", "sql", "rows", "elapsed"];
+        assert_eq!(
+            fields,
+            vec![
+                vec!["rows"],
+                vec!["message", "rows"],
+                vec!["message", "rows"],
+                vec!["message", "rows", "elapsed"],
+                all.clone(),
+                all,
+            ]
+        );
+        assert_eq!(names(&events[2].spans[0].fields), vec!["rows"]);
+    }
+
+    #[test]
+    fn field_policy_empty_prefix() {
+        use crate::{EventKind, FieldPolicy, SerdeLayer};
+
+        let layer = SerdeLayer::new()
+            .with_field_policy("", FieldPolicy::Deny(vec!["token".to_string()]))
+            .with_field_policy("app", FieldPolicy::AllowOnly(vec!["token".to_string()]));
+        let events = capture(layer, || {
+            tracing::info!(target: "other", token = 1, user = 2, "login");
+            tracing::info!(target: "app::auth", token = 1, user = 2, "login");
+        });
+        let names: Vec<Vec<_>> = events
+            .iter()
+            .map(|e| match &e.kind {
+                EventKind::Event(fields) => fields.keys().map(String::as_str).collect(),
+                k => panic!("unexpected {:?}", k),
+            })
+            .collect();
+        assert_eq!(names, vec![vec!["message", "user"], vec!["token"]]);
+    }
+
+    #[test]
+    fn filter_diagnostics() {
+        use crate::{Budget, BudgetOverflow, EventKind, Level, SerdeLayer, SuppressReason};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let layer = SerdeLayer::new()
+            .with_filter_diagnostics(true)
+            .with_max_level(Level::Debug)
+            .with_exclude_targets(vec!["noisy".to_string()])
+            .with_event_sampling(2, 3)
+            .with_budget(Budget {
+                max_bytes_per_interval: 10_000,
+                interval: Duration::from_secs(3600),
+                overflow: BudgetOverflow::DropAll,
+            })
+            .with_writer(Arc::clone(&buffer))
+            .finish();
+        let dispatch = tracing::Dispatch::new(tracing_subscriber::registry().with(layer));
+        tracing::dispatcher::with_default(&dispatch, || {
+            for i in 0..500 {
+                tracing::info!(i, "hot loop");
+                tracing::trace!(i, "too verbose");
+                tracing::warn!(target: "noisy", i, "excluded");
+            }
+        });
+        let layer = dispatch
+            .downcast_ref::<SerdeLayer<Json, (), Arc<Mutex<Vec<u8>>>>>()
+            .unwrap();
+        let diags = layer.diagnostics();
+        assert_eq!(diags.len(), 3);
+        assert!(diags.iter().all(|d| d.seen == 500));
+
+        let written = |level| {
+            let buffer = buffer.lock().unwrap();
+            Json.iter_reader(buffer.as_slice())
+                .map(Result::unwrap)
+                .filter(|e| e.level == level && matches!(e.kind, EventKind::Event(_)))
+                .count() as u64
+        };
+        let diag = |target: &str, line: u32| {
+            diags
+                .iter()
+                .find(|d| d.target == target && d.line == Some(line))
+                .unwrap()
+                .clone()
+        };
+        let first_line = diags.iter().filter_map(|d| d.line).min().unwrap();
+        let hot = diag(module_path!(), first_line);
+        let verbose = diag(module_path!(), first_line + 1);
+        let noisy = diag("noisy", first_line + 2);
+
+        assert_eq!(verbose.suppressed(SuppressReason::Level), 500);
+        assert_eq!(noisy.suppressed(SuppressReason::Target), 500);
+        assert_eq!(verbose.written() + noisy.written(), 0);
+        let sampled = hot.suppressed(SuppressReason::EventSampling);
+        assert_eq!(sampled, layer.sampled_out_events());
+        assert!(sampled > 0 && sampled < 500, "{}", sampled);
+        // The budget runs out before every sampled event is written
+        assert!(hot.suppressed(SuppressReason::Budget) > 0);
+        assert_eq!(hot.written(), written(Level::Info));
+        assert_eq!(
+            hot.seen,
+            hot.written()
+                + SuppressReason::ALL
+                    .iter()
+                    .map(|&r| hot.suppressed(r))
+                    .sum::<u64>()
+        );
+
+        buffer.lock().unwrap().clear();
+        layer.emit_diagnostics();
+        let buffer = buffer.lock().unwrap();
+        let events: Vec<_> = Json
+            .iter_reader(buffer.as_slice())
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        assert_eq!(events.len(), 3);
+        for (event, diag) in events.iter().zip(&diags) {
+            assert_eq!(event.target, "tracing_subscriber_serde::diagnostics");
+            assert_eq!(
+                event.field("callsite_target"),
+                Some(&crate::FieldValue::Str(diag.target.clone()))
+            );
+            assert_eq!(
+                event.field("suppressed_budget"),
+                Some(&crate::FieldValue::Int(
+                    diag.suppressed(SuppressReason::Budget) as i64
+                ))
+            );
+        }
+    }
+
+    fn run_trace_sampling(
+        keep: f64,
+        always_keep_errors: bool,
+        f: impl FnOnce(),
+    ) -> Vec<crate::Event> {
+        use crate::{SerdeLayer, SpanEvents};
+
+        let layer = SerdeLayer::new()
+            .with_span_events(SpanEvents::FULL)
+            .with_span_records(true)
+            .with_trace_sampling(keep, 42)
+            .with_always_keep_errors(always_keep_errors);
+        capture(layer, f)
+    }
+
+    #[test]
+    fn trace_sampling() {
+        let events = run_trace_sampling(0.25, false, || {
+            for trace in 0..200 {
+                let root = tracing::info_span!("root", trace, x = tracing::field::Empty);
+                let _root = root.enter();
+                let _child = tracing::info_span!("child").entered();
+                tracing::info!("hello");
+                root.record("x", &1);
+                tracing::info!("goodbye");
+            }
+            tracing::info!("outside");
+        });
+
+        let outside = events.iter().filter(|e| e.spans.is_empty()).count();
+        assert_eq!(outside, 1);
+
+        let mut counts = std::collections::HashMap::new();
+        for e in events.iter().filter(|e| !e.spans.is_empty()) {
+            let trace = match e.spans[0].fields["trace"] {
+                crate::FieldValue::Int(t) => t,
+                _ => unreachable!(),
+            };
+            *counts.entry(trace).or_insert(0) += 1;
+        }
+
+        // create, enter, record and close for root, create, enter, exit and close for child, two events
+        assert!(counts.values().all(|&n| n == 11), "{:?}", counts);
+        assert!((25..75).contains(&counts.len()), "{}", counts.len());
+
+        let again = run_trace_sampling(0.25, false, || {
+            for trace in 0..200 {
+                let _root = tracing::info_span!("root", trace).entered();
+            }
+        });
+        let kept = |events: &[crate::Event]| -> std::collections::BTreeSet<i64> {
+            events
+                .iter()
+                .filter_map(|e| match e.spans.get(0)?.fields.get("trace")? {
+                    crate::FieldValue::Int(t) => Some(*t),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(kept(&events), kept(&again));
+    }
+
+    #[test]
+    fn trace_sampling_keep_errors() {
+        let trace = || {
+            let _outer = tracing::info_span!("outer").entered();
+            let _inner = tracing::info_span!("inner").entered();
+            tracing::info!("dropped");
+            tracing::error!("kept");
+        };
+
+        assert!(run_trace_sampling(0.0, false, trace).is_empty());
+
+        let events = run_trace_sampling(0.0, true, trace);
+        let kinds: Vec<_> = events
+            .iter()
+            .map(|e| {
+                let name = e.spans.last().map(|s| s.name.as_str());
+                match &e.kind {
+                    crate::EventKind::Event(f) => (format!("{:?}", f["message"]), name),
+                    kind => (format!("{:?}", kind), name),
+                }
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("SpanCreate".to_string(), Some("outer")),
+                ("SpanCreate".to_string(), Some("inner")),
+                (r#"Str("kept")"#.to_string(), Some("inner")),
+                ("SpanExit".to_string(), Some("inner")),
+                ("SpanClose(None)".to_string(), Some("inner")),
+                ("SpanExit".to_string(), Some("outer")),
+                ("SpanClose(None)".to_string(), Some("outer")),
+            ]
+        );
+        assert_eq!(events[1].spans.len(), 2);
+    }
+
+    #[test]
+    fn healthcheck() {
+        assert!(SerdeLayer::new().finish().healthcheck().is_ok());
+
+        let dir = temp_dir("healthcheck");
+        let path = dir.join("read-only");
+        std::fs::write(&path, b"").unwrap();
+        let read_only = std::fs::File::open(&path).unwrap();
+        let layer = SerdeLayer::new().with_writer(read_only).finish();
+        assert!(layer.healthcheck().is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn run_budget(budget: crate::Budget, f: impl FnOnce()) -> (usize, Vec<crate::Event>) {
+        use crate::SerdeLayer;
+
+        let layer = SerdeLayer::new()
+            .with_source_location(false)
+            .with_budget(budget);
+        let buffer = capture_output(layer, f);
+        let events = crate::format::Json
+            .iter_reader(buffer.as_slice())
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        (buffer.len(), events)
+    }
+
+    #[test]
+    fn budget() {
+        use crate::{Budget, BudgetOverflow, Level};
+        use std::time::Duration;
+
+        let log = || {
+            for i in 0..200 {
+                tracing::info!(i, "filler");
+            }
+            for i in 0..10 {
+                tracing::error!(i, "failure");
+            }
+        };
+        let budget = |overflow| Budget {
+            max_bytes_per_interval: 8000,
+            interval: Duration::from_secs(3600),
+            overflow,
+        };
+        let count =
+            |events: &[crate::Event], level| events.iter().filter(|e| e.level == level).count();
+
+        let (len, events) = run_budget(budget(BudgetOverflow::DropLowSeverityFirst), log);
+        let record_len = len / events.len();
+        assert!(len <= 8000, "{}", len);
+        assert_eq!(count(&events, Level::Error), 10);
+        let info = count(&events, Level::Info);
+        assert!(info < 200);
+        assert!((info + 1) * record_len >= 6000, "{}", info);
+
+        let (len, events) = run_budget(budget(BudgetOverflow::DropAll), log);
+        assert!(len <= 8000 && len + record_len > 8000, "{}", len);
+        assert_eq!(count(&events, Level::Error), 0);
+    }
+
+    #[test]
+    fn budget_summary() {
+        use crate::{Budget, BudgetOverflow, EventKind, Level};
+        use std::time::Duration;
+
+        let budget = Budget {
+            max_bytes_per_interval: 500,
+            interval: Duration::from_millis(200),
+            overflow: BudgetOverflow::DropAll,
+        };
+        let (_, events) = run_budget(budget, || {
+            for i in 0..20 {
+                tracing::info!(i, "filler");
+            }
+            tracing::warn!("dropped");
+            std::thread::sleep(Duration::from_millis(250));
+            tracing::info!("next interval");
+        });
+
+        let n = events.len();
+        let kept = n - 2;
+        assert!(kept < 20);
+        let summary = &events[n - 2];
+        assert_eq!(summary.target, "tracing_subscriber_serde::budget");
+        assert_eq!(summary.level, Level::Warn);
+        match &summary.kind {
+            EventKind::Event(fields) => {
+                assert_eq!(
+                    fields["dropped_info"],
+                    crate::FieldValue::Int((20 - kept) as i64)
+                );
+                assert_eq!(fields["dropped_warn"], crate::FieldValue::Int(1));
+                assert_eq!(fields["dropped_error"], crate::FieldValue::Int(0));
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(events[n - 1].level, Level::Info);
+    }
+
+    #[test]
+    fn span_field_names() {
+        use crate::{EventKind, SerdeLayer, SpanEvents};
+
+        let layer = SerdeLayer::new()
+            .with_span_events(SpanEvents::NEW)
+            .with_span_field_names(true);
+        let events = capture(layer, || {
+            let _skipped = tracing::info_span!(
+                "skipped",
+                a = tracing::field::Empty,
+                b = tracing::field::Empty
+            )
+            .entered();
+            let _no_fields = tracing::info_span!("no_fields").entered();
+            tracing::info!("event");
+        });
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0].kind, EventKind::SpanCreate));
+
+        let skipped = &events[0].spans[0];
+        assert!(skipped.fields.is_empty());
+        assert_eq!(
+            skipped.declared_fields,
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+
+        // Only the created span is annotated
+        assert!(events[1].spans[0].declared_fields.is_none());
+        assert_eq!(events[1].spans[1].declared_fields, Some(vec![]));
+        assert!(events[2].spans.iter().all(|s| s.declared_fields.is_none()));
+    }
+
+    #[test]
+    fn span_time_increments() {
+        use crate::time::{ManualClock, SpanTime};
+        use crate::{EventKind, SerdeLayer};
+        use std::time::Duration;
+
+        let run = |f: &dyn Fn(&ManualClock)| {
+            let clock = ManualClock::default();
+            let layer = SerdeLayer::new()
+                .with_span_time_increments(Duration::from_secs(10))
+                .with_clock(clock.clone());
+            let events = capture(layer, || f(&clock));
+            let secs = |t: &SpanTime| (t.busy().as_secs(), t.idle().as_secs());
+            let increments: Vec<_> = events
+                .iter()
+                .filter_map(|e| match &e.kind {
+                    EventKind::SpanProgress(t) => Some(secs(t)),
+                    _ => None,
+                })
+                .collect();
+            let total = match &events.last().unwrap().kind {
+                EventKind::SpanClose(Some(t)) => secs(t),
+                k => panic!("expected SpanClose, got {:?}", k),
+            };
+            (increments, total)
+        };
+        let advance = |clock: &ManualClock, secs| clock.advance(Duration::from_secs(secs));
+
+        let (increments, total) = run(&|clock| {
+            let span = tracing::info_span!("long");
+            for &(idle, busy) in &[(0, 7), (5, 3), (20, 1)] {
+                advance(clock, idle);
+                let _entered = span.enter();
+                advance(clock, busy);
+            }
+        });
+        assert_eq!(increments, vec![(7, 5), (3, 20), (1, 0)]);
+        assert_eq!(total, (11, 25));
+
+        // Events inside a span which stays entered report its progress
+        let (increments, total) = run(&|clock| {
+            let _entered = tracing::info_span!("entered").entered();
+            for _ in 0..3 {
+                advance(clock, 12);
+                tracing::info!("tick");
+            }
+        });
+        assert_eq!(increments, vec![(12, 0), (12, 0), (12, 0), (0, 0)]);
+        assert_eq!(total, (36, 0));
     }
 }
@@ -0,0 +1,49 @@
+/// Decides which targets are recorded by [`SerdeLayerBuilder::with_include_targets`](super::SerdeLayerBuilder::with_include_targets)
+/// and [`SerdeLayerBuilder::with_exclude_targets`](super::SerdeLayerBuilder::with_exclude_targets).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TargetFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+/// Whether `prefix` matches `target` on a module path boundary, so `hyper` matches `hyper::client` but not
+/// `hyperlocal`.
+fn matches(prefix: &str, target: &str) -> Option<usize> {
+    let rest = target.strip_prefix(prefix)?;
+    if rest.is_empty() || rest.starts_with("::") || prefix.is_empty() {
+        Some(prefix.len())
+    } else {
+        None
+    }
+}
+
+fn longest_match(prefixes: &[String], target: &str) -> Option<usize> {
+    prefixes.iter().filter_map(|p| matches(p, target)).max()
+}
+
+impl TargetFilter {
+    pub fn include(&mut self, targets: impl IntoIterator<Item = String>) {
+        self.include.extend(targets);
+    }
+
+    pub fn exclude(&mut self, targets: impl IntoIterator<Item = String>) {
+        self.exclude.extend(targets);
+    }
+
+    /// The longest matching prefix decides, with exclusion winning a tie.  Targets matching neither list are
+    /// only recorded if there are no included targets.
+    pub fn enabled(&self, target: &str) -> bool {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return true;
+        }
+        match (
+            longest_match(&self.include, target),
+            longest_match(&self.exclude, target),
+        ) {
+            (Some(i), Some(e)) => i > e,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => self.include.is_empty(),
+        }
+    }
+}
@@ -1,5 +1,14 @@
-use crate::{Event, EventKind, FieldValue, Span};
+use crate::time::Clock;
+use crate::writer::WriteEvent;
+#[cfg(feature = "consumer")]
+use crate::{consumer::StreamFormat, format::Json};
+use crate::{Event, EventKind, FieldValue, SerdeFormat, SerdeLayerBuilder, Span};
+use indexmap::IndexMap;
 use itertools::Itertools;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::layer::SubscriberExt;
 
 use self::ser::SpanItem;
 
@@ -22,42 +31,61 @@ pub fn eq_field_values(a: &FieldValue, b: &ser::FieldValue) -> bool {
     use crate::FieldValue::*;
 
     match (a, b) {
-        (Int(a), ser::Int(b)) => a == b,
         (Bool(a), ser::Bool(b)) => a == b,
         (Float(a), ser::Float(b)) => float_eq(*a, *b),
         (Str(a), ser::Str(b)) => a == b,
+        (Null, ser::Null) => true,
+        // Integers compare by value, regardless of variant
+        (a, b @ (ser::Int(_) | ser::UInt(_) | ser::I128(_) | ser::U128(_))) => {
+            *a == FieldValue::from(b)
+        }
         _ => false,
     }
 }
 
-pub fn eq_kind(a: &EventKind, b: &ser::EventKind) -> bool {
+fn eq_event_fields(a_fields: &IndexMap<String, FieldValue>, b_fields: &ser::EventFields) -> bool {
     // We should preserve the order of fields
-    match (a, b) {
-        (EventKind::Event(a_fields), ser::EventKind::Event(b_fields)) => {
-            if a_fields.len() != b_fields.len() {
-                return false;
-            }
+    if a_fields.len() != b_fields.len() {
+        return false;
+    }
 
-            if !a_fields
-                .iter()
-                .zip(b_fields)
-                .all(|((af, av), (bf, bv))| af == bf && eq_field_values(av, bv))
-            {
-                return false;
-            }
+    if !a_fields
+        .iter()
+        .zip(b_fields)
+        .all(|((af, av), (bf, bv))| af == bf && eq_field_values(av, bv))
+    {
+        return false;
+    }
 
-            for (name, val) in b_fields {
-                match a_fields.get(*name) {
-                    Some(v) if eq_field_values(v, val) => continue,
-                    _ => return false,
-                }
-            }
-            return true;
+    for (name, val) in b_fields {
+        match a_fields.get(*name) {
+            Some(v) if eq_field_values(v, val) => continue,
+            _ => return false,
+        }
+    }
+    true
+}
+
+pub fn eq_kind(a: &EventKind, b: &ser::EventKind) -> bool {
+    match (a, b) {
+        (EventKind::Event(a_fields), ser::EventKind::Event(b_fields)) => {
+            eq_event_fields(a_fields, b_fields)
+        }
+        (EventKind::SpanRecord(a_fields), ser::EventKind::SpanRecord(b_fields)) => {
+            eq_event_fields(a_fields, b_fields)
         }
         (EventKind::SpanCreate, ser::EventKind::SpanCreate) => true,
         (EventKind::SpanEnter, ser::EventKind::SpanEnter) => true,
         (EventKind::SpanExit, ser::EventKind::SpanExit) => true,
         (EventKind::SpanClose(a), ser::EventKind::SpanClose(b)) => a == b,
+        (EventKind::SpanProgress(a), ser::EventKind::SpanProgress(b)) => a == b,
+        (
+            EventKind::SpanFollowsFrom { from, to },
+            ser::EventKind::SpanFollowsFrom {
+                from: b_from,
+                to: b_to,
+            },
+        ) => from == b_from && to == b_to,
         _ => false,
     }
 }
@@ -65,10 +93,23 @@ pub fn eq_kind(a: &EventKind, b: &ser::EventKind) -> bool {
 pub fn eq_span(a: &Span, b: &[ser::SpanItem]) -> bool {
     match &b[0] {
         ser::SpanItem::Field { .. } => panic!("first element should be SpanItem::Start"),
-        ser::SpanItem::Start { span_name, id } => {
+        ser::SpanItem::Start {
+            span_name,
+            id,
+            declared_fields,
+        } => {
             if &a.id != id || a.name != *span_name {
                 return false;
             }
+            let declared_fields =
+                declared_fields.map(|fs| fs.iter().map(|f| f.name()).collect::<Vec<_>>());
+            let a_declared_fields = a
+                .declared_fields
+                .as_ref()
+                .map(|v| v.iter().map(String::as_str).collect::<Vec<_>>());
+            if a_declared_fields != declared_fields {
+                return false;
+            }
             let fields = &b[1..];
             if a.fields.len() != fields.len() {
                 return false;
@@ -122,12 +163,39 @@ pub fn eq_event_ser_event(a: &Event, b: &ser::Event) -> bool {
         src_file,
         src_line,
         time,
+        uptime_ns,
+        syslog_pri,
+        instance_id,
+        time_missing,
+        crate_name,
+        crate_version,
+        globals,
+        pid,
+        host,
+        name,
+        module,
+        seq,
+        id,
+        written_time: _,
     } = a;
 
     eq_kind(kind, &b.kind)
         && level == &b.level
         && target == &b.target
         && time == &b.time
+        && uptime_ns == &b.uptime_ns
+        && syslog_pri == &b.syslog_pri
+        && instance_id == &b.instance_id
+        && time_missing == &b.time_missing
+        && crate_name.as_deref() == b.crate_name
+        && crate_version.as_deref() == b.crate_version
+        && globals.iter().eq(b.globals.iter().map(|(n, v)| (n, v)))
+        && pid == &b.pid
+        && host.as_deref() == b.host
+        && name.as_deref() == b.name
+        && module.as_deref() == b.module
+        && seq == &b.seq
+        && id == &b.id
         && thread_id == &b.thread_id
         && thread_name.as_ref().map(String::as_str) == b.thread_name
         && src_line == &b.src_line
@@ -147,12 +215,40 @@ pub fn eq_event(a: &Event, b: &Event) -> bool {
         src_file,
         src_line,
         time,
+        uptime_ns,
+        syslog_pri,
+        instance_id,
+        time_missing,
+        crate_name,
+        crate_version,
+        globals,
+        pid,
+        host,
+        name,
+        module,
+        seq,
+        id,
+        written_time,
     } = a;
 
     if !(kind == &b.kind
         && level == &b.level
         && target == &b.target
         && time == &b.time
+        && uptime_ns == &b.uptime_ns
+        && syslog_pri == &b.syslog_pri
+        && instance_id == &b.instance_id
+        && time_missing == &b.time_missing
+        && crate_name == &b.crate_name
+        && crate_version == &b.crate_version
+        && globals == &b.globals
+        && pid == &b.pid
+        && host == &b.host
+        && name == &b.name
+        && module == &b.module
+        && seq == &b.seq
+        && id == &b.id
+        && written_time == &b.written_time
         && thread_id == &b.thread_id
         && thread_name == &b.thread_name
         && src_line == &b.src_line
@@ -169,3 +265,45 @@ pub fn eq_event(a: &Event, b: &Event) -> bool {
     }
     true
 }
+
+/// Run `f` with `layer` as the only layer of the subscriber, and return what it wrote.
+pub fn capture_output<F, C, W>(layer: SerdeLayerBuilder<F, C, W>, f: impl FnOnce()) -> Vec<u8>
+where
+    F: SerdeFormat + Send + Sync + 'static,
+    C: Clock + Send + Sync + 'static,
+    W: WriteEvent,
+{
+    let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let layer = layer.with_writer(Arc::clone(&buffer)).finish();
+    tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), f);
+    let output = buffer.lock().unwrap();
+    output.clone()
+}
+
+/// Run `f` with `layer` as the only layer of the subscriber, and read back the events it wrote.
+#[cfg(feature = "consumer")]
+pub fn capture<C, W>(layer: SerdeLayerBuilder<Json, C, W>, f: impl FnOnce()) -> Vec<Event>
+where
+    C: Clock + Send + Sync + 'static,
+    W: WriteEvent,
+{
+    let output = capture_output(layer, f);
+    Json.iter_reader(output.as_slice())
+        .collect::<std::io::Result<_>>()
+        .unwrap()
+}
+
+/// A new, empty directory for a test's files.  Unique to the process and the call, so tests running at the
+/// same time never share files.
+pub fn temp_dir(name: &str) -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "tracing-subscriber-serde-{}-{}-{}",
+        name,
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
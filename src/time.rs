@@ -1,5 +1,7 @@
 //! Utilities and traits for storing and producing span timings and event timestamps.
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// Timing information about a span's lifetime.
@@ -25,29 +27,45 @@ impl SpanTime {
 pub(crate) struct SpanTimer {
     busy: u64,
     idle: u64,
+    /// Whether the time since `last_update` is busy
+    entered: bool,
     last_update: Instant,
+    /// The busy and idle time already reported by [`SpanTimer::increment`]
+    reported: SpanTime,
+    last_report: Instant,
 }
 
 impl SpanTimer {
-    pub fn new() -> Self {
+    pub fn new(now: Instant) -> Self {
         SpanTimer {
             busy: 0,
             idle: 0,
-            last_update: Instant::now(),
+            entered: false,
+            last_update: now,
+            reported: SpanTime { busy: 0, idle: 0 },
+            last_report: now,
         }
     }
 }
 
 impl SpanTimer {
-    pub fn start_busy(&mut self) {
-        let now = Instant::now();
-        self.idle += now.duration_since(self.last_update).as_nanos() as u64;
-        self.last_update = now;
+    pub fn start_busy(&mut self, now: Instant) {
+        self.update(now);
+        self.entered = true;
+    }
+
+    pub fn end_busy(&mut self, now: Instant) {
+        self.update(now);
+        self.entered = false;
     }
 
-    pub fn end_busy(&mut self) {
-        let now = Instant::now();
-        self.busy += now.duration_since(self.last_update).as_nanos() as u64;
+    fn update(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_update).as_nanos() as u64;
+        if self.entered {
+            self.busy += elapsed;
+        } else {
+            self.idle += elapsed;
+        }
         self.last_update = now;
     }
 
@@ -57,6 +75,27 @@ impl SpanTimer {
             idle: self.idle,
         }
     }
+
+    /// The busy and idle time since the last increment, up to `now`, if at least `interval` has passed since
+    /// then.
+    pub fn increment(&mut self, now: Instant, interval: Duration) -> Option<SpanTime> {
+        if now.duration_since(self.last_report) < interval {
+            return None;
+        }
+        self.update(now);
+        self.last_report = now;
+        Some(self.remainder())
+    }
+
+    /// The busy and idle time since the last increment.  The increments sum to [`SpanTimer::finish`].
+    pub fn remainder(&mut self) -> SpanTime {
+        let delta = SpanTime {
+            busy: self.busy - self.reported.busy,
+            idle: self.idle - self.reported.idle,
+        };
+        self.reported = self.finish();
+        delta
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -97,12 +136,89 @@ impl From<UnixTime> for SystemTime {
     }
 }
 
+#[cfg(feature = "chrono")]
+#[cfg_attr(docsrs, doc(cfg(feature = "chrono")))]
+impl From<UnixTime> for chrono::DateTime<chrono::Utc> {
+    fn from(t: UnixTime) -> Self {
+        SystemTime::from(t).into()
+    }
+}
+
+impl UnixTime {
+    /// Format as an [RFC 3339](https://datatracker.ietf.org/doc/html/rfc3339) timestamp in UTC with
+    /// nanosecond precision, e.g. `2022-01-31T09:05:00.000000123Z`.
+    pub fn to_rfc3339(&self) -> String {
+        let days = self.seconds / 86400;
+        let secs = self.seconds % 86400;
+        // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719468;
+        let era = z / 146097;
+        let doe = z % 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + u64::from(month <= 2);
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs % 3600 / 60,
+            secs % 60,
+            self.nanos
+        )
+    }
+}
+
 /// Tells the time in the only time worth telling: [`UnixTime`].
 pub trait Clock {
     /// Get the current time for timestamping purposes.
     ///
     /// Returning `None` indicates no timestamp should be recorded.
     fn time(&self) -> Option<UnixTime>;
+
+    /// Whether [`Clock::time`] produces timestamps, as recorded in the
+    /// [`Preamble`](crate::writer::Preamble).  Asked instead of calling `time`, which may change the clock.
+    /// The default is `true`.
+    fn has_timestamps(&self) -> bool {
+        true
+    }
+
+    /// Get the current instant, used to time spans.  The default is [`Instant::now`], which can't go
+    /// backwards even when the time does.
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Boxed clocks, such as `Box<dyn Clock + Send + Sync>`, let the clock be chosen at runtime without changing
+/// the type of the [`SerdeLayer`](crate::SerdeLayer):
+/// ```
+/// use tracing_subscriber_serde::{SerdeLayer, time::{Clock, ManualClock, SystemClock}};
+///
+/// let testing = std::env::var_os("TESTING").is_some();
+/// let clock: Box<dyn Clock + Send + Sync> = if testing {
+///     Box::new(ManualClock::default())
+/// } else {
+///     Box::new(SystemClock::default())
+/// };
+/// let layer = SerdeLayer::new().with_clock(clock).finish();
+/// ```
+impl<C: Clock + ?Sized> Clock for Box<C> {
+    fn time(&self) -> Option<UnixTime> {
+        (**self).time()
+    }
+
+    fn has_timestamps(&self) -> bool {
+        (**self).has_timestamps()
+    }
+
+    fn instant(&self) -> Instant {
+        (**self).instant()
+    }
 }
 
 #[derive(Copy, Clone, Default)]
@@ -124,4 +240,105 @@ impl Clock for () {
     fn time(&self) -> Option<UnixTime> {
         None
     }
+
+    fn has_timestamps(&self) -> bool {
+        false
+    }
+}
+
+/// A [`Clock`] which only changes when told to, for tests which check timestamps or span timings.
+///
+/// Clones share the same time, so a test can keep a clone to step the clock given to a
+/// [`SerdeLayer`](crate::SerdeLayer):
+/// ```
+/// use std::time::Duration;
+/// use tracing_subscriber_serde::{SerdeLayer, time::ManualClock};
+///
+/// let clock = ManualClock::new(Duration::from_secs(1_600_000_000).into());
+/// let layer = SerdeLayer::new().with_clock(clock.clone()).finish();
+/// // ... emit an event ...
+/// clock.advance(Duration::from_millis(5));
+/// // ... emit another event, 5ms later ...
+/// ```
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    /// Nanoseconds since the UNIX epoch
+    nanos: Arc<AtomicU64>,
+    /// The instant corresponding to the UNIX epoch.  Only differences between instants matter, so this
+    /// needn't be in the past.
+    epoch: Instant,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock {
+            nanos: Arc::default(),
+            epoch: Instant::now(),
+        }
+    }
+}
+
+impl ManualClock {
+    /// A clock stopped at `time`.
+    pub fn new(time: UnixTime) -> Self {
+        let clock = ManualClock::default();
+        clock.set(time);
+        clock
+    }
+
+    /// Set the time.
+    pub fn set(&self, time: UnixTime) {
+        let nanos = Duration::from(time).as_nanos() as u64;
+        self.nanos.store(nanos, Ordering::SeqCst);
+    }
+
+    /// Move the time forward by `d`.
+    pub fn advance(&self, d: Duration) {
+        self.nanos.fetch_add(d.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn time(&self) -> Option<UnixTime> {
+        Some(Duration::from_nanos(self.nanos.load(Ordering::SeqCst)).into())
+    }
+
+    fn instant(&self) -> Instant {
+        self.epoch + Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3339() {
+        let at = |s, n| UnixTime::from(Duration::new(s, n)).to_rfc3339();
+        assert_eq!(at(0, 0), "1970-01-01T00:00:00.000000000Z");
+        assert_eq!(at(951782400, 1), "2000-02-29T00:00:00.000000001Z");
+        assert_eq!(
+            at(1643619900, 123_456_789),
+            "2022-01-31T09:05:00.123456789Z"
+        );
+        assert_eq!(
+            at(4102444799, 999_999_999),
+            "2099-12-31T23:59:59.999999999Z"
+        );
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn chrono_matches_rfc3339() {
+        use chrono::{DateTime, SecondsFormat, Utc};
+
+        for &(s, n) in &[(0, 0), (951782400, 1), (1643619900, 123_456_789)] {
+            let t = UnixTime::from(Duration::new(s, n));
+            let dt = DateTime::<Utc>::from(t.clone());
+            assert_eq!(
+                dt.to_rfc3339_opts(SecondsFormat::Nanos, true),
+                t.to_rfc3339()
+            );
+        }
+    }
 }
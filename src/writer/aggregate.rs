@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use flume::{RecvTimeoutError, Sender};
+use serde::Serialize;
+
+use super::{EventInfo, LockResultExt, Preamble, WriteEvent};
+use crate::subscriber::serialize::{Event, EventFields, EventKind, FieldValue};
+use crate::time::{Clock, SystemClock};
+use crate::{Level, SerdeFormat};
+
+const SUMMARY_TARGET: &str = "tracing_subscriber_serde::summary";
+
+/// Field names of the counts of each level, indexed by [`Level`]
+const LEVEL_NAMES: [&str; 5] = ["trace", "debug", "info", "warn", "error"];
+
+#[derive(Default)]
+struct Counts {
+    /// The number of events of each level
+    levels: [u64; 5],
+    targets: HashMap<String, u64>,
+    /// Events of targets which didn't fit in `targets`
+    other: u64,
+}
+
+struct AggregateState {
+    counts: Counts,
+    window_start: Instant,
+}
+
+/// The writer and its configuration, shared with the timer thread
+struct Shared<W, F, C> {
+    inner: W,
+    fmt: F,
+    clock: C,
+    interval: Duration,
+    top_targets: usize,
+    max_targets: usize,
+    state: Mutex<AggregateState>,
+}
+
+/// Writes summaries while no events arrive, and the final summary when dropped
+struct Timer {
+    /// Dropping this stops the thread
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// A writer which counts events by level and target instead of writing them, and periodically writes a
+/// summary event to the inner `WriteEvent`.  Useful as a cheap, always-on baseline.
+///
+/// The summary is an [`Level::Info`] event with target `"tracing_subscriber_serde::summary"`, a timestamp
+/// from the [`Clock`] (see [`Aggregate::with_clock`]) and the fields:
+/// - `message`: `"summary"`
+/// - `window_ms`: the length of the period covered by the summary
+/// - `trace`, `debug`, `info`, `warn` and `error`: the number of events of each level
+/// - `target.<target>`: the number of events of each of the most common targets, see
+///   [`Aggregate::top_targets`]
+/// - `other_targets`: the number of events of all other targets
+///
+/// A summary is written every `interval`, when [`Aggregate::summarize`] is called, and when the writer is
+/// dropped, unless no events were counted.  The first event counted starts a background thread which
+/// writes summaries while no events arrive; errors writing from that thread are ignored.  With a zero
+/// `interval`, a summary is written after every event and no thread is started.
+///
+/// Summaries are written without holding the lock used to count events, so a slow inner writer doesn't
+/// hold up other threads.  Events written with [`WriteEvent::write`], which carry no level or target, are
+/// passed straight through.
+pub struct Aggregate<W, F, C = SystemClock> {
+    // Dropped first, so the timer thread writes the final summary while the writer is still alive
+    timer: OnceLock<Timer>,
+    shared: Arc<Shared<W, F, C>>,
+}
+
+impl<W: WriteEvent, F: SerdeFormat> Aggregate<W, F> {
+    /// Write summaries to `inner` using `fmt`.  By default, a summary is written every 60 seconds with the
+    /// 10 most common targets, and at most 1000 targets are counted.
+    pub fn new(inner: W, fmt: F) -> Self {
+        Aggregate {
+            timer: OnceLock::new(),
+            shared: Arc::new(Shared {
+                inner,
+                fmt,
+                clock: SystemClock::default(),
+                interval: Duration::from_secs(60),
+                top_targets: 10,
+                max_targets: 1000,
+                state: Mutex::new(AggregateState {
+                    counts: Counts::default(),
+                    window_start: Instant::now(),
+                }),
+            }),
+        }
+    }
+}
+
+impl<W: WriteEvent, F: SerdeFormat, C: Clock> Aggregate<W, F, C> {
+    fn configure(&mut self) -> &mut Shared<W, F, C> {
+        // The timer thread only starts when the first event is counted
+        Arc::get_mut(&mut self.shared).expect("Aggregate configured after use")
+    }
+
+    /// Sets the time between summaries.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.configure().interval = interval;
+        self
+    }
+
+    /// Sets the number of targets reported individually in each summary.  The remaining targets are counted
+    /// in `other_targets`.
+    pub fn top_targets(mut self, k: usize) -> Self {
+        self.configure().top_targets = k;
+        self
+    }
+
+    /// Sets the maximum number of distinct targets counted between summaries, bounding memory use.  Events
+    /// of further targets are counted in `other_targets`.  Should be at least
+    /// [`top_targets`](Aggregate::top_targets).
+    pub fn max_targets(mut self, n: usize) -> Self {
+        self.configure().max_targets = n;
+        self
+    }
+
+    /// Use the supplied [`Clock`] to timestamp summaries, instead of [`SystemClock`].
+    ///
+    /// The length of each period is measured with [`std::time::Instant`] regardless of the clock.
+    pub fn with_clock<C2: Clock>(self, clock: C2) -> Aggregate<W, F, C2> {
+        let Shared {
+            inner,
+            fmt,
+            clock: _,
+            interval,
+            top_targets,
+            max_targets,
+            state,
+        } = Arc::try_unwrap(self.shared)
+            .ok()
+            .expect("Aggregate configured after use");
+        Aggregate {
+            timer: OnceLock::new(),
+            shared: Arc::new(Shared {
+                inner,
+                fmt,
+                clock,
+                interval,
+                top_targets,
+                max_targets,
+                state,
+            }),
+        }
+    }
+
+    /// Write a summary of the events counted since the previous summary now, and start a new period.  Does
+    /// nothing if no events were counted.
+    pub fn summarize(&self) -> io::Result<()> {
+        self.shared.summarize()
+    }
+}
+
+impl<W: WriteEvent, F: SerdeFormat, C: Clock> Shared<W, F, C> {
+    fn summarize(&self) -> io::Result<()> {
+        let taken = take_window(&mut self.state.lock().ignore_poisoned());
+        self.write_summary(taken)
+    }
+
+    /// Summarize if the current period is over, returning the time until the next one is
+    fn tick(&self) -> Duration {
+        let mut state = self.state.lock().ignore_poisoned();
+        let elapsed = state.window_start.elapsed();
+        if elapsed < self.interval {
+            return self.interval - elapsed;
+        }
+        let taken = take_window(&mut state);
+        drop(state);
+        let _ = self.write_summary(taken);
+        self.interval
+    }
+
+    fn write_summary(&self, (window, counts): (Duration, Counts)) -> io::Result<()> {
+        if counts.levels.iter().all(|&n| n == 0) {
+            return Ok(());
+        }
+
+        let mut targets: Vec<_> = counts.targets.into_iter().collect();
+        // Most common first, ties broken by name so summaries are deterministic
+        targets.sort_unstable_by(|(a, m), (b, n)| n.cmp(m).then_with(|| a.cmp(b)));
+        let other = counts.other
+            + targets
+                .iter()
+                .skip(self.top_targets)
+                .map(|(_, n)| n)
+                .sum::<u64>();
+        targets.truncate(self.top_targets);
+        let target_fields: Vec<_> = targets
+            .into_iter()
+            .map(|(target, n)| (format!("target.{}", target), n))
+            .collect();
+
+        let mut fields = EventFields::new();
+        fields.push(("message", FieldValue::Str("summary".into())));
+        fields.push(("window_ms", FieldValue::from_u128(window.as_millis())));
+        for (name, n) in LEVEL_NAMES.iter().zip(&counts.levels) {
+            fields.push((name, FieldValue::from_u128((*n).into())));
+        }
+        for (name, n) in &target_fields {
+            fields.push((name.as_str(), FieldValue::from_u128((*n).into())));
+        }
+        fields.push(("other_targets", FieldValue::from_u128(other.into())));
+
+        let event = Event::synthetic(
+            Level::Info,
+            SUMMARY_TARGET,
+            EventKind::Event(fields),
+            self.clock.time(),
+        );
+        self.inner
+            .write_event(&EventInfo::from_event(&event), &self.fmt, &event)
+    }
+
+    /// Count an event, returning the counts to summarize if the current period is over
+    fn count(&self, info: &EventInfo<'_>) -> Option<(Duration, Counts)> {
+        let mut state = self.state.lock().ignore_poisoned();
+        let counts = &mut state.counts;
+        counts.levels[info.level() as usize] += 1;
+        if let Some(n) = counts.targets.get_mut(info.target()) {
+            *n += 1;
+        } else if counts.targets.len() < self.max_targets {
+            counts.targets.insert(info.target().to_string(), 1);
+        } else {
+            counts.other += 1;
+        }
+
+        if state.window_start.elapsed() >= self.interval {
+            Some(take_window(&mut state))
+        } else {
+            None
+        }
+    }
+}
+
+/// End the current period, returning its length and counts
+fn take_window(state: &mut AggregateState) -> (Duration, Counts) {
+    let now = Instant::now();
+    let window = now.duration_since(state.window_start);
+    state.window_start = now;
+    (window, std::mem::take(&mut state.counts))
+}
+
+impl Timer {
+    fn spawn<W, F, C>(shared: Arc<Shared<W, F, C>>) -> Self
+    where
+        W: WriteEvent + Send + Sync + 'static,
+        F: SerdeFormat + Send + Sync + 'static,
+        C: Clock + Send + Sync + 'static,
+    {
+        let (stop, stopped) = flume::bounded(0);
+        let handle = std::thread::spawn(move || {
+            let mut wait = shared.interval;
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(wait) {
+                wait = shared.tick();
+            }
+            let _ = shared.summarize();
+        });
+        Timer {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl<W, F, C> WriteEvent for Aggregate<W, F, C>
+where
+    W: WriteEvent + Send + Sync + 'static,
+    F: SerdeFormat + Send + Sync + 'static,
+    C: Clock + Send + Sync + 'static,
+{
+    fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
+        self.shared.inner.write(fmt, event)
+    }
+
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        _fmt: impl SerdeFormat,
+        _event: impl Serialize,
+    ) -> io::Result<()> {
+        if !self.shared.interval.is_zero() {
+            self.timer
+                .get_or_init(|| Timer::spawn(Arc::clone(&self.shared)));
+        }
+        match self.shared.count(info) {
+            Some(taken) => self.shared.write_summary(taken),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.shared.inner.flush()
+    }
+
+    fn set_preamble(&self, preamble: &[u8]) {
+        self.shared.inner.set_preamble(preamble)
+    }
+
+    fn serialize_preamble(&self, _fmt: impl SerdeFormat, preamble: &Preamble) {
+        // Only summaries are written, in the writer's own format
+        self.shared
+            .inner
+            .serialize_preamble(&self.shared.fmt, preamble)
+    }
+
+    fn healthcheck(&self) -> io::Result<()> {
+        self.shared.inner.healthcheck()
+    }
+}
+
+#[cfg(all(test, feature = "consumer"))]
+mod tests {
+    use super::*;
+    use crate::consumer::StreamFormat;
+    use crate::format::Json;
+    use crate::time::{ManualClock, UnixTime};
+    use crate::SerdeLayer;
+    use std::sync::Arc;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn summaries(interval: Duration, max_targets: usize) -> Vec<crate::Event> {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let writer = Aggregate::new(Arc::clone(&buffer), Json)
+            .interval(interval)
+            .top_targets(2)
+            .max_targets(max_targets);
+        let layer = SerdeLayer::new().with_writer(writer).finish();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            for i in 0..100 {
+                match i % 5 {
+                    0 => tracing::error!(target: "app::db", "error"),
+                    1 => tracing::warn!(target: "app::db", "warn"),
+                    2 => tracing::info!(target: "app::http", "info"),
+                    3 => tracing::debug!(target: "app::cache", "debug"),
+                    _ => tracing::trace!(target: "hyper", "trace"),
+                }
+            }
+        });
+        // The layer, and so the writer, has been dropped, writing the final summary
+
+        let buffer = buffer.lock().unwrap();
+        Json.iter_reader(buffer.as_slice())
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    fn count(summary: &crate::Event, field: &str) -> u64 {
+        match &summary.kind {
+            crate::EventKind::Event(fields) => match fields.get(field) {
+                Some(crate::FieldValue::Int(n)) => *n as u64,
+                None => 0,
+                v => panic!("unexpected value {:?}", v),
+            },
+            _ => panic!("not a summary"),
+        }
+    }
+
+    fn total(summaries: &[crate::Event], fields: &[&str]) -> u64 {
+        summaries
+            .iter()
+            .flat_map(|s| fields.iter().map(move |f| count(s, f)))
+            .sum()
+    }
+
+    #[test]
+    fn counts_add_up() {
+        let summaries = summaries(Duration::from_secs(3600), 1000);
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0];
+        assert_eq!(summary.target, SUMMARY_TARGET);
+        assert_eq!(total(&summaries, &LEVEL_NAMES), 100);
+        for level in &LEVEL_NAMES {
+            assert_eq!(count(summary, level), 20);
+        }
+        assert_eq!(count(summary, "target.app::db"), 40);
+        // Ties are broken by name
+        assert_eq!(count(summary, "target.app::cache"), 20);
+        assert_eq!(count(summary, "target.app::http"), 0);
+        assert_eq!(count(summary, "other_targets"), 40);
+    }
+
+    #[test]
+    fn bounded_targets() {
+        // Every event after the first two targets goes straight to the other bucket
+        let summaries = summaries(Duration::from_secs(3600), 2);
+        let summary = &summaries[0];
+        assert_eq!(count(summary, "target.app::db"), 40);
+        assert_eq!(count(summary, "target.app::http"), 20);
+        assert_eq!(count(summary, "other_targets"), 40);
+    }
+
+    #[test]
+    fn periodic_summaries() {
+        let summaries = summaries(Duration::from_secs(0), 1000);
+        // Summaries are written after every event
+        assert_eq!(summaries.len(), 100);
+        assert_eq!(total(&summaries, &LEVEL_NAMES), 100);
+        assert_eq!(
+            total(
+                &summaries,
+                &[
+                    "target.app::db",
+                    "target.app::http",
+                    "target.app::cache",
+                    "target.hyper",
+                    "other_targets"
+                ]
+            ),
+            100
+        );
+    }
+
+    #[test]
+    fn idle_periods_flush() {
+        let time = UnixTime::from(Duration::from_secs(1_600_000_000));
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let writer = Aggregate::new(Arc::clone(&buffer), Json)
+            .interval(Duration::from_millis(20))
+            .with_clock(ManualClock::new(time.clone()));
+        let layer = SerdeLayer::new().with_writer(writer).finish();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::info!("only event");
+            // No further events arrive to trigger the summary
+            std::thread::sleep(Duration::from_millis(200));
+            let buffer = buffer.lock().unwrap();
+            let summaries: Vec<crate::Event> = Json
+                .iter_reader(buffer.as_slice())
+                .collect::<io::Result<_>>()
+                .unwrap();
+            assert_eq!(summaries.len(), 1);
+            assert_eq!(count(&summaries[0], "info"), 1);
+            assert_eq!(summaries[0].time, Some(time));
+        });
+    }
+}
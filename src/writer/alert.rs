@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::{EventInfo, LockResultExt, Preamble, WriteEvent};
+use crate::{Level, SerdeFormat};
+
+struct AlertState {
+    errors: VecDeque<Instant>,
+    last_alert: Option<Instant>,
+}
+
+/// A wrapper type which calls a user-supplied closure when the number of
+/// [`Level::Error`] events within a sliding window reaches a threshold.
+///
+/// The closure is called with the number of errors in the window, and is called at most once per cooldown
+/// period.  By default, the window and cooldown are both 60 seconds and the threshold is 10 errors.
+///
+/// Events are always passed on to the inner `WriteEvent`.
+pub struct AlertWriter<W> {
+    inner: W,
+    window: Duration,
+    cooldown: Duration,
+    threshold: usize,
+    on_alert: Box<dyn Fn(usize) + Send + Sync>,
+    state: Mutex<AlertState>,
+}
+
+impl<W: WriteEvent> AlertWriter<W> {
+    /// Wrap the inner `WriteEvent`, calling `on_alert` when the error rate exceeds the threshold.
+    pub fn new(inner: W, on_alert: impl Fn(usize) + Send + Sync + 'static) -> Self {
+        AlertWriter {
+            inner,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(60),
+            threshold: 10,
+            on_alert: Box::new(on_alert),
+            state: Mutex::new(AlertState {
+                errors: VecDeque::new(),
+                last_alert: None,
+            }),
+        }
+    }
+
+    /// Sets the length of the sliding window in which errors are counted.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Sets the number of errors within the window which triggers an alert.
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Sets the minimum time between alerts.
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    fn record_error(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().ignore_poisoned();
+
+        state.errors.push_back(now);
+        while let Some(&t) = state.errors.front() {
+            if now.duration_since(t) > self.window {
+                state.errors.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let num_errors = state.errors.len();
+        if num_errors < self.threshold {
+            return;
+        }
+
+        let cooled_down = match state.last_alert {
+            Some(t) => now.duration_since(t) >= self.cooldown,
+            None => true,
+        };
+
+        if cooled_down {
+            state.last_alert = Some(now);
+            // Don't hold the lock while running user code
+            drop(state);
+            (self.on_alert)(num_errors);
+        }
+    }
+}
+
+impl<W: WriteEvent> WriteEvent for AlertWriter<W> {
+    fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
+        self.inner.write(fmt, event)
+    }
+
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        fmt: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        if info.level() == Level::Error {
+            self.record_error();
+        }
+        self.inner.write_event(info, fmt, event)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn set_preamble(&self, preamble: &[u8]) {
+        self.inner.set_preamble(preamble)
+    }
+
+    fn serialize_preamble(&self, fmt: impl SerdeFormat, preamble: &Preamble) {
+        self.inner.serialize_preamble(fmt, preamble)
+    }
+
+    fn healthcheck(&self) -> io::Result<()> {
+        self.inner.healthcheck()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn alerts_once_per_cooldown() {
+        let alerts = Arc::new(AtomicUsize::new(0));
+        let writer = {
+            let alerts = Arc::clone(&alerts);
+            AlertWriter::new(Mutex::new(Vec::<u8>::new()), move |_| {
+                alerts.fetch_add(1, Ordering::SeqCst);
+            })
+            .threshold(3)
+            .window(Duration::from_secs(60))
+            .cooldown(Duration::from_secs(60))
+        };
+
+        for _ in 0..5 {
+            writer
+                .write_event(&EventInfo::new(Level::Info, "test"), Json, "ok")
+                .unwrap();
+        }
+        assert_eq!(alerts.load(Ordering::SeqCst), 0);
+
+        for _ in 0..20 {
+            writer
+                .write_event(&EventInfo::new(Level::Error, "test"), Json, "oh no")
+                .unwrap();
+        }
+        assert_eq!(alerts.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            writer.inner.lock().unwrap().split(|&b| b == b'\n').count(),
+            26
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::{self, Write};
+
+use crate::time::{Clock, SystemClock};
+use crate::SerdeFormat;
+
+/// A [Writer](std::io::Write) wrapper which adds the time each record was written, as the `"wt"` key
+/// of each JSON record.
+///
+/// This is intended to wrap the writer given to [`NonBlocking`](crate::writer::NonBlocking), so that the
+/// time is taken on the writer thread.  The difference between the written time and the event timestamp shows
+/// how far behind the writer is; see [`writer_lag_stats`](crate::consumer::writer_lag_stats).  The written time
+/// is deserialized into [`Event::written_time`](crate::Event::written_time).
+///
+/// Records are expected to be JSON objects terminated by newlines.  Any other lines are passed through
+/// unchanged.
+pub struct Annotate<W> {
+    inner: W,
+    clock: SystemClock,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Annotate<W> {
+    /// Wrap the inner Writer.  Returns an error if `fmt` does not produce
+    /// [JSON lines](SerdeFormat::is_json_lines), such as [`Json`](crate::format::Json).
+    pub fn new(inner: W, fmt: &impl SerdeFormat) -> io::Result<Self> {
+        if !fmt.is_json_lines() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Annotate requires a format which produces JSON objects separated by newlines",
+            ));
+        }
+        Ok(Annotate {
+            inner,
+            clock: SystemClock::default(),
+            pending: Vec::new(),
+        })
+    }
+}
+
+impl<W: Write> Write for Annotate<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+
+        let time = self.clock.time();
+        let mut output = Vec::with_capacity(self.pending.len() + 32);
+        let mut start = 0;
+
+        while let Some(k) = self.pending[start..].iter().position(|&b| b == b'\n') {
+            let line = &self.pending[start..start + k];
+            match (line.last(), time.as_ref()) {
+                (Some(b'}'), Some(t)) if line.len() > 2 => {
+                    output.extend_from_slice(&line[..line.len() - 1]);
+                    writeln!(
+                        &mut output,
+                        ",\"wt\":{{\"s\":{},\"n\":{}}}}}",
+                        t.seconds, t.nanos
+                    )?;
+                }
+                _ => {
+                    output.extend_from_slice(line);
+                    output.push(b'\n');
+                }
+            }
+            start += k + 1;
+        }
+
+        self.pending.drain(..start);
+        self.inner.write_all(&output)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(all(test, feature = "consumer"))]
+mod tests {
+    use super::*;
+    use crate::consumer::{writer_lag_stats, StreamFormat};
+    use crate::format::Json;
+    use crate::writer::NonBlocking;
+    use crate::SerdeLayer;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    struct StallingWriter {
+        buffer: Arc<Mutex<Vec<u8>>>,
+        stall: Option<Duration>,
+    }
+
+    impl Write for StallingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if let Some(stall) = self.stall.take() {
+                std::thread::sleep(stall);
+            }
+            self.buffer.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rejects_non_json() {
+        struct NotJson;
+        impl SerdeFormat for NotJson {
+            fn message_size_hint(&self) -> usize {
+                0
+            }
+
+            fn serialize(&self, _: impl Write, _: impl serde::Serialize) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        assert!(Annotate::new(Vec::new(), &NotJson).is_err());
+        assert!(Annotate::new(Vec::new(), &Json).is_ok());
+    }
+
+    #[test]
+    fn measures_writer_lag() {
+        let stall = Duration::from_millis(300);
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let writer = StallingWriter {
+            buffer: Arc::clone(&buffer),
+            stall: Some(stall),
+        };
+        let (writer, guard) = NonBlocking::new().finish(Annotate::new(writer, &Json).unwrap());
+
+        let layer = SerdeLayer::new()
+            .with_clock(SystemClock::default())
+            .with_writer(writer)
+            .finish();
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("first");
+            tracing::info!("second");
+        });
+        drop(guard);
+
+        let buffer = buffer.lock().unwrap();
+        let events = Json.iter_reader(buffer.as_slice());
+        let stats = writer_lag_stats(events).unwrap();
+        assert_eq!(stats.count(), 2);
+        assert!(stats.max().unwrap() >= stall);
+    }
+}
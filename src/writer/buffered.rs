@@ -0,0 +1,167 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use super::{LockResultExt, WriteEvent};
+use crate::SerdeFormat;
+
+const DEFAULT_CAPACITY: usize = 8 * 1024;
+
+/// Create a [`BufferedStdout`] writer, along with a guard which flushes it when dropped.
+pub fn stdout_buffered() -> (BufferedStdout, BufferedStdoutGuard) {
+    let writer = BufferedStdout {
+        buf: Arc::new(Mutex::new(Vec::with_capacity(DEFAULT_CAPACITY))),
+        capacity: DEFAULT_CAPACITY,
+    };
+    let guard = BufferedStdoutGuard {
+        writer: writer.clone(),
+    };
+    (writer, guard)
+}
+
+/// A line-buffered writer to [`Stdout`](std::io::Stdout).  Each record is serialized in memory and
+/// written up to its final newline, so text formats reach `Stdout` one whole record at a time.  Records
+/// of formats without newlines, e.g. MessagePack, are batched until the buffer is full.
+///
+/// Each write holds the `Stdout` lock, so records are never interleaved with output from other threads
+/// printing to `Stdout`.  Created with [`stdout_buffered`].
+#[derive(Clone, Debug)]
+pub struct BufferedStdout {
+    buf: Arc<Mutex<Vec<u8>>>,
+    capacity: usize,
+}
+
+/// Flushes the [`BufferedStdout`] writer when dropped.
+#[derive(Debug)]
+pub struct BufferedStdoutGuard {
+    writer: BufferedStdout,
+}
+
+impl Drop for BufferedStdoutGuard {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+fn write_batch(buf: &mut Vec<u8>) -> io::Result<()> {
+    let result = io::stdout().lock().write_all(buf);
+    buf.clear();
+    result
+}
+
+/// Write `buf` up to and including its last newline, keeping the rest.
+fn write_lines(buf: &mut Vec<u8>) -> io::Result<()> {
+    let end = match buf.iter().rposition(|&b| b == b'\n') {
+        Some(i) => i + 1,
+        None => return Ok(()),
+    };
+    let mut stdout = io::stdout().lock();
+    let result = stdout.write_all(&buf[..end]).and_then(|_| stdout.flush());
+    buf.drain(..end);
+    result
+}
+
+impl WriteEvent for BufferedStdout {
+    fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
+        let buf = &mut *self.buf.lock().ignore_poisoned();
+        let len = buf.len();
+        if let Err(e) = fmt.serialize(&mut *buf, event) {
+            // Don't leave a partial record in the buffer
+            buf.truncate(len);
+            return Err(e);
+        }
+        write_lines(buf)?;
+        if buf.len() >= self.capacity {
+            write_batch(buf)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let buf = &mut *self.buf.lock().ignore_poisoned();
+        write_batch(buf)?;
+        io::stdout().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Json;
+    use std::process::Command;
+
+    const CHILD_ENV_VAR: &str = "TRACING_SUBSCRIBER_SERDE_STDOUT_CHILD";
+    const NUM_RECORDS: usize = 2000;
+    const COMPETING_LINE: &str = "competing printer line";
+
+    fn write_records(writer: impl WriteEvent) {
+        let printer = std::thread::spawn(|| {
+            for _ in 0..NUM_RECORDS {
+                println!("{}", COMPETING_LINE);
+            }
+        });
+
+        let long_string: String = std::iter::repeat('x').take(300).collect();
+        for i in 0..NUM_RECORDS {
+            let record = serde_json::json!({ "i": i, "s": &long_string, "f": [1, 2, 3] });
+            writer.write(Json, record).unwrap();
+        }
+        printer.join().unwrap();
+    }
+
+    /// Not a real test; this is run in a child process by `records_are_not_interleaved`.
+    #[test]
+    fn stdout_child() {
+        match std::env::var(CHILD_ENV_VAR).as_deref() {
+            Ok("unbuffered") => write_records(io::stdout()),
+            Ok("buffered") => {
+                let (writer, guard) = stdout_buffered();
+                // Every line must be written without waiting for the guard
+                std::mem::forget(guard);
+                write_records(writer);
+            }
+            _ => {}
+        }
+    }
+
+    fn check_child_output(mode: &str) {
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(&[
+                "--exact",
+                "writer::buffered::tests::stdout_child",
+                "--nocapture",
+                "--quiet",
+            ])
+            .args(&["--test-threads", "1"])
+            .env(CHILD_ENV_VAR, mode)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut records = 0;
+        let mut competing = 0;
+        for line in stdout.lines() {
+            if line == COMPETING_LINE {
+                competing += 1;
+            } else if line.starts_with('{') {
+                serde_json::from_str::<serde_json::Value>(line)
+                    .unwrap_or_else(|e| panic!("failed to parse line ({}): {:?}", e, line));
+                records += 1;
+            }
+        }
+        assert_eq!(records, NUM_RECORDS);
+        assert_eq!(competing, NUM_RECORDS);
+    }
+
+    #[test]
+    fn records_are_not_interleaved() {
+        check_child_output("unbuffered");
+    }
+
+    #[test]
+    fn buffered_records_are_not_interleaved() {
+        check_child_output("buffered");
+    }
+}
@@ -0,0 +1,238 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// A compression method for [`AnyCompression`], chosen at runtime.
+///
+/// Parses from `"none"`, `"gzip"` and `"zstd"` (ignoring case), e.g. to choose the compression with an
+/// environment variable.  Each method other than `None` requires the crate feature of the same name.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum CompressionMethod {
+    /// Write the output unchanged.
+    None,
+    /// Compress the output as a single gzip stream.
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// Compress the output as a single zstd frame.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl FromStr for CompressionMethod {
+    type Err = io::Error;
+
+    /// Returns an error for unknown methods, and methods whose feature is disabled.
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(CompressionMethod::None),
+            #[cfg(feature = "gzip")]
+            "gzip" => Ok(CompressionMethod::Gzip),
+            #[cfg(feature = "zstd")]
+            "zstd" => Ok(CompressionMethod::Zstd),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported compression method {:?}", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CompressionMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CompressionMethod::None => "none",
+            #[cfg(feature = "gzip")]
+            CompressionMethod::Gzip => "gzip",
+            #[cfg(feature = "zstd")]
+            CompressionMethod::Zstd => "zstd",
+        })
+    }
+}
+
+enum Encoder<W: Write> {
+    None(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    fn get_mut(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::None(w) => w,
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(w) => w,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(w) => w,
+        }
+    }
+
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::None(mut w) => w.flush().map(|_| w),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(w) => w.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(w) => w.finish(),
+        }
+    }
+}
+
+/// A [Writer](std::io::Write) wrapper which compresses the whole output with a [`CompressionMethod`]
+/// chosen at runtime, so the compression can be configured without changing the type of the writer.
+///
+/// The compressed stream is finished when the writer is dropped, or with [`AnyCompression::finish`], which
+/// also reports errors.  Events written before the stream is finished may not be readable, so wrap this in a
+/// [`Mutex`](std::sync::Mutex) rather than giving it to a writer which never drops it.
+///
+/// ```no_run
+/// use tracing_subscriber_serde::writer::{AnyCompression, CompressionMethod};
+/// use std::sync::Mutex;
+///
+/// let method = std::env::var("LOG_COMPRESSION")
+///     .map_or(Ok(CompressionMethod::None), |m| m.parse())
+///     .unwrap();
+/// let file = std::fs::File::create("log.json")?;
+/// let writer = Mutex::new(AnyCompression::new(method, file)?);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct AnyCompression<W: Write> {
+    /// Only `None` once finished
+    encoder: Option<Encoder<W>>,
+}
+
+impl<W: Write> AnyCompression<W> {
+    /// Compress the output written to `inner` with `method`.
+    pub fn new(method: CompressionMethod, inner: W) -> io::Result<Self> {
+        let encoder = match method {
+            CompressionMethod::None => Encoder::None(inner),
+            #[cfg(feature = "gzip")]
+            CompressionMethod::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                inner,
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "zstd")]
+            CompressionMethod::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(inner, 0)?),
+        };
+        Ok(AnyCompression {
+            encoder: Some(encoder),
+        })
+    }
+
+    /// Finish the compressed stream and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.encoder.take().unwrap().finish()
+    }
+}
+
+impl<W: Write> Write for AnyCompression<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder.as_mut().unwrap().get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.as_mut().unwrap().get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for AnyCompression<W> {
+    fn drop(&mut self) {
+        if let Some(encoder) = self.encoder.take() {
+            let _ = encoder.finish();
+        }
+    }
+}
+
+#[cfg(all(test, feature = "consumer"))]
+mod tests {
+    use super::*;
+    use crate::consumer::StreamFormat;
+    use crate::format::Json;
+    use crate::SerdeLayer;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// A buffer which outlives the layer owning the writer.
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Write events through `method`, dropping the writer to finish the stream.
+    fn compressed(method: CompressionMethod) -> Vec<u8> {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let writer = Mutex::new(AnyCompression::new(method, Shared(Arc::clone(&buffer))).unwrap());
+        let layer = SerdeLayer::new().with_writer(writer).finish();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            for i in 0..100 {
+                tracing::info!(i, "compressed");
+            }
+        });
+        let buffer = buffer.lock().unwrap();
+        buffer.clone()
+    }
+
+    fn count_events(data: &[u8]) -> usize {
+        Json.iter_reader(data).map(Result::unwrap).count()
+    }
+
+    #[test]
+    fn parse_methods() {
+        assert_eq!(
+            "NONE".parse::<CompressionMethod>().unwrap(),
+            CompressionMethod::None
+        );
+        assert!("lz4".parse::<CompressionMethod>().is_err());
+        #[cfg(feature = "gzip")]
+        assert_eq!(
+            CompressionMethod::Gzip
+                .to_string()
+                .parse::<CompressionMethod>()
+                .unwrap(),
+            CompressionMethod::Gzip
+        );
+    }
+
+    #[test]
+    fn none() {
+        assert_eq!(count_events(&compressed(CompressionMethod::None)), 100);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip() {
+        use std::io::Read;
+
+        let data = compressed(CompressionMethod::Gzip);
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(data.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert!(decompressed.len() > data.len());
+        assert_eq!(count_events(&decompressed), 100);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() {
+        let data = compressed(CompressionMethod::Zstd);
+        let decompressed = zstd::stream::decode_all(data.as_slice()).unwrap();
+        assert!(decompressed.len() > data.len());
+        assert_eq!(count_events(&decompressed), 100);
+
+        // Finishing explicitly gives back the inner writer
+        let mut writer = AnyCompression::new(CompressionMethod::Zstd, Vec::new()).unwrap();
+        writer.write_all(b"{}\n").unwrap();
+        let data = writer.finish().unwrap();
+        assert_eq!(zstd::stream::decode_all(data.as_slice()).unwrap(), b"{}\n");
+    }
+}
@@ -0,0 +1,186 @@
+use std::io;
+
+use serde::Serialize;
+
+use super::{EventInfo, Preamble, WriteEvent};
+use crate::SerdeFormat;
+
+/// A writer which serializes each event twice, with a different format for each of two writers, e.g. to write
+/// both [`Json`](crate::format::Json) and [`MessagePack`](crate::format::MessagePack) while migrating from
+/// one to the other.
+///
+/// The format of the [`SerdeLayer`](crate::SerdeLayer) is ignored: events are serialized with `fmt_a` for
+/// `writer_a`, and `fmt_b` for `writer_b`.  An event is written to both writers even if the first fails,
+/// and the first error is returned.
+///
+/// The [`Preamble`] is serialized with each format and forwarded to both writers.
+///
+/// ```
+/// # #[cfg(feature = "messagepack")] {
+/// use tracing_subscriber_serde::format::{Json, MessagePack};
+/// use tracing_subscriber_serde::writer::DualFormat;
+/// use tracing_subscriber_serde::SerdeLayer;
+/// use std::sync::Mutex;
+///
+/// let json = Mutex::new(std::fs::File::create("log.json")?);
+/// let msgpack = Mutex::new(std::fs::File::create("log.msgpack")?);
+/// let layer = SerdeLayer::new()
+///     .with_writer(DualFormat::new(Json, json, MessagePack::default(), msgpack))
+///     .finish();
+/// # std::fs::remove_file("log.json")?;
+/// # std::fs::remove_file("log.msgpack")?;
+/// # }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct DualFormat<FA, FB, WA, WB> {
+    fmt_a: FA,
+    writer_a: WA,
+    fmt_b: FB,
+    writer_b: WB,
+}
+
+impl<FA, FB, WA, WB> DualFormat<FA, FB, WA, WB>
+where
+    FA: SerdeFormat,
+    FB: SerdeFormat,
+    WA: WriteEvent,
+    WB: WriteEvent,
+{
+    /// Write events serialized with `fmt_a` to `writer_a`, and serialized with `fmt_b` to `writer_b`.
+    pub fn new(fmt_a: FA, writer_a: WA, fmt_b: FB, writer_b: WB) -> Self {
+        DualFormat {
+            fmt_a,
+            writer_a,
+            fmt_b,
+            writer_b,
+        }
+    }
+
+    /// The writer for the first format.
+    pub fn writer_a(&self) -> &WA {
+        &self.writer_a
+    }
+
+    /// The writer for the second format.
+    pub fn writer_b(&self) -> &WB {
+        &self.writer_b
+    }
+}
+
+impl<FA, FB, WA, WB> WriteEvent for DualFormat<FA, FB, WA, WB>
+where
+    FA: SerdeFormat,
+    FB: SerdeFormat,
+    WA: WriteEvent,
+    WB: WriteEvent,
+{
+    fn write(&self, _: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
+        let a = self.writer_a.write(&self.fmt_a, &event);
+        let b = self.writer_b.write(&self.fmt_b, &event);
+        a.and(b)
+    }
+
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        _: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        let a = self.writer_a.write_event(info, &self.fmt_a, &event);
+        let b = self.writer_b.write_event(info, &self.fmt_b, &event);
+        a.and(b)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let a = self.writer_a.flush();
+        a.and(self.writer_b.flush())
+    }
+
+    fn serialize_preamble(&self, _: impl SerdeFormat, preamble: &Preamble) {
+        self.writer_a.serialize_preamble(&self.fmt_a, preamble);
+        self.writer_b.serialize_preamble(&self.fmt_b, preamble);
+    }
+
+    fn healthcheck(&self) -> io::Result<()> {
+        self.writer_a.healthcheck()?;
+        self.writer_b.healthcheck()
+    }
+}
+
+#[cfg(all(test, feature = "consumer", feature = "messagepack"))]
+mod tests {
+    use super::*;
+    use crate::consumer::StreamFormat;
+    use crate::format::{Json, MessagePack};
+    use crate::test_utils::eq_event;
+    use crate::SerdeLayer;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn writes_both_formats() {
+        let json = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let msgpack = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let writer = DualFormat::new(
+            Json,
+            Arc::clone(&json),
+            MessagePack::default(),
+            Arc::clone(&msgpack),
+        );
+        let layer = SerdeLayer::new().with_writer(writer).finish();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            let _span = tracing::info_span!("migration", step = 1).entered();
+            tracing::info!(answer = 42, "dual");
+        });
+
+        let json = json.lock().unwrap();
+        let msgpack = msgpack.lock().unwrap();
+        let from_json: Vec<_> = Json
+            .iter_reader(json.as_slice())
+            .map(Result::unwrap)
+            .collect();
+        let from_msgpack: Vec<_> = MessagePack::default()
+            .iter_reader(msgpack.as_slice())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(from_json.len(), 1);
+        assert_eq!(from_msgpack.len(), 1);
+        assert!(eq_event(&from_json[0], &from_msgpack[0]));
+        assert_eq!(from_msgpack[0].field("answer"), Some(&42.into()));
+        assert_eq!(from_msgpack[0].field("step"), Some(&1.into()));
+    }
+
+    #[test]
+    fn preamble_in_both_formats() {
+        use crate::writer::Preamble;
+        use serde::Deserialize;
+
+        #[derive(Default)]
+        struct PreambleWriter(Mutex<Vec<u8>>);
+
+        impl WriteEvent for PreambleWriter {
+            fn write(&self, _: impl SerdeFormat, _: impl Serialize) -> io::Result<()> {
+                Ok(())
+            }
+
+            fn set_preamble(&self, preamble: &[u8]) {
+                *self.0.lock().unwrap() = preamble.to_vec();
+            }
+        }
+
+        let json = Arc::new(PreambleWriter::default());
+        let msgpack = Arc::new(PreambleWriter::default());
+        let writer = DualFormat::new(
+            Json,
+            Arc::clone(&json),
+            MessagePack::default(),
+            Arc::clone(&msgpack),
+        );
+        let _layer = SerdeLayer::new().with_writer(writer).finish();
+
+        let from_json: Preamble = serde_json::from_slice(&json.0.lock().unwrap()).unwrap();
+        let msgpack = msgpack.0.lock().unwrap();
+        let from_msgpack = Preamble::deserialize(&mut rmp_serde::Deserializer::new(&msgpack[..]));
+        assert_eq!(from_json, from_msgpack.unwrap());
+    }
+}
@@ -0,0 +1,128 @@
+use crate::subscriber::serialize::{self as ser, SpanItem};
+use crate::time::UnixTime;
+use crate::{FieldValue, Level};
+use std::fmt;
+
+/// Field lookup on a borrowed event.  A trait object is used since [`ser::Event`] is invariant in its
+/// lifetimes.
+trait Fields {
+    fn field(&self, name: &str) -> Option<FieldValue>;
+}
+
+impl Fields for ser::Event<'_, '_> {
+    fn field(&self, name: &str) -> Option<FieldValue> {
+        let fields = match &self.kind {
+            ser::EventKind::Event(fields) | ser::EventKind::EventWithInherited(fields, _) => {
+                Some(fields)
+            }
+            _ => None,
+        };
+        let val = fields
+            .and_then(|fields| fields.iter().find(|(n, _)| *n == name).map(|(_, v)| v))
+            .or_else(|| {
+                self.spans
+                    .as_items()
+                    .iter()
+                    .rev()
+                    .find_map(|item| match item {
+                        SpanItem::Field { name: n, val } if *n == name => Some(val),
+                        _ => None,
+                    })
+            })?;
+        Some(val.into())
+    }
+}
+
+impl Fields for crate::Event {
+    fn field(&self, name: &str) -> Option<FieldValue> {
+        crate::Event::field(self, name).cloned()
+    }
+}
+
+/// Information about the event being written, passed to [`WriteEvent::write_event`](super::WriteEvent::write_event).
+///
+/// This allows writers to treat events differently, e.g. depending on their level or the value of a field,
+/// without deserializing them.
+#[derive(Clone, Copy)]
+pub struct EventInfo<'a> {
+    level: Level,
+    target: &'a str,
+    time: Option<&'a UnixTime>,
+    fields: Option<&'a dyn Fields>,
+}
+
+impl<'a> EventInfo<'a> {
+    /// Information about an event with the given level and target, but no time, fields or spans.
+    pub fn new(level: Level, target: &'a str) -> Self {
+        EventInfo {
+            level,
+            target,
+            time: None,
+            fields: None,
+        }
+    }
+
+    pub(crate) fn from_event(event: &'a ser::Event<'_, '_>) -> Self {
+        EventInfo {
+            level: event.level,
+            target: event.target,
+            time: event.time.as_ref(),
+            fields: Some(event),
+        }
+    }
+
+    /// Information about an owned event, e.g. one buffered before it could be written.
+    pub(crate) fn from_owned(event: &'a crate::Event) -> Self {
+        EventInfo {
+            level: event.level,
+            target: &event.target,
+            time: event.time.as_ref(),
+            fields: Some(event),
+        }
+    }
+
+    /// The level of the event
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// The target of the event
+    pub fn target(&self) -> &'a str {
+        self.target
+    }
+
+    /// The time of the event, if the layer records timestamps
+    pub fn time(&self) -> Option<&'a UnixTime> {
+        self.time
+    }
+
+    /// Look up a field by name, first in the event's own fields, then in its spans from the innermost outwards.
+    /// Behaves like [`Event::field`](crate::Event::field), but returns an owned value.
+    pub fn field(&self, name: &str) -> Option<FieldValue> {
+        self.fields?.field(name)
+    }
+}
+
+impl fmt::Debug for EventInfo<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventInfo")
+            .field("level", &self.level)
+            .field("target", &self.target)
+            .finish()
+    }
+}
+
+impl From<&ser::FieldValue> for FieldValue {
+    fn from(v: &ser::FieldValue) -> Self {
+        match v {
+            ser::FieldValue::Bool(x) => FieldValue::Bool(*x),
+            ser::FieldValue::Float(x) => FieldValue::Float(*x),
+            ser::FieldValue::Int(x) => FieldValue::Int(*x),
+            ser::FieldValue::UInt(x) => FieldValue::UInt(*x),
+            ser::FieldValue::I128(x) => FieldValue::I128(*x),
+            ser::FieldValue::U128(x) => FieldValue::U128(*x),
+            ser::FieldValue::Null => FieldValue::Null,
+            ser::FieldValue::Str(x) => FieldValue::Str(x.to_string()),
+        }
+    }
+}
@@ -4,12 +4,28 @@
 //! to write serialized events out to a file, socket, terminal or other `Writer`.
 use crate::SerdeFormat;
 use serde::Serialize;
+use std::cell::RefCell;
+use std::fs::File;
 use std::io::{self, Stderr, Stdout, Write};
-use std::sync::{Arc, LockResult, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LockResult, Mutex, Once, Weak};
 
+mod aggregate;
+mod alert;
+mod annotate;
+mod buffered;
+mod compress;
+mod dual;
+mod info;
 mod nonblocking;
+mod preamble;
+mod preinit;
+mod rotate;
+mod route;
+mod shard;
+mod store;
 
-trait LockResultExt<Guard> {
+pub(crate) trait LockResultExt<Guard> {
     fn ignore_poisoned(self) -> Guard;
 }
 
@@ -22,7 +38,20 @@ impl<Guard> LockResultExt<Guard> for LockResult<Guard> {
     }
 }
 
+pub use aggregate::Aggregate;
+pub use alert::AlertWriter;
+pub use annotate::Annotate;
+pub use buffered::{stdout_buffered, BufferedStdout, BufferedStdoutGuard};
+pub use compress::{AnyCompression, CompressionMethod};
+pub use dual::DualFormat;
+pub use info::EventInfo;
 pub use nonblocking::{FlushGuard, NonBlocking, NonBlockingBuilder};
+pub use preamble::Preamble;
+pub use preinit::PreInitBuffer;
+pub use rotate::{ManifestEntry, SizeRotatingWriter};
+pub use route::LevelRouter;
+pub use shard::ShardWriter;
+pub use store::{EventFilter, EventStore};
 
 /// Serializes the tracing event by constructing a [Writer](std::io::Write)
 /// and calling [`SerdeFormat::serialize`] on `fmt` with the Writer and `event`.
@@ -55,27 +84,171 @@ pub trait WriteEvent {
         PanicOnError::new(self)
     }
 
+    /// Flush the writer when the program panics, by installing a [panic hook](std::panic::set_hook).
+    ///
+    /// Default is to rely on the writer's destructor to flush any buffered events.
+    fn flush_on_panic(self) -> FlushOnPanic<Self>
+    where
+        Self: Sized + Send + Sync + 'static,
+    {
+        FlushOnPanic::new(self)
+    }
+
     /// Serializes the tracing event using the supplied `fmt`.
     fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()>;
+
+    /// Serializes a tracing event, described by `info`.  This is what [`SerdeLayer`](crate::SerdeLayer) calls.
+    ///
+    /// The default implementation ignores `info` and calls [`WriteEvent::write`].  Wrappers
+    /// should forward this to their inner writer.
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        fmt: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        let _ = info;
+        self.write(fmt, event)
+    }
+
+    /// Flush any buffered output.
+    ///
+    /// The default implementation does nothing.
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Receive the serialized [`Preamble`] of the layer, called once when the
+    /// [`SerdeLayer`](crate::SerdeLayer) is built.  Writers can keep it alongside their output, as
+    /// [`SizeRotatingWriter`] does.  The consumer iterators only read events, so it shouldn't be written into
+    /// the stream of events itself.
+    ///
+    /// The default implementation ignores the preamble.  Wrappers should forward this to their inner writer.
+    fn set_preamble(&self, preamble: &[u8]) {
+        let _ = preamble;
+    }
+
+    /// Serialize the [`Preamble`] of the layer using `fmt`, and pass it to [`WriteEvent::set_preamble`].  This
+    /// is what [`SerdeLayer`](crate::SerdeLayer) calls.
+    ///
+    /// Writers which serialize events with a format of their own, such as [`DualFormat`], override this to
+    /// serialize the preamble with it too.  Wrappers should forward this to their inner writer.
+    fn serialize_preamble(&self, fmt: impl SerdeFormat, preamble: &Preamble) {
+        let mut buf = Vec::with_capacity(fmt.message_size_hint());
+        if fmt.serialize(&mut buf, preamble).is_ok() {
+            self.set_preamble(&buf);
+        }
+    }
+
+    /// Check that the writer can currently accept records, without writing one.
+    ///
+    /// The default implementation does nothing.  Wrappers should forward this to their inner writer.
+    fn healthcheck(&self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 impl<'a, T: WriteEvent> WriteEvent for &'a T {
     fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
         <T as WriteEvent>::write(self, fmt, event)
     }
+
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        fmt: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        <T as WriteEvent>::write_event(self, info, fmt, event)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        <T as WriteEvent>::flush(self)
+    }
+
+    fn set_preamble(&self, preamble: &[u8]) {
+        <T as WriteEvent>::set_preamble(self, preamble)
+    }
+
+    fn serialize_preamble(&self, fmt: impl SerdeFormat, preamble: &Preamble) {
+        <T as WriteEvent>::serialize_preamble(self, fmt, preamble)
+    }
+
+    fn healthcheck(&self) -> io::Result<()> {
+        <T as WriteEvent>::healthcheck(self)
+    }
 }
 
 impl<T: WriteEvent> WriteEvent for Arc<T> {
     fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
         T::write(&*self, fmt, event)
     }
+
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        fmt: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        T::write_event(&*self, info, fmt, event)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        T::flush(&*self)
+    }
+
+    fn set_preamble(&self, preamble: &[u8]) {
+        T::set_preamble(self, preamble)
+    }
+
+    fn serialize_preamble(&self, fmt: impl SerdeFormat, preamble: &Preamble) {
+        T::serialize_preamble(self, fmt, preamble)
+    }
+
+    fn healthcheck(&self) -> io::Result<()> {
+        T::healthcheck(self)
+    }
+}
+
+thread_local! {
+    static RECORD_BUF: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Serialize the event into a reusable thread-local buffer, then write the whole record with a single
+/// call to `write_all`.
+fn write_whole_record(
+    mut writer: impl Write,
+    fmt: impl SerdeFormat,
+    event: impl Serialize,
+) -> io::Result<()> {
+    RECORD_BUF.with(|buf| match buf.try_borrow_mut() {
+        Ok(mut buf) => {
+            buf.clear();
+            fmt.serialize(&mut *buf, event)?;
+            writer.write_all(&buf)
+        }
+        // Only happens if serialization itself writes an event
+        Err(_) => {
+            let mut buf = Vec::with_capacity(fmt.message_size_hint());
+            fmt.serialize(&mut buf, event)?;
+            writer.write_all(&buf)
+        }
+    })
 }
 
 macro_rules! impl_writeevent_for_stdpipe {
     ($t:path) => {
         impl WriteEvent for $t {
             fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
-                fmt.serialize(self.lock(), event)
+                write_whole_record(self, fmt, event)
+            }
+
+            fn flush(&self) -> io::Result<()> {
+                self.lock().flush()
+            }
+
+            fn healthcheck(&self) -> io::Result<()> {
+                self.lock().flush()
             }
         }
     };
@@ -89,6 +262,30 @@ impl<W: Write> WriteEvent for Mutex<W> {
         let writer = &mut *self.lock().ignore_poisoned();
         fmt.serialize(writer, event)
     }
+
+    fn flush(&self) -> io::Result<()> {
+        self.lock().ignore_poisoned().flush()
+    }
+
+    fn healthcheck(&self) -> io::Result<()> {
+        self.lock().ignore_poisoned().flush()
+    }
+}
+
+impl WriteEvent for File {
+    fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
+        fmt.serialize(self, event)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Write::flush(&mut &*self)
+    }
+
+    /// Fails if the file was not opened for writing.
+    fn healthcheck(&self) -> io::Result<()> {
+        Write::write(&mut &*self, &[])?;
+        Write::flush(&mut &*self)
+    }
 }
 
 macro_rules! fail_message {
@@ -119,6 +316,34 @@ impl<T: WriteEvent> WriteEvent for PanicOnError<T> {
         }
         Ok(())
     }
+
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        fmt: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        if let Err(e) = self.0.write_event(info, fmt, event) {
+            panic!("{}", fail_message!(e))
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.0.flush()
+    }
+
+    fn set_preamble(&self, preamble: &[u8]) {
+        self.0.set_preamble(preamble)
+    }
+
+    fn serialize_preamble(&self, fmt: impl SerdeFormat, preamble: &Preamble) {
+        self.0.serialize_preamble(fmt, preamble)
+    }
+
+    fn healthcheck(&self) -> io::Result<()> {
+        self.0.healthcheck()
+    }
 }
 
 /// A wrapper type for printing a warning when the inner `WriteEvent`
@@ -143,4 +368,229 @@ impl<T: WriteEvent> WriteEvent for WarnOnError<T> {
         }
         Ok(())
     }
+
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        fmt: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        if let Err(e) = self.0.write_event(info, fmt, event) {
+            eprintln!("{}", fail_message!(e))
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.0.flush()
+    }
+
+    fn set_preamble(&self, preamble: &[u8]) {
+        self.0.set_preamble(preamble)
+    }
+
+    fn serialize_preamble(&self, fmt: impl SerdeFormat, preamble: &Preamble) {
+        self.0.serialize_preamble(fmt, preamble)
+    }
+
+    fn healthcheck(&self) -> io::Result<()> {
+        self.0.healthcheck()
+    }
+}
+
+/// A wrapper type which flushes the inner `WriteEvent` when the program panics.
+///
+/// Writers such as `Mutex<BufWriter<File>>` only flush when dropped, which may never happen
+/// if the program panics.  The first `FlushOnPanic` installs a [panic hook](std::panic::set_hook)
+/// which calls [`WriteEvent::flush`] on every `FlushOnPanic` writer before running the previously
+/// installed hook.
+///
+/// A writer which is in use when the panic occurs is skipped, since the panic may have happened
+/// while it was locked, e.g. in the middle of serializing an event.  The hook only holds weak
+/// references to the writers, so dropped writers are skipped too.
+pub struct FlushOnPanic<T>(Arc<PanicFlushed<T>>);
+
+struct PanicFlushed<T> {
+    inner: T,
+    /// The number of calls into `inner` in progress
+    in_use: AtomicUsize,
+}
+
+/// Writers to flush when the program panics.  See [`FlushOnPanic`].
+static PANIC_FLUSHED: Mutex<Vec<Weak<dyn FlushIfIdle>>> = Mutex::new(Vec::new());
+
+static INSTALL_PANIC_HOOK: Once = Once::new();
+
+trait FlushIfIdle: Send + Sync {
+    fn flush_if_idle(&self);
+}
+
+impl<T: WriteEvent + Send + Sync> FlushIfIdle for PanicFlushed<T> {
+    fn flush_if_idle(&self) {
+        if self.in_use.load(Ordering::SeqCst) == 0 {
+            let _ = self.inner.flush();
+        }
+    }
+}
+
+fn flush_panic_flushed() {
+    // Registering a writer is the only other use of the lock, so there's nothing to flush if it's busy
+    if let Ok(writers) = PANIC_FLUSHED.try_lock() {
+        for writer in writers.iter().filter_map(Weak::upgrade) {
+            writer.flush_if_idle();
+        }
+    }
+}
+
+/// Marks a [`PanicFlushed`] writer as no longer in use when dropped.
+struct InUse<'a>(&'a AtomicUsize);
+
+impl Drop for InUse<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<T: WriteEvent + Send + Sync + 'static> FlushOnPanic<T> {
+    /// Wrap the inner `WriteEvent`, registering it with the panic hook, which is installed if it
+    /// hasn't been already.
+    pub fn new(inner: T) -> Self {
+        INSTALL_PANIC_HOOK.call_once(|| {
+            let prev_hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                flush_panic_flushed();
+                prev_hook(info);
+            }));
+        });
+        let inner = Arc::new(PanicFlushed {
+            inner,
+            in_use: AtomicUsize::new(0),
+        });
+        let weak: Weak<dyn FlushIfIdle> = Arc::downgrade(&inner) as Weak<PanicFlushed<T>>;
+        let mut writers = PANIC_FLUSHED.lock().ignore_poisoned();
+        writers.retain(|w| w.strong_count() > 0);
+        writers.push(weak);
+        FlushOnPanic(inner)
+    }
+}
+
+impl<T> FlushOnPanic<T> {
+    fn use_inner<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.0.in_use.fetch_add(1, Ordering::SeqCst);
+        let _in_use = InUse(&self.0.in_use);
+        f(&self.0.inner)
+    }
+}
+
+impl<T: WriteEvent> WriteEvent for FlushOnPanic<T> {
+    fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
+        self.use_inner(|w| w.write(fmt, event))
+    }
+
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        fmt: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        self.use_inner(|w| w.write_event(info, fmt, event))
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.use_inner(|w| w.flush())
+    }
+
+    fn set_preamble(&self, preamble: &[u8]) {
+        self.use_inner(|w| w.set_preamble(preamble))
+    }
+
+    fn serialize_preamble(&self, fmt: impl SerdeFormat, preamble: &Preamble) {
+        self.use_inner(|w| w.serialize_preamble(fmt, preamble))
+    }
+
+    fn healthcheck(&self) -> io::Result<()> {
+        self.use_inner(|w| w.healthcheck())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Json;
+    use std::io::BufWriter;
+
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_to_poisoned_mutex() {
+        let writer = Arc::new(Mutex::new(Vec::new()));
+        writer.write(Json, "before").unwrap();
+
+        let poisoner = Arc::clone(&writer);
+        let result = std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("panicked while writing");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(writer.is_poisoned());
+
+        writer.write(Json, "after").unwrap();
+        writer.flush().unwrap();
+        let output = writer.lock().ignore_poisoned().clone();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\"before\"\n\"after\"\n"
+        );
+    }
+
+    #[test]
+    fn flushes_on_panic() {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let writer = Mutex::new(BufWriter::new(SharedBuffer(Arc::clone(&buffer)))).flush_on_panic();
+
+        writer.write(Json, "first").unwrap();
+        writer.write(Json, 2).unwrap();
+        assert!(buffer.lock().unwrap().is_empty());
+
+        let result = std::panic::catch_unwind(|| panic!("oh no"));
+        assert!(result.is_err());
+
+        let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        assert_eq!(output, "\"first\"\n2\n");
+    }
+
+    #[test]
+    fn panic_while_writing() {
+        struct Unserializable;
+
+        impl Serialize for Unserializable {
+            fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+                panic!("can't serialize")
+            }
+        }
+
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let writer = Mutex::new(BufWriter::new(SharedBuffer(Arc::clone(&buffer)))).flush_on_panic();
+        writer.write(Json, "first").unwrap();
+
+        // The hook skips the writer, which is locked by the panicking thread, instead of deadlocking
+        let result = std::panic::catch_unwind(|| writer.write(Json, Unserializable));
+        assert!(result.is_err());
+
+        writer.flush().unwrap();
+        let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("\"first\"\n"), "{}", output);
+    }
 }
@@ -1,29 +1,142 @@
-use flume::{Receiver, Sender, TrySendError};
+use flume::{Receiver, RecvTimeoutError, Sender, TrySendError};
+use std::fmt;
 use std::io::{self, Write};
-
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 use serde::Serialize;
 
-use super::WriteEvent;
-use crate::SerdeFormat;
+use super::{LockResultExt, WriteEvent};
+use crate::subscriber::serialize::{Event, EventKind, FieldValue};
+use crate::time::{Clock, SystemClock};
+use crate::{Level, SerdeFormat};
 
 pub const DEFAULT_BUFFERED_RECORDS_LIMIT: usize = 128_000;
 
+const HEARTBEAT_TARGET: &str = "tracing_subscriber_serde::heartbeat";
+
+type SerializeHeartbeat = dyn Fn(&mut Vec<u8>) -> io::Result<()> + Send + Sync;
+
+#[derive(Clone)]
+struct Heartbeat {
+    interval: Duration,
+    serialize: Arc<SerializeHeartbeat>,
+}
+
+impl fmt::Debug for Heartbeat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Heartbeat")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+type OnIoError = dyn Fn(io::Error) + Send + Sync;
+
+/// What the writer thread does with I/O errors.
+#[derive(Clone)]
+enum IoErrorHandler {
+    Print,
+    Ignore,
+    Callback(Arc<OnIoError>),
+}
+
+impl IoErrorHandler {
+    fn into_callback(self) -> Box<dyn Fn(io::Error) + Send> {
+        match self {
+            IoErrorHandler::Print => {
+                Box::new(|e| eprintln!("WriterThread: failed to write log record: {}", e))
+            }
+            IoErrorHandler::Ignore => Box::new(|_| {}),
+            IoErrorHandler::Callback(f) => Box::new(move |e| f(e)),
+        }
+    }
+}
+
+impl fmt::Debug for IoErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IoErrorHandler::Print => "Print",
+            IoErrorHandler::Ignore => "Ignore",
+            IoErrorHandler::Callback(_) => "Callback",
+        })
+    }
+}
+
+/// Accounting of the bytes of records waiting to be written.
+#[derive(Debug)]
+struct BufferedBytes {
+    used: AtomicUsize,
+    max: usize,
+    lock: Mutex<()>,
+    freed: Condvar,
+}
+
+impl BufferedBytes {
+    fn new(max: usize) -> Self {
+        BufferedBytes {
+            used: AtomicUsize::new(0),
+            max,
+            lock: Mutex::new(()),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Reserve space for a record of `n` bytes, if there is room.  A record is always accepted when nothing
+    /// is buffered, so records larger than the bound can still be written.
+    fn try_reserve(&self, n: usize) -> bool {
+        let mut used = self.used.load(Ordering::Relaxed);
+        loop {
+            if used > 0 && used.saturating_add(n) > self.max {
+                return false;
+            }
+            match self.used.compare_exchange_weak(
+                used,
+                used + n,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(u) => used = u,
+            }
+        }
+    }
+
+    /// Reserve space for a record of `n` bytes, waiting for space to be freed if necessary.
+    fn reserve(&self, n: usize) {
+        let mut guard = self.lock.lock().ignore_poisoned();
+        while !self.try_reserve(n) {
+            guard = self.freed.wait(guard).ignore_poisoned();
+        }
+    }
+
+    fn release(&self, n: usize) {
+        self.used.fetch_sub(n, Ordering::AcqRel);
+        let _guard = self.lock.lock().ignore_poisoned();
+        self.freed.notify_all();
+    }
+}
+
 /// Constructs a [`NonBlocking`].
 #[derive(Clone, Debug)]
 pub struct NonBlockingBuilder {
     lossy: bool,
     max_buffered_records: usize,
-    print_io_errors: bool,
+    max_buffered_bytes: usize,
+    io_errors: IoErrorHandler,
+    heartbeat: Option<Heartbeat>,
 }
 
 impl Default for NonBlockingBuilder {
     fn default() -> Self {
         NonBlockingBuilder {
             lossy: false,
-            print_io_errors: true,
+            io_errors: IoErrorHandler::Print,
             max_buffered_records: DEFAULT_BUFFERED_RECORDS_LIMIT,
+            max_buffered_bytes: usize::MAX,
+            heartbeat: None,
         }
     }
 }
@@ -33,7 +146,19 @@ const PANIC_MSG_DEAD_WRITER: &'static str = "writer thread has died";
 impl NonBlockingBuilder {
     /// Don't print I/O errors from the Writer thread to STDERR.
     pub fn silence_io_errors(mut self) -> Self {
-        self.print_io_errors = false;
+        self.io_errors = IoErrorHandler::Ignore;
+        self
+    }
+
+    /// Call `f` with I/O errors from the Writer thread, including the error from the final flush when the
+    /// [`FlushGuard`] is dropped, instead of printing them to STDERR.  For example, `f` can count errors
+    /// in a metric.  `f` runs on the Writer thread, so it should not block, or emit tracing events
+    /// which are written by the same `NonBlocking`.
+    pub fn on_error<F>(mut self, f: F) -> Self
+    where
+        F: Fn(io::Error) + Send + Sync + 'static,
+    {
+        self.io_errors = IoErrorHandler::Callback(Arc::new(f));
         self
     }
 
@@ -44,6 +169,15 @@ impl NonBlockingBuilder {
         self
     }
 
+    /// Sets the maximum total size in bytes of buffered events.  This applies in addition to
+    /// [`NonBlockingBuilder::buf_size`], and the same [`lossy`](NonBlockingBuilder::lossy) behaviour
+    /// applies when it is reached.  A single event larger than the limit is still accepted if no other events
+    /// are buffered.  Unlimited by default.
+    pub fn max_buffered_bytes(mut self, max: usize) -> Self {
+        self.max_buffered_bytes = max;
+        self
+    }
+
     /// If the buffer is full, events will be dropped if `lossy = true`,
     /// otherwise the `NonBlocking` will block until the buffer has space.
     pub fn lossy(mut self, lossy: bool) -> Self {
@@ -51,22 +185,59 @@ impl NonBlockingBuilder {
         self
     }
 
+    /// Emit a heartbeat event from the writer thread whenever no events have been written for `interval`,
+    /// so that a quiet process can be distinguished from a dead one.  Disabled by default.
+    ///
+    /// The heartbeat is an [`Level::Info`] event with the message `"heartbeat"`, target
+    /// `"tracing_subscriber_serde::heartbeat"` and a timestamp from [`SystemClock`], serialized using `fmt`.
+    pub fn heartbeat<F>(mut self, interval: Duration, fmt: F) -> Self
+    where
+        F: SerdeFormat + Send + Sync + 'static,
+    {
+        self.heartbeat = Some(Heartbeat {
+            interval,
+            serialize: Arc::new(move |buf| fmt.serialize(buf, heartbeat_event())),
+        });
+        self
+    }
+
     /// Finish configuration.
     pub fn finish<W: Write + Send + 'static>(self, writer: W) -> (NonBlocking, FlushGuard) {
-        let guard = WriterThread::spawn(writer, self.max_buffered_records, self.print_io_errors);
+        let buffered_bytes = Arc::new(BufferedBytes::new(self.max_buffered_bytes));
+        let guard = WriterThread::spawn(
+            writer,
+            self.max_buffered_records,
+            self.io_errors.into_callback(),
+            self.heartbeat,
+            Arc::clone(&buffered_bytes),
+        );
 
         let writer = NonBlocking {
             sender: guard.sender.clone(),
             lossy: self.lossy,
+            buffered_bytes,
+            dropped: Arc::new(AtomicU64::new(0)),
             // message_buf_initial_capacity: self.max_buffered_records,
         };
         (writer, guard)
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+fn heartbeat_event() -> Event<'static, 'static> {
+    let message = ("message", FieldValue::Str("heartbeat".into()));
+    Event::synthetic(
+        Level::Info,
+        HEARTBEAT_TARGET,
+        EventKind::Event(smallvec::smallvec![message]),
+        SystemClock::default().time(),
+    )
+}
+
+#[derive(Debug)]
 enum Message {
     Record(Vec<u8>),
+    /// Probe the writer and reply with the result
+    Healthcheck(Sender<io::Result<()>>),
     Shutdown,
 }
 
@@ -75,10 +246,16 @@ enum Message {
 ///
 /// Non-blocking is in quotes because it is only non-blocking if `lossy` is set to `false`
 /// with [`NonBlockingBuilder::lossy`].
+///
+/// Events are timestamped and serialized on the thread which logged them, before they are queued, so
+/// timestamps are the time of the event, not the time it was written, no matter how far behind the writer
+/// thread is.
 #[derive(Clone, Debug)]
 pub struct NonBlocking {
     sender: Sender<Message>,
     lossy: bool,
+    buffered_bytes: Arc<BufferedBytes>,
+    dropped: Arc<AtomicU64>,
     // message_buf_initial_capacity: usize,
 }
 
@@ -104,6 +281,18 @@ impl NonBlocking {
     pub fn new() -> NonBlockingBuilder {
         NonBlockingBuilder::default()
     }
+
+    /// The total size in bytes of the events waiting to be written, including any event currently being
+    /// written.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes.used.load(Ordering::Acquire)
+    }
+
+    /// The number of events dropped because the buffer was full, in [lossy](NonBlockingBuilder::lossy) mode.
+    /// Shared by all clones of this writer.
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
 }
 
 impl WriteEvent for NonBlocking {
@@ -111,34 +300,65 @@ impl WriteEvent for NonBlocking {
         let mut buf = Vec::with_capacity(fmt.message_size_hint());
         fmt.serialize(&mut buf, event)
             .expect("bug: Failed to serialize event");
+        let len = buf.len();
         if self.lossy {
+            if !self.buffered_bytes.try_reserve(len) {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
             match self.sender.try_send(Message::Record(buf)) {
                 Err(TrySendError::Disconnected(_)) => panic!("{}", PANIC_MSG_DEAD_WRITER),
-                _ => {}
+                Err(TrySendError::Full(_)) => {
+                    self.buffered_bytes.release(len);
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(()) => {}
             }
         } else {
+            self.buffered_bytes.reserve(len);
             self.sender
                 .send(Message::Record(buf))
                 .expect(PANIC_MSG_DEAD_WRITER);
         }
         Ok(())
     }
+
+    /// Sends a probe through the writer thread, which flushes the underlying writer and replies with the
+    /// result.  Blocks until all events queued before the probe have been written, even if lossy.
+    fn healthcheck(&self) -> io::Result<()> {
+        let (ack, result) = flume::bounded(1);
+        let dead_writer = || io::Error::new(io::ErrorKind::BrokenPipe, PANIC_MSG_DEAD_WRITER);
+        self.sender
+            .send(Message::Healthcheck(ack))
+            .map_err(|_| dead_writer())?;
+        result.recv().map_err(|_| dead_writer())?
+    }
 }
 
 struct WriterThread<W> {
     queue: Receiver<Message>,
     writer: W,
-    print_io_errs: bool,
+    on_error: Box<dyn Fn(io::Error) + Send>,
+    heartbeat: Option<Heartbeat>,
+    buffered_bytes: Arc<BufferedBytes>,
 }
 
 impl<W: Write + Send + 'static> WriterThread<W> {
-    pub fn spawn(writer: W, max_buffered: usize, print_io_errs: bool) -> FlushGuard {
+    pub fn spawn(
+        writer: W,
+        max_buffered: usize,
+        on_error: Box<dyn Fn(io::Error) + Send>,
+        heartbeat: Option<Heartbeat>,
+        buffered_bytes: Arc<BufferedBytes>,
+    ) -> FlushGuard {
         let (sender, receiver) = flume::bounded(max_buffered);
 
         let mut thread = WriterThread {
             queue: receiver,
             writer,
-            print_io_errs,
+            on_error,
+            heartbeat,
+            buffered_bytes,
         };
 
         let thread_handle = std::thread::spawn(move || thread.run());
@@ -150,10 +370,8 @@ impl<W: Write + Send + 'static> WriterThread<W> {
     }
 
     fn handle_io_err(&mut self, err: Option<io::Error>) {
-        if self.print_io_errs {
-            if let Some(e) = err {
-                eprintln!("WriterThread: failed to write log record: {}", e)
-            }
+        if let Some(e) = err {
+            (self.on_error)(e)
         }
     }
 
@@ -161,8 +379,13 @@ impl<W: Write + Send + 'static> WriterThread<W> {
         match msg {
             Message::Record(data) => {
                 let e = self.writer.write(&data).err();
+                self.buffered_bytes.release(data.len());
                 self.handle_io_err(e);
             }
+            Message::Healthcheck(ack) => {
+                let result = self.writer.write(&[]).and_then(|_| self.writer.flush());
+                let _ = ack.send(result);
+            }
             Message::Shutdown => unreachable!(),
         }
     }
@@ -175,9 +398,32 @@ impl<W: Write + Send + 'static> WriterThread<W> {
         }
     }
 
+    fn write_heartbeat(&mut self) {
+        let mut buf = Vec::new();
+        if let Some(heartbeat) = self.heartbeat.as_ref() {
+            let e = (heartbeat.serialize)(&mut buf)
+                .and_then(|_| self.writer.write_all(&buf))
+                .err();
+            self.handle_io_err(e);
+        }
+    }
+
+    fn recv(&mut self) -> Message {
+        match self.heartbeat.as_ref().map(|h| h.interval) {
+            Some(interval) => loop {
+                match self.queue.recv_timeout(interval) {
+                    Ok(msg) => return msg,
+                    Err(RecvTimeoutError::Timeout) => self.write_heartbeat(),
+                    Err(RecvTimeoutError::Disconnected) => panic!("{}", PANIC_MSG_DEAD_WRITER),
+                }
+            },
+            None => self.queue.recv().unwrap(),
+        }
+    }
+
     fn run(&mut self) {
         loop {
-            match self.queue.recv().unwrap() {
+            match self.recv() {
                 Message::Shutdown => {
                     self.drain();
                     break;
@@ -289,6 +535,37 @@ mod tests {
         assert_eq!(output, "0\n1\n2\n3\n4\n");
     }
 
+    #[test]
+    fn on_error_callback() {
+        struct FailingWriter;
+
+        impl Write for FailingWriter {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::Other, "write failed"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Err(io::Error::new(io::ErrorKind::Other, "flush failed"))
+            }
+        }
+
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let (writer, g) = {
+            let errors = Arc::clone(&errors);
+            NonBlocking::new()
+                .on_error(move |e| errors.lock().unwrap().push(e.to_string()))
+                .finish(FailingWriter)
+        };
+        writer.write(Json, "first").unwrap();
+        writer.write(Json, "second").unwrap();
+        drop(g);
+
+        assert_eq!(
+            *errors.lock().unwrap(),
+            vec!["write failed", "write failed", "flush failed"]
+        );
+    }
+
     #[test]
     fn drops_logs_when_full() {
         let mut writer = TestWriter::new(None, None);
@@ -311,6 +588,8 @@ mod tests {
             eprintln!("send {}", message);
             writer.write(Json, message).unwrap();
         }
+        assert_eq!(writer.dropped_count(), 10 - num_buffered as u64);
+        assert_eq!(writer.clone().dropped_count(), writer.dropped_count());
 
         for _ in 0..(num_buffered + 1) {
             eprintln!("signalling writer...");
@@ -324,5 +603,198 @@ mod tests {
 
         let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
         assert_eq!(output, "\"first\"\n0\n1\n\"hello world\"\n");
+        assert_eq!(writer.dropped_count(), 10 - num_buffered as u64);
+    }
+
+    #[cfg(feature = "consumer")]
+    #[test]
+    fn timestamps_are_taken_at_event_time() {
+        use crate::consumer::StreamFormat;
+        use crate::time::SystemClock;
+        use crate::SerdeLayer;
+        use std::time::SystemTime;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let mut writer = TestWriter::new(None, None);
+        let buffer = Arc::clone(&writer.buffer);
+        let signal = writer.signalled();
+        let (writer, g) = NonBlocking::new().finish(writer);
+
+        let layer = SerdeLayer::new()
+            .with_clock(SystemClock::default())
+            .with_writer(writer)
+            .finish();
+        let before = SystemTime::now();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::info!("hello");
+        });
+        let after = SystemTime::now();
+
+        // The writer thread is stuck on the event until signalled
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(buffer.lock().unwrap().is_empty());
+        signal.send();
+        drop(g);
+
+        let buffer = buffer.lock().unwrap();
+        let event = Json.iter_reader(buffer.as_slice()).next().unwrap().unwrap();
+        let time = SystemTime::from(event.time.unwrap());
+        assert!(before <= time && time <= after);
+    }
+
+    #[cfg(feature = "consumer")]
+    #[test]
+    fn sequence_numbers_across_threads() {
+        use crate::consumer::StreamFormat;
+        use crate::{FieldValue, SerdeLayer};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let (threads, events_per_thread) = (4, 50);
+        let writer = TestWriter::new(None, None);
+        let buffer = Arc::clone(&writer.buffer);
+        let (writer, g) = NonBlocking::new().finish(writer);
+        let layer = SerdeLayer::new()
+            .with_sequence_numbers(true)
+            .with_writer(writer)
+            .finish();
+        let dispatch = tracing::Dispatch::new(tracing_subscriber::registry().with(layer));
+
+        let first = crate::sequence_number();
+        let handles: Vec<_> = (0..threads)
+            .map(|t| {
+                let dispatch = dispatch.clone();
+                std::thread::spawn(move || {
+                    tracing::dispatcher::with_default(&dispatch, || {
+                        for i in 0..events_per_thread {
+                            tracing::info!(t, i);
+                        }
+                    })
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        let last = crate::sequence_number();
+        drop(dispatch);
+        drop(g);
+
+        let buffer = buffer.lock().unwrap();
+        let mut per_thread = vec![Vec::new(); threads];
+        for event in Json.iter_reader(buffer.as_slice()) {
+            let event = event.unwrap();
+            let t = match event.field("t") {
+                Some(FieldValue::Int(t)) => *t as usize,
+                other => panic!("unexpected thread field {:?}", other),
+            };
+            per_thread[t].push(event.seq.unwrap());
+        }
+
+        let mut all = Vec::new();
+        for seqs in per_thread {
+            assert_eq!(seqs.len(), events_per_thread);
+            // Strictly increasing in the order each thread emitted its events
+            assert!(seqs.windows(2).all(|w| w[0] < w[1]), "{:?}", seqs);
+            all.extend(seqs);
+        }
+        all.sort_unstable();
+        all.dedup();
+        assert_eq!(all.len(), threads * events_per_thread);
+        // Other tests may take numbers from the same counter concurrently
+        assert!(all.iter().all(|&seq| first <= seq && seq < last));
+    }
+
+    #[test]
+    fn heartbeat_when_idle() {
+        let writer = TestWriter::new(None, None);
+        let buffer = Arc::clone(&writer.buffer);
+        let count_heartbeats = || {
+            String::from_utf8(buffer.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .filter(|l| l.contains(HEARTBEAT_TARGET))
+                .count()
+        };
+
+        let (writer, g) = NonBlocking::new()
+            .heartbeat(Duration::from_millis(50), Json)
+            .finish(writer);
+
+        writer.write(Json, "hello").unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+        let heartbeats = count_heartbeats();
+        assert!(heartbeats >= 2, "only {} heartbeats", heartbeats);
+
+        drop(g);
+        let heartbeats = count_heartbeats();
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(count_heartbeats(), heartbeats);
+    }
+
+    #[test]
+    fn bounds_buffered_bytes() {
+        let record = "x".repeat(398);
+        let record_len = record.len() + 3; // quotes and newline
+
+        for &lossy in &[true, false] {
+            let mut writer = TestWriter::new(None, None);
+            let writer_continue = writer.signalled();
+            let buffer = Arc::clone(&writer.buffer);
+            let (writer, g) = NonBlocking::new()
+                .lossy(lossy)
+                .max_buffered_bytes(1000)
+                .finish(writer);
+
+            let producer = {
+                let writer = writer.clone();
+                let record = record.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..5 {
+                        writer.write(Json, &record).unwrap();
+                    }
+                })
+            };
+            std::thread::sleep(Duration::from_millis(300));
+            assert_eq!(writer.buffered_bytes(), 2 * record_len);
+            assert_eq!(producer.is_finished(), lossy);
+
+            let num_written = if lossy { 2 } else { 5 };
+            for _ in 0..num_written {
+                writer_continue.send();
+            }
+            producer.join().unwrap();
+            drop(g);
+
+            assert_eq!(writer.buffered_bytes(), 0);
+            assert_eq!(buffer.lock().unwrap().len(), num_written * record_len);
+        }
+    }
+
+    #[test]
+    fn healthcheck() {
+        struct Broken;
+
+        impl Write for Broken {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::PermissionDenied.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Err(io::ErrorKind::PermissionDenied.into())
+            }
+        }
+
+        let writer = TestWriter::new(None, None);
+        let buffer = Arc::clone(&writer.buffer);
+        let (writer, g) = NonBlocking::new().finish(writer);
+        writer.write(Json, "queued").unwrap();
+        writer.healthcheck().unwrap();
+        assert_eq!(&*buffer.lock().unwrap(), b"\"queued\"\n");
+        drop(g);
+        assert!(writer.healthcheck().is_err());
+
+        let (writer, _g) = NonBlocking::new().finish(Broken);
+        let err = writer.healthcheck().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
     }
 }
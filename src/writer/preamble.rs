@@ -0,0 +1,155 @@
+use crate::SpanIdMode;
+use serde::{Deserialize, Serialize};
+
+/// A record describing which options a [`SerdeLayer`](crate::SerdeLayer) was configured with.
+///
+/// When the layer is built, it serializes its `Preamble` with its [`SerdeFormat`](crate::SerdeFormat) and hands
+/// the bytes to its writer with [`WriteEvent::set_preamble`](super::WriteEvent::set_preamble), by way of
+/// [`WriteEvent::serialize_preamble`](super::WriteEvent::serialize_preamble).  Most writers ignore it;
+/// [`SizeRotatingWriter`](super::SizeRotatingWriter) writes it to a file next to the log files, where it can be
+/// read back with e.g. `serde_json::from_slice`, so consumers know how the events were recorded.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Preamble {
+    /// Whether thread names are recorded.
+    pub thread_names: bool,
+    /// Whether thread IDs are recorded.
+    pub thread_ids: bool,
+    /// Which events span IDs are recorded on.
+    pub span_ids: SpanIdMode,
+    /// Whether span fields are only recorded on span creation.
+    pub span_fields_on_create_only: bool,
+    /// Whether source locations are recorded.
+    pub source_location: bool,
+    /// Whether the layer's clock produces timestamps.
+    pub timestamps: bool,
+    /// Whether uptimes are recorded.
+    pub uptime: bool,
+    /// Whether span busy and idle times are recorded.
+    pub time_spans: bool,
+    /// Whether [`EventKind::SpanRecord`](crate::EventKind::SpanRecord) events are emitted.
+    pub span_records: bool,
+    /// Whether [`EventKind::SpanFollowsFrom`](crate::EventKind::SpanFollowsFrom) events are emitted.
+    #[serde(default)]
+    pub span_follows_from: bool,
+    /// If events are sampled, the `n` such that one in `n` events is kept on average.  See
+    /// [`SerdeLayerBuilder::with_event_sampling`](crate::SerdeLayerBuilder::with_event_sampling).
+    #[serde(default)]
+    pub event_sample_one_in: Option<u64>,
+    /// Whether whole traces are sampled, so events of the traces which weren't kept are missing.  See
+    /// [`SerdeLayerBuilder::with_trace_sampling`](crate::SerdeLayerBuilder::with_trace_sampling).
+    #[serde(default)]
+    pub trace_sampling: bool,
+    /// Whether syslog priorities are recorded.
+    #[serde(default)]
+    pub syslog: bool,
+    /// Whether sequence numbers are recorded.
+    #[serde(default)]
+    pub sequence_numbers: bool,
+    /// Whether event IDs are recorded, with `SerdeLayerBuilder::with_event_id`.
+    #[serde(default)]
+    pub event_ids: bool,
+    /// The most verbose level enabled when the layer was attached to its subscriber, from `"trace"` to
+    /// `"off"`.  Only recorded with
+    /// [`SerdeLayerBuilder::with_max_level_snapshot`](crate::SerdeLayerBuilder::with_max_level_snapshot).
+    #[serde(default)]
+    pub max_level: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::SystemClock;
+    use crate::{SerdeFormat, SerdeLayer, WriteEvent};
+    use serde::Serialize;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct PreambleWriter(Mutex<Option<Vec<u8>>>);
+
+    impl WriteEvent for PreambleWriter {
+        fn write(&self, _: impl SerdeFormat, _: impl Serialize) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_preamble(&self, preamble: &[u8]) {
+            *self.0.lock().unwrap() = Some(preamble.to_vec());
+        }
+    }
+
+    #[test]
+    fn layer_sets_preamble() {
+        let writer = Arc::new(PreambleWriter::default());
+        let _layer = SerdeLayer::new()
+            .with_clock(SystemClock::default())
+            .with_span_ids(SpanIdMode::LifecycleOnly)
+            .with_source_location(false)
+            .with_writer(Arc::clone(&writer).warn_on_error())
+            .finish();
+
+        let preamble = writer.0.lock().unwrap().take().unwrap();
+        let preamble: Preamble = serde_json::from_slice(&preamble).unwrap();
+        assert_eq!(
+            preamble,
+            Preamble {
+                thread_names: false,
+                thread_ids: false,
+                span_ids: SpanIdMode::LifecycleOnly,
+                span_fields_on_create_only: false,
+                source_location: false,
+                timestamps: true,
+                uptime: false,
+                time_spans: false,
+                span_records: false,
+                span_follows_from: false,
+                event_sample_one_in: None,
+                trace_sampling: false,
+                syslog: false,
+                sequence_numbers: false,
+                event_ids: false,
+                max_level: None,
+            }
+        );
+
+        let writer = Arc::new(PreambleWriter::default());
+        let _layer = SerdeLayer::new()
+            .with_syslog(crate::SyslogFacility::User)
+            .with_trace_sampling(0.5, 1)
+            .with_sequence_numbers(true)
+            .with_writer(Arc::clone(&writer))
+            .finish();
+        let preamble = writer.0.lock().unwrap().take().unwrap();
+        let preamble: Preamble = serde_json::from_slice(&preamble).unwrap();
+        assert!(preamble.syslog && preamble.trace_sampling && preamble.sequence_numbers);
+        assert!(!preamble.event_ids);
+        // The default clock tells no time
+        assert!(!preamble.timestamps);
+    }
+
+    #[test]
+    fn max_level_snapshot() {
+        use tracing_subscriber::filter::LevelFilter;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let max_level = |filter: LevelFilter, own: crate::Level| {
+            let writer = Arc::new(PreambleWriter::default());
+            let layer = SerdeLayer::new()
+                .with_max_level_snapshot(true)
+                .with_max_level(own)
+                .with_writer(Arc::clone(&writer))
+                .finish();
+            // Not known until the layer is attached
+            assert!(writer.0.lock().unwrap().is_none());
+
+            let _subscriber = tracing_subscriber::registry().with(filter).with(layer);
+            let preamble = writer.0.lock().unwrap().take().unwrap();
+            let preamble: Preamble = serde_json::from_slice(&preamble).unwrap();
+            preamble.max_level.unwrap()
+        };
+
+        assert_eq!(max_level(LevelFilter::INFO, crate::Level::Trace), "info");
+        assert_eq!(max_level(LevelFilter::DEBUG, crate::Level::Warn), "warn");
+        assert_eq!(max_level(LevelFilter::OFF, crate::Level::Trace), "off");
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use serde::Serialize;
+
+use super::{EventInfo, LockResultExt, WriteEvent};
+use crate::{Event, Level, SerdeFormat};
+
+/// A record serialized before the inner writer was available.
+struct Record {
+    bytes: Vec<u8>,
+    json_lines: bool,
+    info: Option<BufferedInfo>,
+}
+
+/// What a record written with [`WriteEvent::write_event`] is replayed with.
+struct BufferedInfo {
+    level: Level,
+    target: String,
+    /// The event itself, for writers which serialize it with a format of their own, and for its time and
+    /// fields.  `None` if the record isn't an [`Event`].
+    event: Option<Event>,
+}
+
+/// Writes a record which has already been serialized, ignoring the event.
+struct Verbatim<'a>(&'a Record);
+
+impl SerdeFormat for Verbatim<'_> {
+    fn message_size_hint(&self) -> usize {
+        self.0.bytes.len()
+    }
+
+    fn serialize(&self, mut buf: impl Write, _event: impl Serialize) -> io::Result<()> {
+        buf.write_all(&self.0.bytes)
+    }
+
+    fn is_json_lines(&self) -> bool {
+        self.0.json_lines
+    }
+}
+
+struct Buffer {
+    records: VecDeque<Record>,
+    preamble: Option<Vec<u8>>,
+}
+
+struct Shared<W> {
+    inner: OnceLock<W>,
+    /// Only locked until the inner writer is set, which happens while holding the lock.
+    buffer: Mutex<Buffer>,
+    capacity: usize,
+    dropped: AtomicU64,
+}
+
+/// A writer which buffers records until the real writer is available, e.g. when the log file is only
+/// opened after reading the application's config.
+///
+/// Until [`PreInitBuffer::activate`] is called, up to `capacity` records are kept in memory; any further
+/// records are dropped and counted in [`PreInitBuffer::dropped`].  On activation the buffered records are
+/// written to the inner writer in order, and all later records are passed straight through.  Records written
+/// concurrently with activation are never lost or reordered with respect to the buffered records.
+///
+/// Buffered events are kept both as serialized by the layer's format, and as an owned [`Event`], so they are
+/// replayed with their time and fields, and writers which serialize events with a format of their own, such
+/// as [`DualFormat`](super::DualFormat), get the event itself.  Records written with [`WriteEvent::write`]
+/// rather than [`WriteEvent::write_event`] are only kept as serialized, so the inner writer must write them
+/// with the format it is passed.
+///
+/// The buffer is cheap to clone, and clones share the same state:
+/// ```
+/// use tracing_subscriber_serde::{SerdeLayer, writer::PreInitBuffer};
+///
+/// let pre_init = PreInitBuffer::new(1000);
+/// let layer = SerdeLayer::new().with_writer(pre_init.clone()).finish();
+/// // ... install the layer, read the config, open the log file ...
+/// pre_init.activate(std::io::stderr()).unwrap();
+/// ```
+pub struct PreInitBuffer<W>(Arc<Shared<W>>);
+
+impl<W> Clone for PreInitBuffer<W> {
+    fn clone(&self) -> Self {
+        PreInitBuffer(Arc::clone(&self.0))
+    }
+}
+
+impl<W: WriteEvent> PreInitBuffer<W> {
+    /// Buffer at most `capacity` records until activated.
+    pub fn new(capacity: usize) -> Self {
+        PreInitBuffer(Arc::new(Shared {
+            inner: OnceLock::new(),
+            buffer: Mutex::new(Buffer {
+                records: VecDeque::new(),
+                preamble: None,
+            }),
+            capacity,
+            dropped: AtomicU64::new(0),
+        }))
+    }
+
+    /// Write the buffered records to `inner`, then pass all further records straight through to it.  Returns
+    /// the first error from writing the buffered records, after attempting to write all of them.
+    ///
+    /// Fails with [`io::ErrorKind::AlreadyExists`] if the buffer has already been activated, in which case
+    /// `inner` is dropped.
+    pub fn activate(&self, inner: W) -> io::Result<()> {
+        let mut buffer = self.0.buffer.lock().ignore_poisoned();
+        if self.0.inner.get().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "PreInitBuffer has already been activated",
+            ));
+        }
+
+        if let Some(preamble) = buffer.preamble.take() {
+            inner.set_preamble(&preamble);
+        }
+        let mut result = Ok(());
+        for record in buffer.records.drain(..) {
+            let written = match &record.info {
+                Some(BufferedInfo {
+                    event: Some(event), ..
+                }) => inner.write_event(&EventInfo::from_owned(event), Verbatim(&record), event),
+                Some(info) => inner.write_event(
+                    &EventInfo::new(info.level, &info.target),
+                    Verbatim(&record),
+                    (),
+                ),
+                None => inner.write(Verbatim(&record), ()),
+            };
+            if result.is_ok() {
+                result = written;
+            }
+        }
+        buffer.records.shrink_to_fit();
+
+        // Writers which find the buffer unlocked after this see the inner writer.
+        if self.0.inner.set(inner).is_err() {
+            unreachable!("only set while holding the lock");
+        }
+        result
+    }
+
+    /// Returns `true` once [`PreInitBuffer::activate`] has been called.
+    pub fn is_active(&self) -> bool {
+        self.0.inner.get().is_some()
+    }
+
+    /// The number of records dropped because the buffer was full.
+    pub fn dropped(&self) -> u64 {
+        self.0.dropped.load(Ordering::Relaxed)
+    }
+
+    fn write_or_buffer(
+        &self,
+        info: Option<&EventInfo<'_>>,
+        fmt: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        let write = |inner: &W| match info {
+            Some(info) => inner.write_event(info, &fmt, &event),
+            None => inner.write(&fmt, &event),
+        };
+        if let Some(inner) = self.0.inner.get() {
+            return write(inner);
+        }
+
+        let mut buffer = self.0.buffer.lock().ignore_poisoned();
+        // Activated while waiting for the lock
+        if let Some(inner) = self.0.inner.get() {
+            drop(buffer);
+            return write(inner);
+        }
+        if buffer.records.len() >= self.0.capacity {
+            self.0.dropped.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        let mut bytes = Vec::with_capacity(fmt.message_size_hint());
+        fmt.serialize(&mut bytes, &event)?;
+        let info = info.map(|info| BufferedInfo {
+            level: info.level(),
+            target: info.target().to_string(),
+            event: serde_json::to_vec(&event)
+                .and_then(|buf| serde_json::from_slice(&buf))
+                .ok(),
+        });
+        buffer.records.push_back(Record {
+            bytes,
+            json_lines: fmt.is_json_lines(),
+            info,
+        });
+        Ok(())
+    }
+}
+
+impl<W: WriteEvent> WriteEvent for PreInitBuffer<W> {
+    fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
+        self.write_or_buffer(None, fmt, event)
+    }
+
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        fmt: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        self.write_or_buffer(Some(info), fmt, event)
+    }
+
+    /// Does nothing until activated.
+    fn flush(&self) -> io::Result<()> {
+        match self.0.inner.get() {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn set_preamble(&self, preamble: &[u8]) {
+        let buffer = &mut *self.0.buffer.lock().ignore_poisoned();
+        match self.0.inner.get() {
+            Some(inner) => inner.set_preamble(preamble),
+            None => buffer.preamble = Some(preamble.to_vec()),
+        }
+    }
+
+    /// Always succeeds until activated.
+    fn healthcheck(&self) -> io::Result<()> {
+        match self.0.inner.get() {
+            Some(inner) => inner.healthcheck(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Json;
+    use std::sync::Barrier;
+    use std::thread;
+
+    fn lines(buffer: &Mutex<Vec<u8>>) -> Vec<String> {
+        String::from_utf8(buffer.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn buffers_until_activated() {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let pre_init = PreInitBuffer::new(2);
+
+        pre_init.write(Json, 1).unwrap();
+        pre_init
+            .write_event(&EventInfo::new(Level::Info, "test"), Json, 2)
+            .unwrap();
+        pre_init.write(Json, 3).unwrap();
+        assert_eq!(pre_init.dropped(), 1);
+        assert!(!pre_init.is_active());
+
+        pre_init.activate(Arc::clone(&buffer)).unwrap();
+        assert!(pre_init.is_active());
+        assert_eq!(lines(&buffer), vec!["1", "2"]);
+
+        pre_init.write(Json, 4).unwrap();
+        assert_eq!(lines(&buffer), vec!["1", "2", "4"]);
+
+        let err = pre_init.activate(Arc::new(Mutex::new(Vec::new())));
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    #[cfg(feature = "consumer")]
+    fn replays_events_through_dual_format() {
+        use crate::consumer::StreamFormat;
+        use crate::time::{ManualClock, UnixTime};
+        use crate::writer::DualFormat;
+        use crate::{FieldValue, SerdeLayer};
+        use std::time::Duration;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        /// Records what each event was written with.
+        #[derive(Default)]
+        struct InfoWriter(Mutex<Vec<(Option<UnixTime>, Option<FieldValue>)>>);
+
+        impl WriteEvent for InfoWriter {
+            fn write(&self, _: impl SerdeFormat, _: impl Serialize) -> io::Result<()> {
+                Ok(())
+            }
+
+            fn write_event(
+                &self,
+                info: &EventInfo<'_>,
+                _: impl SerdeFormat,
+                _: impl Serialize,
+            ) -> io::Result<()> {
+                let entry = (info.time().cloned(), info.field("answer"));
+                self.0.lock().unwrap().push(entry);
+                Ok(())
+            }
+        }
+
+        let json = Arc::new(Mutex::new(Vec::new()));
+        let infos = Arc::new(InfoWriter::default());
+        let time = UnixTime::from(Duration::from_secs(1_600_000_000));
+        let pre_init = PreInitBuffer::new(10);
+        let layer = SerdeLayer::new()
+            .with_clock(ManualClock::new(time.clone()))
+            .with_writer(pre_init.clone())
+            .finish();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::info!(answer = 42, "buffered");
+        });
+        pre_init
+            .activate(DualFormat::new(
+                Json,
+                Arc::clone(&json),
+                Json,
+                Arc::clone(&infos),
+            ))
+            .unwrap();
+
+        let json = json.lock().unwrap();
+        let events: Vec<_> = Json
+            .iter_reader(json.as_slice())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].field("answer"), Some(&FieldValue::Int(42)));
+        assert_eq!(
+            *infos.0.lock().unwrap(),
+            vec![(Some(time), Some(FieldValue::Int(42)))]
+        );
+    }
+
+    #[test]
+    fn activation_races_with_writes() {
+        const THREADS: usize = 4;
+        const RECORDS: usize = 2000;
+
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let pre_init = PreInitBuffer::new(THREADS * RECORDS);
+        let barrier = Arc::new(Barrier::new(THREADS + 1));
+
+        let writers: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let pre_init = pre_init.clone();
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    for i in 0..RECORDS {
+                        pre_init.write(Json, (t, i)).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        barrier.wait();
+        pre_init.activate(Arc::clone(&buffer)).unwrap();
+        for w in writers {
+            w.join().unwrap();
+        }
+
+        assert_eq!(pre_init.dropped(), 0);
+        let mut next = [0; THREADS];
+        for line in lines(&buffer) {
+            let (t, i): (usize, usize) = serde_json::from_str(&line).unwrap();
+            assert_eq!(i, next[t], "thread {} out of order", t);
+            next[t] += 1;
+        }
+        assert_eq!(next, [RECORDS; THREADS]);
+    }
+}
@@ -0,0 +1,348 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use super::{EventInfo, LockResultExt, WriteEvent};
+use crate::time::UnixTime;
+use crate::SerdeFormat;
+
+struct Current {
+    file: File,
+    len: u64,
+    events: u64,
+    first: Option<UnixTime>,
+    last: Option<UnixTime>,
+}
+
+/// An entry in the manifest of a [`SizeRotatingWriter`], describing an old file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The current path of the file, e.g. `app.log.1`
+    pub file: String,
+    /// The time of the earliest event in the file which had one
+    pub first: Option<UnixTime>,
+    /// The time of the latest event in the file which had one
+    pub last: Option<UnixTime>,
+    /// The number of records written to the file by this writer
+    pub events: u64,
+}
+
+/// A writer which starts a new file when the current one would grow past a size limit, keeping a bounded
+/// number of old files.
+///
+/// Events are written to the base path, e.g. `app.log`.  When a record would take the file past `max_bytes`,
+/// the file is renamed to `app.log.1`, older files are shifted along (`app.log.1` to `app.log.2`, and so
+/// on), and the record is written to a fresh `app.log`.  Files beyond `app.log.<max_files>` are deleted.
+///
+/// Each record is serialized before it is written, so records are never split across files.  A single
+/// record larger than `max_bytes` gets a file to itself.  The [`Preamble`](crate::writer::Preamble), if
+/// any, applies to every file, and is written to a file of its own, `app.log.preamble`, so the log files only
+/// contain events.
+///
+/// With [`with_manifest`](Self::with_manifest), a manifest file lists the time range of each old file, so
+/// readers can pick the files covering a time range without opening them.
+pub struct SizeRotatingWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    manifest: Option<PathBuf>,
+    current: Mutex<Current>,
+}
+
+impl SizeRotatingWriter {
+    /// Write to `path`, appending to it if it already exists, and keep at most `max_files` old files of at
+    /// most `max_bytes` each.
+    pub fn new(path: impl Into<PathBuf>, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+        Ok(SizeRotatingWriter {
+            path,
+            max_bytes,
+            max_files,
+            manifest: None,
+            current: Mutex::new(Current {
+                file,
+                len,
+                events: 0,
+                first: None,
+                last: None,
+            }),
+        })
+    }
+
+    /// Keep a manifest of the old files at `path`, with a JSON [`ManifestEntry`] per line, oldest first.
+    ///
+    /// The manifest is rewritten each time a file is rotated out: entries follow their files as they are
+    /// shifted along, and the entries of deleted files are removed.  It is replaced by renaming a temporary
+    /// file, so readers never see it half-written.  Only records written by this writer are counted, not those
+    /// already in the file it appended to.
+    pub fn with_manifest(mut self, path: impl Into<PathBuf>) -> Self {
+        self.manifest = Some(path.into());
+        self
+    }
+
+    /// Read the entries of a manifest written by [`with_manifest`](Self::with_manifest), oldest first.
+    pub fn read_manifest(path: impl AsRef<Path>) -> io::Result<Vec<ManifestEntry>> {
+        let data = fs::read(path)?;
+        serde_json::Deserializer::from_slice(&data)
+            .into_iter()
+            .map(|entry| entry.map_err(io::Error::from))
+            .collect()
+    }
+
+    /// The path of the `n`th old file, or the current file if `n` is 0.
+    pub fn path(&self, n: usize) -> PathBuf {
+        rotated_path(&self.path, n)
+    }
+
+    /// The path of the file the [`Preamble`](crate::writer::Preamble) is written to.
+    pub fn preamble_path(&self) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(".preamble");
+        PathBuf::from(name)
+    }
+
+    /// Shift the old files along, and replace the current file with an empty one.
+    fn rotate(&self, current: &mut Current) -> io::Result<()> {
+        current.file.flush()?;
+        // Named as before the shift, like the entries already in the manifest
+        let entry = ManifestEntry {
+            file: self.path(0).to_string_lossy().into_owned(),
+            first: current.first.take(),
+            last: current.last.take(),
+            events: std::mem::take(&mut current.events),
+        };
+        if self.max_files > 0 {
+            remove_if_exists(&self.path(self.max_files))?;
+            for n in (0..self.max_files).rev() {
+                let from = self.path(n);
+                if from.exists() {
+                    fs::rename(&from, self.path(n + 1))?;
+                }
+            }
+        }
+        current.file = File::create(&self.path)?;
+        current.len = 0;
+        if let Some(manifest) = &self.manifest {
+            self.update_manifest(manifest, entry)?;
+        }
+        Ok(())
+    }
+
+    /// Rename the entries of the manifest after the files were shifted along, dropping those of deleted files,
+    /// and add `entry` for the file which was just rotated out.
+    fn update_manifest(&self, manifest: &Path, entry: ManifestEntry) -> io::Result<()> {
+        let entries = match Self::read_manifest(manifest) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut data = Vec::new();
+        for mut entry in entries.into_iter().chain(std::iter::once(entry)) {
+            let shifted = (0..self.max_files)
+                .find(|&n| self.path(n) == Path::new(&entry.file))
+                .map(|n| self.path(n + 1));
+            if let Some(path) = shifted {
+                entry.file = path.to_string_lossy().into_owned();
+                serde_json::to_writer(&mut data, &entry)?;
+                data.push(b'\n');
+            }
+        }
+        let mut tmp = OsString::from(manifest.as_os_str());
+        tmp.push(".tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, manifest)
+    }
+
+    fn write_record(&self, buf: &[u8], time: Option<&UnixTime>) -> io::Result<()> {
+        let mut current = self.current.lock().ignore_poisoned();
+        if current.len > 0 && current.len + buf.len() as u64 > self.max_bytes {
+            self.rotate(&mut current)?;
+        }
+        current.file.write_all(buf)?;
+        current.len += buf.len() as u64;
+        current.events += 1;
+        if let Some(time) = time {
+            if current.first.as_ref().map_or(true, |first| time < first) {
+                current.first = Some(time.clone());
+            }
+            if current.last.as_ref().map_or(true, |last| time > last) {
+                current.last = Some(time.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    if n == 0 {
+        return path.to_path_buf();
+    }
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl WriteEvent for SizeRotatingWriter {
+    fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(fmt.message_size_hint());
+        fmt.serialize(&mut buf, event)?;
+        self.write_record(&buf, None)
+    }
+
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        fmt: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(fmt.message_size_hint());
+        fmt.serialize(&mut buf, event)?;
+        self.write_record(&buf, info.time())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.current.lock().ignore_poisoned().file.flush()
+    }
+
+    /// Written to [`preamble_path`](SizeRotatingWriter::preamble_path), replacing any earlier preamble.
+    fn set_preamble(&self, preamble: &[u8]) {
+        let _ = fs::write(self.preamble_path(), preamble);
+    }
+
+    fn healthcheck(&self) -> io::Result<()> {
+        let mut current = self.current.lock().ignore_poisoned();
+        current.file.write_all(&[])?;
+        current.file.flush()
+    }
+}
+
+#[cfg(all(test, feature = "consumer"))]
+mod tests {
+    use super::*;
+    use crate::consumer::{IterFile, StreamFormat};
+    use crate::format::Json;
+    use crate::test_utils::temp_dir;
+    use crate::SerdeLayer;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn rotates_whole_records() {
+        let dir = temp_dir("rotate");
+        let max_bytes = 800;
+
+        let writer = SizeRotatingWriter::new(dir.join("app.log"), max_bytes, 2).unwrap();
+        let paths: Vec<_> = (0..4).map(|n| writer.path(n)).collect();
+        let layer = SerdeLayer::new().with_writer(writer).finish();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            for i in 0..30 {
+                tracing::info!(i, "rotating");
+            }
+        });
+
+        assert!(!paths[3].exists());
+        let mut seen = Vec::new();
+        for path in paths[..3].iter().rev() {
+            assert!(
+                fs::metadata(path).unwrap().len() <= max_bytes,
+                "{:?} too large",
+                path
+            );
+            let events = Json.iter_file(path).map(Result::unwrap);
+            let n = seen.len();
+            seen.extend(events.map(|e| e.field("i").cloned().unwrap()));
+            assert!(seen.len() > n, "{:?} is empty", path);
+        }
+
+        // The newest events are kept, in order
+        let expected: Vec<_> = (30 - seen.len() as i64..30).map(Into::into).collect();
+        assert_eq!(seen, expected);
+
+        let preamble: crate::writer::Preamble =
+            serde_json::from_slice(&fs::read(dir.join("app.log.preamble")).unwrap()).unwrap();
+        assert!(preamble.source_location);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn preamble_in_own_file() {
+        let dir = temp_dir("rotate-preamble");
+
+        let writer = SizeRotatingWriter::new(dir.join("app.log"), 10, 1).unwrap();
+        writer.set_preamble(b"# header\n");
+        writer.write(Json, "first").unwrap();
+        writer.write(Json, "second").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(writer.path(1)).unwrap(), "\"first\"\n");
+        assert_eq!(fs::read_to_string(writer.path(0)).unwrap(), "\"second\"\n");
+        assert_eq!(
+            fs::read_to_string(writer.preamble_path()).unwrap(),
+            "# header\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn manifest_lists_rotated_files() {
+        use crate::time::ManualClock;
+        use std::time::Duration;
+
+        let dir = temp_dir("rotate-manifest");
+        let manifest = dir.join("manifest.jsonl");
+        let start = Duration::from_secs(1_600_000_000);
+        let clock = ManualClock::new(start.into());
+
+        let writer = SizeRotatingWriter::new(dir.join("app.log"), 600, 2)
+            .unwrap()
+            .with_manifest(&manifest);
+        let paths: Vec<_> = (0..4).map(|n| writer.path(n)).collect();
+        let layer = SerdeLayer::new()
+            .with_clock(clock.clone())
+            .with_writer(writer)
+            .finish();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            // Enough for the oldest files to be deleted
+            for i in 0..30 {
+                tracing::info!(i, "rotating");
+                clock.advance(Duration::from_secs(1));
+            }
+        });
+        assert!(!paths[3].exists());
+
+        // Each entry describes the file it names, and deleted files aren't listed
+        let entries = SizeRotatingWriter::read_manifest(&manifest).unwrap();
+        let names: Vec<_> = entries.iter().map(|e| PathBuf::from(&e.file)).collect();
+        assert_eq!(names, vec![paths[2].clone(), paths[1].clone()]);
+        assert!(entries[0].first > Some(start.into()), "no file was deleted");
+        let mut previous = None;
+        for entry in &entries {
+            let events: Vec<_> = Json.iter_file(&entry.file).map(Result::unwrap).collect();
+            assert_eq!(events.len() as u64, entry.events);
+            assert_eq!(entry.first, events.first().unwrap().time);
+            assert_eq!(entry.last, events.last().unwrap().time);
+            assert!(entry.first < entry.last);
+            // Oldest first, without gaps
+            if let Some(previous) = previous {
+                assert_eq!(
+                    Duration::from(entry.first.clone().unwrap()),
+                    Duration::from(previous) + Duration::from_secs(1)
+                );
+            }
+            previous = entry.last.clone();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
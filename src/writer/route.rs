@@ -0,0 +1,110 @@
+use std::io;
+
+use serde::Serialize;
+
+use super::{EventInfo, Preamble, WriteEvent};
+use crate::{Level, SerdeFormat};
+
+/// A writer which sends events at or above a level to one writer, and all other events to another, e.g. to
+/// keep warnings and errors in a separate file.
+///
+/// Events are routed on [`EventInfo::level`], before they are serialized.  Records written with
+/// [`WriteEvent::write`] rather than [`WriteEvent::write_event`] have no level, and go to the low writer.
+pub struct LevelRouter<H, L> {
+    threshold: Level,
+    high: H,
+    low: L,
+}
+
+impl<H: WriteEvent, L: WriteEvent> LevelRouter<H, L> {
+    /// Send events at `threshold` or above to `high`, and all others to `low`.
+    pub fn new(threshold: Level, high: H, low: L) -> Self {
+        LevelRouter {
+            threshold,
+            high,
+            low,
+        }
+    }
+
+    /// The writer for events at or above the threshold.
+    pub fn high(&self) -> &H {
+        &self.high
+    }
+
+    /// The writer for events below the threshold.
+    pub fn low(&self) -> &L {
+        &self.low
+    }
+}
+
+impl<H: WriteEvent, L: WriteEvent> WriteEvent for LevelRouter<H, L> {
+    fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
+        self.low.write(fmt, event)
+    }
+
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        fmt: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        if info.level() >= self.threshold {
+            self.high.write_event(info, fmt, event)
+        } else {
+            self.low.write_event(info, fmt, event)
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let high = self.high.flush();
+        self.low.flush().and(high)
+    }
+
+    fn set_preamble(&self, preamble: &[u8]) {
+        self.high.set_preamble(preamble);
+        self.low.set_preamble(preamble);
+    }
+
+    fn serialize_preamble(&self, fmt: impl SerdeFormat, preamble: &Preamble) {
+        self.high.serialize_preamble(&fmt, preamble);
+        self.low.serialize_preamble(&fmt, preamble);
+    }
+
+    fn healthcheck(&self) -> io::Result<()> {
+        self.high.healthcheck()?;
+        self.low.healthcheck()
+    }
+}
+
+#[cfg(all(test, feature = "consumer"))]
+mod tests {
+    use super::*;
+    use crate::consumer::StreamFormat;
+    use crate::format::Json;
+    use crate::SerdeLayer;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn routes_by_level() {
+        let high = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let low = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let writer = LevelRouter::new(Level::Warn, Arc::clone(&high), Arc::clone(&low));
+        let layer = SerdeLayer::new().with_writer(writer).finish();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::debug!("debug");
+            tracing::info!("info");
+            tracing::warn!("warn");
+            tracing::error!("error");
+        });
+
+        let levels = |buffer: &Mutex<Vec<u8>>| -> Vec<Level> {
+            let buffer = buffer.lock().unwrap();
+            Json.iter_reader(buffer.as_slice())
+                .map(|e| e.unwrap().level)
+                .collect()
+        };
+        assert_eq!(levels(&high), vec![Level::Warn, Level::Error]);
+        assert_eq!(levels(&low), vec![Level::Debug, Level::Info]);
+    }
+}
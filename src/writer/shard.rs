@@ -0,0 +1,158 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+
+use serde::Serialize;
+
+use super::{EventInfo, Preamble, WriteEvent};
+use crate::{FieldValue, SerdeFormat};
+
+type HashFn = Box<dyn Fn(&FieldValue) -> u64 + Send + Sync>;
+
+/// A writer which distributes events over several inner writers (shards), using a hash of the value of a
+/// field.  Events with the same value always go to the same shard, so e.g. all the events of a request can be
+/// ingested by the same downstream worker.
+///
+/// The field is looked up with [`EventInfo::field`], so it may belong to the event or any of its spans.
+/// Events without the field, and events written with [`WriteEvent::write`] rather than
+/// [`WriteEvent::write_event`], go to the first shard.
+pub struct ShardWriter<W> {
+    field: String,
+    shards: Vec<W>,
+    hash: HashFn,
+}
+
+fn default_hash(v: &FieldValue) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    v.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl<W: WriteEvent> ShardWriter<W> {
+    /// Shard events over `shards` by the value of `field`.
+    ///
+    /// The default hash function is deterministic, but may change between versions of Rust.  Use
+    /// [`hash_with`](Self::hash_with) if shard assignments need to be stable across builds.
+    ///
+    /// # Panics
+    /// Panics if `shards` is empty.
+    pub fn new(field: impl Into<String>, shards: Vec<W>) -> Self {
+        assert!(!shards.is_empty(), "ShardWriter needs at least one shard");
+        ShardWriter {
+            field: field.into(),
+            shards,
+            hash: Box::new(default_hash),
+        }
+    }
+
+    /// Use a different hash function.  The shard is the hash modulo the number of shards.
+    pub fn hash_with(mut self, hash: impl Fn(&FieldValue) -> u64 + Send + Sync + 'static) -> Self {
+        self.hash = Box::new(hash);
+        self
+    }
+
+    /// The inner writers
+    pub fn shards(&self) -> &[W] {
+        &self.shards
+    }
+
+    fn shard(&self, info: &EventInfo<'_>) -> &W {
+        let k = match info.field(&self.field) {
+            Some(v) => ((self.hash)(&v) % self.shards.len() as u64) as usize,
+            None => 0,
+        };
+        &self.shards[k]
+    }
+}
+
+impl<W: WriteEvent> WriteEvent for ShardWriter<W> {
+    fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
+        self.shards[0].write(fmt, event)
+    }
+
+    fn write_event(
+        &self,
+        info: &EventInfo<'_>,
+        fmt: impl SerdeFormat,
+        event: impl Serialize,
+    ) -> io::Result<()> {
+        self.shard(info).write_event(info, fmt, event)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let mut result = Ok(());
+        for shard in &self.shards {
+            if let Err(e) = shard.flush() {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    fn set_preamble(&self, preamble: &[u8]) {
+        for shard in &self.shards {
+            shard.set_preamble(preamble);
+        }
+    }
+
+    fn serialize_preamble(&self, fmt: impl SerdeFormat, preamble: &Preamble) {
+        for shard in &self.shards {
+            shard.serialize_preamble(&fmt, preamble);
+        }
+    }
+
+    fn healthcheck(&self) -> io::Result<()> {
+        self.shards.iter().try_for_each(|shard| shard.healthcheck())
+    }
+}
+
+#[cfg(all(test, feature = "consumer"))]
+mod tests {
+    use super::*;
+    use crate::consumer::StreamFormat;
+    use crate::format::Json;
+    use crate::SerdeLayer;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn same_key_same_shard() {
+        let buffers: Vec<_> = (0..4)
+            .map(|_| Arc::new(Mutex::new(Vec::<u8>::new())))
+            .collect();
+        let writer = ShardWriter::new("request_id", buffers.iter().map(Arc::clone).collect());
+        let layer = SerdeLayer::new().with_writer(writer).finish();
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..3 {
+                for request_id in 0..20 {
+                    let _span = tracing::info_span!("request", request_id).entered();
+                    tracing::info!("hello");
+                }
+                tracing::info!(request_id = 7, "explicit");
+                tracing::info!("no request");
+            }
+        });
+
+        let mut shard_of_key = HashMap::new();
+        for (k, buffer) in buffers.iter().enumerate() {
+            let buffer = buffer.lock().unwrap();
+            for e in Json.iter_reader(buffer.as_slice()) {
+                let e = e.unwrap();
+                match e.field("request_id") {
+                    Some(key) => {
+                        let shard = *shard_of_key.entry(key.clone()).or_insert(k);
+                        assert_eq!(shard, k, "{:?} in multiple shards", key);
+                    }
+                    None => assert_eq!(k, 0),
+                }
+            }
+        }
+        assert_eq!(shard_of_key.len(), 20);
+        let mut used: Vec<_> = shard_of_key.values().collect();
+        used.sort();
+        used.dedup();
+        assert!(used.len() > 1);
+    }
+}
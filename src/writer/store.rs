@@ -0,0 +1,291 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, RwLock};
+
+use flume::{Receiver, Sender, TrySendError};
+use serde::Serialize;
+
+use super::{LockResultExt, WriteEvent};
+use crate::{Event, Level, SerdeFormat};
+
+const NUM_LEVELS: usize = 5;
+
+type EvictCallback = Box<dyn Fn(&Event) + Send + Sync>;
+
+/// Selects events from an [`EventStore`].  The default filter matches every event.
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    min_level: Option<Level>,
+    target: Option<String>,
+}
+
+impl EventFilter {
+    /// A filter which matches every event.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match events at `level` or above.
+    pub fn min_level(mut self, level: Level) -> Self {
+        self.min_level = Some(level);
+        self
+    }
+
+    /// Only match events with exactly this target.
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Returns `true` if `event` matches the filter.
+    pub fn matches(&self, event: &Event) -> bool {
+        self.min_level.iter().all(|&l| event.level >= l)
+            && self.target.iter().all(|t| &event.target == t)
+    }
+}
+
+struct Store {
+    /// Sequence number of the oldest retained event.
+    first_seq: u64,
+    events: VecDeque<Arc<Event>>,
+    by_level: [VecDeque<u64>; NUM_LEVELS],
+    by_target: HashMap<String, VecDeque<u64>>,
+    subscribers: Vec<Sender<Arc<Event>>>,
+}
+
+impl Store {
+    fn get(&self, seq: u64) -> &Arc<Event> {
+        &self.events[(seq - self.first_seq) as usize]
+    }
+
+    fn push(&mut self, event: Arc<Event>) {
+        let seq = self.first_seq + self.events.len() as u64;
+        self.by_level[event.level as usize].push_back(seq);
+        self.by_target
+            .entry(event.target.clone())
+            .or_default()
+            .push_back(seq);
+        // A subscriber which has fallen behind misses the event, rather than holding up the writer
+        self.subscribers.retain(|s| {
+            !matches!(
+                s.try_send(Arc::clone(&event)),
+                Err(TrySendError::Disconnected(_))
+            )
+        });
+        self.events.push_back(event);
+    }
+
+    fn pop(&mut self) -> Option<Arc<Event>> {
+        let event = self.events.pop_front()?;
+        // Events are evicted in insertion order, so the evicted event is at the front of its indexes.
+        self.by_level[event.level as usize].pop_front();
+        if let Some(seqs) = self.by_target.get_mut(&event.target) {
+            seqs.pop_front();
+            if seqs.is_empty() {
+                self.by_target.remove(&event.target);
+            }
+        }
+        self.first_seq += 1;
+        Some(event)
+    }
+}
+
+/// A writer which keeps the most recent events in memory, for embedding a log viewer in an application.
+///
+/// Events are serialized as JSON and read back into [`Event`]s as they are written, so the [`SerdeFormat`]
+/// of the layer is ignored.  Once `capacity` events are held, the oldest events are evicted.  Retained events can be
+/// queried by level and target with [`EventStore::query`], and new events can be followed with
+/// [`EventStore::subscribe`].
+///
+/// To query the store while it is in use by a layer, give the layer an `Arc<EventStore>`.
+pub struct EventStore {
+    capacity: usize,
+    on_evict: Option<EvictCallback>,
+    store: RwLock<Store>,
+}
+
+impl EventStore {
+    /// Create a store holding at most `capacity` events.
+    pub fn new(capacity: usize) -> Self {
+        EventStore {
+            capacity,
+            on_evict: None,
+            store: RwLock::new(Store {
+                first_seq: 0,
+                events: VecDeque::with_capacity(capacity),
+                by_level: Default::default(),
+                by_target: HashMap::new(),
+                subscribers: Vec::new(),
+            }),
+        }
+    }
+
+    /// Call `f` with each event as it is evicted, oldest first.
+    pub fn on_evict(mut self, f: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        self.on_evict = Some(Box::new(f));
+        self
+    }
+
+    /// The number of events currently held.
+    pub fn len(&self) -> usize {
+        self.store.read().ignore_poisoned().events.len()
+    }
+
+    /// Returns `true` if no events are held.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The most recent `limit` events matching `filter`, oldest first.
+    pub fn query(&self, filter: &EventFilter, limit: usize) -> Vec<Arc<Event>> {
+        let store = self.store.read().ignore_poisoned();
+        let mut seqs: Vec<u64> = match (&filter.target, filter.min_level) {
+            (Some(target), _) => match store.by_target.get(target) {
+                Some(seqs) => seqs.iter().copied().collect(),
+                None => return Vec::new(),
+            },
+            (None, Some(level)) => {
+                let mut seqs: Vec<_> = store.by_level[level as usize..]
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .collect();
+                seqs.sort_unstable();
+                seqs
+            }
+            (None, None) => {
+                (store.first_seq..store.first_seq + store.events.len() as u64).collect()
+            }
+        };
+
+        seqs.retain(|&seq| filter.matches(store.get(seq)));
+        let start = seqs.len().saturating_sub(limit);
+        seqs[start..]
+            .iter()
+            .map(|&seq| Arc::clone(store.get(seq)))
+            .collect()
+    }
+
+    /// Receive the events written from now on, in order.  At most `capacity` events are buffered for the
+    /// receiver: while it is full, further events are not sent to it, so a slow or abandoned receiver
+    /// doesn't hold up the writer or grow without bound.
+    pub fn subscribe(&self, capacity: usize) -> Receiver<Arc<Event>> {
+        let (sender, receiver) = flume::bounded(capacity);
+        self.store
+            .write()
+            .ignore_poisoned()
+            .subscribers
+            .push(sender);
+        receiver
+    }
+}
+
+impl WriteEvent for EventStore {
+    fn write(&self, _fmt: impl SerdeFormat, event: impl Serialize) -> io::Result<()> {
+        let event: Event = serde_json::to_vec(&event)
+            .and_then(|buf| serde_json::from_slice(&buf))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut evicted = Vec::new();
+        {
+            let mut store = self.store.write().ignore_poisoned();
+            while store.events.len() >= self.capacity.max(1) {
+                evicted.extend(store.pop());
+            }
+            store.push(Arc::new(event));
+        }
+
+        // Don't hold the lock while running user code
+        if let Some(on_evict) = &self.on_evict {
+            for e in &evicted {
+                on_evict(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventKind, FieldValue, SerdeLayer};
+    use std::sync::Mutex;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn message(e: &Event) -> i64 {
+        match &e.kind {
+            EventKind::Event(fields) => match fields["i"] {
+                FieldValue::Int(i) => i,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn event_store() {
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let store = {
+            let evicted = Arc::clone(&evicted);
+            Arc::new(EventStore::new(6).on_evict(move |e| evicted.lock().unwrap().push(message(e))))
+        };
+        let subscriber = store.subscribe(100);
+        let lagging = store.subscribe(3);
+
+        let layer = SerdeLayer::new().with_writer(Arc::clone(&store)).finish();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            for i in 0..10 {
+                match i % 3 {
+                    0 => tracing::warn!(target: "a", i),
+                    1 => tracing::info!(target: "b", i),
+                    _ => tracing::debug!(target: "a", i),
+                }
+            }
+        });
+
+        assert_eq!(store.len(), 6);
+        assert_eq!(*evicted.lock().unwrap(), vec![0, 1, 2, 3]);
+
+        let query = |filter: EventFilter, limit| -> Vec<_> {
+            store
+                .query(&filter, limit)
+                .iter()
+                .map(|e| message(e))
+                .collect()
+        };
+        assert_eq!(query(EventFilter::new(), 100), vec![4, 5, 6, 7, 8, 9]);
+        assert_eq!(query(EventFilter::new(), 2), vec![8, 9]);
+        assert_eq!(
+            query(EventFilter::new().min_level(Level::Info), 100),
+            vec![4, 6, 7, 9]
+        );
+        assert_eq!(query(EventFilter::new().target("a"), 100), vec![5, 6, 8, 9]);
+        assert_eq!(
+            query(EventFilter::new().target("a").min_level(Level::Warn), 1),
+            vec![9]
+        );
+        assert!(query(EventFilter::new().target("c"), 100).is_empty());
+
+        let received: Vec<_> = subscriber.try_iter().map(|e| message(&e)).collect();
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+        let received: Vec<_> = lagging.try_iter().map(|e| message(&e)).collect();
+        assert_eq!(received, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn wide_integers() {
+        let store = Arc::new(EventStore::new(1));
+        let layer = SerdeLayer::new().with_writer(Arc::clone(&store)).finish();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::info!(huge = u128::MAX);
+        });
+
+        let events = store.query(&EventFilter::new(), 1);
+        match &events[0].kind {
+            EventKind::Event(fields) => {
+                assert_eq!(fields["huge"], FieldValue::Str(u128::MAX.to_string()))
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! Backtraces are only captured when enabled by an environment variable, which can only be set safely before
+//! any other threads start, so this test has its own process and no test harness.
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber_serde::consumer::StreamFormat;
+use tracing_subscriber_serde::format::Json;
+use tracing_subscriber_serde::{Event, EventKind, FieldValue, SerdeLayer};
+
+fn main() {
+    // Read on the first capture
+    std::env::set_var("RUST_LIB_BACKTRACE", "1");
+
+    let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let layer = SerdeLayer::new()
+        .with_backtrace_on_error(true)
+        .with_writer(Arc::clone(&buffer))
+        .finish();
+    tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+        tracing::warn!("warning");
+        tracing::error!("failure");
+    });
+
+    let events: Vec<_> = Json
+        .iter_reader(buffer.lock().unwrap().as_slice())
+        .collect::<std::io::Result<_>>()
+        .unwrap();
+    let backtrace = |e: &Event| match &e.kind {
+        EventKind::Event(fields) => fields.get("backtrace").cloned(),
+        _ => unreachable!(),
+    };
+    assert_eq!(backtrace(&events[0]), None);
+    match backtrace(&events[1]) {
+        Some(FieldValue::Str(s)) => assert!(!s.is_empty()),
+        v => panic!("expected a backtrace, got {:?}", v),
+    }
+}
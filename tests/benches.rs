@@ -1,13 +1,14 @@
 use std::io::Write;
 use std::path::Path;
+use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::fmt::MakeWriter;
-
-#[path = "../benches/common.rs"]
-mod bench_utils;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber_serde::bench_util::{run_workload_with_layer, workloads, InMemoryWriter};
+use tracing_subscriber_serde::{SerdeLayer, SpanEvents};
 
 #[test]
 fn inmemory_writes_to_file() {
-    let (writer, g) = bench_utils::InMemoryWriter::new(Some("test.txt"));
+    let (writer, g) = InMemoryWriter::new(Some("test.txt"));
     let mut w = writer.make_writer();
     writeln!(&mut w, "hello world").unwrap();
     drop(w);
@@ -24,19 +25,72 @@ fn inmemory_writes_to_file() {
 
 #[test]
 fn same_number_of_records() {
-    let (s, g) = bench_utils::setup_tsjson(Some("ts.json"));
-    tracing::subscriber::with_default(s, || bench_utils::workloads::simple(5));
-    drop(g);
+    let writer = InMemoryWriter::default();
+    let l = tracing_subscriber::fmt::Layer::new()
+        .json()
+        .with_span_list(true)
+        .with_current_span(false)
+        .with_span_events(FmtSpan::FULL)
+        .with_writer(writer.clone());
+    let s = tracing_subscriber::registry().with(l);
+    tracing::subscriber::with_default(s, || workloads::simple(5));
+    let ts_json = String::from_utf8(writer.take()).unwrap();
 
-    let (s, g) = bench_utils::setup_serde_json(Some("full.json"));
-    tracing::subscriber::with_default(s, || bench_utils::workloads::simple(5));
-    drop(g);
+    let serde_json =
+        run_workload_with_layer(SerdeLayer::new().with_span_events(SpanEvents::FULL), || {
+            workloads::simple(5)
+        });
+    let serde_json = String::from_utf8(serde_json).unwrap();
 
-    let ts_json = std::fs::read_to_string("ts.json").unwrap();
-    let full_json = std::fs::read_to_string("full.json").unwrap();
+    assert_eq!(ts_json.lines().count(), serde_json.lines().count());
+}
 
-    assert_eq!(ts_json.lines().count(), full_json.lines().count());
+#[test]
+fn span_source_locations_size() {
+    let layer = || SerdeLayer::new().with_span_events(SpanEvents::FULL);
+    let full = run_workload_with_layer(layer(), || workloads::simple(5));
+    let events_only = run_workload_with_layer(layer().with_source_location_spans(false), || {
+        workloads::simple(5)
+    });
+    let none =
+        run_workload_with_layer(layer().with_source_location(false), || workloads::simple(5));
+
+    println!(
+        "simple workload: {} bytes with all source locations, {} with event locations only, {} with none",
+        full.len(),
+        events_only.len(),
+        none.len()
+    );
+    // Span events make up most of the records
+    assert!(full.len() - events_only.len() > events_only.len() - none.len());
+}
+
+#[test]
+fn fixtures_are_deterministic() {
+    use tracing_subscriber_serde::bench_util::generate_fixture;
+    use tracing_subscriber_serde::format::Json;
+
+    let a = generate_fixture(Json, || workloads::many_fields(10));
+    let b = generate_fixture(Json, || workloads::many_fields(10));
+    assert!(!a.is_empty());
+    assert_eq!(a, b);
+}
+
+#[cfg(feature = "consumer")]
+#[test]
+fn fixtures_deserialize() {
+    use tracing_subscriber_serde::bench_util::generate_fixture;
+    use tracing_subscriber_serde::consumer::StreamFormat;
+    use tracing_subscriber_serde::format::Json;
 
-    std::fs::remove_file("ts.json").ok();
-    std::fs::remove_file("full.json").ok(); // s
+    let data = generate_fixture(Json, || workloads::deeply_nested((5, 10)));
+    let num_lines = data
+        .split(|&b| b == b'\n')
+        .filter(|l| !l.is_empty())
+        .count();
+    let num_events = Json
+        .iter_reader(data.as_slice())
+        .map(|e| e.unwrap())
+        .count();
+    assert_eq!(num_lines, num_events);
 }
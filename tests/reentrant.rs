@@ -0,0 +1,57 @@
+//! Events emitted by the writer itself, with the global default dispatcher.  `tracing` drops these for scoped
+//! dispatchers, so this is the only test in its process.
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber_serde::consumer::StreamFormat;
+use tracing_subscriber_serde::format::Json;
+use tracing_subscriber_serde::writer::WriteEvent;
+use tracing_subscriber_serde::{FieldValue, SerdeFormat, SerdeLayer, SuppressReason};
+
+/// Logs while holding the lock on its buffer, so a nested write would deadlock.
+struct LoggingWriter(Arc<Mutex<Vec<u8>>>);
+
+impl WriteEvent for LoggingWriter {
+    fn write(&self, fmt: impl SerdeFormat, event: impl Serialize) -> std::io::Result<()> {
+        let mut buffer = self.0.lock().unwrap();
+        tracing::warn!("writing");
+        fmt.serialize(&mut *buffer, event)
+    }
+}
+
+#[test]
+fn reentrant_events_are_dropped() {
+    let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let layer = SerdeLayer::new()
+        .with_filter_diagnostics(true)
+        .with_writer(LoggingWriter(Arc::clone(&buffer)))
+        .finish();
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer)).unwrap();
+
+    let span = tracing::info_span!("outer span");
+    span.in_scope(|| {
+        for i in 0..3 {
+            tracing::info!(i, "outer");
+        }
+    });
+    drop(span);
+
+    let messages: Vec<_> = Json
+        .iter_reader(buffer.lock().unwrap().as_slice())
+        .filter_map(|e| e.unwrap().field("message").cloned())
+        .collect();
+    assert_eq!(messages, vec![FieldValue::from("outer"); 3]);
+
+    let diags = tracing::dispatcher::get_default(|dispatch| {
+        dispatch
+            .downcast_ref::<SerdeLayer<Json, (), LoggingWriter>>()
+            .unwrap()
+            .diagnostics()
+    });
+    let nested = diags
+        .iter()
+        .find(|d| d.suppressed(SuppressReason::Reentrant) > 0)
+        .unwrap();
+    assert_eq!(nested.seen, nested.suppressed(SuppressReason::Reentrant));
+    assert_eq!(nested.written(), 0);
+}